@@ -0,0 +1,282 @@
+use crate::{
+    config::{BrokerConfig, parse_properties},
+    log::LogManager,
+    metadata::{
+        BrokerEndpoint, BrokerRecord, MetadataWriter, RecordBatch as MetadataBatch,
+        RegisterBrokerRecord,
+    },
+    raft::RaftQuorum,
+    request::{ApiType, ErrorCode, write_compact_nullable_string, write_compact_string},
+    unsigned_varint_encode,
+};
+use anyhow::{Result, bail};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use uuid::Uuid;
+
+use std::{collections::BTreeMap, sync::Mutex};
+
+const META_PROPERTIES: &str = "meta.properties";
+pub const BROKER_REGISTRATION_VERSION: i16 = 3;
+
+/// A broker as last registered in the metadata log.
+#[derive(Debug, Clone)]
+pub struct Broker {
+    pub id: i32,
+    pub epoch: i64,
+    pub incarnation_id: Uuid,
+    pub endpoints: Box<[BrokerEndpoint]>,
+    pub rack: Option<Bytes>,
+    pub fenced: bool,
+    pub in_controlled_shutdown: bool,
+}
+
+impl Broker {
+    /// The broker's endpoint on the named listener.
+    pub fn endpoint(&self, listener_name: &[u8]) -> Option<&BrokerEndpoint> {
+        self.endpoints
+            .iter()
+            .find(|endpoint| endpoint.name == listener_name)
+    }
+}
+
+/// The brokers of the cluster, replayed from the registration, fencing and unregistration
+/// records of the metadata log. With the embedded quorum this broker registers itself with
+/// the active controller on startup. A log written by someone else is left alone, and a
+/// broker it does not list yet knows only of itself, unregistered.
+#[derive(Debug)]
+pub struct BrokerRegistry {
+    node_id: i32,
+    // Clients are given the endpoints on the listener this broker advertises first
+    listener_name: Bytes,
+    cluster_id: Option<Bytes>,
+    brokers: Mutex<BTreeMap<i32, Broker>>,
+}
+
+impl BrokerRegistry {
+    pub fn new(
+        config: &BrokerConfig,
+        metadata: &[MetadataBatch],
+        logs: &LogManager,
+        writer: &MetadataWriter,
+    ) -> Self {
+        let mut brokers = BTreeMap::new();
+        for record in metadata.iter().flat_map(|batch| batch.broker_records()) {
+            apply(&mut brokers, record);
+        }
+
+        let listener_name = config
+            .broker_listeners()
+            .next()
+            .map(|listener| Bytes::from(listener.name.clone()))
+            .unwrap_or_default();
+        let cluster_id = std::fs::read_to_string(config.metadata_log_dir.join(META_PROPERTIES))
+            .ok()
+            .and_then(|content| parse_properties(&content).remove("cluster.id"))
+            .map(Bytes::from);
+
+        if let MetadataWriter::Log(_) = writer
+            && !brokers.contains_key(&config.node_id)
+        {
+            apply(
+                &mut brokers,
+                &BrokerRecord::Register(registration(config, logs)),
+            );
+        }
+
+        Self {
+            node_id: config.node_id,
+            listener_name,
+            cluster_id,
+            brokers: Mutex::new(brokers),
+        }
+    }
+
+    /// Registers this broker with the active controller through BrokerRegistration,
+    /// returning the broker epoch it was given. The registration shows up here once it is
+    /// committed and replayed.
+    pub async fn register_with_controller(
+        &self,
+        config: &BrokerConfig,
+        logs: &LogManager,
+        quorum: &RaftQuorum,
+    ) -> Result<i64> {
+        let record = registration(config, logs);
+
+        let mut body = BytesMut::new();
+        body.put_i32(record.broker_id);
+        write_compact_string(&mut body, quorum.cluster_id());
+        body.put_u128(record.incarnation_id.as_u128());
+        unsigned_varint_encode(&mut body, record.endpoints.len());
+        for endpoint in record.endpoints.iter() {
+            write_compact_string(&mut body, &endpoint.name);
+            write_compact_string(&mut body, &endpoint.host);
+            body.put_u16(endpoint.port);
+            body.put_i16(endpoint.security_protocol);
+            // Tags
+            body.put_i8(0x00);
+        }
+        // Supported features
+        unsigned_varint_encode(&mut body, 0);
+        write_compact_nullable_string(&mut body, record.rack.as_deref());
+        // Migrating ZooKeeper broker
+        body.put_i8(0);
+        unsigned_varint_encode(&mut body, record.log_dirs.len());
+        for dir in record.log_dirs.iter() {
+            body.put_u128(dir.as_u128());
+        }
+        // Previous broker epoch
+        body.put_i64(-1);
+        // Tags
+        body.put_i8(0x00);
+
+        let mut response = quorum
+            .call_leader(
+                ApiType::BrokerRegistration,
+                BROKER_REGISTRATION_VERSION,
+                &body,
+            )
+            .await?;
+        if response.remaining() < 4 + 2 + 8 {
+            bail!("truncated registration response");
+        }
+        let _throttle_time = response.get_i32();
+        let error_code = response.get_i16();
+        let broker_epoch = response.get_i64();
+        if error_code != ErrorCode::None as i16 {
+            bail!("controller returned error {error_code}");
+        }
+
+        Ok(broker_epoch)
+    }
+
+    /// Replays the broker records of newly committed metadata.
+    pub fn replay(&self, metadata: &[MetadataBatch]) {
+        let mut brokers = self.brokers.lock().expect("broker registry lock poisoned");
+        for record in metadata.iter().flat_map(|batch| batch.broker_records()) {
+            apply(&mut brokers, record);
+        }
+    }
+
+    pub fn node_id(&self) -> i32 {
+        self.node_id
+    }
+
+    pub fn listener_name(&self) -> &Bytes {
+        &self.listener_name
+    }
+
+    pub fn cluster_id(&self) -> Option<&Bytes> {
+        self.cluster_id.as_ref()
+    }
+
+    /// The broker clients are pointed at for controller requests. As in KRaft, where
+    /// clients cannot reach the quorum, that is a live broker: this one.
+    pub fn controller_id(&self) -> i32 {
+        self.node_id
+    }
+
+    /// The broker coordinating the groups or transactional ids hashed to a partition of
+    /// `__consumer_offsets` or `__transaction_state`: the partition's leader. The internal
+    /// topics are kept outside the metadata log, so their partitions are spread over the
+    /// live brokers by id. A broker that knows of no live broker yet leads them all.
+    pub fn coordinator(&self, partition: i32) -> i32 {
+        let brokers = self.brokers(false);
+        match brokers.len() {
+            0 => self.node_id,
+            len => brokers[partition as usize % len].id,
+        }
+    }
+
+    /// Checks that this broker coordinates the groups or transactional ids hashed to the
+    /// partition, the only one to serve requests for them.
+    pub fn check_coordinator(&self, partition: i32) -> Result<(), ErrorCode> {
+        match self.coordinator(partition) {
+            coordinator if coordinator == self.node_id => Ok(()),
+            _ => Err(ErrorCode::NotCoordinator),
+        }
+    }
+
+    /// Registered brokers ordered by id, fenced ones only when asked for.
+    pub fn brokers(&self, include_fenced: bool) -> Vec<Broker> {
+        let brokers = self.brokers.lock().expect("broker registry lock poisoned");
+        brokers
+            .values()
+            .filter(|broker| include_fenced || !broker.fenced)
+            .cloned()
+            .collect()
+    }
+}
+
+/// This broker's registration with its advertised endpoints and log directories, the epoch
+/// left for whoever writes it to fill in.
+fn registration(config: &BrokerConfig, logs: &LogManager) -> RegisterBrokerRecord {
+    let endpoints = config
+        .broker_listeners()
+        .map(|listener| BrokerEndpoint {
+            name: Bytes::from(listener.name.clone()),
+            host: match listener.host.is_empty() {
+                true => Bytes::from_static(b"localhost"),
+                false => Bytes::from(listener.host.clone()),
+            },
+            port: listener.port,
+            security_protocol: config.security_protocol(&listener.name),
+        })
+        .collect::<Vec<_>>();
+    let rack = config
+        .property("broker.rack")
+        .map(|rack| Bytes::copy_from_slice(rack.as_bytes()));
+    let log_dirs = logs.log_dirs().iter().map(|dir| dir.id).collect::<Vec<_>>();
+
+    RegisterBrokerRecord {
+        version: RegisterBrokerRecord::VERSION,
+        broker_id: config.node_id,
+        is_migrating_zk_broker: false,
+        incarnation_id: Uuid::new_v4(),
+        broker_epoch: -1,
+        endpoints: endpoints.into_boxed_slice(),
+        features: Box::new([]),
+        rack,
+        fenced: false,
+        in_controlled_shutdown: false,
+        log_dirs: log_dirs.into_boxed_slice(),
+        tags: 0,
+    }
+}
+
+/// Replays a record onto the registry. Changes for an epoch other than the broker's current
+/// registration are stale and ignored.
+fn apply(brokers: &mut BTreeMap<i32, Broker>, change: &BrokerRecord) {
+    match change {
+        BrokerRecord::Register(record) => {
+            brokers.insert(
+                record.broker_id,
+                Broker {
+                    id: record.broker_id,
+                    epoch: record.broker_epoch,
+                    incarnation_id: record.incarnation_id,
+                    endpoints: record.endpoints.clone(),
+                    rack: record.rack.clone(),
+                    fenced: record.fenced,
+                    in_controlled_shutdown: record.in_controlled_shutdown,
+                },
+            );
+        }
+        BrokerRecord::Unregister(record) => {
+            if brokers
+                .get(&record.broker_id)
+                .is_some_and(|broker| broker.epoch == record.broker_epoch)
+            {
+                brokers.remove(&record.broker_id);
+            }
+        }
+        BrokerRecord::Fence(record) | BrokerRecord::Unfence(record) => {
+            let fenced = matches!(change, BrokerRecord::Fence(_));
+            if let Some(broker) = brokers
+                .get_mut(&record.broker_id)
+                .filter(|broker| broker.epoch == record.broker_epoch)
+            {
+                broker.fenced = fenced;
+            }
+        }
+    }
+}
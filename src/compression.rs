@@ -0,0 +1,261 @@
+use anyhow::{Context, Result, bail};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use flate2::{Compression as GzipLevel, read::GzDecoder, write::GzEncoder};
+use ruzstd::{
+    decoding::StreamingDecoder,
+    encoding::{CompressionLevel, compress_to_vec},
+};
+
+use std::io::{Read, Write};
+use thiserror::Error;
+
+// Framing used by the Java client's snappy-java `SnappyOutputStream`
+const XERIAL_MAGIC: &[u8] = &[0x82, b'S', b'N', b'A', b'P', b'P', b'Y', 0x00];
+const XERIAL_HEADER_LEN: usize = 16;
+const XERIAL_BLOCK_SIZE: usize = 32 * 1024;
+
+pub const COMPRESSION_MASK: i16 = 0x07;
+
+/// Decompressing stopped once the output outgrew the caller's limit, so a small batch
+/// cannot expand into an arbitrarily large allocation.
+#[derive(Debug, Error)]
+#[error("decompressed records exceed {0} bytes")]
+pub struct DecompressedTooLarge(pub usize);
+
+#[repr(i16)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Compression {
+    None = 0,
+    Gzip = 1,
+    Snappy = 2,
+    Lz4 = 3,
+    Zstd = 4,
+}
+
+impl Compression {
+    pub fn from_attributes(attributes: i16) -> Result<Self> {
+        Self::try_from(attributes & COMPRESSION_MASK)
+    }
+
+    /// Maps a topic's `compression.type` onto a codec. `producer` (and anything unset)
+    /// returns `None` as the broker keeps whatever the producer sent.
+    pub fn from_config(value: &[u8]) -> Option<Self> {
+        match value {
+            b"uncompressed" => Some(Self::None),
+            b"gzip" => Some(Self::Gzip),
+            b"snappy" => Some(Self::Snappy),
+            b"lz4" => Some(Self::Lz4),
+            b"zstd" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Decompresses a batch's records, failing with [`DecompressedTooLarge`] as soon as
+    /// they outgrow `max_len` bytes.
+    pub fn decompress(&self, data: &[u8], max_len: usize) -> Result<Bytes> {
+        // One byte past the limit tells an oversized payload from one that fits exactly
+        let limit = max_len.saturating_add(1) as u64;
+        let mut out = Vec::new();
+        match self {
+            Self::None => out.extend_from_slice(data),
+            Self::Gzip => {
+                GzDecoder::new(data)
+                    .take(limit)
+                    .read_to_end(&mut out)
+                    .context("decoding gzip records")?;
+            }
+            Self::Snappy => out = snappy_decompress(data, max_len)?,
+            Self::Lz4 => {
+                lz4_flex::frame::FrameDecoder::new(data)
+                    .take(limit)
+                    .read_to_end(&mut out)
+                    .context("decoding lz4 records")?;
+            }
+            Self::Zstd => {
+                StreamingDecoder::new(data)
+                    .context("reading zstd frame header")?
+                    .take(limit)
+                    .read_to_end(&mut out)
+                    .context("decoding zstd records")?;
+            }
+        }
+
+        if out.len() > max_len {
+            bail!(DecompressedTooLarge(max_len));
+        }
+
+        Ok(Bytes::from(out))
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Bytes> {
+        let out = match self {
+            Self::None => data.to_vec(),
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), GzipLevel::default());
+                encoder.write_all(data).context("encoding gzip records")?;
+                encoder.finish().context("finishing gzip stream")?
+            }
+            Self::Snappy => snappy_compress(data)?,
+            Self::Lz4 => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                encoder.write_all(data).context("encoding lz4 records")?;
+                encoder.finish().context("finishing lz4 frame")?
+            }
+            Self::Zstd => compress_to_vec(data, CompressionLevel::Fastest),
+        };
+
+        Ok(Bytes::from(out))
+    }
+}
+
+impl TryFrom<i16> for Compression {
+    type Error = anyhow::Error;
+
+    fn try_from(value: i16) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Gzip),
+            2 => Ok(Self::Snappy),
+            3 => Ok(Self::Lz4),
+            4 => Ok(Self::Zstd),
+            _ => bail!("invalid compression codec: {value}"),
+        }
+    }
+}
+
+/// Snappy payloads come either xerial-framed (Java clients) or as a single raw block
+/// (librdkafka and friends), so both are accepted. Every block states its decompressed
+/// length up front, which is checked against `max_len` before decoding it.
+fn snappy_decompress(data: &[u8], max_len: usize) -> Result<Vec<u8>> {
+    let mut decoder = snap::raw::Decoder::new();
+    if !data.starts_with(XERIAL_MAGIC) {
+        let len = snap::raw::decompress_len(data).context("reading snappy length")?;
+        if len > max_len {
+            bail!(DecompressedTooLarge(max_len));
+        }
+
+        return decoder
+            .decompress_vec(data)
+            .context("decoding snappy records");
+    }
+
+    if data.len() < XERIAL_HEADER_LEN {
+        bail!("truncated xerial snappy header");
+    }
+
+    let mut out = Vec::new();
+    let mut blocks = Bytes::copy_from_slice(&data[XERIAL_HEADER_LEN..]);
+    while blocks.has_remaining() {
+        if blocks.remaining() < 4 {
+            bail!("truncated xerial snappy block length");
+        }
+
+        let block_len = blocks.get_i32();
+        if block_len < 0 || block_len as usize > blocks.remaining() {
+            bail!("invalid xerial snappy block length: {block_len}");
+        }
+
+        let block = blocks.split_to(block_len as usize);
+        let len = snap::raw::decompress_len(&block).context("reading snappy block length")?;
+        if out.len() + len > max_len {
+            bail!(DecompressedTooLarge(max_len));
+        }
+
+        let decoded = decoder
+            .decompress_vec(&block)
+            .context("decoding snappy block")?;
+        out.extend_from_slice(&decoded);
+    }
+
+    Ok(out)
+}
+
+fn snappy_compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = snap::raw::Encoder::new();
+    let mut out = BytesMut::new();
+    out.extend_from_slice(XERIAL_MAGIC);
+    // Version and minimum compatible version
+    out.put_i32(1);
+    out.put_i32(1);
+
+    for chunk in data.chunks(XERIAL_BLOCK_SIZE) {
+        let block = encoder
+            .compress_vec(chunk)
+            .context("encoding snappy block")?;
+        out.put_i32(block.len() as i32);
+        out.extend_from_slice(&block);
+    }
+
+    Ok(out.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODECS: [Compression; 5] = [
+        Compression::None,
+        Compression::Gzip,
+        Compression::Snappy,
+        Compression::Lz4,
+        Compression::Zstd,
+    ];
+
+    fn records() -> Vec<u8> {
+        (0..100_000u32)
+            .flat_map(|i| (i % 251).to_be_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn codecs_round_trip() {
+        let data = records();
+        for codec in CODECS {
+            let compressed = codec.compress(&data).unwrap();
+            let decompressed = codec.decompress(&compressed, data.len()).unwrap();
+            assert_eq!(decompressed, data, "{codec:?}");
+        }
+    }
+
+    #[test]
+    fn decompression_stops_at_the_limit() {
+        let data = vec![0u8; 1 << 20];
+        for codec in CODECS {
+            let compressed = codec.compress(&data).unwrap();
+            let err = codec.decompress(&compressed, data.len() - 1).unwrap_err();
+            assert!(err.is::<DecompressedTooLarge>(), "{codec:?}: {err:#}");
+        }
+    }
+
+    #[test]
+    fn raw_snappy_blocks_are_accepted() {
+        let data = records();
+        let raw = snap::raw::Encoder::new().compress_vec(&data).unwrap();
+        assert_eq!(
+            Compression::Snappy.decompress(&raw, data.len()).unwrap(),
+            data
+        );
+
+        let err = Compression::Snappy.decompress(&raw, 16).unwrap_err();
+        assert!(err.is::<DecompressedTooLarge>());
+    }
+
+    #[test]
+    fn codec_comes_from_the_low_attribute_bits() {
+        assert_eq!(
+            Compression::from_attributes(0x14).unwrap(),
+            Compression::Zstd
+        );
+        assert_eq!(
+            Compression::from_attributes(0x30).unwrap(),
+            Compression::None
+        );
+        assert!(Compression::from_attributes(0x05).is_err());
+    }
+
+    #[test]
+    fn producer_compression_type_keeps_the_batch_codec() {
+        assert_eq!(Compression::from_config(b"lz4"), Some(Compression::Lz4));
+        assert_eq!(Compression::from_config(b"producer"), None);
+    }
+}
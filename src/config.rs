@@ -0,0 +1,1223 @@
+use crate::{
+    log::{FlushPolicy, LogManager},
+    metadata::{
+        METADATA_FRAME_VERSION, MetadataCache, MetadataWriter, RecordBatch as MetadataBatch,
+    },
+    raft::RaftQuorum,
+    record::Record,
+    request::{
+        ApiType, ErrorCode, TryGet, read_compact_len, read_compact_nullable_string,
+        write_compact_nullable_string, write_compact_string,
+    },
+    unsigned_varint_decode, unsigned_varint_encode,
+};
+use anyhow::{Context, Result, bail};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+const DEFAULT_LISTENERS: &str = "PLAINTEXT://:9092";
+const DEFAULT_LOG_DIR: &str = "/tmp/kraft-combined-logs";
+const DEFAULT_NODE_ID: i32 = 1;
+const DEFAULT_WORKER_COUNT: usize = 10;
+// Kafka's socket.request.max.bytes default
+const DEFAULT_REQUEST_MAX_BYTES: usize = 104857600;
+// Version config changes are forwarded to the active controller with
+const INCREMENTAL_ALTER_CONFIGS_VERSION: i16 = 1;
+
+/// A named endpoint from `listeners` or `advertised.listeners`, as in
+/// `PLAINTEXT://localhost:9092`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listener {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl Listener {
+    fn parse_list(value: &str) -> Result<Vec<Self>> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|listener| !listener.is_empty())
+            .map(|listener| {
+                let (name, address) = listener
+                    .split_once("://")
+                    .with_context(|| format!("listener {listener:?} has no name"))?;
+                let (host, port) = address
+                    .rsplit_once(':')
+                    .with_context(|| format!("listener {listener:?} has no port"))?;
+                let port = port
+                    .parse()
+                    .with_context(|| format!("listener {listener:?} has an invalid port"))?;
+
+                Ok(Self {
+                    name: name.to_string(),
+                    host: host.trim_matches(['[', ']']).to_string(),
+                    port,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A member of the KRaft quorum from `controller.quorum.voters`, as in `1@localhost:9093`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuorumVoter {
+    pub id: i32,
+    pub host: String,
+    pub port: u16,
+}
+
+impl QuorumVoter {
+    fn parse_list(value: &str) -> Result<Vec<Self>> {
+        let voters = value
+            .split(',')
+            .map(str::trim)
+            .filter(|voter| !voter.is_empty())
+            .map(|voter| {
+                let (id, address) = voter
+                    .split_once('@')
+                    .with_context(|| format!("voter {voter:?} is not id@host:port"))?;
+                let (host, port) = address
+                    .rsplit_once(':')
+                    .with_context(|| format!("voter {voter:?} has no port"))?;
+
+                Ok(Self {
+                    id: id
+                        .parse()
+                        .with_context(|| format!("voter {voter:?} has an invalid id"))?,
+                    host: host.trim_matches(['[', ']']).to_string(),
+                    port: port
+                        .parse()
+                        .with_context(|| format!("voter {voter:?} has an invalid port"))?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut ids = BTreeSet::new();
+        if let Some(voter) = voters.iter().find(|voter| !ids.insert(voter.id)) {
+            bail!("voter {} is listed twice", voter.id);
+        }
+        Ok(voters)
+    }
+
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+/// Broker settings, read from a `server.properties` style file and overridden from the
+/// command line with `--override key=value`, the way Kafka's start script takes them.
+#[derive(Debug, Clone)]
+pub struct BrokerConfig {
+    pub node_id: i32,
+    pub listeners: Vec<Listener>,
+    pub advertised_listeners: Vec<Listener>,
+    pub controller_listener_names: Vec<String>,
+    // `broker` and `controller`. Without any, the metadata log is generated externally
+    pub process_roles: Vec<String>,
+    pub quorum_voters: Vec<QuorumVoter>,
+    pub log_dirs: Vec<PathBuf>,
+    pub metadata_log_dir: PathBuf,
+    pub worker_count: usize,
+    pub request_max_bytes: usize,
+    properties: HashMap<String, String>,
+}
+
+impl Default for BrokerConfig {
+    fn default() -> Self {
+        Self::from_properties(HashMap::new()).expect("default configuration is valid")
+    }
+}
+
+impl BrokerConfig {
+    /// Reads the configuration from the program arguments: an optional properties file
+    /// followed by any number of overrides.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut properties = HashMap::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let assignment = match arg.as_str() {
+                "--override" => args.next().context("--override needs a key=value")?,
+                _ if arg.starts_with("--override=") => arg["--override=".len()..].to_string(),
+                _ if arg.starts_with("--") => bail!("unknown flag {arg:?}"),
+                _ => {
+                    let content = std::fs::read_to_string(&arg)
+                        .with_context(|| format!("reading configuration file {arg}"))?;
+                    properties.extend(parse_properties(&content));
+                    continue;
+                }
+            };
+
+            let (key, value) = assignment
+                .split_once('=')
+                .with_context(|| format!("override {assignment:?} is not key=value"))?;
+            properties.insert(key.trim().to_string(), value.trim().to_string());
+        }
+
+        Self::from_properties(properties)
+    }
+
+    fn from_properties(properties: HashMap<String, String>) -> Result<Self> {
+        let get = |key: &str| properties.get(key).map(String::as_str);
+        let parse_number = |key: &str| -> Result<Option<i64>> {
+            get(key)
+                .map(|value| value.parse::<i64>())
+                .transpose()
+                .with_context(|| format!("{key} is not a number"))
+        };
+
+        let node_id = match parse_number("node.id")?.or(parse_number("broker.id")?) {
+            Some(node_id) => i32::try_from(node_id).context("node.id is out of range")?,
+            None => DEFAULT_NODE_ID,
+        };
+        let listeners = Listener::parse_list(get("listeners").unwrap_or(DEFAULT_LISTENERS))?;
+        let advertised_listeners = match get("advertised.listeners") {
+            Some(value) => Listener::parse_list(value)?,
+            None => listeners.clone(),
+        };
+        let controller_listener_names = get("controller.listener.names")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect::<Vec<String>>();
+
+        let process_roles = get("process.roles")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|role| !role.is_empty())
+            .map(String::from)
+            .collect::<Vec<_>>();
+        if let Some(role) = process_roles
+            .iter()
+            .find(|role| !matches!(role.as_str(), "broker" | "controller"))
+        {
+            bail!("unknown process role {role:?}");
+        }
+        let quorum_voters =
+            QuorumVoter::parse_list(get("controller.quorum.voters").unwrap_or_default())?;
+        if !process_roles.is_empty() && quorum_voters.is_empty() {
+            bail!("process.roles needs controller.quorum.voters");
+        }
+        if process_roles.iter().any(|role| role == "controller") {
+            if !quorum_voters.iter().any(|voter| voter.id == node_id) {
+                bail!("controller {node_id} is not in controller.quorum.voters");
+            }
+            if !listeners
+                .iter()
+                .any(|listener| controller_listener_names.contains(&listener.name))
+            {
+                bail!("controllers need a listener named in controller.listener.names");
+            }
+        }
+
+        let log_dirs: Vec<PathBuf> = get("log.dirs")
+            .or(get("log.dir"))
+            .unwrap_or(DEFAULT_LOG_DIR)
+            .split(',')
+            .map(str::trim)
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .collect();
+        if log_dirs.is_empty() {
+            bail!("log.dirs names no directory");
+        }
+        let metadata_log_dir = get("metadata.log.dir")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| log_dirs[0].clone());
+
+        let worker_count = match parse_number("num.io.threads")? {
+            Some(count) if count > 0 => count as usize,
+            Some(_) => bail!("num.io.threads has to be positive"),
+            None => DEFAULT_WORKER_COUNT,
+        };
+        let request_max_bytes = match parse_number("socket.request.max.bytes")? {
+            Some(max) if max > 0 => max as usize,
+            Some(_) => bail!("socket.request.max.bytes has to be positive"),
+            None => DEFAULT_REQUEST_MAX_BYTES,
+        };
+
+        Ok(Self {
+            node_id,
+            listeners,
+            advertised_listeners,
+            controller_listener_names,
+            process_roles,
+            quorum_voters,
+            log_dirs,
+            metadata_log_dir,
+            worker_count,
+            request_max_bytes,
+            properties,
+        })
+    }
+
+    /// Addresses to accept clients on. Controller listeners serve the KRaft quorum and are
+    /// bound apart, see `controller_bind_addresses`.
+    pub fn bind_addresses(&self) -> Vec<String> {
+        self.listeners
+            .iter()
+            .filter(|listener| !self.controller_listener_names.contains(&listener.name))
+            .map(bind_address)
+            .collect()
+    }
+
+    /// Addresses the other voters reach this controller on.
+    pub fn controller_bind_addresses(&self) -> Vec<String> {
+        self.listeners
+            .iter()
+            .filter(|listener| self.controller_listener_names.contains(&listener.name))
+            .map(bind_address)
+            .collect()
+    }
+
+    /// Whether this node serves clients. Nodes without `process.roles` are brokers only.
+    pub fn is_broker(&self) -> bool {
+        self.process_roles.is_empty() || self.process_roles.iter().any(|role| role == "broker")
+    }
+
+    /// Whether this node is a voter of the embedded KRaft quorum.
+    pub fn is_controller(&self) -> bool {
+        self.process_roles.iter().any(|role| role == "controller")
+    }
+
+    /// Whether the metadata log comes from the embedded quorum, this node voting in it or
+    /// only following it, rather than being generated externally.
+    pub fn uses_quorum(&self) -> bool {
+        !self.process_roles.is_empty()
+    }
+
+    /// Advertised listeners clients may connect to, leaving out the controller ones.
+    pub fn broker_listeners(&self) -> impl Iterator<Item = &Listener> {
+        self.advertised_listeners
+            .iter()
+            .filter(|listener| !self.controller_listener_names.contains(&listener.name))
+    }
+
+    /// Host and port clients are told to connect to, from the first advertised listener
+    /// that is not a controller one.
+    pub fn advertised_endpoint(&self) -> (String, i32) {
+        self.broker_listeners()
+            .next()
+            .map(|listener| {
+                let host = if listener.host.is_empty() {
+                    "localhost".to_string()
+                } else {
+                    listener.host.clone()
+                };
+                (host, listener.port as i32)
+            })
+            .unwrap_or_else(|| ("localhost".to_string(), 9092))
+    }
+
+    /// Kafka's `SecurityProtocol` id for a listener, mapped through
+    /// `listener.security.protocol.map` or else taken from the listener's own name.
+    pub fn security_protocol(&self, listener_name: &str) -> i16 {
+        let protocol = self
+            .property("listener.security.protocol.map")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|mapping| mapping.split_once(':'))
+            .find(|(name, _)| name.trim() == listener_name)
+            .map_or(listener_name, |(_, protocol)| protocol.trim());
+
+        match protocol {
+            "SSL" => 1,
+            "SASL_PLAINTEXT" => 2,
+            "SASL_SSL" => 3,
+            _ => 0,
+        }
+    }
+
+    /// A property as set in the configuration file or overridden on the command line.
+    pub fn property(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(String::as_str)
+    }
+
+    pub fn property_names(&self) -> impl Iterator<Item = &str> {
+        self.properties.keys().map(String::as_str)
+    }
+}
+
+fn bind_address(listener: &Listener) -> String {
+    let host = if listener.host.is_empty() {
+        "0.0.0.0"
+    } else {
+        &listener.host
+    };
+    format!("{host}:{}", listener.port)
+}
+
+/// Parses `key=value` lines, skipping blank lines and `#` or `!` comments. Keys may also be
+/// separated from their value by a colon.
+pub fn parse_properties(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('!'))
+        .filter_map(|line| {
+            let (key, value) = line.split_once(['=', ':'])?;
+            Some((key.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Where the value DescribeConfigs reports for a config comes from.
+#[repr(i8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigSource {
+    DynamicTopic = 1,
+    DynamicBroker = 2,
+    DynamicDefaultBroker = 3,
+    StaticBroker = 4,
+    Default = 5,
+}
+
+#[repr(i8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigType {
+    Unknown = 0,
+    Boolean = 1,
+    String = 2,
+    Int = 3,
+    Long = 5,
+    List = 7,
+}
+
+/// How IncrementalAlterConfigs changes a config.
+#[repr(i8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigOperation {
+    Set = 0,
+    Delete = 1,
+    Append = 2,
+    Subtract = 3,
+}
+
+impl TryFrom<i8> for ConfigOperation {
+    type Error = ErrorCode;
+
+    fn try_from(value: i8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Set),
+            1 => Ok(Self::Delete),
+            2 => Ok(Self::Append),
+            3 => Ok(Self::Subtract),
+            _ => Err(ErrorCode::InvalidRequest),
+        }
+    }
+}
+
+/// A topic config this broker knows, with the broker property that sets its default for
+/// every topic.
+#[derive(Debug)]
+pub struct ConfigDef {
+    pub name: &'static str,
+    pub synonym: &'static str,
+    pub config_type: ConfigType,
+    pub default: &'static str,
+    // Smallest accepted value of numeric configs
+    min: i64,
+    // Accepted values, or items of list configs, anything goes when empty
+    valid_values: &'static [&'static str],
+}
+
+pub const TOPIC_CONFIGS: &[ConfigDef] = &[
+    ConfigDef {
+        name: "cleanup.policy",
+        synonym: "log.cleanup.policy",
+        config_type: ConfigType::List,
+        default: "delete",
+        min: 0,
+        valid_values: &["compact", "delete"],
+    },
+    ConfigDef {
+        name: "compression.type",
+        synonym: "compression.type",
+        config_type: ConfigType::String,
+        default: "producer",
+        min: 0,
+        valid_values: &["uncompressed", "zstd", "lz4", "snappy", "gzip", "producer"],
+    },
+    ConfigDef {
+        name: "delete.retention.ms",
+        synonym: "log.cleaner.delete.retention.ms",
+        config_type: ConfigType::Long,
+        default: "86400000",
+        min: 0,
+        valid_values: &[],
+    },
+    ConfigDef {
+        name: "flush.messages",
+        synonym: "log.flush.interval.messages",
+        config_type: ConfigType::Long,
+        default: "9223372036854775807",
+        min: 1,
+        valid_values: &[],
+    },
+    ConfigDef {
+        name: "flush.ms",
+        synonym: "log.flush.interval.ms",
+        config_type: ConfigType::Long,
+        default: "9223372036854775807",
+        min: 0,
+        valid_values: &[],
+    },
+    ConfigDef {
+        name: "max.message.bytes",
+        synonym: "message.max.bytes",
+        config_type: ConfigType::Int,
+        default: "1048588",
+        min: 0,
+        valid_values: &[],
+    },
+    ConfigDef {
+        name: "message.timestamp.after.max.ms",
+        synonym: "log.message.timestamp.after.max.ms",
+        config_type: ConfigType::Long,
+        default: "9223372036854775807",
+        min: 0,
+        valid_values: &[],
+    },
+    ConfigDef {
+        name: "message.timestamp.before.max.ms",
+        synonym: "log.message.timestamp.before.max.ms",
+        config_type: ConfigType::Long,
+        default: "9223372036854775807",
+        min: 0,
+        valid_values: &[],
+    },
+    ConfigDef {
+        name: "message.timestamp.difference.max.ms",
+        synonym: "log.message.timestamp.difference.max.ms",
+        config_type: ConfigType::Long,
+        default: "9223372036854775807",
+        min: 0,
+        valid_values: &[],
+    },
+    ConfigDef {
+        name: "message.timestamp.type",
+        synonym: "log.message.timestamp.type",
+        config_type: ConfigType::String,
+        default: "CreateTime",
+        min: 0,
+        valid_values: &["CreateTime", "LogAppendTime"],
+    },
+    ConfigDef {
+        name: "min.insync.replicas",
+        synonym: "min.insync.replicas",
+        config_type: ConfigType::Int,
+        default: "1",
+        min: 1,
+        valid_values: &[],
+    },
+    ConfigDef {
+        name: "retention.bytes",
+        synonym: "log.retention.bytes",
+        config_type: ConfigType::Long,
+        default: "-1",
+        min: i64::MIN,
+        valid_values: &[],
+    },
+    ConfigDef {
+        name: "retention.ms",
+        synonym: "log.retention.ms",
+        config_type: ConfigType::Long,
+        default: "604800000",
+        min: -1,
+        valid_values: &[],
+    },
+    ConfigDef {
+        name: "segment.bytes",
+        synonym: "log.segment.bytes",
+        config_type: ConfigType::Int,
+        default: "1073741824",
+        min: 14,
+        valid_values: &[],
+    },
+];
+
+impl ConfigDef {
+    pub fn find(name: &str) -> Option<&'static Self> {
+        TOPIC_CONFIGS.iter().find(|def| def.name == name)
+    }
+
+    pub fn find_synonym(property: &str) -> Option<&'static Self> {
+        TOPIC_CONFIGS.iter().find(|def| def.synonym == property)
+    }
+
+    fn validate(&self, value: &str) -> Result<(), String> {
+        match self.config_type {
+            ConfigType::Int => match value.trim().parse::<i32>() {
+                Ok(number) if (number as i64) < self.min => {
+                    Err(format!("Value must be at least {}", self.min))
+                }
+                Ok(_) => Ok(()),
+                Err(_) => Err("Expected value to be a 32-bit integer".to_string()),
+            },
+            ConfigType::Long => match value.trim().parse::<i64>() {
+                Ok(number) if number < self.min => {
+                    Err(format!("Value must be at least {}", self.min))
+                }
+                Ok(_) => Ok(()),
+                Err(_) => Err("Expected value to be a 64-bit integer".to_string()),
+            },
+            ConfigType::Boolean if !["true", "false"].contains(&value.to_lowercase().as_str()) => {
+                Err("Expected value to be either true or false".to_string())
+            }
+            ConfigType::List => list_items(value)
+                .find(|item| !self.valid_values.contains(item))
+                .map_or(Ok(()), |item| {
+                    Err(format!(
+                        "Invalid value {item}, must be one of {:?}",
+                        self.valid_values
+                    ))
+                }),
+            _ if !self.valid_values.is_empty() && !self.valid_values.contains(&value) => Err(
+                format!("String must be one of: {}", self.valid_values.join(", ")),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn list_items(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+/// Credentials set in the properties file are never handed out.
+fn is_sensitive(name: &str) -> bool {
+    name.contains("password")
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigSynonym {
+    pub name: String,
+    pub value: Option<String>,
+    pub source: ConfigSource,
+}
+
+/// A config as DescribeConfigs reports it. Its value and source are those of its first
+/// synonym, the one that takes precedence.
+#[derive(Debug, Clone)]
+pub struct ConfigEntry {
+    pub name: String,
+    pub read_only: bool,
+    pub is_sensitive: bool,
+    pub config_type: ConfigType,
+    pub synonyms: Vec<ConfigSynonym>,
+}
+
+impl ConfigEntry {
+    pub fn value(&self) -> Option<&str> {
+        match self.is_sensitive {
+            true => None,
+            false => self.synonyms.first()?.value.as_deref(),
+        }
+    }
+
+    pub fn source(&self) -> ConfigSource {
+        self.synonyms
+            .first()
+            .map_or(ConfigSource::Default, |synonym| synonym.source)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub error_code: ErrorCode,
+    pub message: String,
+}
+
+impl ConfigError {
+    fn new(error_code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            error_code,
+            message: message.into(),
+        }
+    }
+}
+
+/// A dynamic config set on, or removed from, a topic or broker through the metadata log.
+#[derive(Debug, Clone)]
+pub struct ConfigRecord {
+    pub version: i8,
+    pub resource_type: i8,
+    pub resource_name: Bytes,
+    pub name: Bytes,
+    pub value: Option<Bytes>,
+    pub tags: i8,
+}
+
+impl ConfigRecord {
+    pub const RECORD_TYPE: i8 = 4;
+    pub const TOPIC_RESOURCE: i8 = 2;
+    pub const BROKER_RESOURCE: i8 = 4;
+
+    pub fn new(mut buf: Bytes) -> Self {
+        let version = buf.get_i8();
+        let resource_type = buf.get_i8();
+        let name_len = unsigned_varint_decode(&mut buf);
+        let resource_name = buf.split_to(name_len as usize);
+        let key_len = unsigned_varint_decode(&mut buf);
+        let name = buf.split_to(key_len as usize);
+
+        // Compact nullable string, a zero length prefix marks a deleted config
+        let value = if buf[0] == 0x00 {
+            buf.advance(1);
+            None
+        } else {
+            let value_len = unsigned_varint_decode(&mut buf);
+            Some(buf.split_to(value_len as usize))
+        };
+        let tags = buf.get_i8();
+
+        Self {
+            version,
+            resource_type,
+            resource_name,
+            name,
+            value,
+            tags,
+        }
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        buf.put_i8(METADATA_FRAME_VERSION);
+        buf.put_i8(Self::RECORD_TYPE);
+        buf.put_i8(self.version);
+        buf.put_i8(self.resource_type);
+        unsigned_varint_encode(&mut buf, self.resource_name.len());
+        buf.put_slice(&self.resource_name);
+        unsigned_varint_encode(&mut buf, self.name.len());
+        buf.put_slice(&self.name);
+        match &self.value {
+            Some(value) => {
+                unsigned_varint_encode(&mut buf, value.len());
+                buf.put_slice(value);
+            }
+            None => buf.put_u8(0x00),
+        }
+        buf.put_i8(self.tags);
+        buf.freeze()
+    }
+}
+
+// Config overrides per resource type and name
+type ConfigOverrides = HashMap<(i8, Bytes), BTreeMap<String, String>>;
+
+/// Dynamic topic and broker configs, replayed from the `ConfigRecord`s of the metadata log
+/// and appended to it when altered. Topic configs fall back to the broker's dynamic
+/// defaults, then to its properties, then to Kafka's defaults.
+#[derive(Debug)]
+pub struct ConfigManager {
+    broker: Arc<BrokerConfig>,
+    metadata: Arc<MetadataCache>,
+    logs: Arc<LogManager>,
+    writer: MetadataWriter,
+    overrides: Mutex<ConfigOverrides>,
+}
+
+impl ConfigManager {
+    pub fn new(
+        broker: Arc<BrokerConfig>,
+        metadata: Arc<MetadataCache>,
+        logs: Arc<LogManager>,
+        writer: MetadataWriter,
+    ) -> Self {
+        let configs = Self {
+            broker,
+            metadata: Arc::clone(&metadata),
+            logs,
+            writer,
+            overrides: Mutex::new(HashMap::new()),
+        };
+        configs.replay(&metadata.snapshot());
+        configs
+    }
+
+    /// Applies the config records of batches replayed from the metadata log, then hands
+    /// the logs their updated flush policies.
+    pub fn replay(&self, metadata: &[MetadataBatch]) {
+        {
+            let mut overrides = self.overrides.lock().expect("config lock poisoned");
+            for record in metadata.iter().flat_map(|batch| batch.config_records()) {
+                let key = (record.resource_type, record.resource_name.clone());
+                let name = String::from_utf8_lossy(&record.name).into_owned();
+                match &record.value {
+                    Some(value) => {
+                        let value = String::from_utf8_lossy(value).into_owned();
+                        overrides.entry(key).or_default().insert(name, value);
+                    }
+                    None => {
+                        if let Some(configs) = overrides.get_mut(&key) {
+                            configs.remove(&name);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.apply_flush_policies();
+    }
+
+    /// A topic's config, resolved through its synonyms down to Kafka's default.
+    pub fn topic_config(&self, topic_name: &Bytes, key: &str) -> Option<Bytes> {
+        let def = ConfigDef::find(key)?;
+        let overrides = self.overrides.lock().expect("config lock poisoned");
+        let value = self
+            .topic_synonyms(&overrides, topic_name, def)
+            .into_iter()
+            .find_map(|synonym| synonym.value)?;
+        Some(Bytes::from(value))
+    }
+
+    fn topic_exists(&self, topic_name: &Bytes) -> bool {
+        self.metadata
+            .snapshot()
+            .iter()
+            .any(|batch| batch.get_topic_uuid(topic_name).is_some())
+    }
+
+    /// Topics name an existing topic, brokers either this one or, when empty, the
+    /// cluster-wide defaults.
+    fn check_resource(&self, resource_type: i8, resource_name: &Bytes) -> Result<(), ConfigError> {
+        match resource_type {
+            ConfigRecord::TOPIC_RESOURCE if self.topic_exists(resource_name) => Ok(()),
+            ConfigRecord::TOPIC_RESOURCE => Err(ConfigError::new(
+                ErrorCode::UnknownTopicOrPartition,
+                format!(
+                    "Topic {} does not exist",
+                    String::from_utf8_lossy(resource_name)
+                ),
+            )),
+            ConfigRecord::BROKER_RESOURCE
+                if resource_name.is_empty()
+                    || resource_name[..] == *self.broker.node_id.to_string().as_bytes() =>
+            {
+                Ok(())
+            }
+            ConfigRecord::BROKER_RESOURCE => Err(ConfigError::new(
+                ErrorCode::InvalidRequest,
+                format!("Unexpected broker id, expected {}", self.broker.node_id),
+            )),
+            _ => Err(ConfigError::new(
+                ErrorCode::InvalidRequest,
+                format!("Unsupported resource type {resource_type}"),
+            )),
+        }
+    }
+
+    /// Every place a broker property can be set, in order of precedence.
+    fn broker_synonyms(&self, overrides: &ConfigOverrides, property: &str) -> Vec<ConfigSynonym> {
+        let node = Bytes::from(self.broker.node_id.to_string());
+        let dynamic = [
+            (node, ConfigSource::DynamicBroker),
+            (Bytes::new(), ConfigSource::DynamicDefaultBroker),
+        ];
+
+        let mut synonyms = Vec::new();
+        for (broker, source) in dynamic {
+            let value = overrides
+                .get(&(ConfigRecord::BROKER_RESOURCE, broker))
+                .and_then(|configs| configs.get(property));
+            if let Some(value) = value {
+                synonyms.push(ConfigSynonym {
+                    name: property.to_string(),
+                    value: Some(value.clone()),
+                    source,
+                });
+            }
+        }
+        if let Some(value) = self.broker.property(property) {
+            synonyms.push(ConfigSynonym {
+                name: property.to_string(),
+                value: Some(value.to_string()),
+                source: ConfigSource::StaticBroker,
+            });
+        }
+        if let Some(def) = ConfigDef::find_synonym(property) {
+            synonyms.push(ConfigSynonym {
+                name: property.to_string(),
+                value: Some(def.default.to_string()),
+                source: ConfigSource::Default,
+            });
+        }
+
+        synonyms
+    }
+
+    fn topic_synonyms(
+        &self,
+        overrides: &ConfigOverrides,
+        topic_name: &Bytes,
+        def: &ConfigDef,
+    ) -> Vec<ConfigSynonym> {
+        let value = overrides
+            .get(&(ConfigRecord::TOPIC_RESOURCE, topic_name.clone()))
+            .and_then(|configs| configs.get(def.name));
+
+        let mut synonyms = Vec::new();
+        if let Some(value) = value {
+            synonyms.push(ConfigSynonym {
+                name: def.name.to_string(),
+                value: Some(value.clone()),
+                source: ConfigSource::DynamicTopic,
+            });
+        }
+        synonyms.extend(self.broker_synonyms(overrides, def.synonym));
+        synonyms
+    }
+
+    /// Every config of a resource, or only the named ones, sorted by name.
+    pub fn describe(
+        &self,
+        resource_type: i8,
+        resource_name: &Bytes,
+        keys: Option<&[Bytes]>,
+    ) -> Result<Vec<ConfigEntry>, ConfigError> {
+        self.check_resource(resource_type, resource_name)?;
+        let overrides = self.overrides.lock().expect("config lock poisoned");
+
+        let mut entries = Vec::new();
+        if resource_type == ConfigRecord::TOPIC_RESOURCE {
+            for def in TOPIC_CONFIGS {
+                entries.push(ConfigEntry {
+                    name: def.name.to_string(),
+                    read_only: false,
+                    is_sensitive: false,
+                    config_type: def.config_type,
+                    synonyms: self.topic_synonyms(&overrides, resource_name, def),
+                });
+            }
+        } else if resource_name.is_empty() {
+            let defaults = overrides.get(&(ConfigRecord::BROKER_RESOURCE, Bytes::new()));
+            for (name, value) in defaults.into_iter().flatten() {
+                entries.push(ConfigEntry {
+                    name: name.clone(),
+                    read_only: false,
+                    is_sensitive: false,
+                    config_type: ConfigDef::find_synonym(name)
+                        .map_or(ConfigType::Unknown, |def| def.config_type),
+                    synonyms: vec![ConfigSynonym {
+                        name: name.clone(),
+                        value: Some(value.clone()),
+                        source: ConfigSource::DynamicDefaultBroker,
+                    }],
+                });
+            }
+        } else {
+            let mut names: BTreeSet<&str> = self.broker.property_names().collect();
+            names.extend(TOPIC_CONFIGS.iter().map(|def| def.synonym));
+            for name in names {
+                let def = ConfigDef::find_synonym(name);
+                entries.push(ConfigEntry {
+                    name: name.to_string(),
+                    read_only: def.is_none(),
+                    is_sensitive: is_sensitive(name),
+                    config_type: def.map_or(ConfigType::Unknown, |def| def.config_type),
+                    synonyms: self.broker_synonyms(&overrides, name),
+                });
+            }
+        }
+
+        if let Some(keys) = keys {
+            entries.retain(|entry| keys.iter().any(|key| key[..] == *entry.name.as_bytes()));
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(entries)
+    }
+
+    /// Applies config changes to a resource and records them in the metadata log. With
+    /// `replace`, as AlterConfigs does, configs left out of `changes` go back to their
+    /// default. Nothing is changed when any of them is invalid.
+    ///
+    /// With the embedded quorum the changes are sent to the active controller, and take
+    /// effect here once they are committed and replayed.
+    pub async fn alter(
+        &self,
+        resource_type: i8,
+        resource_name: &Bytes,
+        changes: &[(Bytes, ConfigOperation, Option<Bytes>)],
+        replace: bool,
+        validate_only: bool,
+    ) -> Result<(), ConfigError> {
+        let altered = self.altered_configs(resource_type, resource_name, changes, replace)?;
+        if validate_only || altered.is_empty() {
+            return Ok(());
+        }
+
+        match &self.writer {
+            MetadataWriter::Log(_) => self.write(resource_type, resource_name, &altered),
+            MetadataWriter::Quorum(quorum) => {
+                forward_alter(quorum, resource_type, resource_name, &altered).await
+            }
+        }
+    }
+
+    /// The configs `changes` alter, with their new value or none when they are removed.
+    fn altered_configs(
+        &self,
+        resource_type: i8,
+        resource_name: &Bytes,
+        changes: &[(Bytes, ConfigOperation, Option<Bytes>)],
+        replace: bool,
+    ) -> Result<Vec<(String, Option<String>)>, ConfigError> {
+        self.check_resource(resource_type, resource_name)?;
+
+        let names: BTreeSet<&Bytes> = changes.iter().map(|(name, _, _)| name).collect();
+        if names.len() != changes.len() {
+            return Err(ConfigError::new(
+                ErrorCode::InvalidRequest,
+                "Error due to duplicate config keys",
+            ));
+        }
+
+        let overrides = self.overrides.lock().expect("config lock poisoned");
+        let key = (resource_type, resource_name.clone());
+        let current = overrides.get(&key).cloned().unwrap_or_default();
+        drop(overrides);
+        let mut updated = if replace {
+            BTreeMap::new()
+        } else {
+            current.clone()
+        };
+
+        for (name, operation, value) in changes {
+            let name = String::from_utf8_lossy(name).into_owned();
+            let def = match resource_type {
+                ConfigRecord::TOPIC_RESOURCE => ConfigDef::find(&name).ok_or_else(|| {
+                    ConfigError::new(
+                        ErrorCode::InvalidConfig,
+                        format!("Unknown topic config name: {name}"),
+                    )
+                })?,
+                _ => ConfigDef::find_synonym(&name).ok_or_else(|| {
+                    ConfigError::new(
+                        ErrorCode::InvalidConfig,
+                        format!("Cannot update these configs dynamically: {name}"),
+                    )
+                })?,
+            };
+            let value = value
+                .as_ref()
+                .map(|value| String::from_utf8_lossy(value).into_owned());
+
+            match operation {
+                ConfigOperation::Set => {
+                    let value = value.ok_or_else(|| {
+                        ConfigError::new(
+                            ErrorCode::InvalidRequest,
+                            format!("Null value not supported for : {name}"),
+                        )
+                    })?;
+                    if let Err(reason) = def.validate(&value) {
+                        return Err(ConfigError::new(
+                            ErrorCode::InvalidConfig,
+                            format!("Invalid value {value} for configuration {name}: {reason}"),
+                        ));
+                    }
+                    updated.insert(name, value);
+                }
+                ConfigOperation::Delete => {
+                    updated.remove(&name);
+                }
+                ConfigOperation::Append | ConfigOperation::Subtract => {
+                    if def.config_type != ConfigType::List {
+                        return Err(ConfigError::new(
+                            ErrorCode::InvalidConfig,
+                            format!("Config value append is not allowed for config key: {name}"),
+                        ));
+                    }
+
+                    let existing = updated.get(&name).map_or(def.default, String::as_str);
+                    let mut items: Vec<&str> = list_items(existing).collect();
+                    let value = value.unwrap_or_default();
+                    for item in list_items(&value) {
+                        match operation {
+                            ConfigOperation::Append if !items.contains(&item) => items.push(item),
+                            ConfigOperation::Subtract => items.retain(|existing| *existing != item),
+                            _ => {}
+                        }
+                    }
+
+                    let value = items.join(",");
+                    if let Err(reason) = def.validate(&value) {
+                        return Err(ConfigError::new(
+                            ErrorCode::InvalidConfig,
+                            format!("Invalid value {value} for configuration {name}: {reason}"),
+                        ));
+                    }
+                    updated.insert(name, value);
+                }
+            }
+        }
+
+        let mut altered: Vec<(String, Option<String>)> = current
+            .keys()
+            .filter(|name| !updated.contains_key(*name))
+            .map(|name| (name.clone(), None))
+            .collect();
+        altered.extend(
+            updated
+                .into_iter()
+                .filter(|(name, value)| current.get(name) != Some(value))
+                .map(|(name, value)| (name, Some(value))),
+        );
+        Ok(altered)
+    }
+
+    /// Appends the altered configs to the metadata log and applies them.
+    fn write(
+        &self,
+        resource_type: i8,
+        resource_name: &Bytes,
+        altered: &[(String, Option<String>)],
+    ) -> Result<(), ConfigError> {
+        let records = altered
+            .iter()
+            .map(|(name, value)| {
+                let record = ConfigRecord {
+                    version: 0,
+                    resource_type,
+                    resource_name: resource_name.clone(),
+                    name: Bytes::from(name.clone()),
+                    value: value.clone().map(Bytes::from),
+                    tags: 0,
+                };
+                Record::new(None, Some(record.encode()))
+            })
+            .collect();
+
+        let mut overrides = self.overrides.lock().expect("config lock poisoned");
+        if let Err(err) = self.writer.append(records) {
+            eprintln!("altering configs: {err:#}");
+            return Err(ConfigError::new(ErrorCode::Unknown, format!("{err:#}")));
+        }
+
+        let key = (resource_type, resource_name.clone());
+        let configs = overrides.entry(key.clone()).or_default();
+        for (name, value) in altered {
+            match value {
+                Some(value) => configs.insert(name.clone(), value.clone()),
+                None => configs.remove(name),
+            };
+        }
+        if configs.is_empty() {
+            overrides.remove(&key);
+        }
+        drop(overrides);
+
+        self.apply_flush_policies();
+        Ok(())
+    }
+
+    /// Hands the logs the flush policy every topic resolves to.
+    fn apply_flush_policies(&self) {
+        let policy = |messages: Option<Bytes>, ms: Option<Bytes>| {
+            FlushPolicy::from_configs(messages.as_deref(), ms.as_deref())
+        };
+
+        let default = {
+            let overrides = self.overrides.lock().expect("config lock poisoned");
+            let resolve = |property: &str| {
+                self.broker_synonyms(&overrides, property)
+                    .into_iter()
+                    .find_map(|synonym| synonym.value)
+                    .map(Bytes::from)
+            };
+            policy(
+                resolve("log.flush.interval.messages"),
+                resolve("log.flush.interval.ms"),
+            )
+        };
+        self.logs.set_default_flush_policy(default);
+
+        let metadata = self.metadata.snapshot();
+        for topic_name in metadata.iter().flat_map(|batch| batch.topic_names()) {
+            let topic_policy = policy(
+                self.topic_config(topic_name, "flush.messages"),
+                self.topic_config(topic_name, "flush.ms"),
+            );
+            self.logs.set_flush_policy(topic_name, topic_policy);
+        }
+    }
+}
+
+/// Sends altered configs to the active controller as an IncrementalAlterConfigs, setting
+/// or deleting each of them.
+async fn forward_alter(
+    quorum: &RaftQuorum,
+    resource_type: i8,
+    resource_name: &Bytes,
+    altered: &[(String, Option<String>)],
+) -> Result<(), ConfigError> {
+    let mut body = BytesMut::new();
+    unsigned_varint_encode(&mut body, 1);
+    body.put_i8(resource_type);
+    write_compact_string(&mut body, resource_name);
+    unsigned_varint_encode(&mut body, altered.len());
+    for (name, value) in altered {
+        let operation = match value {
+            Some(_) => ConfigOperation::Set,
+            None => ConfigOperation::Delete,
+        };
+        write_compact_string(&mut body, name.as_bytes());
+        body.put_i8(operation as i8);
+        write_compact_nullable_string(&mut body, value.as_deref().map(str::as_bytes));
+        // Tags
+        body.put_i8(0x00);
+    }
+    // Tags
+    body.put_i8(0x00);
+    // Validate only
+    body.put_i8(0);
+    // Tags
+    body.put_i8(0x00);
+
+    let response = quorum
+        .call_leader(
+            ApiType::IncrementalAlterConfigs,
+            INCREMENTAL_ALTER_CONFIGS_VERSION,
+            &body,
+        )
+        .await;
+    let result = response.and_then(|mut response| {
+        let _throttle_time = response.try_get_i32()?;
+        if read_compact_len(&mut response)? != 1 {
+            bail!("expected the result of one resource");
+        }
+        let error_code = response.try_get_i16()?;
+        let error_message = read_compact_nullable_string(&mut response)?;
+        Ok((error_code, error_message))
+    });
+
+    let (error_code, error_message) = result.map_err(|err| {
+        eprintln!("forwarding config changes to the active controller: {err:#}");
+        ConfigError::new(ErrorCode::Unknown, format!("{err:#}"))
+    })?;
+    let error_code = match error_code {
+        0 => return Ok(()),
+        code if code == ErrorCode::NotController as i16 => ErrorCode::NotController,
+        code if code == ErrorCode::RequestTimedOut as i16 => ErrorCode::RequestTimedOut,
+        code if code == ErrorCode::InvalidRequest as i16 => ErrorCode::InvalidRequest,
+        _ => ErrorCode::Unknown,
+    };
+    let message = error_message
+        .map(|message| String::from_utf8_lossy(&message).into_owned())
+        .unwrap_or_else(|| format!("{error_code:?}"));
+    Err(ConfigError::new(error_code, message))
+}
@@ -0,0 +1,908 @@
+use crate::{current_time_ms, metadata::RecordBatch, request::ErrorCode};
+use bytes::Bytes;
+use uuid::Uuid;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+pub const UNIFORM_ASSIGNOR: &[u8] = b"uniform";
+pub const RANGE_ASSIGNOR: &[u8] = b"range";
+// Kafka's group.consumer.session.timeout.ms and group.consumer.heartbeat.interval.ms defaults
+const SESSION_TIMEOUT_MS: i64 = 45000;
+pub const HEARTBEAT_INTERVAL_MS: i32 = 5000;
+// Member epochs with a special meaning in ConsumerGroupHeartbeat
+pub const JOIN_GROUP_MEMBER_EPOCH: i32 = 0;
+pub const LEAVE_GROUP_MEMBER_EPOCH: i32 = -1;
+pub const LEAVE_GROUP_STATIC_MEMBER_EPOCH: i32 = -2;
+
+/// Partitions per topic id.
+pub type Assignment = BTreeMap<Uuid, BTreeSet<i32>>;
+
+fn difference(a: &Assignment, b: &Assignment) -> Assignment {
+    a.iter()
+        .filter_map(|(topic_id, partitions)| {
+            let left: BTreeSet<i32> = match b.get(topic_id) {
+                Some(other) => partitions.difference(other).copied().collect(),
+                None => partitions.clone(),
+            };
+            (!left.is_empty()).then_some((*topic_id, left))
+        })
+        .collect()
+}
+
+fn intersection(a: &Assignment, b: &Assignment) -> Assignment {
+    a.iter()
+        .filter_map(|(topic_id, partitions)| {
+            let both: BTreeSet<i32> = partitions.intersection(b.get(topic_id)?).copied().collect();
+            (!both.is_empty()).then_some((*topic_id, both))
+        })
+        .collect()
+}
+
+fn partitions_of(assignment: &Assignment) -> impl Iterator<Item = (Uuid, i32)> + '_ {
+    assignment
+        .iter()
+        .flat_map(|(topic_id, partitions)| partitions.iter().map(|p| (*topic_id, *p)))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConsumerGroupState {
+    Empty,
+    Assigning,
+    Reconciling,
+    Stable,
+    Dead,
+}
+
+impl ConsumerGroupState {
+    /// Name as reported by ConsumerGroupDescribe and ListGroups.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Empty => "Empty",
+            Self::Assigning => "Assigning",
+            Self::Reconciling => "Reconciling",
+            Self::Stable => "Stable",
+            Self::Dead => "Dead",
+        }
+    }
+}
+
+/// Where a member stands in moving from its current assignment to its target one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemberState {
+    Stable,
+    // Waiting for the member to give up partitions that are no longer its own
+    UnrevokedPartitions,
+    // Waiting for other members to give up partitions that are now this member's
+    UnreleasedPartitions,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConsumerGroupHeartbeatParams {
+    pub group_id: Bytes,
+    pub member_id: Bytes,
+    pub member_epoch: i32,
+    pub instance_id: Option<Bytes>,
+    pub rack_id: Option<Bytes>,
+    pub rebalance_timeout_ms: i32,
+    pub subscribed_topic_names: Option<Vec<Bytes>>,
+    pub server_assignor: Option<Bytes>,
+    pub owned_partitions: Option<Assignment>,
+    pub client_id: Bytes,
+    pub client_host: Bytes,
+}
+
+#[derive(Debug)]
+pub struct ConsumerGroupHeartbeatResult {
+    pub error_code: ErrorCode,
+    pub member_id: Option<Bytes>,
+    pub member_epoch: i32,
+    // Only sent when the member's assignment changed since the last heartbeat
+    pub assignment: Option<Assignment>,
+}
+
+impl ConsumerGroupHeartbeatResult {
+    pub fn error(error_code: ErrorCode) -> Self {
+        Self {
+            error_code,
+            member_id: None,
+            member_epoch: -1,
+            assignment: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConsumerMember {
+    pub member_id: Bytes,
+    pub instance_id: Option<Bytes>,
+    pub rack_id: Option<Bytes>,
+    pub client_id: Bytes,
+    pub client_host: Bytes,
+    pub member_epoch: i32,
+    pub previous_member_epoch: i32,
+    pub state: MemberState,
+    pub rebalance_timeout_ms: i32,
+    pub subscribed_topic_names: Vec<Bytes>,
+    pub server_assignor: Option<Bytes>,
+    pub assigned: Assignment,
+    pub pending_revocation: Assignment,
+    last_heartbeat: i64,
+    revocation_deadline: Option<i64>,
+    // Whether the member has been sent its current assignment
+    assignment_sent: bool,
+}
+
+/// A consumer group following the KIP-848 protocol: the broker computes a target assignment
+/// whenever the group epoch moves, and every member reconciles towards its share through its
+/// own heartbeats, giving up partitions before anyone else is handed them.
+#[derive(Debug)]
+pub struct ConsumerGroup {
+    pub group_id: Bytes,
+    pub state: ConsumerGroupState,
+    pub group_epoch: i32,
+    pub assignment_epoch: i32,
+    pub members: BTreeMap<Bytes, ConsumerMember>,
+    pub target_assignment: HashMap<Bytes, Assignment>,
+    // When the group entered its current state
+    pub state_timestamp: i64,
+    static_members: HashMap<Bytes, Bytes>,
+    // Member currently holding each partition, whether assigned or pending revocation
+    owners: HashMap<(Uuid, i32), Bytes>,
+}
+
+impl ConsumerGroup {
+    pub fn new(group_id: Bytes) -> Self {
+        Self {
+            group_id,
+            state: ConsumerGroupState::Empty,
+            group_epoch: 0,
+            assignment_epoch: 0,
+            members: BTreeMap::new(),
+            target_assignment: HashMap::new(),
+            state_timestamp: current_time_ms(),
+            static_members: HashMap::new(),
+            owners: HashMap::new(),
+        }
+    }
+
+    /// Assignor picked by most members, or the uniform one if none asked for any.
+    pub fn assignor(&self) -> Bytes {
+        let mut votes: BTreeMap<&Bytes, usize> = BTreeMap::new();
+        for assignor in self
+            .members
+            .values()
+            .filter_map(|m| m.server_assignor.as_ref())
+        {
+            *votes.entry(assignor).or_default() += 1;
+        }
+
+        votes
+            .into_iter()
+            .max_by_key(|(_, count)| *count)
+            .map(|(assignor, _)| assignor.clone())
+            .unwrap_or_else(|| Bytes::from_static(UNIFORM_ASSIGNOR))
+    }
+
+    /// Union of every member's subscription.
+    pub fn subscribed_topics(&self) -> BTreeSet<Bytes> {
+        self.members
+            .values()
+            .flat_map(|member| member.subscribed_topic_names.iter().cloned())
+            .collect()
+    }
+
+    fn update_state(&mut self, now: i64) {
+        let state = if self.members.is_empty() {
+            ConsumerGroupState::Empty
+        } else if self.group_epoch > self.assignment_epoch {
+            ConsumerGroupState::Assigning
+        } else if self.members.values().any(|member| {
+            member.member_epoch != self.assignment_epoch || member.state != MemberState::Stable
+        }) {
+            ConsumerGroupState::Reconciling
+        } else {
+            ConsumerGroupState::Stable
+        };
+
+        if state != self.state {
+            self.state = state;
+            self.state_timestamp = now;
+        }
+    }
+
+    fn remove_member(&mut self, member_id: &Bytes, now: i64) {
+        let Some(member) = self.members.remove(member_id) else {
+            return;
+        };
+
+        if let Some(instance_id) = &member.instance_id {
+            self.static_members.remove(instance_id);
+        }
+        self.owners.retain(|_, owner| owner != member_id);
+        self.target_assignment.remove(member_id);
+        self.group_epoch += 1;
+        self.update_state(now);
+    }
+
+    /// Recomputes every member's target assignment once the group epoch moved past it.
+    fn maybe_assign(&mut self, metadata: &[RecordBatch]) {
+        if self.group_epoch <= self.assignment_epoch {
+            return;
+        }
+
+        let topics = resolve_topics(metadata, &self.subscribed_topics());
+        let subscriptions: Vec<(&Bytes, &[Bytes])> = self
+            .members
+            .values()
+            .map(|member| (&member.member_id, member.subscribed_topic_names.as_slice()))
+            .collect();
+
+        self.target_assignment = if self.assignor() == RANGE_ASSIGNOR {
+            range_assign(&subscriptions, &topics)
+        } else {
+            uniform_assign(&subscriptions, &topics, &self.target_assignment)
+        };
+        self.assignment_epoch = self.group_epoch;
+    }
+
+    /// Moves a member towards its target assignment. Partitions it must give up are kept
+    /// back until the member reports it no longer owns them, and partitions another member
+    /// still holds are only handed out once released.
+    fn reconcile(&mut self, member_id: &Bytes, owned: Option<&Assignment>, now: i64) {
+        let target = self
+            .target_assignment
+            .get(member_id)
+            .cloned()
+            .unwrap_or_default();
+        let target_epoch = self.assignment_epoch;
+        let member = self.members.get_mut(member_id).expect("reconciled member");
+
+        if member.state == MemberState::UnrevokedPartitions {
+            let revoked = owned.is_some_and(|owned| {
+                partitions_of(&member.pending_revocation).all(|(topic_id, partition)| {
+                    !owned
+                        .get(&topic_id)
+                        .is_some_and(|partitions| partitions.contains(&partition))
+                })
+            });
+            if !revoked {
+                return;
+            }
+
+            for partition in partitions_of(&member.pending_revocation) {
+                self.owners.remove(&partition);
+            }
+            member.pending_revocation.clear();
+            member.revocation_deadline = None;
+            member.state = MemberState::Stable;
+        }
+
+        if member.member_epoch == target_epoch && member.state == MemberState::Stable {
+            return;
+        }
+
+        let kept = intersection(&member.assigned, &target);
+        let revoking = difference(&member.assigned, &target);
+        if !revoking.is_empty() {
+            member.assigned = kept;
+            member.pending_revocation = revoking;
+            member.state = MemberState::UnrevokedPartitions;
+            member.revocation_deadline = Some(now + member.rebalance_timeout_ms.max(0) as i64);
+            member.assignment_sent = false;
+            return;
+        }
+
+        let mut assigned = kept;
+        let mut unreleased = false;
+        for (topic_id, partition) in partitions_of(&difference(&target, &member.assigned)) {
+            match self.owners.get(&(topic_id, partition)) {
+                Some(owner) if owner != member_id => unreleased = true,
+                _ => {
+                    self.owners.insert((topic_id, partition), member_id.clone());
+                    assigned.entry(topic_id).or_default().insert(partition);
+                }
+            }
+        }
+
+        if assigned != member.assigned {
+            member.assigned = assigned;
+            member.assignment_sent = false;
+        }
+        if member.member_epoch != target_epoch {
+            member.previous_member_epoch = member.member_epoch;
+            member.member_epoch = target_epoch;
+            member.assignment_sent = false;
+        }
+        member.state = if unreleased {
+            MemberState::UnreleasedPartitions
+        } else {
+            MemberState::Stable
+        };
+    }
+
+    /// Handles a member's heartbeat: joins, leaves, subscription changes and the next step
+    /// of its reconciliation.
+    pub fn heartbeat(
+        &mut self,
+        params: ConsumerGroupHeartbeatParams,
+        metadata: &[RecordBatch],
+    ) -> ConsumerGroupHeartbeatResult {
+        let now = current_time_ms();
+
+        if params.member_epoch == LEAVE_GROUP_MEMBER_EPOCH
+            || params.member_epoch == LEAVE_GROUP_STATIC_MEMBER_EPOCH
+        {
+            if !self.members.contains_key(&params.member_id) {
+                return ConsumerGroupHeartbeatResult::error(ErrorCode::UnknownMemberId);
+            }
+
+            self.remove_member(&params.member_id, now);
+            return ConsumerGroupHeartbeatResult {
+                error_code: ErrorCode::None,
+                member_id: Some(params.member_id),
+                member_epoch: params.member_epoch,
+                assignment: None,
+            };
+        }
+
+        let mut changed = false;
+        let member_id = if params.member_epoch == JOIN_GROUP_MEMBER_EPOCH {
+            let member_id = if params.member_id.is_empty() {
+                Bytes::from(Uuid::new_v4().to_string())
+            } else {
+                params.member_id.clone()
+            };
+
+            if let Some(instance_id) = &params.instance_id {
+                match self.static_members.get(instance_id) {
+                    Some(current) if *current != member_id => {
+                        return ConsumerGroupHeartbeatResult::error(
+                            ErrorCode::UnreleasedInstanceId,
+                        );
+                    }
+                    _ => {
+                        self.static_members
+                            .insert(instance_id.clone(), member_id.clone());
+                    }
+                }
+            }
+
+            // A member joining again after being fenced starts over
+            if self.members.contains_key(&member_id) {
+                self.owners.retain(|_, owner| *owner != member_id);
+            }
+            self.members.insert(
+                member_id.clone(),
+                ConsumerMember {
+                    member_id: member_id.clone(),
+                    instance_id: params.instance_id.clone(),
+                    rack_id: None,
+                    client_id: params.client_id.clone(),
+                    client_host: params.client_host.clone(),
+                    member_epoch: 0,
+                    previous_member_epoch: -1,
+                    state: MemberState::Stable,
+                    rebalance_timeout_ms: params.rebalance_timeout_ms,
+                    subscribed_topic_names: Vec::new(),
+                    server_assignor: None,
+                    assigned: Assignment::new(),
+                    pending_revocation: Assignment::new(),
+                    last_heartbeat: now,
+                    revocation_deadline: None,
+                    assignment_sent: false,
+                },
+            );
+            changed = true;
+            member_id
+        } else {
+            let Some(member) = self.members.get(&params.member_id) else {
+                return ConsumerGroupHeartbeatResult::error(ErrorCode::UnknownMemberId);
+            };
+
+            // A member that missed the response bumping its epoch may retry with the previous
+            // one, as long as it does not claim partitions it was not given
+            let retried = params.member_epoch == member.previous_member_epoch
+                && params
+                    .owned_partitions
+                    .as_ref()
+                    .is_some_and(|owned| difference(owned, &member.assigned).is_empty());
+            if params.member_epoch != member.member_epoch && !retried {
+                return ConsumerGroupHeartbeatResult::error(ErrorCode::FencedMemberEpoch);
+            }
+
+            params.member_id.clone()
+        };
+
+        let member = self
+            .members
+            .get_mut(&member_id)
+            .expect("member joined above");
+        member.last_heartbeat = now;
+        member.rack_id = params.rack_id.or(member.rack_id.take());
+        if params.rebalance_timeout_ms > 0 {
+            member.rebalance_timeout_ms = params.rebalance_timeout_ms;
+        }
+        if let Some(mut topics) = params.subscribed_topic_names {
+            topics.sort();
+            topics.dedup();
+            if topics != member.subscribed_topic_names {
+                member.subscribed_topic_names = topics;
+                changed = true;
+            }
+        }
+        if params.server_assignor.is_some() && params.server_assignor != member.server_assignor {
+            member.server_assignor = params.server_assignor;
+            changed = true;
+        }
+        // A member reporting other partitions than we believe it owns needs its assignment again
+        if params
+            .owned_partitions
+            .as_ref()
+            .is_some_and(|owned| *owned != member.assigned)
+        {
+            member.assignment_sent = false;
+        }
+
+        if changed {
+            self.group_epoch += 1;
+        }
+        self.maybe_assign(metadata);
+        self.reconcile(&member_id, params.owned_partitions.as_ref(), now);
+        self.update_state(now);
+
+        let member = self
+            .members
+            .get_mut(&member_id)
+            .expect("member joined above");
+        let assignment = (!member.assignment_sent).then(|| member.assigned.clone());
+        member.assignment_sent = true;
+
+        ConsumerGroupHeartbeatResult {
+            error_code: ErrorCode::None,
+            member_id: Some(member_id),
+            member_epoch: member.member_epoch,
+            assignment,
+        }
+    }
+
+    /// Fences members whose session expired or who held on to revoked partitions past
+    /// their rebalance timeout.
+    pub fn expire_members(&mut self, now: i64) {
+        let expired: Vec<Bytes> = self
+            .members
+            .values()
+            .filter(|member| {
+                now - member.last_heartbeat > SESSION_TIMEOUT_MS
+                    || member
+                        .revocation_deadline
+                        .is_some_and(|deadline| now > deadline)
+            })
+            .map(|member| member.member_id.clone())
+            .collect();
+
+        for member_id in expired {
+            self.remove_member(&member_id, now);
+        }
+    }
+
+    /// Checks the member id and epoch an offset commit or fetch was sent with.
+    pub fn validate_member_epoch(
+        &self,
+        member_id: &Bytes,
+        member_epoch: i32,
+    ) -> Result<(), ErrorCode> {
+        let member = self
+            .members
+            .get(member_id)
+            .ok_or(ErrorCode::UnknownMemberId)?;
+        if member_epoch != member.member_epoch {
+            return Err(ErrorCode::StaleMemberEpoch);
+        }
+
+        Ok(())
+    }
+}
+
+/// Topic ids and partitions of the given topics, skipping any the cluster does not know.
+pub fn resolve_topics<'a>(
+    metadata: &[RecordBatch],
+    topic_names: impl IntoIterator<Item = &'a Bytes>,
+) -> BTreeMap<Bytes, (Uuid, Vec<i32>)> {
+    topic_names
+        .into_iter()
+        .filter_map(|name| {
+            let uuid = metadata
+                .iter()
+                .find_map(|batch| batch.get_topic_uuid(name))?;
+            let mut partitions: Vec<i32> = metadata
+                .iter()
+                .filter_map(|batch| batch.get_topic_partitions_from_uuid(&uuid))
+                .flatten()
+                .map(|partition| partition.partition_id)
+                .collect();
+            partitions.sort_unstable();
+            partitions.dedup();
+
+            Some((name.clone(), (uuid, partitions)))
+        })
+        .collect()
+}
+
+/// Spreads all subscribed partitions evenly over the members. Members keep what they
+/// were previously assigned up to their fair share, the rest goes to the least loaded
+/// subscriber.
+fn uniform_assign(
+    members: &[(&Bytes, &[Bytes])],
+    topics: &BTreeMap<Bytes, (Uuid, Vec<i32>)>,
+    previous: &HashMap<Bytes, Assignment>,
+) -> HashMap<Bytes, Assignment> {
+    let mut assignment: HashMap<Bytes, Assignment> = members
+        .iter()
+        .map(|(member_id, _)| ((*member_id).clone(), Assignment::new()))
+        .collect();
+    if members.is_empty() {
+        return assignment;
+    }
+
+    let subscribers = |topic: &Bytes| -> Vec<&Bytes> {
+        members
+            .iter()
+            .filter(|(_, subscribed)| subscribed.contains(topic))
+            .map(|(member_id, _)| *member_id)
+            .collect()
+    };
+
+    let partitions: Vec<(&Bytes, Uuid, i32)> = topics
+        .iter()
+        .filter(|(name, _)| !subscribers(name).is_empty())
+        .flat_map(|(name, (uuid, partitions))| partitions.iter().map(move |p| (name, *uuid, *p)))
+        .collect();
+    let min_quota = partitions.len() / members.len();
+    let mut extra_quota = partitions.len() % members.len();
+    let mut counts: HashMap<&Bytes, usize> = HashMap::new();
+    let mut unassigned = Vec::new();
+
+    let previous_owner = |uuid: Uuid, partition: i32| {
+        previous.iter().find_map(|(member_id, assignment)| {
+            assignment
+                .get(&uuid)
+                .is_some_and(|partitions| partitions.contains(&partition))
+                .then_some(member_id)
+        })
+    };
+
+    for (name, uuid, partition) in partitions.iter().copied() {
+        let owner = previous_owner(uuid, partition)
+            .and_then(|owner| members.iter().find(|(member_id, _)| *member_id == owner))
+            .filter(|(_, subscribed)| subscribed.contains(name));
+        let Some((owner, _)) = owner else {
+            unassigned.push((name, uuid, partition));
+            continue;
+        };
+
+        let count = counts.entry(owner).or_default();
+        if *count < min_quota || (*count == min_quota && extra_quota > 0) {
+            if *count == min_quota {
+                extra_quota -= 1;
+            }
+            *count += 1;
+            assignment
+                .get_mut(*owner)
+                .expect("every member has an assignment")
+                .entry(uuid)
+                .or_default()
+                .insert(partition);
+        } else {
+            unassigned.push((name, uuid, partition));
+        }
+    }
+
+    // Partitions of topics with the fewest subscribers go first, while those members still
+    // have room for them
+    unassigned.sort_by_key(|(name, _, _)| subscribers(name).len());
+    for (name, uuid, partition) in unassigned {
+        let Some(member_id) = subscribers(name)
+            .into_iter()
+            .min_by_key(|member_id| (counts.get(member_id).copied().unwrap_or(0), *member_id))
+        else {
+            continue;
+        };
+
+        *counts.entry(member_id).or_default() += 1;
+        assignment
+            .get_mut(member_id)
+            .expect("every member has an assignment")
+            .entry(uuid)
+            .or_default()
+            .insert(partition);
+    }
+
+    assignment
+}
+
+/// Gives each subscriber of a topic a contiguous range of its partitions, so members end
+/// up with the same partition numbers across co-partitioned topics.
+fn range_assign(
+    members: &[(&Bytes, &[Bytes])],
+    topics: &BTreeMap<Bytes, (Uuid, Vec<i32>)>,
+) -> HashMap<Bytes, Assignment> {
+    let mut assignment: HashMap<Bytes, Assignment> = members
+        .iter()
+        .map(|(member_id, _)| ((*member_id).clone(), Assignment::new()))
+        .collect();
+
+    for (name, (uuid, partitions)) in topics {
+        let subscribers: Vec<&Bytes> = members
+            .iter()
+            .filter(|(_, subscribed)| subscribed.contains(name))
+            .map(|(member_id, _)| *member_id)
+            .collect();
+        if subscribers.is_empty() {
+            continue;
+        }
+
+        let per_member = partitions.len() / subscribers.len();
+        let extra = partitions.len() % subscribers.len();
+        for (i, member_id) in subscribers.into_iter().enumerate() {
+            let start = i * per_member + i.min(extra);
+            let len = per_member + usize::from(i < extra);
+            if len == 0 {
+                continue;
+            }
+
+            assignment
+                .get_mut(member_id)
+                .expect("every member has an assignment")
+                .entry(*uuid)
+                .or_default()
+                .extend(&partitions[start..start + len]);
+        }
+    }
+
+    assignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDERS: Uuid = Uuid::from_u128(1);
+    const PAYMENTS: Uuid = Uuid::from_u128(2);
+
+    fn topics(specs: &[(&'static str, Uuid, i32)]) -> BTreeMap<Bytes, (Uuid, Vec<i32>)> {
+        specs
+            .iter()
+            .map(|(name, uuid, partitions)| {
+                (
+                    Bytes::from_static(name.as_bytes()),
+                    (*uuid, (0..*partitions).collect()),
+                )
+            })
+            .collect()
+    }
+
+    fn assignment(partitions: &[(Uuid, &[i32])]) -> Assignment {
+        partitions
+            .iter()
+            .map(|(uuid, partitions)| (*uuid, partitions.iter().copied().collect()))
+            .collect()
+    }
+
+    fn count(assignment: &Assignment) -> usize {
+        assignment.values().map(BTreeSet::len).sum()
+    }
+
+    #[test]
+    fn uniform_assignor_spreads_partitions_evenly() {
+        let (a, b, c) = (Bytes::from("a"), Bytes::from("b"), Bytes::from("c"));
+        let orders = [Bytes::from("orders")];
+        let both = [Bytes::from("orders"), Bytes::from("payments")];
+        let members: Vec<(&Bytes, &[Bytes])> = vec![(&a, &both), (&b, &both), (&c, &orders)];
+        let topics = topics(&[("orders", ORDERS, 4), ("payments", PAYMENTS, 3)]);
+
+        let assigned = uniform_assign(&members, &topics, &HashMap::new());
+        let mut counts: Vec<usize> = assigned.values().map(count).collect();
+        counts.sort_unstable();
+        assert_eq!(counts, [2, 2, 3]);
+        // Only subscribers get a topic's partitions
+        assert!(!assigned[&c].contains_key(&PAYMENTS));
+        let payments: usize = assigned
+            .values()
+            .filter_map(|assignment| assignment.get(&PAYMENTS))
+            .map(BTreeSet::len)
+            .sum();
+        assert_eq!(payments, 3);
+    }
+
+    #[test]
+    fn uniform_assignor_keeps_previous_partitions_up_to_the_fair_share() {
+        let (a, b) = (Bytes::from("a"), Bytes::from("b"));
+        let orders = [Bytes::from("orders")];
+        let topics = topics(&[("orders", ORDERS, 4)]);
+
+        let alone: Vec<(&Bytes, &[Bytes])> = vec![(&a, &orders)];
+        let previous = uniform_assign(&alone, &topics, &HashMap::new());
+        assert_eq!(previous[&a], assignment(&[(ORDERS, &[0, 1, 2, 3])]));
+
+        // A second member takes half, and the first keeps the rest of what it had
+        let members: Vec<(&Bytes, &[Bytes])> = vec![(&a, &orders), (&b, &orders)];
+        let assigned = uniform_assign(&members, &topics, &previous);
+        assert_eq!(assigned[&a], assignment(&[(ORDERS, &[0, 1])]));
+        assert_eq!(assigned[&b], assignment(&[(ORDERS, &[2, 3])]));
+
+        // Members that leave give everything back to those remaining
+        let assigned = uniform_assign(&alone, &topics, &assigned);
+        assert_eq!(assigned[&a], assignment(&[(ORDERS, &[0, 1, 2, 3])]));
+    }
+
+    #[test]
+    fn range_assignor_hands_out_matching_ranges_per_topic() {
+        let (a, b) = (Bytes::from("a"), Bytes::from("b"));
+        let orders = [Bytes::from("orders")];
+        let both = [Bytes::from("orders"), Bytes::from("payments")];
+        let topics = topics(&[("orders", ORDERS, 3), ("payments", PAYMENTS, 3)]);
+
+        let members: Vec<(&Bytes, &[Bytes])> = vec![(&a, &both), (&b, &both)];
+        let assigned = range_assign(&members, &topics);
+        // The first members take the remainder, and co-partitioned topics line up
+        assert_eq!(
+            assigned[&a],
+            assignment(&[(ORDERS, &[0, 1]), (PAYMENTS, &[0, 1])])
+        );
+        assert_eq!(
+            assigned[&b],
+            assignment(&[(ORDERS, &[2]), (PAYMENTS, &[2])])
+        );
+
+        let members: Vec<(&Bytes, &[Bytes])> = vec![(&a, &both), (&b, &orders)];
+        let assigned = range_assign(&members, &topics);
+        assert_eq!(
+            assigned[&a],
+            assignment(&[(ORDERS, &[0, 1]), (PAYMENTS, &[0, 1, 2])])
+        );
+        assert_eq!(assigned[&b], assignment(&[(ORDERS, &[2])]));
+    }
+
+    fn heartbeat(
+        group: &mut ConsumerGroup,
+        member_id: &str,
+        member_epoch: i32,
+        owned: Option<Assignment>,
+    ) -> ConsumerGroupHeartbeatResult {
+        group.heartbeat(
+            ConsumerGroupHeartbeatParams {
+                group_id: group.group_id.clone(),
+                member_id: Bytes::copy_from_slice(member_id.as_bytes()),
+                member_epoch,
+                instance_id: None,
+                rack_id: None,
+                rebalance_timeout_ms: 60000,
+                subscribed_topic_names: Some(vec![Bytes::from("orders")]),
+                server_assignor: None,
+                owned_partitions: owned,
+                client_id: Bytes::from("client"),
+                client_host: Bytes::from("/127.0.0.1"),
+            },
+            &[],
+        )
+    }
+
+    /// Moves the group to a new target assignment, as the assignor would.
+    fn retarget(group: &mut ConsumerGroup, target: &[(&str, Assignment)]) {
+        group.group_epoch += 1;
+        group.assignment_epoch = group.group_epoch;
+        group.target_assignment = target
+            .iter()
+            .map(|(member_id, assignment)| {
+                (
+                    Bytes::copy_from_slice(member_id.as_bytes()),
+                    assignment.clone(),
+                )
+            })
+            .collect();
+    }
+
+    #[test]
+    fn partitions_are_revoked_before_being_assigned_elsewhere() {
+        let mut group = ConsumerGroup::new(Bytes::from("group"));
+        let joined = heartbeat(&mut group, "a", JOIN_GROUP_MEMBER_EPOCH, None);
+        assert_eq!(joined.error_code, ErrorCode::None);
+        heartbeat(&mut group, "b", JOIN_GROUP_MEMBER_EPOCH, None);
+
+        retarget(&mut group, &[("a", assignment(&[(ORDERS, &[0, 1])]))]);
+        let epoch = group.assignment_epoch;
+        let result = heartbeat(
+            &mut group,
+            "a",
+            joined.member_epoch,
+            Some(Assignment::new()),
+        );
+        assert_eq!(result.member_epoch, epoch);
+        assert_eq!(result.assignment, Some(assignment(&[(ORDERS, &[0, 1])])));
+
+        retarget(
+            &mut group,
+            &[
+                ("a", assignment(&[(ORDERS, &[0])])),
+                ("b", assignment(&[(ORDERS, &[1])])),
+            ],
+        );
+        let next_epoch = group.assignment_epoch;
+
+        // b moves to the new epoch but cannot have partition 1 while a still owns it
+        let b_epoch = group.members[&Bytes::from("b")].member_epoch;
+        let result = heartbeat(&mut group, "b", b_epoch, Some(Assignment::new()));
+        assert_eq!(result.member_epoch, next_epoch);
+        assert_eq!(result.assignment, Some(Assignment::new()));
+        assert_eq!(
+            group.members[&Bytes::from("b")].state,
+            MemberState::UnreleasedPartitions
+        );
+
+        // a is asked to give up partition 1 and stays on its epoch until it has
+        let owned = assignment(&[(ORDERS, &[0, 1])]);
+        let result = heartbeat(&mut group, "a", epoch, Some(owned.clone()));
+        assert_eq!(result.member_epoch, epoch);
+        assert_eq!(result.assignment, Some(assignment(&[(ORDERS, &[0])])));
+        assert_eq!(
+            group.members[&Bytes::from("a")].state,
+            MemberState::UnrevokedPartitions
+        );
+        assert_eq!(group.state, ConsumerGroupState::Reconciling);
+
+        // Still holding it, a is reminded of what it is left with
+        let result = heartbeat(&mut group, "a", epoch, Some(owned));
+        assert_eq!(result.member_epoch, epoch);
+        assert_eq!(result.assignment, Some(assignment(&[(ORDERS, &[0])])));
+        assert!(group.members[&Bytes::from("b")].assigned.is_empty());
+
+        let result = heartbeat(&mut group, "a", epoch, Some(assignment(&[(ORDERS, &[0])])));
+        assert_eq!(result.member_epoch, next_epoch);
+        assert_eq!(group.members[&Bytes::from("a")].state, MemberState::Stable);
+
+        // Released, partition 1 goes to b on its next heartbeat
+        let result = heartbeat(&mut group, "b", next_epoch, Some(Assignment::new()));
+        assert_eq!(result.assignment, Some(assignment(&[(ORDERS, &[1])])));
+        assert_eq!(group.members[&Bytes::from("b")].state, MemberState::Stable);
+        assert_eq!(group.state, ConsumerGroupState::Stable);
+    }
+
+    #[test]
+    fn member_epochs_fence_stale_heartbeats() {
+        let mut group = ConsumerGroup::new(Bytes::from("group"));
+        let joined = heartbeat(&mut group, "a", JOIN_GROUP_MEMBER_EPOCH, None);
+
+        retarget(&mut group, &[("a", assignment(&[(ORDERS, &[0])]))]);
+        let previous_epoch = joined.member_epoch;
+        let result = heartbeat(&mut group, "a", previous_epoch, Some(Assignment::new()));
+        let epoch = result.member_epoch;
+        assert!(epoch > previous_epoch);
+
+        // A member that missed the bump may retry with the previous epoch, as long as it
+        // claims nothing it was not given
+        let result = heartbeat(&mut group, "a", previous_epoch, Some(Assignment::new()));
+        assert_eq!(result.error_code, ErrorCode::None);
+        assert_eq!(result.member_epoch, epoch);
+        let result = heartbeat(
+            &mut group,
+            "a",
+            previous_epoch,
+            Some(assignment(&[(ORDERS, &[0, 1])])),
+        );
+        assert_eq!(result.error_code, ErrorCode::FencedMemberEpoch);
+
+        let result = heartbeat(&mut group, "a", epoch + 1, None);
+        assert_eq!(result.error_code, ErrorCode::FencedMemberEpoch);
+        let result = heartbeat(&mut group, "unknown", epoch, None);
+        assert_eq!(result.error_code, ErrorCode::UnknownMemberId);
+
+        assert_eq!(
+            group.validate_member_epoch(&Bytes::from("a"), previous_epoch),
+            Err(ErrorCode::StaleMemberEpoch)
+        );
+        assert_eq!(
+            group.validate_member_epoch(&Bytes::from("a"), epoch),
+            Ok(())
+        );
+    }
+}
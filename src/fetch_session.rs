@@ -0,0 +1,355 @@
+use crate::{current_time_ms, request::ErrorCode, request::fetch::PartitionRequest};
+use uuid::Uuid;
+
+use std::{collections::HashMap, sync::Mutex};
+
+// Kafka's max.incremental.fetch.session.cache.slots and min.incremental.fetch.session.eviction.ms
+// defaults
+const MAX_FETCH_SESSIONS: usize = 1000;
+const MIN_EVICTION_MS: i64 = 120000;
+// Session ids and epochs with a special meaning in fetch requests
+pub const INVALID_SESSION_ID: i32 = 0;
+pub const INITIAL_EPOCH: i32 = 0;
+pub const FINAL_EPOCH: i32 = -1;
+
+/// A partition tracked by a session, with what the client was last told about it.
+#[derive(Debug)]
+struct CachedPartition {
+    topic_id: Uuid,
+    request: PartitionRequest,
+    high_watermark: i64,
+    last_stable_offset: i64,
+    log_start_offset: i64,
+}
+
+#[derive(Debug)]
+struct FetchSession {
+    // Epoch the next incremental request has to carry
+    next_epoch: i32,
+    last_used: i64,
+    partitions: Vec<CachedPartition>,
+}
+
+impl FetchSession {
+    fn position(&self, topic_id: &Uuid, partition_id: i32) -> Option<usize> {
+        self.partitions.iter().position(|cached| {
+            cached.topic_id == *topic_id && cached.request.partition_id == partition_id
+        })
+    }
+}
+
+/// What a fetch request has to answer once its session has been resolved.
+#[derive(Debug)]
+pub struct FetchContext {
+    // Session id to send back, zero when the fetch is sessionless
+    pub session_id: i32,
+    // Whether only partitions that changed since the last response are sent back
+    pub incremental: bool,
+    pub partitions: Vec<(Uuid, PartitionRequest)>,
+}
+
+/// Fetch sessions (KIP-227) let clients that fetch many partitions only send and receive the
+/// ones that changed. The least recently used sessions are evicted once the cache is full.
+#[derive(Debug, Default)]
+pub struct FetchSessionCache {
+    sessions: Mutex<HashMap<i32, FetchSession>>,
+}
+
+impl FetchSessionCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolves the session a fetch request refers to: full requests close any session they
+    /// name and, with the initial epoch, open a new one, while incremental requests update
+    /// theirs with the partitions they add and forget.
+    pub fn new_context(
+        &self,
+        session_id: i32,
+        session_epoch: i32,
+        partitions: Vec<(Uuid, PartitionRequest)>,
+        forgotten: &[(Uuid, i32)],
+    ) -> Result<FetchContext, ErrorCode> {
+        let mut sessions = self.sessions.lock().expect("fetch session lock poisoned");
+        let now = current_time_ms();
+
+        if session_epoch == INITIAL_EPOCH || session_epoch == FINAL_EPOCH {
+            if session_id != INVALID_SESSION_ID {
+                sessions.remove(&session_id);
+            }
+
+            let session_id = if session_epoch == INITIAL_EPOCH {
+                Self::create(&mut sessions, &partitions, now)
+            } else {
+                INVALID_SESSION_ID
+            };
+
+            return Ok(FetchContext {
+                session_id,
+                incremental: false,
+                partitions,
+            });
+        }
+
+        let session = sessions
+            .get_mut(&session_id)
+            .ok_or(ErrorCode::FetchSessionIdNotFound)?;
+        if session_epoch != session.next_epoch {
+            return Err(ErrorCode::InvalidFetchSessionEpoch);
+        }
+
+        for (topic_id, partition_id) in forgotten {
+            if let Some(index) = session.position(topic_id, *partition_id) {
+                session.partitions.remove(index);
+            }
+        }
+        for (topic_id, request) in partitions {
+            match session.position(&topic_id, request.partition_id) {
+                Some(index) => session.partitions[index].request = request,
+                None => session.partitions.push(CachedPartition {
+                    topic_id,
+                    request,
+                    high_watermark: -1,
+                    last_stable_offset: -1,
+                    log_start_offset: -1,
+                }),
+            }
+        }
+
+        session.next_epoch = session.next_epoch.checked_add(1).unwrap_or(1);
+        session.last_used = now;
+
+        Ok(FetchContext {
+            session_id,
+            incremental: true,
+            partitions: session
+                .partitions
+                .iter()
+                .map(|cached| (cached.topic_id, cached.request.clone()))
+                .collect(),
+        })
+    }
+
+    /// Opens a session for the given partitions, evicting the least recently used one if
+    /// the cache is full. That one has to be idle for a while or track fewer partitions,
+    /// otherwise the fetch goes on without a session.
+    fn create(
+        sessions: &mut HashMap<i32, FetchSession>,
+        partitions: &[(Uuid, PartitionRequest)],
+        now: i64,
+    ) -> i32 {
+        if sessions.len() >= MAX_FETCH_SESSIONS {
+            let evictable = sessions
+                .iter()
+                .min_by_key(|(_, session)| session.last_used)
+                .filter(|(_, session)| {
+                    now - session.last_used > MIN_EVICTION_MS
+                        || session.partitions.len() < partitions.len()
+                })
+                .map(|(session_id, _)| *session_id);
+
+            match evictable {
+                Some(session_id) => sessions.remove(&session_id),
+                None => return INVALID_SESSION_ID,
+            };
+        }
+
+        let session_id = loop {
+            let session_id = (Uuid::new_v4().as_u128() as i32) & i32::MAX;
+            if session_id != INVALID_SESSION_ID && !sessions.contains_key(&session_id) {
+                break session_id;
+            }
+        };
+
+        sessions.insert(
+            session_id,
+            FetchSession {
+                next_epoch: 1,
+                last_used: now,
+                partitions: partitions
+                    .iter()
+                    .map(|(topic_id, request)| CachedPartition {
+                        topic_id: *topic_id,
+                        request: request.clone(),
+                        high_watermark: -1,
+                        last_stable_offset: -1,
+                        log_start_offset: -1,
+                    })
+                    .collect(),
+            },
+        );
+
+        session_id
+    }
+
+    /// Records what a session's client is about to be told of a partition. Returns whether
+    /// an incremental response has to include it: when it carries records or an error, or
+    /// its offsets moved since the last response that did.
+    pub fn update_partition(
+        &self,
+        session_id: i32,
+        topic_id: &Uuid,
+        partition_id: i32,
+        offsets: Option<(i64, i64, i64)>,
+        has_records: bool,
+    ) -> bool {
+        let mut sessions = self.sessions.lock().expect("fetch session lock poisoned");
+        let Some(cached) = sessions.get_mut(&session_id).and_then(|session| {
+            let index = session.position(topic_id, partition_id)?;
+            session.partitions.get_mut(index)
+        }) else {
+            return true;
+        };
+
+        let Some((high_watermark, last_stable_offset, log_start_offset)) = offsets else {
+            return true;
+        };
+
+        let changed = cached.high_watermark != high_watermark
+            || cached.last_stable_offset != last_stable_offset
+            || cached.log_start_offset != log_start_offset;
+        cached.high_watermark = high_watermark;
+        cached.last_stable_offset = last_stable_offset;
+        cached.log_start_offset = log_start_offset;
+
+        changed || has_records
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partition(partition_id: i32) -> PartitionRequest {
+        PartitionRequest {
+            partition_id,
+            current_leader_epoch: 0,
+            fetch_offset: 0,
+            last_fetched_epoch: -1,
+            log_start_offset: 0,
+            partition_max_bytes: 1024,
+        }
+    }
+
+    fn partitions(ids: &[i32]) -> Vec<(Uuid, PartitionRequest)> {
+        let topic_id = Uuid::from_u128(1);
+        ids.iter().map(|id| (topic_id, partition(*id))).collect()
+    }
+
+    #[test]
+    fn incremental_fetches_have_to_carry_the_next_epoch() {
+        let cache = FetchSessionCache::new();
+        let full = cache
+            .new_context(INVALID_SESSION_ID, INITIAL_EPOCH, partitions(&[0, 1]), &[])
+            .unwrap();
+        assert!(!full.incremental);
+        assert_ne!(full.session_id, INVALID_SESSION_ID);
+
+        let next = cache
+            .new_context(full.session_id, 1, Vec::new(), &[])
+            .unwrap();
+        assert!(next.incremental);
+        assert_eq!(next.partitions.len(), 2);
+
+        // Replaying an epoch, or skipping one, is refused
+        assert_eq!(
+            cache
+                .new_context(full.session_id, 1, Vec::new(), &[])
+                .unwrap_err(),
+            ErrorCode::InvalidFetchSessionEpoch
+        );
+        assert_eq!(
+            cache
+                .new_context(full.session_id, 3, Vec::new(), &[])
+                .unwrap_err(),
+            ErrorCode::InvalidFetchSessionEpoch
+        );
+        assert_eq!(
+            cache.new_context(12345, 1, Vec::new(), &[]).unwrap_err(),
+            ErrorCode::FetchSessionIdNotFound
+        );
+    }
+
+    #[test]
+    fn incremental_fetches_add_and_forget_partitions() {
+        let cache = FetchSessionCache::new();
+        let session_id = cache
+            .new_context(INVALID_SESSION_ID, INITIAL_EPOCH, partitions(&[0, 1]), &[])
+            .unwrap()
+            .session_id;
+
+        let topic_id = Uuid::from_u128(1);
+        let context = cache
+            .new_context(session_id, 1, partitions(&[2]), &[(topic_id, 0)])
+            .unwrap();
+        let ids = context
+            .partitions
+            .iter()
+            .map(|(_, request)| request.partition_id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [1, 2]);
+    }
+
+    #[test]
+    fn final_epoch_closes_the_session() {
+        let cache = FetchSessionCache::new();
+        let session_id = cache
+            .new_context(INVALID_SESSION_ID, INITIAL_EPOCH, partitions(&[0]), &[])
+            .unwrap()
+            .session_id;
+
+        let closed = cache
+            .new_context(session_id, FINAL_EPOCH, partitions(&[0]), &[])
+            .unwrap();
+        assert_eq!(closed.session_id, INVALID_SESSION_ID);
+        assert_eq!(
+            cache
+                .new_context(session_id, 1, Vec::new(), &[])
+                .unwrap_err(),
+            ErrorCode::FetchSessionIdNotFound
+        );
+    }
+
+    #[test]
+    fn unchanged_partitions_are_left_out_of_incremental_responses() {
+        let cache = FetchSessionCache::new();
+        let topic_id = Uuid::from_u128(1);
+        let session_id = cache
+            .new_context(INVALID_SESSION_ID, INITIAL_EPOCH, partitions(&[0]), &[])
+            .unwrap()
+            .session_id;
+
+        let offsets = Some((10, 10, 0));
+        assert!(cache.update_partition(session_id, &topic_id, 0, offsets, false));
+        assert!(!cache.update_partition(session_id, &topic_id, 0, offsets, false));
+        assert!(cache.update_partition(session_id, &topic_id, 0, offsets, true));
+        assert!(cache.update_partition(session_id, &topic_id, 0, Some((11, 11, 0)), false));
+        // Errors always go out
+        assert!(cache.update_partition(session_id, &topic_id, 0, None, false));
+    }
+
+    #[test]
+    fn full_cache_only_evicts_idle_or_smaller_sessions() {
+        let mut sessions = HashMap::new();
+        let now = 1_000_000;
+        for _ in 0..MAX_FETCH_SESSIONS {
+            FetchSessionCache::create(&mut sessions, &partitions(&[0, 1]), now);
+        }
+
+        // Every session is busy and as big as the new one
+        let session_id = FetchSessionCache::create(&mut sessions, &partitions(&[0, 1]), now);
+        assert_eq!(session_id, INVALID_SESSION_ID);
+        assert_eq!(sessions.len(), MAX_FETCH_SESSIONS);
+
+        // A bigger session takes the place of a smaller one
+        let session_id = FetchSessionCache::create(&mut sessions, &partitions(&[0, 1, 2]), now);
+        assert_ne!(session_id, INVALID_SESSION_ID);
+        assert_eq!(sessions.len(), MAX_FETCH_SESSIONS);
+
+        // Once idle long enough, the least recently used one goes whatever its size
+        let later = now + MIN_EVICTION_MS + 1;
+        let session_id = FetchSessionCache::create(&mut sessions, &partitions(&[0]), later);
+        assert_ne!(session_id, INVALID_SESSION_ID);
+        assert_eq!(sessions.len(), MAX_FETCH_SESSIONS);
+    }
+}
@@ -0,0 +1,960 @@
+use crate::{
+    consumer_group::{
+        ConsumerGroup, ConsumerGroupHeartbeatParams, ConsumerGroupHeartbeatResult,
+        ConsumerGroupState, JOIN_GROUP_MEMBER_EPOCH, LEAVE_GROUP_STATIC_MEMBER_EPOCH,
+        RANGE_ASSIGNOR, UNIFORM_ASSIGNOR,
+    },
+    current_time_ms,
+    metadata::RecordBatch,
+    offsets::OffsetManager,
+    request::ErrorCode,
+};
+use bytes::{Buf, Bytes};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Mutex, MutexGuard},
+};
+
+pub const CONSUMER_PROTOCOL_TYPE: &[u8] = b"consumer";
+// Kafka's group.min.session.timeout.ms and group.max.session.timeout.ms defaults
+const MIN_SESSION_TIMEOUT_MS: i32 = 6000;
+const MAX_SESSION_TIMEOUT_MS: i32 = 1800000;
+// Kafka's group.initial.rebalance.delay.ms default, giving a starting group's other members
+// a chance to join before the first generation is formed
+const INITIAL_REBALANCE_DELAY_MS: i64 = 3000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GroupState {
+    Empty,
+    PreparingRebalance,
+    CompletingRebalance,
+    Stable,
+    Dead,
+}
+
+impl GroupState {
+    /// Name as reported by DescribeGroups and ListGroups.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Empty => "Empty",
+            Self::PreparingRebalance => "PreparingRebalance",
+            Self::CompletingRebalance => "CompletingRebalance",
+            Self::Stable => "Stable",
+            Self::Dead => "Dead",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct JoinGroupParams {
+    pub group_id: Bytes,
+    pub member_id: Bytes,
+    pub group_instance_id: Option<Bytes>,
+    pub client_id: Bytes,
+    pub client_host: Bytes,
+    pub session_timeout_ms: i32,
+    pub rebalance_timeout_ms: i32,
+    pub protocol_type: Bytes,
+    pub protocols: Vec<(Bytes, Bytes)>,
+    // Clients from JoinGroup v4 on are handed a member id and asked to join again with it
+    pub require_known_member_id: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct JoinGroupResult {
+    pub error_code: ErrorCode,
+    pub generation_id: i32,
+    pub protocol_type: Option<Bytes>,
+    pub protocol_name: Option<Bytes>,
+    pub leader: Bytes,
+    pub member_id: Bytes,
+    // Only the leader gets the members and their metadata, it computes the assignment
+    pub members: Vec<(Bytes, Option<Bytes>, Bytes)>,
+}
+
+impl JoinGroupResult {
+    fn error(member_id: Bytes, error_code: ErrorCode) -> Self {
+        Self {
+            error_code,
+            generation_id: -1,
+            protocol_type: None,
+            protocol_name: None,
+            leader: Bytes::new(),
+            member_id,
+            members: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncGroupResult {
+    pub error_code: ErrorCode,
+    pub protocol_type: Option<Bytes>,
+    pub protocol_name: Option<Bytes>,
+    pub assignment: Bytes,
+}
+
+impl SyncGroupResult {
+    fn error(error_code: ErrorCode) -> Self {
+        Self {
+            error_code,
+            protocol_type: None,
+            protocol_name: None,
+            assignment: Bytes::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Member {
+    pub member_id: Bytes,
+    pub group_instance_id: Option<Bytes>,
+    pub client_id: Bytes,
+    pub client_host: Bytes,
+    pub session_timeout_ms: i32,
+    pub rebalance_timeout_ms: i32,
+    pub protocols: Vec<(Bytes, Bytes)>,
+    pub assignment: Bytes,
+    last_heartbeat: i64,
+    join_waiter: Option<oneshot::Sender<JoinGroupResult>>,
+    sync_waiter: Option<oneshot::Sender<SyncGroupResult>>,
+}
+
+impl Member {
+    pub fn metadata(&self, protocol_name: &[u8]) -> Bytes {
+        self.protocols
+            .iter()
+            .find(|(name, _)| name == protocol_name)
+            .map(|(_, metadata)| metadata.clone())
+            .unwrap_or_default()
+    }
+
+    fn supports(&self, protocol_name: &[u8]) -> bool {
+        self.protocols.iter().any(|(name, _)| name == protocol_name)
+    }
+}
+
+/// A consumer group following the classic rebalance protocol: members join, the leader
+/// computes an assignment and hands it back through SyncGroup, and every membership change
+/// starts a new generation.
+#[derive(Debug)]
+pub struct Group {
+    pub group_id: Bytes,
+    pub state: GroupState,
+    pub generation_id: i32,
+    pub protocol_type: Option<Bytes>,
+    pub protocol_name: Option<Bytes>,
+    pub leader_id: Option<Bytes>,
+    pub members: BTreeMap<Bytes, Member>,
+    // When the group entered its current state
+    pub state_timestamp: i64,
+    // Member ids handed out by MEMBER_ID_REQUIRED that have not joined yet
+    pending_members: HashMap<Bytes, i64>,
+    static_members: HashMap<Bytes, Bytes>,
+    rebalance_deadline: i64,
+    initial_delay_deadline: Option<i64>,
+}
+
+impl Group {
+    fn new(group_id: Bytes) -> Self {
+        Self {
+            group_id,
+            state: GroupState::Empty,
+            generation_id: 0,
+            protocol_type: None,
+            protocol_name: None,
+            leader_id: None,
+            members: BTreeMap::new(),
+            state_timestamp: current_time_ms(),
+            pending_members: HashMap::new(),
+            static_members: HashMap::new(),
+            rebalance_deadline: 0,
+            initial_delay_deadline: None,
+        }
+    }
+
+    fn transition(&mut self, state: GroupState, now: i64) {
+        self.state = state;
+        self.state_timestamp = now;
+    }
+
+    /// Topics the members subscribed to, read from their consumer protocol metadata. Only
+    /// known for consumer groups that have members.
+    pub fn subscribed_topics(&self) -> Option<HashSet<Bytes>> {
+        if self.protocol_type.as_deref() != Some(CONSUMER_PROTOCOL_TYPE) {
+            return None;
+        }
+
+        let protocol_name = self.protocol_name.as_ref()?;
+        let mut topics = HashSet::new();
+        for member in self.members.values() {
+            let mut metadata = member.metadata(protocol_name);
+            if metadata.remaining() < 6 {
+                continue;
+            }
+
+            let _version = metadata.get_i16();
+            let topics_len = metadata.get_i32();
+            for _ in 0..topics_len.max(0) {
+                if metadata.remaining() < 2 {
+                    break;
+                }
+                let len = metadata.get_i16().max(0) as usize;
+                if metadata.remaining() < len {
+                    break;
+                }
+                topics.insert(metadata.split_to(len));
+            }
+        }
+
+        Some(topics)
+    }
+
+    /// Protocol every member supports, picked by the members' summed preference order.
+    fn select_protocol(&self) -> Option<Bytes> {
+        let first = self.members.values().next()?;
+        first
+            .protocols
+            .iter()
+            .map(|(name, _)| name)
+            .filter(|name| self.members.values().all(|member| member.supports(name)))
+            .min_by_key(|name| {
+                self.members
+                    .values()
+                    .filter_map(|member| member.protocols.iter().position(|(n, _)| n == *name))
+                    .sum::<usize>()
+            })
+            .cloned()
+    }
+
+    /// Whether a joining member's protocols fit the group's.
+    fn accepts(&self, protocol_type: &[u8], protocols: &[(Bytes, Bytes)]) -> bool {
+        if protocols.is_empty() || protocol_type.is_empty() {
+            return false;
+        }
+
+        if self.members.is_empty() {
+            return true;
+        }
+
+        self.protocol_type.as_deref() == Some(protocol_type)
+            && protocols
+                .iter()
+                .any(|(name, _)| self.members.values().all(|member| member.supports(name)))
+    }
+
+    fn prepare_rebalance(&mut self, now: i64) {
+        // A pending SyncGroup cannot complete any more, its members have to rejoin
+        for member in self.members.values_mut() {
+            if let Some(waiter) = member.sync_waiter.take() {
+                let _ = waiter.send(SyncGroupResult::error(ErrorCode::RebalanceInProgress));
+            }
+        }
+
+        if self.state == GroupState::Empty {
+            self.initial_delay_deadline = Some(now + INITIAL_REBALANCE_DELAY_MS);
+        }
+
+        let rebalance_timeout = self
+            .members
+            .values()
+            .map(|member| member.rebalance_timeout_ms)
+            .max()
+            .unwrap_or(0);
+        self.rebalance_deadline = now + rebalance_timeout as i64;
+        self.transition(GroupState::PreparingRebalance, now);
+    }
+
+    /// Finishes the join phase once every member rejoined, or the rebalance timed out and
+    /// those that did not are dropped.
+    fn maybe_complete_join(&mut self, now: i64) {
+        if self.state != GroupState::PreparingRebalance {
+            return;
+        }
+
+        if self
+            .initial_delay_deadline
+            .is_some_and(|deadline| now < deadline)
+        {
+            return;
+        }
+
+        let all_joined = self
+            .members
+            .values()
+            .all(|member| member.join_waiter.is_some());
+        if !all_joined && now < self.rebalance_deadline {
+            return;
+        }
+
+        self.members
+            .retain(|_, member| member.join_waiter.is_some());
+        self.static_members
+            .retain(|_, member_id| self.members.contains_key(member_id));
+        self.initial_delay_deadline = None;
+        self.generation_id += 1;
+
+        if self.members.is_empty() {
+            self.transition(GroupState::Empty, now);
+            self.leader_id = None;
+            self.protocol_name = None;
+            return;
+        }
+
+        self.protocol_name = self.select_protocol();
+        if self
+            .leader_id
+            .as_ref()
+            .is_none_or(|leader| !self.members.contains_key(leader))
+        {
+            self.leader_id = self.members.keys().next().cloned();
+        }
+        self.transition(GroupState::CompletingRebalance, now);
+
+        let protocol_name = self.protocol_name.clone().unwrap_or_default();
+        let leader = self.leader_id.clone().unwrap_or_default();
+        let members: Vec<(Bytes, Option<Bytes>, Bytes)> = self
+            .members
+            .values()
+            .map(|member| {
+                (
+                    member.member_id.clone(),
+                    member.group_instance_id.clone(),
+                    member.metadata(&protocol_name),
+                )
+            })
+            .collect();
+
+        for member in self.members.values_mut() {
+            member.assignment = Bytes::new();
+            member.last_heartbeat = now;
+            let Some(waiter) = member.join_waiter.take() else {
+                continue;
+            };
+
+            let _ = waiter.send(JoinGroupResult {
+                error_code: ErrorCode::None,
+                generation_id: self.generation_id,
+                protocol_type: self.protocol_type.clone(),
+                protocol_name: self.protocol_name.clone(),
+                leader: leader.clone(),
+                member_id: member.member_id.clone(),
+                members: if member.member_id == leader {
+                    members.clone()
+                } else {
+                    Vec::new()
+                },
+            });
+        }
+    }
+
+    fn remove_member(&mut self, member_id: &Bytes, now: i64) {
+        let Some(mut member) = self.members.remove(member_id) else {
+            return;
+        };
+
+        if let Some(instance_id) = &member.group_instance_id {
+            self.static_members.remove(instance_id);
+        }
+        if let Some(waiter) = member.join_waiter.take() {
+            let _ = waiter.send(JoinGroupResult::error(
+                member_id.clone(),
+                ErrorCode::UnknownMemberId,
+            ));
+        }
+
+        match self.state {
+            GroupState::PreparingRebalance => self.maybe_complete_join(now),
+            GroupState::Stable | GroupState::CompletingRebalance => {
+                if self.members.is_empty() {
+                    self.generation_id += 1;
+                    self.transition(GroupState::Empty, now);
+                    self.leader_id = None;
+                    self.protocol_name = None;
+                } else {
+                    self.prepare_rebalance(now);
+                }
+            }
+            GroupState::Empty | GroupState::Dead => {}
+        }
+    }
+
+    /// Checks a member id, and the static instance id it claims, against the group.
+    fn validate_member(
+        &self,
+        member_id: &Bytes,
+        group_instance_id: Option<&Bytes>,
+    ) -> Result<(), ErrorCode> {
+        if let Some(instance_id) = group_instance_id
+            && let Some(current) = self.static_members.get(instance_id)
+            && current != member_id
+        {
+            return Err(ErrorCode::FencedInstanceId);
+        }
+
+        if !self.members.contains_key(member_id) {
+            return Err(ErrorCode::UnknownMemberId);
+        }
+
+        Ok(())
+    }
+}
+
+/// Owns every consumer group hosted by this broker, which coordinates all of them. Group ids
+/// are shared between classic groups and those on the consumer protocol, an id only ever
+/// names one of them. When both are locked, classic groups are locked first.
+#[derive(Debug, Default)]
+pub struct GroupCoordinator {
+    groups: Mutex<HashMap<Bytes, Group>>,
+    consumer_groups: Mutex<HashMap<Bytes, ConsumerGroup>>,
+}
+
+impl GroupCoordinator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, HashMap<Bytes, Group>> {
+        self.groups.lock().expect("group coordinator lock poisoned")
+    }
+
+    pub fn consumer_lock(&self) -> MutexGuard<'_, HashMap<Bytes, ConsumerGroup>> {
+        self.consumer_groups
+            .lock()
+            .expect("group coordinator lock poisoned")
+    }
+
+    /// Adds or refreshes a member. The returned receiver resolves once the join phase
+    /// completes, or right away if the request is rejected.
+    pub fn join_group(&self, params: JoinGroupParams) -> oneshot::Receiver<JoinGroupResult> {
+        let (tx, rx) = oneshot::channel();
+        let now = current_time_ms();
+        let reject = |tx: oneshot::Sender<JoinGroupResult>, member_id: &Bytes, code| {
+            let _ = tx.send(JoinGroupResult::error(member_id.clone(), code));
+        };
+
+        if params.group_id.is_empty() {
+            reject(tx, &params.member_id, ErrorCode::InvalidGroupId);
+            return rx;
+        }
+
+        if params.session_timeout_ms < MIN_SESSION_TIMEOUT_MS
+            || params.session_timeout_ms > MAX_SESSION_TIMEOUT_MS
+        {
+            reject(tx, &params.member_id, ErrorCode::InvalidSessionTimeout);
+            return rx;
+        }
+
+        let mut groups = self.lock();
+        {
+            // An empty group can switch protocols, one with members cannot
+            let mut consumer_groups = self.consumer_lock();
+            match consumer_groups
+                .get(&params.group_id)
+                .map(|group| group.state)
+            {
+                Some(ConsumerGroupState::Empty) => {
+                    consumer_groups.remove(&params.group_id);
+                }
+                Some(_) => {
+                    reject(tx, &params.member_id, ErrorCode::InconsistentGroupProtocol);
+                    return rx;
+                }
+                None => {}
+            }
+        }
+        let group = groups
+            .entry(params.group_id.clone())
+            .or_insert_with(|| Group::new(params.group_id.clone()));
+
+        if group.state == GroupState::Dead {
+            reject(tx, &params.member_id, ErrorCode::CoordinatorNotAvailable);
+            return rx;
+        }
+
+        if !group.accepts(&params.protocol_type, &params.protocols) {
+            reject(tx, &params.member_id, ErrorCode::InconsistentGroupProtocol);
+            return rx;
+        }
+
+        let mut member_id = params.member_id.clone();
+        if member_id.is_empty() {
+            member_id = Bytes::from(format!(
+                "{}-{}",
+                String::from_utf8_lossy(&params.client_id),
+                Uuid::new_v4()
+            ));
+
+            match &params.group_instance_id {
+                // A static member rejoining under a new id replaces its old incarnation
+                Some(instance_id) => {
+                    if let Some(old_id) = group.static_members.remove(instance_id)
+                        && let Some(mut old) = group.members.remove(&old_id)
+                    {
+                        if let Some(waiter) = old.join_waiter.take() {
+                            let _ = waiter.send(JoinGroupResult::error(
+                                old_id.clone(),
+                                ErrorCode::FencedInstanceId,
+                            ));
+                        }
+                        if group.leader_id.as_ref() == Some(&old_id) {
+                            group.leader_id = Some(member_id.clone());
+                        }
+                    }
+                    group
+                        .static_members
+                        .insert(instance_id.clone(), member_id.clone());
+                }
+                None if params.require_known_member_id => {
+                    group.pending_members.insert(member_id.clone(), now);
+                    reject(tx, &member_id, ErrorCode::MemberIdRequired);
+                    return rx;
+                }
+                None => {}
+            }
+        } else if group.pending_members.remove(&member_id).is_none()
+            && let Err(error_code) =
+                group.validate_member(&member_id, params.group_instance_id.as_ref())
+        {
+            reject(tx, &member_id, error_code);
+            return rx;
+        }
+
+        if group.members.is_empty() {
+            group.protocol_type = Some(params.protocol_type.clone());
+        }
+        // The first member to join leads the group until it leaves
+        if group.leader_id.is_none() {
+            group.leader_id = Some(member_id.clone());
+        }
+
+        let member = group
+            .members
+            .entry(member_id.clone())
+            .or_insert_with(|| Member {
+                member_id: member_id.clone(),
+                group_instance_id: params.group_instance_id.clone(),
+                client_id: params.client_id.clone(),
+                client_host: params.client_host.clone(),
+                session_timeout_ms: params.session_timeout_ms,
+                rebalance_timeout_ms: params.rebalance_timeout_ms,
+                protocols: Vec::new(),
+                assignment: Bytes::new(),
+                last_heartbeat: now,
+                join_waiter: None,
+                sync_waiter: None,
+            });
+
+        // A member that is stable and rejoins with the same protocols just gets the current
+        // generation back
+        let unchanged = member.protocols == params.protocols;
+        member.session_timeout_ms = params.session_timeout_ms;
+        member.rebalance_timeout_ms = params.rebalance_timeout_ms;
+        member.protocols = params.protocols;
+        member.last_heartbeat = now;
+        if let Some(waiter) = member.join_waiter.replace(tx) {
+            let _ = waiter.send(JoinGroupResult::error(
+                member_id.clone(),
+                ErrorCode::UnknownMemberId,
+            ));
+        }
+
+        match group.state {
+            GroupState::Stable if unchanged && group.leader_id.as_ref() != Some(&member_id) => {
+                let member = group.members.get_mut(&member_id).expect("inserted above");
+                let waiter = member.join_waiter.take().expect("set above");
+                let _ = waiter.send(JoinGroupResult {
+                    error_code: ErrorCode::None,
+                    generation_id: group.generation_id,
+                    protocol_type: group.protocol_type.clone(),
+                    protocol_name: group.protocol_name.clone(),
+                    leader: group.leader_id.clone().unwrap_or_default(),
+                    member_id,
+                    members: Vec::new(),
+                });
+            }
+            GroupState::PreparingRebalance => group.maybe_complete_join(now),
+            _ => {
+                group.prepare_rebalance(now);
+                group.maybe_complete_join(now);
+            }
+        }
+
+        rx
+    }
+
+    /// Stores the leader's assignment and hands every member its share. Followers wait
+    /// until the leader's SyncGroup arrives.
+    pub fn sync_group(
+        &self,
+        group_id: &Bytes,
+        generation_id: i32,
+        member_id: &Bytes,
+        group_instance_id: Option<&Bytes>,
+        assignments: Vec<(Bytes, Bytes)>,
+    ) -> oneshot::Receiver<SyncGroupResult> {
+        let (tx, rx) = oneshot::channel();
+        let mut groups = self.lock();
+        let Some(group) = groups.get_mut(group_id) else {
+            let _ = tx.send(SyncGroupResult::error(ErrorCode::UnknownMemberId));
+            return rx;
+        };
+
+        if let Err(error_code) = group.validate_member(member_id, group_instance_id) {
+            let _ = tx.send(SyncGroupResult::error(error_code));
+            return rx;
+        }
+
+        if generation_id != group.generation_id {
+            let _ = tx.send(SyncGroupResult::error(ErrorCode::IllegalGeneration));
+            return rx;
+        }
+
+        let protocol_type = group.protocol_type.clone();
+        let protocol_name = group.protocol_name.clone();
+        let result = |assignment: Bytes| SyncGroupResult {
+            error_code: ErrorCode::None,
+            protocol_type: protocol_type.clone(),
+            protocol_name: protocol_name.clone(),
+            assignment,
+        };
+
+        match group.state {
+            GroupState::CompletingRebalance => {
+                let member = group.members.get_mut(member_id).expect("validated above");
+                member.last_heartbeat = current_time_ms();
+                member.sync_waiter = Some(tx);
+
+                if group.leader_id.as_ref() != Some(member_id) {
+                    return rx;
+                }
+
+                let mut assignments: HashMap<Bytes, Bytes> = assignments.into_iter().collect();
+                group.transition(GroupState::Stable, current_time_ms());
+                for member in group.members.values_mut() {
+                    member.assignment = assignments.remove(&member.member_id).unwrap_or_default();
+                    if let Some(waiter) = member.sync_waiter.take() {
+                        let _ = waiter.send(result(member.assignment.clone()));
+                    }
+                }
+            }
+            GroupState::Stable => {
+                let _ = tx.send(result(group.members[member_id].assignment.clone()));
+            }
+            GroupState::PreparingRebalance => {
+                let _ = tx.send(SyncGroupResult::error(ErrorCode::RebalanceInProgress));
+            }
+            GroupState::Empty | GroupState::Dead => {
+                let _ = tx.send(SyncGroupResult::error(ErrorCode::UnknownMemberId));
+            }
+        }
+
+        rx
+    }
+
+    pub fn heartbeat(
+        &self,
+        group_id: &Bytes,
+        generation_id: i32,
+        member_id: &Bytes,
+        group_instance_id: Option<&Bytes>,
+    ) -> ErrorCode {
+        let mut groups = self.lock();
+        let Some(group) = groups.get_mut(group_id) else {
+            return ErrorCode::UnknownMemberId;
+        };
+
+        if let Err(error_code) = group.validate_member(member_id, group_instance_id) {
+            return error_code;
+        }
+
+        if generation_id != group.generation_id {
+            return ErrorCode::IllegalGeneration;
+        }
+
+        group
+            .members
+            .get_mut(member_id)
+            .expect("validated above")
+            .last_heartbeat = current_time_ms();
+
+        match group.state {
+            GroupState::PreparingRebalance => ErrorCode::RebalanceInProgress,
+            GroupState::CompletingRebalance | GroupState::Stable => ErrorCode::None,
+            GroupState::Empty | GroupState::Dead => ErrorCode::UnknownMemberId,
+        }
+    }
+
+    /// Removes members from the group, returning an error per member. Static members may
+    /// leave by instance id alone.
+    pub fn leave_group(
+        &self,
+        group_id: &Bytes,
+        members: &[(Bytes, Option<Bytes>)],
+    ) -> Result<Vec<ErrorCode>, ErrorCode> {
+        let mut groups = self.lock();
+        let group = groups.get_mut(group_id).ok_or(ErrorCode::UnknownMemberId)?;
+        let now = current_time_ms();
+
+        Ok(members
+            .iter()
+            .map(|(member_id, group_instance_id)| {
+                let member_id = match group_instance_id {
+                    Some(instance_id) if member_id.is_empty() => {
+                        match group.static_members.get(instance_id) {
+                            Some(member_id) => member_id.clone(),
+                            None => return ErrorCode::UnknownMemberId,
+                        }
+                    }
+                    _ => member_id.clone(),
+                };
+
+                if let Err(error_code) =
+                    group.validate_member(&member_id, group_instance_id.as_ref())
+                {
+                    return error_code;
+                }
+
+                group.remove_member(&member_id, now);
+                ErrorCode::None
+            })
+            .collect())
+    }
+
+    /// Handles a ConsumerGroupHeartbeat, creating the group when its first member joins.
+    pub fn consumer_group_heartbeat(
+        &self,
+        params: ConsumerGroupHeartbeatParams,
+        metadata: &[RecordBatch],
+    ) -> ConsumerGroupHeartbeatResult {
+        let error = ConsumerGroupHeartbeatResult::error;
+        let joining = params.member_epoch == JOIN_GROUP_MEMBER_EPOCH;
+
+        if params.group_id.is_empty()
+            || params.member_epoch < LEAVE_GROUP_STATIC_MEMBER_EPOCH
+            || (params.member_id.is_empty() && !joining)
+        {
+            return error(ErrorCode::InvalidRequest);
+        }
+        // Joining members have to say what they subscribe to and that they own nothing
+        if joining
+            && (params.rebalance_timeout_ms < 0
+                || params.subscribed_topic_names.is_none()
+                || params
+                    .owned_partitions
+                    .as_ref()
+                    .is_some_and(|o| !o.is_empty()))
+        {
+            return error(ErrorCode::InvalidRequest);
+        }
+        if let Some(assignor) = &params.server_assignor
+            && assignor != UNIFORM_ASSIGNOR
+            && assignor != RANGE_ASSIGNOR
+        {
+            return error(ErrorCode::UnsupportedAssignor);
+        }
+
+        let mut groups = self.lock();
+        match groups.get(&params.group_id).map(|group| group.state) {
+            // Groups that only have committed offsets so far take up the new protocol
+            Some(GroupState::Empty) => {
+                groups.remove(&params.group_id);
+            }
+            Some(_) => return error(ErrorCode::GroupIdNotFound),
+            None => {}
+        }
+
+        let mut consumer_groups = self.consumer_lock();
+        let group = if joining {
+            consumer_groups
+                .entry(params.group_id.clone())
+                .or_insert_with(|| ConsumerGroup::new(params.group_id.clone()))
+        } else {
+            match consumer_groups.get_mut(&params.group_id) {
+                Some(group) => group,
+                None => return error(ErrorCode::GroupIdNotFound),
+            }
+        };
+
+        group.heartbeat(params, metadata)
+    }
+
+    /// Checks that a member may commit offsets for the group. Commits without a generation
+    /// come from consumers managing their own assignment and are only accepted while the
+    /// group has no members, creating it if needed. Members of consumer protocol groups
+    /// commit with their member epoch in place of the generation.
+    pub fn validate_offset_commit(
+        &self,
+        group_id: &Bytes,
+        generation_id: i32,
+        member_id: &Bytes,
+        group_instance_id: Option<&Bytes>,
+    ) -> Result<(), ErrorCode> {
+        if group_id.is_empty() {
+            return Err(ErrorCode::InvalidGroupId);
+        }
+
+        let mut groups = self.lock();
+        if let Some(group) = self.consumer_lock().get(group_id) {
+            if generation_id < 0 && member_id.is_empty() {
+                return match group.state {
+                    ConsumerGroupState::Empty => Ok(()),
+                    _ => Err(ErrorCode::UnknownMemberId),
+                };
+            }
+
+            return group.validate_member_epoch(member_id, generation_id);
+        }
+
+        if generation_id < 0 && member_id.is_empty() {
+            let group = groups
+                .entry(group_id.clone())
+                .or_insert_with(|| Group::new(group_id.clone()));
+            return match group.state {
+                GroupState::Empty => Ok(()),
+                GroupState::Dead => Err(ErrorCode::CoordinatorNotAvailable),
+                _ => Err(ErrorCode::UnknownMemberId),
+            };
+        }
+
+        let group = groups.get(group_id).ok_or(ErrorCode::IllegalGeneration)?;
+        group.validate_member(member_id, group_instance_id)?;
+        if generation_id != group.generation_id {
+            return Err(ErrorCode::IllegalGeneration);
+        }
+
+        match group.state {
+            GroupState::CompletingRebalance => Err(ErrorCode::RebalanceInProgress),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the member a consumer protocol group's offsets are fetched for. Fetches made
+    /// without a member, as by admin clients, and those of classic groups are not checked.
+    pub fn validate_offset_fetch(
+        &self,
+        group_id: &Bytes,
+        member_id: Option<&Bytes>,
+        member_epoch: i32,
+    ) -> Result<(), ErrorCode> {
+        let Some(member_id) = member_id.filter(|_| member_epoch >= 0) else {
+            return Ok(());
+        };
+
+        match self.consumer_lock().get(group_id) {
+            Some(group) => group.validate_member_epoch(member_id, member_epoch),
+            None => Ok(()),
+        }
+    }
+
+    /// When the group last became empty, or `None` while it has members. Unknown groups
+    /// have been empty forever.
+    pub fn empty_since(&self, group_id: &Bytes) -> Option<i64> {
+        let groups = self.lock();
+        if let Some(group) = self.consumer_lock().get(group_id) {
+            return (group.state == ConsumerGroupState::Empty).then_some(group.state_timestamp);
+        }
+
+        match groups.get(group_id) {
+            Some(group) if group.state == GroupState::Empty => Some(group.state_timestamp),
+            Some(_) => None,
+            None => Some(i64::MIN),
+        }
+    }
+
+    /// Registers groups known only from their committed offsets, as after a restart.
+    pub fn load_groups(&self, group_ids: impl IntoIterator<Item = Bytes>) {
+        let mut groups = self.lock();
+        for group_id in group_ids {
+            groups
+                .entry(group_id.clone())
+                .or_insert_with(|| Group::new(group_id));
+        }
+    }
+
+    /// Deletes an empty group along with its committed offsets.
+    pub fn delete_group(&self, group_id: &Bytes, offsets: &OffsetManager) -> ErrorCode {
+        if group_id.is_empty() {
+            return ErrorCode::InvalidGroupId;
+        }
+
+        let mut groups = self.lock();
+        let mut consumer_groups = self.consumer_lock();
+        match consumer_groups.get(group_id).map(|group| group.state) {
+            Some(ConsumerGroupState::Empty) => {}
+            Some(ConsumerGroupState::Dead) => return ErrorCode::GroupIdNotFound,
+            Some(_) => return ErrorCode::NonEmptyGroup,
+            None => match groups.get(group_id).map(|group| group.state) {
+                None | Some(GroupState::Dead) => return ErrorCode::GroupIdNotFound,
+                Some(GroupState::Empty) => {}
+                Some(_) => return ErrorCode::NonEmptyGroup,
+            },
+        }
+
+        let partitions: Vec<(Bytes, i32)> = offsets
+            .group_offsets(group_id)
+            .into_iter()
+            .map(|(topic, partition, _)| (topic, partition))
+            .collect();
+        if let Err(error_code) = offsets.delete(group_id, &partitions) {
+            return error_code;
+        }
+
+        groups.remove(group_id);
+        consumer_groups.remove(group_id);
+        ErrorCode::None
+    }
+
+    /// Forgets a group that has no members left, once nothing refers to it any more.
+    pub fn remove_if_empty(&self, group_id: &Bytes) {
+        let mut groups = self.lock();
+        if groups
+            .get(group_id)
+            .is_some_and(|group| group.state == GroupState::Empty)
+        {
+            groups.remove(group_id);
+        }
+
+        let mut consumer_groups = self.consumer_lock();
+        if consumer_groups
+            .get(group_id)
+            .is_some_and(|group| group.state == ConsumerGroupState::Empty)
+        {
+            consumer_groups.remove(group_id);
+        }
+    }
+
+    /// Expires members whose session timed out and completes join phases whose deadline
+    /// passed. Run periodically.
+    pub fn tick(&self) {
+        let now = current_time_ms();
+        let mut groups = self.lock();
+        for group in groups.values_mut() {
+            group
+                .pending_members
+                .retain(|_, created| now - *created < MAX_SESSION_TIMEOUT_MS as i64);
+
+            let expired: Vec<Bytes> = group
+                .members
+                .values()
+                .filter(|member| {
+                    member.join_waiter.is_none()
+                        && now - member.last_heartbeat > member.session_timeout_ms as i64
+                })
+                .map(|member| member.member_id.clone())
+                .collect();
+            for member_id in expired {
+                group.remove_member(&member_id, now);
+            }
+
+            group.maybe_complete_join(now);
+        }
+
+        for group in self.consumer_lock().values_mut() {
+            group.expire_members(now);
+        }
+    }
+}
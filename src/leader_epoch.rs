@@ -0,0 +1,216 @@
+use anyhow::{Context, Result, bail};
+
+use std::path::{Path, PathBuf};
+
+const CHECKPOINT_FILE: &str = "leader-epoch-checkpoint";
+const CHECKPOINT_VERSION: i32 = 0;
+
+pub const UNDEFINED_EPOCH: i32 = -1;
+pub const UNDEFINED_OFFSET: i64 = -1;
+
+/// The first offset written in a leader epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EpochEntry {
+    pub epoch: i32,
+    pub start_offset: i64,
+}
+
+/// Where each leader epoch of a partition starts, kept in the partition's
+/// `leader-epoch-checkpoint` file in Kafka's format: a version line, a count line, then one
+/// `epoch start_offset` line per entry. Replicas compare these to find where their logs
+/// diverged after a leader change.
+#[derive(Debug)]
+pub struct LeaderEpochCache {
+    path: PathBuf,
+    // Ordered by epoch and start offset alike
+    entries: Vec<EpochEntry>,
+}
+
+impl LeaderEpochCache {
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(CHECKPOINT_FILE);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err).context("reading leader epoch checkpoint"),
+        };
+
+        let mut lines = content.lines();
+        let mut entries = Vec::new();
+        if let Some(version) = lines.next() {
+            if version.trim().parse::<i32>().ok() != Some(CHECKPOINT_VERSION) {
+                bail!("unsupported leader epoch checkpoint version {version:?}");
+            }
+            let count = lines
+                .next()
+                .and_then(|count| count.trim().parse::<usize>().ok())
+                .context("leader epoch checkpoint has no entry count")?;
+            for line in lines.by_ref().take(count) {
+                let entry = line
+                    .split_once(' ')
+                    .and_then(|(epoch, start_offset)| {
+                        Some(EpochEntry {
+                            epoch: epoch.trim().parse().ok()?,
+                            start_offset: start_offset.trim().parse().ok()?,
+                        })
+                    })
+                    .with_context(|| format!("malformed leader epoch entry {line:?}"))?;
+                entries.push(entry);
+            }
+            if entries.len() != count {
+                bail!("leader epoch checkpoint is missing entries");
+            }
+        }
+
+        Ok(Self { path, entries })
+    }
+
+    pub fn checkpoint_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn latest_epoch(&self) -> Option<i32> {
+        self.entries.last().map(|entry| entry.epoch)
+    }
+
+    /// The offset `epoch` starts at, if this log holds it.
+    pub fn start_offset(&self, epoch: i32) -> Option<i64> {
+        self.entries
+            .iter()
+            .find(|entry| entry.epoch == epoch)
+            .map(|entry| entry.start_offset)
+    }
+
+    /// Records that `epoch` starts at `start_offset`, if it is newer than the latest one.
+    /// Entries from a truncated tail starting at or after it are dropped.
+    pub fn assign(&mut self, epoch: i32, start_offset: i64) -> Result<()> {
+        if epoch < 0 || self.latest_epoch().is_some_and(|latest| epoch <= latest) {
+            return Ok(());
+        }
+
+        self.entries
+            .retain(|entry| entry.start_offset < start_offset);
+        self.entries.push(EpochEntry {
+            epoch,
+            start_offset,
+        });
+        self.checkpoint()
+    }
+
+    /// The largest epoch up to `epoch` and the offset it ends at, exclusive, which is where
+    /// the next epoch starts or, for the latest epoch, the log end offset. Undefined for
+    /// epochs newer than any known or older than the first one.
+    pub fn end_offset_for(&self, epoch: i32, log_end_offset: i64) -> (i32, i64) {
+        if epoch == UNDEFINED_EPOCH {
+            return (UNDEFINED_EPOCH, UNDEFINED_OFFSET);
+        }
+        if self.latest_epoch() == Some(epoch) {
+            return (epoch, log_end_offset);
+        }
+
+        let Some(higher) = self.entries.iter().find(|entry| entry.epoch > epoch) else {
+            return (UNDEFINED_EPOCH, UNDEFINED_OFFSET);
+        };
+        // As in Kafka, an epoch older than the first entry ends where the first one starts
+        let floor = self
+            .entries
+            .iter()
+            .rev()
+            .find(|entry| entry.epoch <= epoch)
+            .map_or(epoch, |entry| entry.epoch);
+        (floor, higher.start_offset)
+    }
+
+    /// Drops the epochs starting at or after `end_offset`, when the log is truncated there.
+    pub fn truncate_from_end(&mut self, end_offset: i64) -> Result<()> {
+        let len = self.entries.len();
+        self.entries.retain(|entry| entry.start_offset < end_offset);
+        if self.entries.len() == len {
+            return Ok(());
+        }
+
+        self.checkpoint()
+    }
+
+    /// Rewrites the checkpoint file whole, through a temporary file so a crash leaves either
+    /// version.
+    fn checkpoint(&self) -> Result<()> {
+        let mut content = format!("{CHECKPOINT_VERSION}\n{}\n", self.entries.len());
+        for entry in self.entries.iter() {
+            content.push_str(&format!("{} {}\n", entry.epoch, entry.start_offset));
+        }
+
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, content).context("writing leader epoch checkpoint")?;
+        std::fs::rename(&tmp, &self.path).context("replacing leader epoch checkpoint")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::current_time_ms;
+
+    fn cache(name: &str, entries: &[(i32, i64)]) -> (LeaderEpochCache, PathBuf) {
+        let dir = std::env::temp_dir().join(format!(
+            "leader-epoch-test-{name}-{}-{}",
+            std::process::id(),
+            current_time_ms()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut cache = LeaderEpochCache::load(&dir).unwrap();
+        for (epoch, start_offset) in entries {
+            cache.assign(*epoch, *start_offset).unwrap();
+        }
+        (cache, dir)
+    }
+
+    #[test]
+    fn end_offset_for_known_and_missing_epochs() {
+        let (cache, dir) = cache("end-offset", &[(1, 0), (3, 10), (5, 20)]);
+
+        // The latest epoch runs to the log end offset, older ones to where the next starts
+        assert_eq!(cache.end_offset_for(5, 30), (5, 30));
+        assert_eq!(cache.end_offset_for(3, 30), (3, 20));
+        assert_eq!(cache.end_offset_for(1, 30), (1, 10));
+        // An epoch this log never saw ends with the largest one below it
+        assert_eq!(cache.end_offset_for(4, 30), (3, 20));
+        assert_eq!(cache.end_offset_for(0, 30), (0, 0));
+        // Nothing is known past the latest epoch
+        assert_eq!(
+            cache.end_offset_for(6, 30),
+            (UNDEFINED_EPOCH, UNDEFINED_OFFSET)
+        );
+        assert_eq!(
+            cache.end_offset_for(UNDEFINED_EPOCH, 30),
+            (UNDEFINED_EPOCH, UNDEFINED_OFFSET)
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn end_offset_for_an_empty_cache_is_undefined() {
+        let (cache, dir) = cache("empty", &[]);
+        assert_eq!(
+            cache.end_offset_for(0, 0),
+            (UNDEFINED_EPOCH, UNDEFINED_OFFSET)
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn truncation_and_stale_epochs_survive_a_reload() {
+        let (mut cache, dir) = cache("checkpoint", &[(1, 0), (3, 10), (5, 20)]);
+        // Epochs older than the latest one are ignored
+        cache.assign(2, 25).unwrap();
+        cache.truncate_from_end(15).unwrap();
+        assert_eq!(cache.end_offset_for(3, 15), (3, 15));
+
+        let reloaded = LeaderEpochCache::load(&dir).unwrap();
+        assert_eq!(reloaded.entries, cache.entries);
+        assert_eq!(reloaded.latest_epoch(), Some(3));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
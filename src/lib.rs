@@ -1,7 +1,30 @@
 use bytes::{Buf, BufMut, Bytes, BytesMut};
+pub mod cluster;
+pub mod compression;
+pub mod config;
+pub mod consumer_group;
+pub mod fetch_session;
+pub mod group;
+pub mod leader_epoch;
+pub mod log;
 pub mod metadata;
+pub mod offsets;
+pub mod producer;
+pub mod raft;
+pub mod record;
+pub mod replica;
+pub mod replica_fetcher;
 pub mod request;
 pub mod server;
+pub mod txn;
+
+#[inline]
+pub fn current_time_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("system clock before unix epoch")
+        .as_millis() as i64
+}
 
 #[inline]
 pub fn varint_decode(bytes: &mut Bytes) -> i32 {
@@ -25,6 +48,12 @@ pub fn varint_decode(bytes: &mut Bytes) -> i32 {
 
 #[inline]
 pub fn unsigned_varint_decode(bytes: &mut Bytes) -> u32 {
+    uvarint_decode(bytes).saturating_sub(1)
+}
+
+/// Plain unsigned varint, without the compact length offset `unsigned_varint_decode` removes.
+#[inline]
+pub fn uvarint_decode(bytes: &mut Bytes) -> u32 {
     let mut value = 0;
     let mut shift = 0;
     let mut consumed = 0;
@@ -40,17 +69,62 @@ pub fn unsigned_varint_decode(bytes: &mut Bytes) -> u32 {
     }
 
     bytes.advance(consumed);
+    value
+}
+
+#[inline]
+pub fn unsigned_varint_encode(buf: &mut BytesMut, length: usize) {
+    uvarint_encode(buf, length as u32 + 1);
+}
+
+#[inline]
+pub fn uvarint_encode(buf: &mut BytesMut, mut value: u32) {
+    while value >= 0x80 {
+        buf.put_u8((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+
+    buf.put_u8(value as u8);
+}
+
+#[inline]
+pub fn varint_encode(buf: &mut BytesMut, value: i32) {
+    let mut value = ((value << 1) ^ (value >> 31)) as u32;
+    while value >= 0x80 {
+        buf.put_u8((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
 
-    value.saturating_sub(1)
+    buf.put_u8(value as u8);
+}
+
+#[inline]
+pub fn varlong_decode(bytes: &mut Bytes) -> i64 {
+    let mut value = 0;
+    let mut shift = 0;
+    let mut consumed = 0;
+
+    for byte in bytes.iter() {
+        consumed += 1;
+        value |= ((byte & 0x7F) as u64) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    bytes.advance(consumed);
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
 }
 
 #[inline]
-pub fn unsigned_varint_encode(buf: &mut BytesMut, mut length: usize) {
-    length += 1;
-    while length >= 0x80 {
-        buf.put_u8((length & 0x7F) as u8 | 0x80);
-        length >>= 7;
+pub fn varlong_encode(buf: &mut BytesMut, value: i64) {
+    let mut value = ((value << 1) ^ (value >> 63)) as u64;
+    while value >= 0x80 {
+        buf.put_u8((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
     }
 
-    buf.put_u8(length as u8);
+    buf.put_u8(value as u8);
 }
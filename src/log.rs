@@ -0,0 +1,1444 @@
+use crate::{
+    config::{BrokerConfig, parse_properties},
+    current_time_ms,
+    leader_epoch::LeaderEpochCache,
+    metadata::{METADATA_TOPIC, RecordBatch as MetadataBatch},
+    producer::{CompletedTxn, ProducerState},
+    record::{ControlRecordType, RecordBatch, RecordBatchHeader},
+};
+use anyhow::{Context, Result, bail};
+use bytes::{Bytes, BytesMut};
+use tokio::sync::{Notify, futures::Notified};
+use uuid::Uuid;
+
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    fs::{File, OpenOptions},
+    io::Write,
+    mem::MaybeUninit,
+    os::unix::{ffi::OsStrExt, fs::FileExt},
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+const SEGMENT_FILE: &str = "00000000000000000000.log";
+// Held by whichever process is appending to a log other processes share
+const LOCK_FILE: &str = ".lock";
+const META_PROPERTIES: &str = "meta.properties";
+const BASE64_URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+#[derive(Debug, Clone, Copy)]
+pub struct AppendInfo {
+    pub base_offset: i64,
+    // Offset after the last appended record, which the high watermark has to reach before
+    // the batches are committed
+    pub log_end_offset: i64,
+    pub log_start_offset: i64,
+    // Whether the flush policy already forced the appended batches onto disk
+    pub flushed: bool,
+}
+
+/// When a partition's appends are forced onto disk, from its topic's `flush.messages` and
+/// `flush.ms`. Without either, flushing is left to the OS, as Kafka does by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlushPolicy {
+    pub messages: Option<i64>,
+    pub ms: Option<i64>,
+}
+
+impl FlushPolicy {
+    pub fn from_configs(messages: Option<&[u8]>, ms: Option<&[u8]>) -> Self {
+        let parse = |value: Option<&[u8]>| {
+            std::str::from_utf8(value?)
+                .ok()?
+                .parse::<i64>()
+                .ok()
+                .filter(|value| *value >= 0 && *value != i64::MAX)
+        };
+
+        Self {
+            messages: parse(messages),
+            ms: parse(ms),
+        }
+    }
+}
+
+/// Offset range of a transaction that ended with an ABORT marker, which read_committed
+/// consumers use to drop the transaction's records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AbortedTxn {
+    pub producer_id: i64,
+    pub first_offset: i64,
+    pub last_offset: i64,
+}
+
+/// One of the broker's `log.dirs`, known to the cluster metadata by the `directory.id` in
+/// its `meta.properties`.
+#[derive(Debug)]
+pub struct LogDir {
+    pub path: PathBuf,
+    pub id: Uuid,
+    // Cleared on the first I/O error, after which none of its partitions are served
+    online: AtomicBool,
+}
+
+impl LogDir {
+    /// Reads the directory's id, creating the directory and its `meta.properties` on first
+    /// start. A directory that cannot be read starts offline.
+    fn open(path: PathBuf, node_id: i32) -> Self {
+        match load_directory_id(&path, node_id) {
+            Ok(id) => Self {
+                path,
+                id,
+                online: AtomicBool::new(true),
+            },
+            Err(err) => {
+                eprintln!("log directory {} is offline: {err:#}", path.display());
+                Self {
+                    path,
+                    id: Uuid::nil(),
+                    online: AtomicBool::new(false),
+                }
+            }
+        }
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Relaxed)
+    }
+
+    /// Total and usable bytes of the filesystem holding the directory.
+    pub fn space(&self) -> Result<(i64, i64)> {
+        let path = CString::new(self.path.as_os_str().as_bytes())
+            .context("log directory path holds a NUL byte")?;
+        let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+        // SAFETY: path is NUL terminated and stat is only read once statvfs filled it in
+        if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("reading filesystem stats");
+        }
+        let stat = unsafe { stat.assume_init() };
+
+        let block_size = stat.f_frsize as i64;
+        Ok((
+            stat.f_blocks as i64 * block_size,
+            stat.f_bavail as i64 * block_size,
+        ))
+    }
+}
+
+/// A partition stored in a log directory, as DescribeLogDirs reports it.
+#[derive(Debug, Clone)]
+pub struct PartitionUsage {
+    pub topic_name: Bytes,
+    pub partition: i32,
+    pub size: i64,
+    // How far the log end offset trails the high watermark
+    pub offset_lag: i64,
+}
+
+/// Owns every partition log this broker has opened. Logs are opened lazily on first use
+/// and recover their end offset from the segment already on disk. Partitions are spread
+/// over the `log.dirs`, and a directory failing only takes its own partitions offline.
+#[derive(Debug)]
+pub struct LogManager {
+    dirs: Box<[LogDir]>,
+    // Where __cluster_metadata lives, outside of the partition placement
+    metadata_dir: PathBuf,
+    logs: Mutex<HashMap<(Bytes, i32), PartitionLog>>,
+    // Index in `dirs` of every partition found on disk or placed since. Only taken while
+    // holding the logs lock
+    locations: Mutex<HashMap<(Bytes, i32), usize>>,
+    // Directory the cluster metadata assigned each of this broker's replicas to
+    assignments: Mutex<HashMap<(Bytes, i32), Uuid>>,
+    // Never held while taking the logs lock
+    flush_policies: Mutex<FlushPolicies>,
+    // Woken whenever a log end offset or high watermark moves, for fetches waiting on data
+    offsets_moved: Notify,
+    // Woken after logs are flushed, for produces waiting on their batches to be on disk
+    flushed: Notify,
+}
+
+#[derive(Debug, Default)]
+struct FlushPolicies {
+    // Policy of topics that do not override the broker's log.flush.interval.* settings
+    default: FlushPolicy,
+    topics: HashMap<Bytes, FlushPolicy>,
+}
+
+impl LogManager {
+    pub fn new(config: &BrokerConfig) -> Self {
+        let dirs: Box<[LogDir]> = config
+            .log_dirs
+            .iter()
+            .map(|path| LogDir::open(path.clone(), config.node_id))
+            .collect();
+
+        let mut locations = HashMap::new();
+        for (index, dir) in dirs.iter().enumerate() {
+            if !dir.is_online() {
+                continue;
+            }
+
+            match list_partitions(&dir.path) {
+                Ok(partitions) => {
+                    locations.extend(partitions.into_iter().map(|partition| (partition, index)))
+                }
+                Err(err) => {
+                    eprintln!("log directory {} is offline: {err:#}", dir.path.display());
+                    dir.online.store(false, Ordering::Relaxed);
+                }
+            }
+        }
+
+        Self {
+            dirs,
+            metadata_dir: config.metadata_log_dir.clone(),
+            logs: Mutex::new(HashMap::new()),
+            locations: Mutex::new(locations),
+            assignments: Mutex::new(HashMap::new()),
+            flush_policies: Mutex::new(FlushPolicies::default()),
+            offsets_moved: Notify::new(),
+            flushed: Notify::new(),
+        }
+    }
+
+    pub fn log_dirs(&self) -> &[LogDir] {
+        &self.dirs
+    }
+
+    /// Every partition stored in a log directory, with its size on disk.
+    pub fn dir_usage(&self, dir: &LogDir) -> Result<Vec<PartitionUsage>> {
+        let logs = self.logs.lock().expect("log lock poisoned");
+        let locations = self.locations.lock().expect("location lock poisoned");
+
+        let mut usage = Vec::new();
+        for ((topic_name, partition), index) in locations.iter() {
+            if self.dirs[*index].path != dir.path || topic_name == METADATA_TOPIC {
+                continue;
+            }
+
+            let path = dir.path.join(format!(
+                "{}-{partition}",
+                String::from_utf8_lossy(topic_name)
+            ));
+            let mut size = 0;
+            for entry in std::fs::read_dir(&path).context("listing partition directory")? {
+                let metadata = entry
+                    .and_then(|entry| entry.metadata())
+                    .context("reading partition file size")?;
+                size += metadata.len() as i64;
+            }
+
+            let offset_lag = logs
+                .get(&(topic_name.clone(), *partition))
+                .map_or(0, |log| {
+                    (log.high_watermark() - log.log_end_offset()).max(0)
+                });
+            usage.push(PartitionUsage {
+                topic_name: topic_name.clone(),
+                partition: *partition,
+                size,
+                offset_lag,
+            });
+        }
+
+        Ok(usage)
+    }
+
+    /// Records the directory the cluster metadata assigned each of the broker's replicas to.
+    pub fn load_directory_assignments(&self, metadata: &[MetadataBatch], node_id: i32) {
+        let mut assignments = self.assignments.lock().expect("assignment lock poisoned");
+        for topic_name in metadata.iter().flat_map(|batch| batch.topic_names()) {
+            let partitions = metadata
+                .iter()
+                .filter_map(|batch| batch.get_topic_partitions_from_name(topic_name))
+                .flatten();
+            for partition in partitions {
+                let directory = partition
+                    .replication_ids
+                    .iter()
+                    .position(|replica| *replica == node_id)
+                    .and_then(|index| partition.directories.get(index));
+                if let Some(directory) = directory {
+                    assignments.insert((topic_name.clone(), partition.partition_id), *directory);
+                }
+            }
+        }
+    }
+
+    /// Finds the directory holding a partition: the one the metadata assigned it to, else
+    /// wherever an earlier run left it, else the online directory with the fewest
+    /// partitions.
+    fn locate(&self, topic_name: &Bytes, partition: i32) -> Result<PathBuf> {
+        if topic_name == METADATA_TOPIC {
+            return Ok(self.metadata_dir.clone());
+        }
+
+        let key = (topic_name.clone(), partition);
+        let mut locations = self.locations.lock().expect("location lock poisoned");
+        let assigned = self
+            .assignments
+            .lock()
+            .expect("assignment lock poisoned")
+            .get(&key)
+            .filter(|id| !id.is_nil())
+            .and_then(|id| self.dirs.iter().position(|dir| dir.id == *id));
+        let index = match assigned.or_else(|| locations.get(&key).copied()) {
+            Some(index) => index,
+            None => {
+                let mut load = vec![0; self.dirs.len()];
+                for index in locations.values() {
+                    load[*index] += 1;
+                }
+
+                (0..self.dirs.len())
+                    .filter(|index| self.dirs[*index].is_online())
+                    .min_by_key(|index| load[*index])
+                    .context("no log directory is online")?
+            }
+        };
+
+        let dir = &self.dirs[index];
+        if !dir.is_online() {
+            bail!("log directory {} is offline", dir.path.display());
+        }
+
+        locations.insert(key, index);
+        Ok(dir.path.clone())
+    }
+
+    /// Runs `f` against the partition's log, opening it if this is the first access. An
+    /// I/O error takes the partition's whole directory offline.
+    pub fn with_partition<T>(
+        &self,
+        topic_name: &Bytes,
+        partition: i32,
+        f: impl FnOnce(&mut PartitionLog) -> Result<T>,
+    ) -> Result<T> {
+        let mut logs = self.logs.lock().expect("log lock poisoned");
+        let key = (topic_name.clone(), partition);
+        if !logs.contains_key(&key) {
+            let root = self.locate(topic_name, partition)?;
+            let log = PartitionLog::open(&root, topic_name, partition);
+            let mut log = match log {
+                Ok(log) => log,
+                Err(err) => {
+                    self.fail_if_io_error(&mut logs, &root, &err);
+                    return Err(err).context("opening partition log");
+                }
+            };
+            log.flush_policy = self.flush_policy(topic_name);
+            logs.insert(key.clone(), log);
+        }
+
+        let log = logs.get_mut(&key).expect("inserted above");
+        let dir = log.dir.clone();
+        let offsets = (log.log_end_offset(), log.high_watermark());
+        let result = f(log);
+        if offsets != (log.log_end_offset(), log.high_watermark()) {
+            self.offsets_moved.notify_waiters();
+        }
+        if let Err(err) = &result {
+            self.fail_if_io_error(&mut logs, &dir, err);
+        }
+
+        result
+    }
+
+    /// Resolves once any partition's log end offset or high watermark moves. Enable it
+    /// before reading the logs so a move in between is not missed.
+    pub fn offsets_moved(&self) -> Notified<'_> {
+        self.offsets_moved.notified()
+    }
+
+    /// Takes the directory holding `path` offline when `err` came from the filesystem,
+    /// closing every log in it.
+    fn fail_if_io_error(
+        &self,
+        logs: &mut HashMap<(Bytes, i32), PartitionLog>,
+        path: &Path,
+        err: &anyhow::Error,
+    ) {
+        if !err.chain().any(|cause| cause.is::<std::io::Error>()) {
+            return;
+        }
+
+        let Some(dir) = self.dirs.iter().find(|dir| path.starts_with(&dir.path)) else {
+            return;
+        };
+        if dir.online.swap(false, Ordering::Relaxed) {
+            logs.retain(|_, log| !log.dir.starts_with(&dir.path));
+            eprintln!("log directory {} failed: {err:#}", dir.path.display());
+        }
+    }
+
+    /// Whether the partition has a log, either opened already or left on disk by an earlier
+    /// run. Internal topics use this to avoid creating every partition up front.
+    pub fn has_partition(&self, topic_name: &Bytes, partition: i32) -> bool {
+        let logs = self.logs.lock().expect("log lock poisoned");
+        let key = (topic_name.clone(), partition);
+        logs.contains_key(&key)
+            || self
+                .locations
+                .lock()
+                .expect("location lock poisoned")
+                .get(&key)
+                .is_some_and(|index| self.dirs[*index].is_online())
+    }
+
+    /// Reads the metadata log from `offset` on, along with the offset it ends at. Batches
+    /// written to it by others since it was last read are picked up first.
+    pub fn read_metadata(&self, offset: i64) -> Result<(Bytes, i64)> {
+        self.with_partition(&Bytes::from_static(METADATA_TOPIC), 0, |log| {
+            log.catch_up()?;
+            let log_end_offset = log.log_end_offset();
+            Ok((
+                log.read(offset, log_end_offset, usize::MAX)?,
+                log_end_offset,
+            ))
+        })
+    }
+
+    /// Compacts a partition down to the latest record for every key. Only copying the
+    /// segment and swapping the result in hold the logs lock, not the rewrite.
+    pub fn compact(&self, topic_name: &Bytes, partition: i32) -> Result<()> {
+        let snapshot =
+            self.with_partition(topic_name, partition, |log| log.compaction_snapshot())?;
+        let Some(snapshot) = snapshot else {
+            return Ok(());
+        };
+
+        let compacted = snapshot.rewrite()?;
+        self.with_partition(topic_name, partition, |log| {
+            log.replace_compacted(&snapshot, &compacted)
+        })
+    }
+
+    pub fn append(&self, topic_name: &Bytes, partition: i32, records: Bytes) -> Result<AppendInfo> {
+        self.with_partition(topic_name, partition, |log| log.append(records))
+    }
+
+    pub fn append_replicated(
+        &self,
+        topic_name: &Bytes,
+        partition: i32,
+        records: Bytes,
+    ) -> Result<AppendInfo> {
+        self.with_partition(topic_name, partition, |log| log.append_replicated(records))
+    }
+
+    pub fn flush(&self, topic_name: &Bytes, partition: i32) -> Result<()> {
+        self.with_partition(topic_name, partition, |log| log.flush())?;
+        self.flushed.notify_waiters();
+        Ok(())
+    }
+
+    /// Waits until the partition is flushed up to `offset`. Returns false if it is not by
+    /// `deadline`.
+    pub async fn wait_for_flush(
+        &self,
+        topic_name: &Bytes,
+        partition: i32,
+        offset: i64,
+        deadline: tokio::time::Instant,
+    ) -> bool {
+        loop {
+            // Registered before checking so a flush in between is not missed
+            let flushed = self.flushed.notified();
+            tokio::pin!(flushed);
+            flushed.as_mut().enable();
+
+            match self.with_partition(topic_name, partition, |log| Ok(log.flushed_offset)) {
+                Ok(flushed_offset) if flushed_offset >= offset => return true,
+                Ok(_) => {}
+                Err(err) => {
+                    eprintln!("reading {topic_name:?}-{partition}: {err:#}");
+                    return false;
+                }
+            }
+
+            if tokio::time::timeout_at(deadline, flushed).await.is_err() {
+                return false;
+            }
+        }
+    }
+
+    /// The flush policy of a topic, the broker's unless the topic overrides it.
+    pub fn flush_policy(&self, topic_name: &Bytes) -> FlushPolicy {
+        let policies = self
+            .flush_policies
+            .lock()
+            .expect("flush policy lock poisoned");
+        policies
+            .topics
+            .get(topic_name)
+            .copied()
+            .unwrap_or(policies.default)
+    }
+
+    /// Applies a topic's flush policy to its logs, opened already or not.
+    pub fn set_flush_policy(&self, topic_name: &Bytes, policy: FlushPolicy) {
+        self.flush_policies
+            .lock()
+            .expect("flush policy lock poisoned")
+            .topics
+            .insert(topic_name.clone(), policy);
+
+        let mut logs = self.logs.lock().expect("log lock poisoned");
+        for ((name, _), log) in logs.iter_mut() {
+            if name == topic_name {
+                log.flush_policy = policy;
+            }
+        }
+    }
+
+    /// Changes the policy of topics that have none of their own.
+    pub fn set_default_flush_policy(&self, policy: FlushPolicy) {
+        let overridden: Vec<Bytes> = {
+            let mut policies = self
+                .flush_policies
+                .lock()
+                .expect("flush policy lock poisoned");
+            policies.default = policy;
+            policies.topics.keys().cloned().collect()
+        };
+
+        let mut logs = self.logs.lock().expect("log lock poisoned");
+        for ((name, _), log) in logs.iter_mut() {
+            if !overridden.contains(name) {
+                log.flush_policy = policy;
+            }
+        }
+    }
+
+    /// Flushes every log holding appends older than its `flush.ms`. Run periodically, off
+    /// the async runtime. Due logs are picked under the logs lock but synced outside it, so
+    /// appends go on meanwhile.
+    pub fn flush_due(&self) {
+        let now = current_time_ms();
+        let due: Vec<_> = {
+            let logs = self.logs.lock().expect("log lock poisoned");
+            logs.iter()
+                .filter(|(_, log)| {
+                    log.flush_policy
+                        .ms
+                        .is_some_and(|ms| log.unflushed_messages > 0 && now - log.last_flush >= ms)
+                })
+                .map(|(key, log)| {
+                    let flushed = (log.log_end_offset, log.unflushed_messages, log.rebuilds);
+                    (key.clone(), log.dir.clone(), log.sync_paths(), flushed)
+                })
+                .collect()
+        };
+        if due.is_empty() {
+            return;
+        }
+
+        let synced: Vec<_> = due
+            .into_iter()
+            .map(|(key, dir, paths, flushed)| {
+                let result = sync_files(&paths, &dir);
+                (key, dir, result, flushed)
+            })
+            .collect();
+
+        let mut logs = self.logs.lock().expect("log lock poisoned");
+        for ((topic_name, partition), dir, result, (log_end_offset, messages, rebuilds)) in synced {
+            match result {
+                Ok(()) => {
+                    // A log truncated while syncing may hold other batches at those offsets
+                    if let Some(log) = logs
+                        .get_mut(&(topic_name, partition))
+                        .filter(|log| log.rebuilds == rebuilds)
+                    {
+                        log.flushed(log_end_offset, messages, now);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("flushing {topic_name:?}-{partition}: {err:#}");
+                    self.fail_if_io_error(&mut logs, &dir, &err);
+                }
+            }
+        }
+        drop(logs);
+        self.flushed.notify_waiters();
+    }
+}
+
+/// Syncs a partition's files, and its directory so files renamed into place stay there.
+/// Files that do not exist yet have nothing to sync.
+fn sync_files(paths: &[PathBuf], dir: &Path) -> Result<()> {
+    for path in paths {
+        match File::open(path) {
+            Ok(file) => file
+                .sync_data()
+                .with_context(|| format!("syncing {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err).with_context(|| format!("opening {}", path.display())),
+        }
+    }
+
+    sync_dir(dir)
+}
+
+/// Reads a log directory's id from its `meta.properties`, writing one with a new id if the
+/// directory has none yet. Ids are stored the way Kafka prints them, as unpadded url-safe
+/// base64.
+fn load_directory_id(path: &Path, node_id: i32) -> Result<Uuid> {
+    std::fs::create_dir_all(path).context("creating log directory")?;
+    let meta_path = path.join(META_PROPERTIES);
+    match std::fs::read_to_string(&meta_path) {
+        Ok(content) => {
+            let properties = parse_properties(&content);
+            let id = properties
+                .get("directory.id")
+                .context("meta.properties has no directory.id")?;
+            decode_uuid(id).with_context(|| format!("invalid directory.id {id:?}"))
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            let id = Uuid::new_v4();
+            let content = format!(
+                "version=1\nnode.id={node_id}\ndirectory.id={}\n",
+                encode_uuid(&id)
+            );
+            std::fs::write(&meta_path, content).context("writing meta.properties")?;
+            Ok(id)
+        }
+        Err(err) => Err(err).context("reading meta.properties"),
+    }
+}
+
+/// Partitions left in a log directory by earlier runs, from their `<topic>-<partition>`
+/// folder names.
+fn list_partitions(path: &Path) -> Result<Vec<(Bytes, i32)>> {
+    let mut partitions = Vec::new();
+    for entry in std::fs::read_dir(path).context("listing log directory")? {
+        let entry = entry.context("listing log directory")?;
+        if !entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            continue;
+        }
+
+        let name = entry.file_name();
+        let Some((topic_name, partition)) = name.to_str().and_then(|name| name.rsplit_once('-'))
+        else {
+            continue;
+        };
+        if let Ok(partition) = partition.parse() {
+            partitions.push((Bytes::copy_from_slice(topic_name.as_bytes()), partition));
+        }
+    }
+
+    Ok(partitions)
+}
+
+pub fn encode_uuid(id: &Uuid) -> String {
+    let mut encoded = String::new();
+    let (mut bits, mut bit_count) = (0u32, 0);
+    for byte in id.as_bytes() {
+        bits = (bits << 8) | *byte as u32;
+        bit_count += 8;
+        while bit_count >= 6 {
+            bit_count -= 6;
+            encoded.push(BASE64_URL[(bits >> bit_count) as usize & 0x3f] as char);
+        }
+        bits &= (1 << bit_count) - 1;
+    }
+    if bit_count > 0 {
+        encoded.push(BASE64_URL[(bits << (6 - bit_count)) as usize & 0x3f] as char);
+    }
+
+    encoded
+}
+
+fn decode_uuid(encoded: &str) -> Option<Uuid> {
+    let mut bytes = Vec::with_capacity(16);
+    let (mut bits, mut bit_count) = (0u32, 0);
+    for c in encoded.trim_end_matches('=').bytes() {
+        let value = BASE64_URL.iter().position(|symbol| *symbol == c)?;
+        bits = (bits << 6) | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            bytes.push((bits >> bit_count) as u8);
+        }
+        bits &= (1 << bit_count) - 1;
+    }
+
+    Uuid::from_slice(&bytes).ok()
+}
+
+/// Where a batch sits in the segment, so reads can go straight to the batches they need.
+#[derive(Debug, Clone, Copy)]
+struct BatchPosition {
+    base_offset: i64,
+    last_offset: i64,
+    position: u64,
+    size: u64,
+}
+
+#[derive(Debug)]
+pub struct PartitionLog {
+    dir: PathBuf,
+    // Every complete batch in the segment, in offset order
+    index: Vec<BatchPosition>,
+    // Bumped every time the index is rebuilt, as batches may have moved
+    rebuilds: u64,
+    log_start_offset: i64,
+    log_end_offset: i64,
+    // Set by replication for partitions with other replicas, otherwise everything appended
+    // is committed
+    high_watermark: Option<i64>,
+    // Epoch stamped on the batches appended while this broker leads the partition
+    leader_epoch: Option<i32>,
+    leader_epochs: LeaderEpochCache,
+    producers: ProducerState,
+    aborted_txns: Vec<AbortedTxn>,
+    flush_policy: FlushPolicy,
+    // Messages appended since the last flush, and when that flush happened
+    unflushed_messages: i64,
+    last_flush: i64,
+    // Offset up to which the log is known to be on disk
+    flushed_offset: i64,
+}
+
+impl PartitionLog {
+    pub fn open(root: &Path, topic_name: &Bytes, partition: i32) -> Result<Self> {
+        let name = String::from_utf8(topic_name.to_vec()).context("topic name is not utf-8")?;
+        let dir = root.join(format!("{name}-{partition}"));
+        if !dir.exists() {
+            std::fs::create_dir_all(&dir).context("creating partition directory")?;
+        }
+
+        let mut log = Self {
+            leader_epochs: LeaderEpochCache::load(&dir)?,
+            dir,
+            index: Vec::new(),
+            rebuilds: 0,
+            log_start_offset: 0,
+            log_end_offset: 0,
+            high_watermark: None,
+            leader_epoch: None,
+            producers: ProducerState::default(),
+            aborted_txns: Vec::new(),
+            flush_policy: FlushPolicy::default(),
+            unflushed_messages: 0,
+            last_flush: current_time_ms(),
+            flushed_offset: 0,
+        };
+        log.recover()?;
+
+        Ok(log)
+    }
+
+    /// Recovers the end offset, producer state, aborted transactions and leader epochs from
+    /// what is on disk. Epochs missing from the checkpoint are taken from the batches.
+    fn recover(&mut self) -> Result<()> {
+        self.log_end_offset = self.log_start_offset;
+        self.index.clear();
+        self.rebuilds += 1;
+        self.producers = ProducerState::default();
+        self.aborted_txns.clear();
+
+        let content = self.read_all()?;
+        let mut position = 0;
+        while let Some(header) = RecordBatchHeader::peek(&content[position..]) {
+            if position + header.size() > content.len() {
+                // Partial write at the tail, anything after it is unreadable
+                break;
+            }
+
+            let marker = control_type(&header, &content[position..position + header.size()]);
+            self.log_end_offset = header.next_offset();
+            self.index.push(BatchPosition {
+                base_offset: header.base_offset,
+                last_offset: header.last_offset(),
+                position: position as u64,
+                size: header.size() as u64,
+            });
+            if let Some(txn) = self.producers.update(&header, marker) {
+                self.record_completed(txn);
+            }
+            self.leader_epochs
+                .assign(header.partition_leader_epoch, header.base_offset)?;
+            position += header.size();
+        }
+        self.leader_epochs.truncate_from_end(self.log_end_offset)?;
+        // Whatever was read back is taken as on disk, truncations and compactions sync it
+        self.flushed_offset = self.log_end_offset;
+
+        Ok(())
+    }
+
+    /// Picks up batches appended to the segment by another process, as brokers sharing an
+    /// externally written metadata log do.
+    pub fn catch_up(&mut self) -> Result<()> {
+        let size = match std::fs::metadata(self.segment_path()) {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err).context("reading segment size"),
+        };
+        let end = self
+            .index
+            .last()
+            .map_or(0, |batch| batch.position + batch.size);
+        if size != end {
+            self.recover()?;
+        }
+
+        Ok(())
+    }
+
+    /// Keeps other processes from appending to the log until the returned file is dropped.
+    /// Only writers of a shared log take it, around `catch_up` and their append.
+    pub fn lock(&self) -> Result<File> {
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.dir.join(LOCK_FILE))
+            .context("opening log lock")?;
+        lock.lock().context("locking log")?;
+        Ok(lock)
+    }
+
+    pub fn segment_path(&self) -> PathBuf {
+        self.dir.join(SEGMENT_FILE)
+    }
+
+    /// Forces everything appended so far onto disk, along with the leader epoch
+    /// checkpoint.
+    pub fn flush(&mut self) -> Result<()> {
+        sync_files(&self.sync_paths(), &self.dir)?;
+        self.flushed(
+            self.log_end_offset,
+            self.unflushed_messages,
+            current_time_ms(),
+        );
+        Ok(())
+    }
+
+    /// Files a flush syncs.
+    fn sync_paths(&self) -> [PathBuf; 2] {
+        [
+            self.segment_path(),
+            self.leader_epochs.checkpoint_path().to_path_buf(),
+        ]
+    }
+
+    /// Records that the log reached the disk up to `log_end_offset`, once `messages` of the
+    /// unflushed ones did. Appends made while syncing stay unflushed.
+    fn flushed(&mut self, log_end_offset: i64, messages: i64, now: i64) {
+        self.unflushed_messages = (self.unflushed_messages - messages).max(0);
+        self.last_flush = now;
+        self.flushed_offset = self
+            .flushed_offset
+            .max(log_end_offset.min(self.log_end_offset));
+    }
+
+    /// Reads the whole segment, as internal logs do when rebuilding their state.
+    pub fn read_all(&self) -> Result<Bytes> {
+        match std::fs::read(self.segment_path()) {
+            Ok(content) => Ok(Bytes::from(content)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Bytes::new()),
+            Err(err) => Err(err).context("reading segment"),
+        }
+    }
+
+    pub fn log_start_offset(&self) -> i64 {
+        self.log_start_offset
+    }
+
+    pub fn log_end_offset(&self) -> i64 {
+        self.log_end_offset
+    }
+
+    /// Offset up to which every in-sync replica has the log. Without other replicas
+    /// everything appended is committed as soon as it is on disk.
+    pub fn high_watermark(&self) -> i64 {
+        self.high_watermark.map_or(self.log_end_offset, |offset| {
+            offset.min(self.log_end_offset)
+        })
+    }
+
+    pub fn set_high_watermark(&mut self, offset: i64) {
+        self.high_watermark = Some(offset);
+    }
+
+    /// Makes this broker the partition's leader in `epoch`, which starts at the log end
+    /// offset. Batches appended from now on are stamped with it.
+    pub fn become_leader(&mut self, epoch: i32) -> Result<()> {
+        self.leader_epoch = Some(epoch);
+        self.leader_epochs.assign(epoch, self.log_end_offset)
+    }
+
+    /// Where the epoch this broker leads the partition in started, `None` when it does not
+    /// lead it.
+    pub fn leader_epoch_start_offset(&self) -> Option<i64> {
+        self.leader_epochs.start_offset(self.leader_epoch?)
+    }
+
+    /// Makes this broker a follower, keeping the epochs of the batches it replicates.
+    pub fn become_follower(&mut self) {
+        self.leader_epoch = None;
+    }
+
+    pub fn latest_epoch(&self) -> Option<i32> {
+        self.leader_epochs.latest_epoch()
+    }
+
+    /// The largest epoch up to `epoch` this log holds and the offset it ends at, as asked
+    /// for by OffsetForLeaderEpoch and checked against a follower's last fetched epoch.
+    pub fn end_offset_for_epoch(&self, epoch: i32) -> (i32, i64) {
+        self.leader_epochs
+            .end_offset_for(epoch, self.log_end_offset)
+    }
+
+    /// Drops everything from `offset` on, where a follower's log diverged from its
+    /// leader's.
+    pub fn truncate_to(&mut self, offset: i64) -> Result<()> {
+        if offset >= self.log_end_offset {
+            return Ok(());
+        }
+
+        let end = match self.index.iter().find(|batch| batch.last_offset >= offset) {
+            Some(batch) => batch.position,
+            None => self
+                .index
+                .last()
+                .map_or(0, |batch| batch.position + batch.size),
+        };
+
+        let segment = OpenOptions::new()
+            .write(true)
+            .open(self.segment_path())
+            .context("opening segment for truncation")?;
+        segment.set_len(end).context("truncating segment")?;
+        segment.sync_data().context("syncing segment")?;
+
+        self.recover()?;
+        if let Some(high_watermark) = self.high_watermark {
+            self.high_watermark = Some(high_watermark.min(self.log_end_offset));
+        }
+
+        Ok(())
+    }
+
+    /// Offset below which no transaction is still open, the limit of read_committed
+    /// fetches.
+    pub fn last_stable_offset(&self) -> i64 {
+        let high_watermark = self.high_watermark();
+        self.producers
+            .first_unstable_offset()
+            .map_or(high_watermark, |offset| offset.min(high_watermark))
+    }
+
+    /// Reads the batches holding offsets from `fetch_offset` up to, but excluding,
+    /// `max_offset`. The first batch is always returned whole so a consumer can make
+    /// progress, later ones only while they fit in `max_bytes`. Only the bytes returned are
+    /// read from the segment.
+    pub fn read(&self, fetch_offset: i64, max_offset: i64, max_bytes: usize) -> Result<Bytes> {
+        let first = self
+            .index
+            .partition_point(|batch| batch.last_offset < fetch_offset);
+        let Some(start) = self
+            .index
+            .get(first)
+            .filter(|batch| batch.base_offset < max_offset)
+            .map(|batch| batch.position)
+        else {
+            return Ok(Bytes::new());
+        };
+
+        let mut end = start;
+        for batch in self.index[first..]
+            .iter()
+            .take_while(|batch| batch.base_offset < max_offset)
+        {
+            let next = batch.position + batch.size;
+            if next - start > max_bytes as u64 && end > start {
+                break;
+            }
+            end = next;
+        }
+
+        let mut content = vec![0; (end - start) as usize];
+        File::open(self.segment_path())
+            .and_then(|segment| segment.read_exact_at(&mut content, start))
+            .context("reading segment")?;
+        Ok(Bytes::from(content))
+    }
+
+    /// Aborted transactions overlapping the offsets from `fetch_offset` up to `max_offset`.
+    pub fn aborted_transactions(&self, fetch_offset: i64, max_offset: i64) -> Vec<AbortedTxn> {
+        self.aborted_txns
+            .iter()
+            .filter(|txn| txn.last_offset >= fetch_offset && txn.first_offset < max_offset)
+            .copied()
+            .collect()
+    }
+
+    /// Compacts the log in one go. `LogManager::compact` does the same without holding the
+    /// logs lock while rewriting.
+    pub fn compact(&mut self) -> Result<()> {
+        let Some(snapshot) = self.compaction_snapshot()? else {
+            return Ok(());
+        };
+        let compacted = snapshot.rewrite()?;
+        self.replace_compacted(&snapshot, &compacted)
+    }
+
+    /// Copies the complete batches of the segment for a compaction to rewrite.
+    pub fn compaction_snapshot(&self) -> Result<Option<CompactionSnapshot>> {
+        let Some(end) = self.index.last().map(|batch| batch.position + batch.size) else {
+            return Ok(None);
+        };
+
+        let mut content = vec![0; end as usize];
+        File::open(self.segment_path())
+            .and_then(|segment| segment.read_exact_at(&mut content, 0))
+            .context("reading segment")?;
+        Ok(Some(CompactionSnapshot {
+            dir: self.dir.clone(),
+            content: Bytes::from(content),
+            end,
+            rebuilds: self.rebuilds,
+        }))
+    }
+
+    /// Swaps a rewritten segment in, after copying over the batches appended since its
+    /// snapshot was taken. Dropped if the log was truncated or compacted in between.
+    pub fn replace_compacted(
+        &mut self,
+        snapshot: &CompactionSnapshot,
+        compacted: &Path,
+    ) -> Result<()> {
+        if snapshot.rebuilds != self.rebuilds {
+            std::fs::remove_file(compacted).context("removing stale compacted segment")?;
+            return Ok(());
+        }
+
+        let end = self
+            .index
+            .last()
+            .map_or(0, |batch| batch.position + batch.size);
+        if end > snapshot.end {
+            let mut appended = vec![0; (end - snapshot.end) as usize];
+            File::open(self.segment_path())
+                .and_then(|segment| segment.read_exact_at(&mut appended, snapshot.end))
+                .context("reading segment")?;
+            let mut segment = OpenOptions::new()
+                .append(true)
+                .open(compacted)
+                .context("opening compacted segment")?;
+            segment
+                .write_all(&appended)
+                .context("appending to compacted segment")?;
+            segment.sync_data().context("syncing compacted segment")?;
+        }
+
+        // Swap the compacted copy in whole so a crash leaves one version or the other
+        std::fs::rename(compacted, self.segment_path()).context("replacing segment")?;
+        sync_dir(&self.dir)?;
+
+        // Batches moved, and some are gone
+        self.recover()
+    }
+
+    fn record_completed(&mut self, txn: CompletedTxn) {
+        if txn.aborted {
+            self.aborted_txns.push(AbortedTxn {
+                producer_id: txn.producer_id,
+                first_offset: txn.first_offset,
+                last_offset: txn.last_offset,
+            });
+        }
+    }
+
+    /// Appends already validated batches, assigning each a base offset starting at the
+    /// current log end offset. The base offset sits outside the CRC so no re-checksum is
+    /// needed. Batches from idempotent producers are checked against the producer state
+    /// first, failing with a [`SequenceError`](crate::producer::SequenceError) and writing
+    /// nothing if any of them is a duplicate or out of order.
+    pub fn append(&mut self, records: Bytes) -> Result<AppendInfo> {
+        self.write(records, true)
+    }
+
+    /// Appends batches fetched from the partition's leader as they are, offsets included.
+    /// Batches the log already holds are skipped, and the rest have to continue from the
+    /// log end offset.
+    pub fn append_replicated(&mut self, records: Bytes) -> Result<AppendInfo> {
+        let mut position = 0;
+        while let Some(header) = RecordBatchHeader::peek(&records[position..]) {
+            if header.next_offset() > self.log_end_offset
+                || position + header.size() > records.len()
+            {
+                if header.base_offset != self.log_end_offset {
+                    bail!(
+                        "replicated batch at offset {} does not follow the log end offset {}",
+                        header.base_offset,
+                        self.log_end_offset
+                    );
+                }
+                break;
+            }
+            position += header.size();
+        }
+
+        self.write(records.slice(position..), false)
+    }
+
+    /// Writes batches at the log end, either giving them offsets from there on after
+    /// checking their producer sequences, or keeping the offsets a leader gave them.
+    fn write(&mut self, records: Bytes, assign_offsets: bool) -> Result<AppendInfo> {
+        let base_offset = self.log_end_offset;
+        let mut next_offset = base_offset;
+        let mut producers = self.producers.clone();
+        let mut completed = Vec::new();
+        let mut epochs = Vec::new();
+        let mut positions = Vec::new();
+        let mut out = BytesMut::from(&records[..]);
+        let mut position = 0;
+        while position < out.len() {
+            let Some(mut header) = RecordBatchHeader::peek(&out[position..]) else {
+                bail!("truncated record batch at position {position}");
+            };
+            if position + header.size() > out.len() {
+                bail!("truncated record batch at position {position}");
+            }
+
+            if assign_offsets {
+                producers.check(&header)?;
+                header.base_offset = next_offset;
+                out[position..position + 8].copy_from_slice(&next_offset.to_be_bytes());
+                // The partition leader epoch also sits outside the CRC
+                if let Some(epoch) = self.leader_epoch {
+                    header.partition_leader_epoch = epoch;
+                    out[position + 12..position + 16].copy_from_slice(&epoch.to_be_bytes());
+                }
+            }
+            epochs.push((header.partition_leader_epoch, header.base_offset));
+            positions.push(BatchPosition {
+                base_offset: header.base_offset,
+                last_offset: header.last_offset(),
+                position: position as u64,
+                size: header.size() as u64,
+            });
+            let marker = control_type(&header, &out[position..position + header.size()]);
+            completed.extend(producers.update(&header, marker));
+
+            next_offset = header.next_offset();
+            position += header.size();
+        }
+
+        let mut segment = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.segment_path())
+            .context("opening segment for append")?;
+        let segment_size = segment.metadata().context("reading segment size")?.len();
+        segment.write_all(&out).context("appending to segment")?;
+
+        self.log_end_offset = next_offset;
+        self.index
+            .extend(positions.into_iter().map(|batch| BatchPosition {
+                position: segment_size + batch.position,
+                ..batch
+            }));
+        self.producers = producers;
+        for txn in completed {
+            self.record_completed(txn);
+        }
+        for (epoch, start_offset) in epochs {
+            self.leader_epochs.assign(epoch, start_offset)?;
+        }
+
+        self.unflushed_messages += next_offset - base_offset;
+        let flushed = self
+            .flush_policy
+            .messages
+            .is_some_and(|messages| self.unflushed_messages >= messages);
+        if flushed {
+            self.flush()?;
+        }
+
+        Ok(AppendInfo {
+            base_offset,
+            log_end_offset: next_offset,
+            log_start_offset: self.log_start_offset,
+            flushed,
+        })
+    }
+}
+
+/// Batches a compaction rewrites, copied out of the segment so the rewrite can go on
+/// without holding the logs lock.
+#[derive(Debug)]
+pub struct CompactionSnapshot {
+    dir: PathBuf,
+    content: Bytes,
+    // Segment length the copy covers, later batches are carried over when swapping in
+    end: u64,
+    rebuilds: u64,
+}
+
+impl CompactionSnapshot {
+    /// Writes the batches keeping only the latest record for every key, and returns where.
+    /// Offsets are left untouched, so batches end up with gaps between their records and
+    /// batches with nothing left are dropped. Transactional and control batches are kept
+    /// whole, as is the last batch, which carries the log end offset across restarts.
+    pub fn rewrite(&self) -> Result<PathBuf> {
+        let batches = RecordBatch::decode_all(self.content.clone()).context("decoding segment")?;
+        let Some((last, batches)) = batches.split_last() else {
+            bail!("nothing to compact");
+        };
+
+        let compactable = |batch: &RecordBatch| !batch.is_transactional() && !batch.is_control();
+        let mut latest: HashMap<Bytes, i64> = HashMap::new();
+        for batch in batches.iter().chain(std::iter::once(last)) {
+            if !compactable(batch) {
+                continue;
+            }
+
+            for record in batch.records.iter() {
+                if let Some(key) = &record.key {
+                    latest.insert(key.clone(), batch.base_offset + record.offset_delta as i64);
+                }
+            }
+        }
+
+        let mut out = BytesMut::new();
+        for batch in batches {
+            if !compactable(batch) {
+                out.extend_from_slice(&batch.encode()?);
+                continue;
+            }
+
+            let mut batch = batch.clone();
+            batch.records.retain(|record| match &record.key {
+                Some(key) => {
+                    latest.get(key) == Some(&(batch.base_offset + record.offset_delta as i64))
+                }
+                None => true,
+            });
+            if !batch.records.is_empty() {
+                out.extend_from_slice(&batch.encode()?);
+            }
+        }
+        out.extend_from_slice(&last.encode()?);
+
+        let compacted = self.dir.join(format!("{SEGMENT_FILE}.cleaned"));
+        let mut file = File::create(&compacted).context("creating compacted segment")?;
+        file.write_all(&out).context("writing compacted segment")?;
+        file.sync_data().context("syncing compacted segment")?;
+        Ok(compacted)
+    }
+}
+
+/// Makes the renames and creations in `dir` durable.
+pub(crate) fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("syncing {}", dir.display()))
+}
+
+/// The COMMIT or ABORT marker of a control batch. Control batches are never compressed, so
+/// decoding the single record is cheap.
+fn control_type(header: &RecordBatchHeader, raw: &[u8]) -> Option<ControlRecordType> {
+    if !header.is_control() {
+        return None;
+    }
+
+    RecordBatch::decode(&mut Bytes::copy_from_slice(raw))
+        .ok()
+        .and_then(|batch| batch.control_type())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::{Record, RecordHeader};
+
+    /// A fresh directory under the system temp dir, unique to the test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "log-test-{name}-{}-{}",
+            std::process::id(),
+            current_time_ms()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn record(key: &'static [u8], value: &'static [u8], trace: &'static [u8]) -> Record {
+        let mut record = Record::new(
+            Some(Bytes::from_static(key)),
+            Some(Bytes::from_static(value)),
+        );
+        record.headers = vec![RecordHeader {
+            key: Bytes::from_static(b"traceparent"),
+            value: Some(Bytes::from_static(trace)),
+        }];
+        record
+    }
+
+    #[test]
+    fn compaction_keeps_the_latest_record_and_its_headers() {
+        let root = temp_dir("compaction");
+        let mut log = PartitionLog::open(&root, &Bytes::from_static(b"compacted"), 0).unwrap();
+        for records in [
+            vec![record(b"a", b"1", b"t1"), record(b"b", b"1", b"t2")],
+            vec![record(b"a", b"2", b"t3")],
+            vec![record(b"c", b"1", b"t4")],
+        ] {
+            log.append(RecordBatch::new(0, records).encode().unwrap())
+                .unwrap();
+        }
+
+        log.compact().unwrap();
+        let batches = RecordBatch::decode_all(log.read_all().unwrap()).unwrap();
+        let kept = batches
+            .iter()
+            .flat_map(|batch| {
+                batch.records.iter().map(|record| {
+                    (
+                        batch.base_offset + record.offset_delta as i64,
+                        record.headers[0].value.clone().unwrap(),
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kept,
+            [
+                (1, Bytes::from_static(b"t2")),
+                (2, Bytes::from_static(b"t3")),
+                (3, Bytes::from_static(b"t4")),
+            ]
+        );
+
+        // Offsets survive compaction, so the log reopens at the same end
+        let reopened = PartitionLog::open(&root, &Bytes::from_static(b"compacted"), 0).unwrap();
+        assert_eq!(reopened.log_end_offset(), 4);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn compaction_carries_over_batches_appended_during_the_rewrite() {
+        let root = temp_dir("compaction-appended");
+        let topic = Bytes::from_static(b"compacted");
+        let mut log = PartitionLog::open(&root, &topic, 0).unwrap();
+        for value in [b"1", b"2"] {
+            log.append(
+                RecordBatch::new(0, vec![record(b"a", value, b"t")])
+                    .encode()
+                    .unwrap(),
+            )
+            .unwrap();
+        }
+
+        let snapshot = log.compaction_snapshot().unwrap().unwrap();
+        let compacted = snapshot.rewrite().unwrap();
+        log.append(
+            RecordBatch::new(0, vec![record(b"a", b"3", b"t")])
+                .encode()
+                .unwrap(),
+        )
+        .unwrap();
+        log.replace_compacted(&snapshot, &compacted).unwrap();
+
+        let batches = RecordBatch::decode_all(log.read_all().unwrap()).unwrap();
+        let base_offsets = batches
+            .iter()
+            .map(|batch| batch.base_offset)
+            .collect::<Vec<_>>();
+        assert_eq!(base_offsets, [1, 2]);
+        assert_eq!(log.log_end_offset(), 3);
+
+        // A rewrite of a log truncated meanwhile is thrown away
+        let snapshot = log.compaction_snapshot().unwrap().unwrap();
+        let compacted = snapshot.rewrite().unwrap();
+        log.truncate_to(2).unwrap();
+        log.replace_compacted(&snapshot, &compacted).unwrap();
+        assert!(!compacted.exists());
+        assert_eq!(log.log_end_offset(), 2);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn read_starts_at_the_fetch_offset_and_stops_at_max_bytes() {
+        let root = temp_dir("read");
+        let mut log = PartitionLog::open(&root, &Bytes::from_static(b"read"), 0).unwrap();
+        let mut sizes = Vec::new();
+        for value in [b"1", b"2", b"3"] {
+            let batch = RecordBatch::new(0, vec![record(b"k", value, b"t")])
+                .encode()
+                .unwrap();
+            sizes.push(batch.len());
+            log.append(batch).unwrap();
+        }
+        let base_offsets = |content: Bytes| {
+            RecordBatch::decode_all(content)
+                .unwrap()
+                .iter()
+                .map(|batch| batch.base_offset)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(base_offsets(log.read(1, 3, usize::MAX).unwrap()), [1, 2]);
+        assert_eq!(base_offsets(log.read(1, 2, usize::MAX).unwrap()), [1]);
+        assert_eq!(
+            base_offsets(log.read(0, 3, sizes[0] + sizes[1]).unwrap()),
+            [0, 1]
+        );
+        // The first batch goes out whole even when it is bigger than max_bytes
+        assert_eq!(base_offsets(log.read(2, 3, 1).unwrap()), [2]);
+        assert!(log.read(3, 3, usize::MAX).unwrap().is_empty());
+
+        // Truncation and compaction move batches, reads follow them
+        log.truncate_to(2).unwrap();
+        assert_eq!(base_offsets(log.read(0, 2, usize::MAX).unwrap()), [0, 1]);
+        log.compact().unwrap();
+        assert_eq!(base_offsets(log.read(0, 2, usize::MAX).unwrap()), [1]);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn locked_writers_sharing_a_log_append_after_each_other() {
+        let root = temp_dir("shared");
+        let topic = Bytes::from_static(b"shared");
+        // Each writer has its own view of the log, as brokers sharing it do
+        let writers = (0..2)
+            .map(|_| {
+                let mut log = PartitionLog::open(&root, &topic, 0).unwrap();
+                std::thread::spawn(move || {
+                    for _ in 0..20 {
+                        let _lock = log.lock().unwrap();
+                        log.catch_up().unwrap();
+                        log.append(
+                            RecordBatch::new(0, vec![record(b"a", b"1", b"t")])
+                                .encode()
+                                .unwrap(),
+                        )
+                        .unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let log = PartitionLog::open(&root, &topic, 0).unwrap();
+        let offsets = RecordBatch::decode_all(log.read_all().unwrap())
+            .unwrap()
+            .iter()
+            .map(|batch| batch.base_offset)
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, (0..40).collect::<Vec<_>>());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,21 +1,106 @@
 use anyhow::{Context, Result};
-use codecrafters_kafka::server::{ConnectionHandler, Server};
-use kanal::unbounded_async;
-use tokio::net::TcpListener;
+use codecrafters_kafka::{
+    config::BrokerConfig,
+    log::LogManager,
+    raft::RaftQuorum,
+    server::{ConnectionHandler, ControllerServer, Server, ServerRequest},
+};
+use kanal::{AsyncSender, unbounded_async};
+use tokio::{
+    net::TcpListener,
+    signal::unix::{SignalKind, signal},
+};
+
+use std::sync::Arc;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let listener = TcpListener::bind("0.0.0.0:9092")
-        .await
-        .context("starting server")?;
+    let config = BrokerConfig::from_args(std::env::args().skip(1))
+        .context("loading broker configuration")?;
+    let config = Arc::new(config);
+
+    let logs = Arc::new(LogManager::new(&config));
+    let quorum = match config.uses_quorum() {
+        true => {
+            let quorum = RaftQuorum::new(&config, Arc::clone(&logs))
+                .context("loading metadata quorum state")?;
+            Some(Arc::new(quorum))
+        }
+        false => None,
+    };
+
+    let mut listeners = Vec::new();
+    if config.is_broker() {
+        for address in config.bind_addresses() {
+            listeners.push(bind(&address).await?);
+        }
+    }
+    let mut controller_listeners = Vec::new();
+    if config.is_controller() {
+        for address in config.controller_bind_addresses() {
+            controller_listeners.push(bind(&address).await?);
+        }
+    }
+
+    let mut accepts = Vec::new();
+    if let Some(quorum) = &quorum {
+        // The quorum has to be reachable before this node can catch up with it
+        if config.is_controller() {
+            let mut controller = ControllerServer::new(&config, Arc::clone(quorum));
+            let (tx, rx) = unbounded_async();
+            controller.start(rx);
+            for listener in controller_listeners {
+                let accept = accept_connections(listener, tx.clone(), config.request_max_bytes);
+                accepts.push(tokio::task::spawn(accept));
+            }
+        }
+        quorum.start();
+        quorum.wait_until_caught_up().await;
+    }
+
+    if config.is_broker() {
+        let mut server = Server::new(Arc::clone(&config), logs, quorum.clone());
+        let (tx, rx) = unbounded_async();
+        server.start(rx);
+        for listener in listeners {
+            let accept = accept_connections(listener, tx.clone(), config.request_max_bytes);
+            accepts.push(tokio::task::spawn(accept));
+        }
+    }
+
+    let mut terminate = signal(SignalKind::terminate()).context("handling SIGTERM")?;
+    tokio::select! {
+        accepted = futures_util::future::try_join_all(accepts) => {
+            for result in accepted? {
+                result?;
+            }
+        }
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+
+    // Hand leadership over rather than leaving the quorum to time out
+    if let Some(quorum) = quorum {
+        quorum.resign().await;
+    }
+
+    Ok(())
+}
 
-    let mut server = Server::new();
-    let (tx, rx) = unbounded_async();
-    server.start(rx);
+async fn bind(address: &str) -> Result<TcpListener> {
+    TcpListener::bind(address)
+        .await
+        .with_context(|| format!("starting server on {address}"))
+}
 
+async fn accept_connections(
+    listener: TcpListener,
+    tx: AsyncSender<ServerRequest>,
+    max_request_size: usize,
+) -> Result<()> {
     loop {
         let (stream, _) = listener.accept().await?;
-        let mut handler = ConnectionHandler::new(stream, tx.clone());
+        let mut handler = ConnectionHandler::new(stream, tx.clone(), max_request_size);
         tokio::task::spawn(async move {
             if let Err(err) = handler.handle_connection().await {
                 eprintln!("connection error occurred: {err:#?}");
@@ -1,35 +1,56 @@
 #![allow(dead_code)]
 
-use crate::{unsigned_varint_decode, varint_decode};
-use bytes::{Buf, Bytes};
+use crate::{
+    config::ConfigRecord, current_time_ms, log::LogManager, raft::RaftQuorum, record,
+    unsigned_varint_decode, unsigned_varint_encode, uvarint_decode, uvarint_encode,
+};
+use anyhow::{Context, Result, bail};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use uuid::Uuid;
 
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
-const METADATA_FILE: &str =
-    "/tmp/kraft-combined-logs/__cluster_metadata-0/00000000000000000000.log";
-
-pub fn parse_metadata() -> Box<[RecordBatch]> {
-    let mut content = match std::fs::read(METADATA_FILE) {
-        Ok(content) => Bytes::from(content),
-        Err(_) => return Vec::new().into_boxed_slice(),
-    };
+pub const METADATA_TOPIC: &[u8] = b"__cluster_metadata";
+// Frame version that prefixes every serialised metadata record
+pub(crate) const METADATA_FRAME_VERSION: i8 = 1;
 
+/// Decodes the batches of a metadata log, leaving out the control batches the quorum
+/// writes for itself and the record types brokers have no use for.
+pub fn decode_metadata(mut content: Bytes) -> Result<Box<[RecordBatch]>> {
     let mut batches = Vec::new();
     while content.has_remaining() {
-        let _ = content.get_i64();
-        let batch_len = content.get_i32();
-        let mut batch = Bytes::copy_from_slice(&content[..batch_len as usize]);
-        let batch_header = RecordBatchHeader::new(&mut batch);
-        let total_records = batch.get_i32();
+        let batch = record::RecordBatch::decode(&mut content).context("decoding metadata batch")?;
+        if batch.is_control() {
+            continue;
+        }
 
         let mut topics = HashMap::new();
         let mut partitions = HashMap::new();
+        let mut configs: Vec<ConfigRecord> = Vec::new();
+        let mut brokers: Vec<BrokerRecord> = Vec::new();
+        let mut partition_changes: Vec<PartitionChangeRecord> = Vec::new();
+        let mut next_producer_id = None;
+        let mut metadata_version = None;
         let mut current_topic_id = None;
-        for _ in (0..total_records).into_iter() {
-            let record = Record::new(&mut batch);
-            match record.record_type {
-                RecordType::Feature(_) => {} // TODO: Deal with this if required
+        for record in batch.records.iter() {
+            let value = record.value.clone().with_context(|| {
+                format!(
+                    "metadata record at offset {} has no value",
+                    batch.base_offset + record.offset_delta as i64
+                )
+            })?;
+            let Some(record) = RecordType::new(value)? else {
+                continue;
+            };
+            match record {
+                RecordType::Feature(feature) => {
+                    if feature.name == FeatureRecord::METADATA_VERSION {
+                        metadata_version = Some(feature.feature_level);
+                    }
+                }
                 RecordType::Topic(topic) => {
                     if current_topic_id.is_none() {
                         current_topic_id = Some(topic.uuid);
@@ -41,33 +62,145 @@ pub fn parse_metadata() -> Box<[RecordBatch]> {
                     if let Some(uuid) = current_topic_id
                         && partition.uuid == uuid
                     {
-                        let entry = partitions.entry(uuid).or_insert_with(|| Vec::new());
+                        let entry = partitions.entry(uuid).or_insert_with(Vec::new);
                         entry.push(partition);
                     }
                 }
+                RecordType::Config(config) => configs.push(config),
+                RecordType::Broker(broker) => brokers.push(broker),
+                RecordType::PartitionChange(change) => partition_changes.push(change),
+                RecordType::ProducerIds(producer_ids) => {
+                    next_producer_id = Some(producer_ids.next_producer_id);
+                }
             }
         }
 
         batches.push(RecordBatch {
-            header: batch_header,
+            base_offset: batch.base_offset,
             topics,
             partitions,
+            configs,
+            brokers,
+            partition_changes,
+            next_producer_id,
+            metadata_version,
         });
+    }
 
-        content.advance(batch_len as usize);
+    Ok(batches.into_boxed_slice())
+}
+
+/// Where the records this broker writes to the metadata log go: straight into its copy of
+/// the log when that is generated externally, or through the embedded quorum, which only
+/// takes them on the active controller. Brokers send their ISR changes, producer id blocks
+/// and config changes to the active controller instead, and apply them once replayed.
+#[derive(Debug, Clone)]
+pub enum MetadataWriter {
+    Log(Arc<LogManager>),
+    Quorum(Arc<RaftQuorum>),
+}
+
+impl MetadataWriter {
+    /// Appends the records as one batch, returning the offset it starts at.
+    pub fn append(&self, records: Vec<record::Record>) -> Result<i64> {
+        self.append_with(|_| records)
     }
 
-    batches.into_boxed_slice()
+    /// Appends the batch `records` builds from the offset it will start at, which some
+    /// records carry, like the broker epoch of a registration.
+    pub fn append_with(&self, records: impl FnOnce(i64) -> Vec<record::Record>) -> Result<i64> {
+        match self {
+            Self::Log(logs) => logs.with_partition(&Bytes::from_static(METADATA_TOPIC), 0, |log| {
+                // Continue from whatever other writers appended, keeping them out until
+                // this batch is in
+                let _lock = log.lock()?;
+                log.catch_up()?;
+                let base_offset = log.log_end_offset();
+                let batch = record::RecordBatch::new(current_time_ms(), records(base_offset));
+                let records = batch.encode().context("encoding metadata records")?;
+                log.append(records).context("appending to metadata log")?;
+                Ok(base_offset)
+            }),
+            Self::Quorum(quorum) => quorum.append_with(records),
+        }
+    }
 }
 
+/// The metadata log as decoded so far. Requests take a snapshot of it, which later replays
+/// leave untouched.
 #[derive(Debug)]
+pub struct MetadataCache {
+    batches: Mutex<Arc<Box<[RecordBatch]>>>,
+}
+
+impl MetadataCache {
+    pub fn new(batches: Box<[RecordBatch]>) -> Self {
+        Self {
+            batches: Mutex::new(Arc::new(batches)),
+        }
+    }
+
+    pub fn snapshot(&self) -> Arc<Box<[RecordBatch]>> {
+        Arc::clone(&self.batches.lock().expect("metadata cache lock poisoned"))
+    }
+
+    /// Adds batches replayed from the log after the current ones.
+    pub fn append(&self, replayed: &[RecordBatch]) {
+        if replayed.is_empty() {
+            return;
+        }
+
+        let mut batches = self.batches.lock().expect("metadata cache lock poisoned");
+        let updated = batches.iter().chain(replayed).cloned().collect();
+        *batches = Arc::new(updated);
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct RecordBatch {
-    header: RecordBatchHeader,
+    base_offset: i64,
     topics: HashMap<Bytes, Uuid>,
     partitions: HashMap<Uuid, Vec<PartitionRecord>>,
+    // In log order, a missing value removing the config
+    configs: Vec<ConfigRecord>,
+    // Broker registrations and fencing, in log order
+    brokers: Vec<BrokerRecord>,
+    // Leader and ISR changes, in log order
+    partition_changes: Vec<PartitionChangeRecord>,
+    next_producer_id: Option<i64>,
+    metadata_version: Option<i16>,
 }
 
 impl RecordBatch {
+    pub fn next_producer_id(&self) -> Option<i64> {
+        self.next_producer_id
+    }
+
+    /// The `metadata.version` feature level the batch sets, if it does.
+    pub fn metadata_version(&self) -> Option<i16> {
+        self.metadata_version
+    }
+
+    pub fn config_records(&self) -> &[ConfigRecord] {
+        &self.configs
+    }
+
+    pub fn broker_records(&self) -> &[BrokerRecord] {
+        &self.brokers
+    }
+
+    pub fn partition_change_records(&self) -> &[PartitionChangeRecord] {
+        &self.partition_changes
+    }
+
+    /// Every topic of the batch with its id and partitions.
+    pub fn topic_partitions(&self) -> impl Iterator<Item = (&Bytes, &Uuid, &[PartitionRecord])> {
+        self.topics.iter().filter_map(|(name, uuid)| {
+            let partitions = self.partitions.get(uuid)?;
+            Some((name, uuid, &partitions[..]))
+        })
+    }
+
     pub fn get_topic_partitions_from_name(&self, topic_name: &Bytes) -> Option<&[PartitionRecord]> {
         match self.topics.get(topic_name) {
             Some(uuid) => match self.partitions.get(uuid) {
@@ -85,6 +218,10 @@ impl RecordBatch {
         }
     }
 
+    pub fn topic_names(&self) -> impl Iterator<Item = &Bytes> {
+        self.topics.keys()
+    }
+
     pub fn get_topic_uuid(&self, topic_name: &Bytes) -> Option<Uuid> {
         self.topics.get(topic_name).copied()
     }
@@ -101,120 +238,11 @@ impl RecordBatch {
         false
     }
 
-    pub fn read_log_file(&self, uuid: &Uuid, partition_id: i32) -> Option<Bytes> {
-        let topic_names = self
-            .topics
+    pub fn get_topic_name(&self, uuid: &Uuid) -> Option<Bytes> {
+        self.topics
             .iter()
-            .filter_map(|(name, id)| if id == uuid { Some(name.clone()) } else { None })
-            .collect::<Vec<Bytes>>();
-
-        if let Some(name) = topic_names.first() {
-            let name = String::from_utf8(name.to_vec()).expect("valid utf-8 guaranteed");
-            if let Some(partitions) = self.partitions.get(uuid) {
-                let partition: Vec<&PartitionRecord> = partitions
-                    .iter()
-                    .filter(|p| p.partition_id == partition_id)
-                    .collect();
-
-                match partition.first() {
-                    Some(_) => {
-                        let path = format!(
-                            "/tmp/kraft-combined-logs/{}-{}/00000000000000000000.log",
-                            name, partition_id
-                        );
-
-                        let content = std::fs::read(path).expect("should exist");
-                        if content.is_empty() {
-                            return None;
-                        } else {
-                            return Some(content.into());
-                        }
-                    }
-                    None => {}
-                }
-            }
-        }
-
-        None
-    }
-}
-
-#[derive(Debug)]
-pub struct RecordBatchHeader {
-    leader_epoch: i32,
-    magic: i8,
-    crc: i32,
-    attributes: i16,
-    last_offset_delta: i32,
-    base_timestamp: i64,
-    max_timestamp: i64,
-    producer_id: i64,
-    producer_epoch: i16,
-    base_sequence: i32,
-}
-
-impl RecordBatchHeader {
-    pub fn new(batch: &mut Bytes) -> Self {
-        Self {
-            leader_epoch: batch.get_i32(),
-            magic: batch.get_i8(),
-            crc: batch.get_i32(),
-            attributes: batch.get_i16(),
-            last_offset_delta: batch.get_i32(),
-            base_timestamp: batch.get_i64(),
-            max_timestamp: batch.get_i64(),
-            producer_id: batch.get_i64(),
-            producer_epoch: batch.get_i16(),
-            base_sequence: batch.get_i32(),
-        }
-    }
-}
-
-#[derive(Debug)]
-pub struct Record {
-    attributes: i8,
-    timestamp_delta: i32,
-    offset_delta: i32,
-    key: Bytes,
-    record_type: RecordType,
-}
-
-impl Record {
-    pub fn new(buf: &mut Bytes) -> Self {
-        let record_length = varint_decode(buf);
-        let mut record = Bytes::copy_from_slice(&buf[..record_length as usize]);
-        buf.advance(record_length as usize);
-
-        let attributes = record.get_i8();
-        let timestamp_delta = varint_decode(&mut record);
-        let offset_delta = varint_decode(&mut record);
-        let key_len = varint_decode(&mut record);
-        let key = if key_len < 0 {
-            Bytes::new()
-        } else {
-            let key = Bytes::copy_from_slice(&record[..key_len as usize]);
-            record.advance(key_len as usize);
-            key
-        };
-
-        let value_len = varint_decode(&mut record);
-        let value = if value_len < 0 {
-            Bytes::new()
-        } else {
-            let value = Bytes::copy_from_slice(&record[..value_len as usize]);
-            record.advance(value_len as usize);
-            value
-        };
-        let record_type = RecordType::new(value);
-        assert_eq!(unsigned_varint_decode(&mut record), 0);
-
-        Self {
-            attributes,
-            timestamp_delta,
-            offset_delta,
-            key,
-            record_type,
-        }
+            .find(|(_, id)| *id == uuid)
+            .map(|(name, _)| name.clone())
     }
 }
 
@@ -223,31 +251,51 @@ pub enum RecordType {
     Feature(FeatureRecord),
     Topic(TopicRecord),
     Partition(PartitionRecord),
+    Config(ConfigRecord),
+    ProducerIds(ProducerIdsRecord),
+    Broker(BrokerRecord),
+    PartitionChange(PartitionChangeRecord),
 }
 
 impl RecordType {
-    pub fn new(mut buf: Bytes) -> Self {
+    /// Parses a framed metadata record. `None` for the types brokers ignore, such as
+    /// `RegisterControllerRecord` or `BrokerRegistrationChangeRecord`.
+    pub fn new(mut buf: Bytes) -> Result<Option<Self>> {
+        if buf.remaining() < 2 {
+            bail!("metadata record is missing its frame");
+        }
         let _ = buf.get_i8();
         let record_type = buf.get_i8();
 
-        match record_type {
+        let record = match record_type {
+            0 => Self::Broker(BrokerRecord::Register(RegisterBrokerRecord::new(buf))),
+            1 => Self::Broker(BrokerRecord::Unregister(BrokerEpochRecord::new(buf))),
+            7 => Self::Broker(BrokerRecord::Fence(BrokerEpochRecord::new(buf))),
+            8 => Self::Broker(BrokerRecord::Unfence(BrokerEpochRecord::new(buf))),
             2 => Self::Topic(TopicRecord::new(buf)),
             3 => Self::Partition(PartitionRecord::new(buf)),
+            4 => Self::Config(ConfigRecord::new(buf)),
+            5 => Self::PartitionChange(PartitionChangeRecord::new(buf)),
             12 => Self::Feature(FeatureRecord::new(buf)),
-            _ => unimplemented!(),
-        }
+            15 => Self::ProducerIds(ProducerIdsRecord::new(buf)),
+            _ => return Ok(None),
+        };
+        Ok(Some(record))
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FeatureRecord {
-    version: i8,
-    name: Bytes,
-    feature_level: i16,
-    tags: i8,
+    pub version: i8,
+    pub name: Bytes,
+    pub feature_level: i16,
+    pub tags: i8,
 }
 
 impl FeatureRecord {
+    pub const RECORD_TYPE: i8 = 12;
+    pub const METADATA_VERSION: &[u8] = b"metadata.version";
+
     pub fn new(mut buf: Bytes) -> Self {
         let version = buf.get_i8();
         let name_len = unsigned_varint_decode(&mut buf);
@@ -268,9 +316,21 @@ impl FeatureRecord {
             tags,
         }
     }
+
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        buf.put_i8(METADATA_FRAME_VERSION);
+        buf.put_i8(Self::RECORD_TYPE);
+        buf.put_i8(self.version);
+        unsigned_varint_encode(&mut buf, self.name.len());
+        buf.put_slice(&self.name);
+        buf.put_i16(self.feature_level);
+        buf.put_i8(self.tags);
+        buf.freeze()
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TopicRecord {
     version: i8,
     topic_name: Bytes,
@@ -301,7 +361,324 @@ impl TopicRecord {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub struct ProducerIdsRecord {
+    pub version: i8,
+    pub broker_id: i32,
+    pub broker_epoch: i64,
+    pub next_producer_id: i64,
+    pub tags: i8,
+}
+
+impl ProducerIdsRecord {
+    pub const RECORD_TYPE: i8 = 15;
+
+    pub fn new(mut buf: Bytes) -> Self {
+        Self {
+            version: buf.get_i8(),
+            broker_id: buf.get_i32(),
+            broker_epoch: buf.get_i64(),
+            next_producer_id: buf.get_i64(),
+            tags: buf.get_i8(),
+        }
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        buf.put_i8(METADATA_FRAME_VERSION);
+        buf.put_i8(Self::RECORD_TYPE);
+        buf.put_i8(self.version);
+        buf.put_i32(self.broker_id);
+        buf.put_i64(self.broker_epoch);
+        buf.put_i64(self.next_producer_id);
+        buf.put_i8(self.tags);
+        buf.freeze()
+    }
+}
+
+/// A change to a partition's leader or replicas. Everything but the partition is a tagged
+/// field, left out when it did not change.
+#[derive(Debug, Clone)]
+pub struct PartitionChangeRecord {
+    pub version: i8,
+    pub partition_id: i32,
+    pub uuid: Uuid,
+    pub in_sync_replica_ids: Option<Box<[i32]>>,
+    // -2 when the leader did not change
+    pub leader: i32,
+    pub replication_ids: Option<Box<[i32]>>,
+}
+
+impl PartitionChangeRecord {
+    pub const RECORD_TYPE: i8 = 5;
+    pub const NO_LEADER_CHANGE: i32 = -2;
+    const ISR_TAG: u32 = 0;
+    const LEADER_TAG: u32 = 1;
+    const REPLICAS_TAG: u32 = 2;
+
+    pub fn new(mut buf: Bytes) -> Self {
+        let version = buf.get_i8();
+        let partition_id = buf.get_i32();
+        let uuid = Uuid::from_u128(buf.get_u128());
+
+        let mut in_sync_replica_ids = None;
+        let mut leader = Self::NO_LEADER_CHANGE;
+        let mut replication_ids = None;
+        let tags_len = uvarint_decode(&mut buf);
+        for _ in 0..tags_len {
+            let tag = uvarint_decode(&mut buf);
+            let size = uvarint_decode(&mut buf) as usize;
+            let mut field = buf.split_to(size);
+            let mut read_ids = || {
+                let len = unsigned_varint_decode(&mut field);
+                (0..len)
+                    .map(|_| field.get_i32())
+                    .collect::<Vec<i32>>()
+                    .into_boxed_slice()
+            };
+            match tag {
+                Self::ISR_TAG => in_sync_replica_ids = Some(read_ids()),
+                Self::LEADER_TAG => leader = field.get_i32(),
+                Self::REPLICAS_TAG => replication_ids = Some(read_ids()),
+                // Removing and adding replicas, leader recovery state, directories and ELR
+                _ => {}
+            }
+        }
+
+        Self {
+            version,
+            partition_id,
+            uuid,
+            in_sync_replica_ids,
+            leader,
+            replication_ids,
+        }
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let encode_ids = |ids: &[i32]| {
+            let mut field = BytesMut::new();
+            unsigned_varint_encode(&mut field, ids.len());
+            for id in ids {
+                field.put_i32(*id);
+            }
+            field
+        };
+
+        let mut fields = Vec::new();
+        if let Some(isr) = &self.in_sync_replica_ids {
+            fields.push((Self::ISR_TAG, encode_ids(isr)));
+        }
+        if self.leader != Self::NO_LEADER_CHANGE {
+            let mut field = BytesMut::new();
+            field.put_i32(self.leader);
+            fields.push((Self::LEADER_TAG, field));
+        }
+        if let Some(replicas) = &self.replication_ids {
+            fields.push((Self::REPLICAS_TAG, encode_ids(replicas)));
+        }
+
+        let mut buf = BytesMut::new();
+        buf.put_i8(METADATA_FRAME_VERSION);
+        buf.put_i8(Self::RECORD_TYPE);
+        buf.put_i8(self.version);
+        buf.put_i32(self.partition_id);
+        buf.put_u128(self.uuid.as_u128());
+        uvarint_encode(&mut buf, fields.len() as u32);
+        for (tag, field) in fields {
+            uvarint_encode(&mut buf, tag);
+            uvarint_encode(&mut buf, field.len() as u32);
+            buf.put_slice(&field);
+        }
+        buf.freeze()
+    }
+}
+
+/// Records that change a broker's registration, replayed in order into the broker registry.
+#[derive(Debug, Clone)]
+pub enum BrokerRecord {
+    Register(RegisterBrokerRecord),
+    Unregister(BrokerEpochRecord),
+    Fence(BrokerEpochRecord),
+    Unfence(BrokerEpochRecord),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokerEndpoint {
+    pub name: Bytes,
+    pub host: Bytes,
+    pub port: u16,
+    pub security_protocol: i16,
+}
+
+#[derive(Debug, Clone)]
+pub struct RegisterBrokerRecord {
+    pub version: i8,
+    pub broker_id: i32,
+    pub is_migrating_zk_broker: bool,
+    pub incarnation_id: Uuid,
+    pub broker_epoch: i64,
+    pub endpoints: Box<[BrokerEndpoint]>,
+    // Feature name with its minimum and maximum supported levels
+    pub features: Box<[(Bytes, i16, i16)]>,
+    pub rack: Option<Bytes>,
+    pub fenced: bool,
+    pub in_controlled_shutdown: bool,
+    pub log_dirs: Box<[Uuid]>,
+    pub tags: i8,
+}
+
+impl RegisterBrokerRecord {
+    pub const RECORD_TYPE: i8 = 0;
+    pub const VERSION: i8 = 3;
+
+    pub fn new(mut buf: Bytes) -> Self {
+        let version = buf.get_i8();
+        let broker_id = buf.get_i32();
+        let is_migrating_zk_broker = version >= 2 && buf.get_i8() != 0;
+        let incarnation_id = Uuid::from_u128(buf.get_u128());
+        let broker_epoch = buf.get_i64();
+
+        let endpoints_len = unsigned_varint_decode(&mut buf);
+        let endpoints = (0..endpoints_len)
+            .map(|_| {
+                let name_len = unsigned_varint_decode(&mut buf);
+                let name = buf.split_to(name_len as usize);
+                let host_len = unsigned_varint_decode(&mut buf);
+                let host = buf.split_to(host_len as usize);
+                let port = buf.get_u16();
+                let security_protocol = buf.get_i16();
+                let _tags = buf.get_i8();
+
+                BrokerEndpoint {
+                    name,
+                    host,
+                    port,
+                    security_protocol,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let features_len = unsigned_varint_decode(&mut buf);
+        let features = (0..features_len)
+            .map(|_| {
+                let name_len = unsigned_varint_decode(&mut buf);
+                let name = buf.split_to(name_len as usize);
+                let min_supported = buf.get_i16();
+                let max_supported = buf.get_i16();
+                let _tags = buf.get_i8();
+
+                (name, min_supported, max_supported)
+            })
+            .collect::<Vec<_>>();
+
+        // Compact nullable string, a zero length prefix marks no rack
+        let rack = if buf[0] == 0x00 {
+            buf.advance(1);
+            None
+        } else {
+            let rack_len = unsigned_varint_decode(&mut buf);
+            Some(buf.split_to(rack_len as usize))
+        };
+        let fenced = buf.get_i8() != 0;
+        let in_controlled_shutdown = version >= 1 && buf.get_i8() != 0;
+        let log_dirs = if version >= 3 {
+            let dirs_len = unsigned_varint_decode(&mut buf);
+            (0..dirs_len)
+                .map(|_| Uuid::from_u128(buf.get_u128()))
+                .collect::<Vec<_>>()
+        } else {
+            Vec::new()
+        };
+        let tags = buf.get_i8();
+
+        Self {
+            version,
+            broker_id,
+            is_migrating_zk_broker,
+            incarnation_id,
+            broker_epoch,
+            endpoints: endpoints.into_boxed_slice(),
+            features: features.into_boxed_slice(),
+            rack,
+            fenced,
+            in_controlled_shutdown,
+            log_dirs: log_dirs.into_boxed_slice(),
+            tags,
+        }
+    }
+
+    /// Encodes the record at `VERSION`, whatever version it was read at.
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        buf.put_i8(METADATA_FRAME_VERSION);
+        buf.put_i8(Self::RECORD_TYPE);
+        buf.put_i8(Self::VERSION);
+        buf.put_i32(self.broker_id);
+        buf.put_i8(self.is_migrating_zk_broker as i8);
+        buf.put_u128(self.incarnation_id.as_u128());
+        buf.put_i64(self.broker_epoch);
+
+        unsigned_varint_encode(&mut buf, self.endpoints.len());
+        for endpoint in self.endpoints.iter() {
+            unsigned_varint_encode(&mut buf, endpoint.name.len());
+            buf.put_slice(&endpoint.name);
+            unsigned_varint_encode(&mut buf, endpoint.host.len());
+            buf.put_slice(&endpoint.host);
+            buf.put_u16(endpoint.port);
+            buf.put_i16(endpoint.security_protocol);
+            buf.put_i8(0x00);
+        }
+
+        unsigned_varint_encode(&mut buf, self.features.len());
+        for (name, min_supported, max_supported) in self.features.iter() {
+            unsigned_varint_encode(&mut buf, name.len());
+            buf.put_slice(name);
+            buf.put_i16(*min_supported);
+            buf.put_i16(*max_supported);
+            buf.put_i8(0x00);
+        }
+
+        match &self.rack {
+            Some(rack) => {
+                unsigned_varint_encode(&mut buf, rack.len());
+                buf.put_slice(rack);
+            }
+            None => buf.put_u8(0x00),
+        }
+        buf.put_i8(self.fenced as i8);
+        buf.put_i8(self.in_controlled_shutdown as i8);
+        unsigned_varint_encode(&mut buf, self.log_dirs.len());
+        for dir in self.log_dirs.iter() {
+            buf.put_u128(dir.as_u128());
+        }
+        buf.put_i8(self.tags);
+        buf.freeze()
+    }
+}
+
+/// The shared layout of `UnregisterBrokerRecord`, `FenceBrokerRecord` and
+/// `UnfenceBrokerRecord`: a broker and the registration epoch the change applies to.
+#[derive(Debug, Clone)]
+pub struct BrokerEpochRecord {
+    pub version: i8,
+    pub broker_id: i32,
+    pub broker_epoch: i64,
+    pub tags: i8,
+}
+
+impl BrokerEpochRecord {
+    pub fn new(mut buf: Bytes) -> Self {
+        Self {
+            version: buf.get_i8(),
+            broker_id: buf.get_i32(),
+            broker_epoch: buf.get_i64(),
+            tags: buf.get_i8(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct PartitionRecord {
     pub version: i8,
     pub partition_id: i32,
@@ -355,12 +732,4 @@ impl PartitionRecord {
             tags,
         }
     }
-
-    pub fn read_record(&self) -> Bytes {
-        let paths = std::fs::read_dir("/tmp/kraft-combined-logs").unwrap();
-        for path in paths {
-            eprintln!("{:?}", path.unwrap().path().display());
-        }
-        Bytes::new()
-    }
 }
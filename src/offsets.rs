@@ -0,0 +1,403 @@
+use crate::{
+    current_time_ms,
+    group::GroupCoordinator,
+    log::LogManager,
+    record::{ControlRecordType, Record, RecordBatch},
+    request::{ErrorCode, read_string, write_string},
+};
+use anyhow::{Context, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+pub const OFFSETS_TOPIC: &[u8] = b"__consumer_offsets";
+pub const OFFSETS_TOPIC_PARTITIONS: i32 = 50;
+// Kafka's offsets.retention.minutes default of seven days
+pub const OFFSETS_RETENTION_MS: i64 = 7 * 24 * 60 * 60 * 1000;
+
+/// Partition of `__consumer_offsets` owning a group.
+pub fn partition_for(group_id: &[u8]) -> i32 {
+    key_partition(group_id, OFFSETS_TOPIC_PARTITIONS)
+}
+
+/// Partition of an internal topic owning a key, matching Kafka's
+/// `abs(key.hashCode()) % partitions` so the layout lines up with Java brokers.
+pub fn key_partition(key: &[u8], partitions: i32) -> i32 {
+    let hash = String::from_utf8_lossy(key)
+        .encode_utf16()
+        .fold(0i32, |hash, unit| {
+            hash.wrapping_mul(31).wrapping_add(unit as i32)
+        });
+
+    (hash & 0x7fffffff) % partitions
+}
+
+/// Key of a committed offset record in `__consumer_offsets`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OffsetCommitKey {
+    pub group_id: Bytes,
+    pub topic: Bytes,
+    pub partition: i32,
+}
+
+impl OffsetCommitKey {
+    const VERSION: i16 = 1;
+
+    /// Decodes an offset commit key. Versions 0 and 1 key offsets, anything else is group
+    /// metadata, which is returned as `None`.
+    pub fn decode(mut buf: Bytes) -> Option<Self> {
+        if buf.remaining() < 2 {
+            return None;
+        }
+
+        match buf.get_i16() {
+            0 | 1 => Some(Self {
+                group_id: read_string(&mut buf)?,
+                topic: read_string(&mut buf)?,
+                partition: (buf.remaining() >= 4).then(|| buf.get_i32())?,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        buf.put_i16(Self::VERSION);
+        write_string(&mut buf, &self.group_id);
+        write_string(&mut buf, &self.topic);
+        buf.put_i32(self.partition);
+        buf.freeze()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OffsetCommitValue {
+    pub offset: i64,
+    pub leader_epoch: i32,
+    pub metadata: Bytes,
+    pub commit_timestamp: i64,
+}
+
+impl OffsetCommitValue {
+    const VERSION: i16 = 3;
+
+    pub fn decode(mut buf: Bytes) -> Option<Self> {
+        if buf.remaining() < 2 {
+            return None;
+        }
+
+        let version = buf.get_i16();
+        let offset = (buf.remaining() >= 8).then(|| buf.get_i64())?;
+        let leader_epoch = if version >= 3 {
+            (buf.remaining() >= 4).then(|| buf.get_i32())?
+        } else {
+            -1
+        };
+        let metadata = read_string(&mut buf)?;
+        let commit_timestamp = (buf.remaining() >= 8).then(|| buf.get_i64())?;
+
+        Some(Self {
+            offset,
+            leader_epoch,
+            metadata,
+            commit_timestamp,
+        })
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        buf.put_i16(Self::VERSION);
+        buf.put_i64(self.offset);
+        buf.put_i32(self.leader_epoch);
+        write_string(&mut buf, &self.metadata);
+        buf.put_i64(self.commit_timestamp);
+        buf.freeze()
+    }
+}
+
+/// Committed offsets of every group, in memory and in the `__consumer_offsets` log each
+/// group hashes to. Offsets committed inside a transaction are held back until the
+/// transaction's marker lands.
+#[derive(Debug)]
+pub struct OffsetManager {
+    logs: Arc<LogManager>,
+    offsets: Mutex<OffsetCache>,
+}
+
+#[derive(Debug, Default)]
+struct OffsetCache {
+    committed: HashMap<Bytes, HashMap<(Bytes, i32), OffsetCommitValue>>,
+    // Transactional commits per log partition and producer id
+    pending: HashMap<(i32, i64), Vec<(OffsetCommitKey, OffsetCommitValue)>>,
+}
+
+impl OffsetCache {
+    fn apply(&mut self, key: OffsetCommitKey, value: Option<OffsetCommitValue>) {
+        let offsets = self.committed.entry(key.group_id.clone()).or_default();
+        match value {
+            Some(value) => {
+                offsets.insert((key.topic, key.partition), value);
+            }
+            None => {
+                offsets.remove(&(key.topic, key.partition));
+                if offsets.is_empty() {
+                    self.committed.remove(&key.group_id);
+                }
+            }
+        }
+    }
+
+    fn complete_transaction(&mut self, partition: i32, producer_id: i64, commit: bool) {
+        let Some(offsets) = self.pending.remove(&(partition, producer_id)) else {
+            return;
+        };
+
+        if commit {
+            for (key, value) in offsets {
+                self.apply(key, Some(value));
+            }
+        }
+    }
+}
+
+impl OffsetManager {
+    /// Rebuilds the offset cache by replaying every `__consumer_offsets` partition left by
+    /// earlier runs.
+    pub fn new(logs: Arc<LogManager>) -> Result<Self> {
+        let topic = Bytes::from_static(OFFSETS_TOPIC);
+        let mut cache = OffsetCache::default();
+        for partition in 0..OFFSETS_TOPIC_PARTITIONS {
+            if !logs.has_partition(&topic, partition) {
+                continue;
+            }
+
+            let content = logs
+                .with_partition(&topic, partition, |log| log.read_all())
+                .context("reading offsets log")?;
+            for batch in RecordBatch::decode_all(content).context("decoding offsets log")? {
+                if let Some(marker) = batch.control_type() {
+                    let commit = marker == ControlRecordType::Commit;
+                    cache.complete_transaction(partition, batch.producer_id, commit);
+                    continue;
+                }
+
+                for record in batch.records.iter() {
+                    let Some(key) = record.key.clone().and_then(OffsetCommitKey::decode) else {
+                        continue;
+                    };
+                    let value = record.value.clone().and_then(OffsetCommitValue::decode);
+
+                    match value {
+                        Some(value) if batch.is_transactional() => cache
+                            .pending
+                            .entry((partition, batch.producer_id))
+                            .or_default()
+                            .push((key, value)),
+                        value => cache.apply(key, value),
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            logs,
+            offsets: Mutex::new(cache),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, OffsetCache> {
+        self.offsets.lock().expect("offset cache lock poisoned")
+    }
+
+    /// Persists and caches offsets committed outside a transaction.
+    pub fn commit(
+        &self,
+        group_id: &Bytes,
+        offsets: Vec<(OffsetCommitKey, OffsetCommitValue)>,
+    ) -> Result<(), ErrorCode> {
+        let records = offsets
+            .iter()
+            .map(|(key, value)| Record::new(Some(key.encode()), Some(value.encode())))
+            .collect();
+        self.append(group_id, RecordBatch::new(current_time_ms(), records))?;
+
+        let mut cache = self.lock();
+        for (key, value) in offsets {
+            cache.apply(key, Some(value));
+        }
+
+        Ok(())
+    }
+
+    /// Persists offsets committed inside a transaction. They only become visible once
+    /// [`complete_transaction`](Self::complete_transaction) is called with a commit.
+    pub fn commit_transactional(
+        &self,
+        producer_id: i64,
+        producer_epoch: i16,
+        group_id: &Bytes,
+        offsets: Vec<(OffsetCommitKey, OffsetCommitValue)>,
+    ) -> Result<(), ErrorCode> {
+        let records = offsets
+            .iter()
+            .map(|(key, value)| Record::new(Some(key.encode()), Some(value.encode())))
+            .collect();
+        let mut batch = RecordBatch::new(current_time_ms(), records);
+        batch.producer_id = producer_id;
+        batch.producer_epoch = producer_epoch;
+        batch.set_transactional();
+        self.append(group_id, batch)?;
+
+        self.lock()
+            .pending
+            .entry((partition_for(group_id), producer_id))
+            .or_default()
+            .extend(offsets);
+
+        Ok(())
+    }
+
+    /// Applies or drops a producer's pending offsets once its marker is written to the
+    /// offsets partition.
+    pub fn complete_transaction(&self, partition: i32, producer_id: i64, commit: bool) {
+        self.lock()
+            .complete_transaction(partition, producer_id, commit);
+    }
+
+    pub fn committed(
+        &self,
+        group_id: &Bytes,
+        topic: &Bytes,
+        partition: i32,
+    ) -> Option<OffsetCommitValue> {
+        self.lock()
+            .committed
+            .get(group_id)
+            .and_then(|offsets| offsets.get(&(topic.clone(), partition)))
+            .cloned()
+    }
+
+    /// Every partition the group committed an offset for.
+    pub fn group_offsets(&self, group_id: &Bytes) -> Vec<(Bytes, i32, OffsetCommitValue)> {
+        let cache = self.lock();
+        let mut offsets: Vec<(Bytes, i32, OffsetCommitValue)> = cache
+            .committed
+            .get(group_id)
+            .map(|offsets| {
+                offsets
+                    .iter()
+                    .map(|((topic, partition), value)| (topic.clone(), *partition, value.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        offsets.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+        offsets
+    }
+
+    /// Whether a transaction has uncommitted offsets for the group's partition.
+    pub fn has_pending(&self, group_id: &Bytes, topic: &Bytes, partition: i32) -> bool {
+        self.lock().pending.values().flatten().any(|(key, _)| {
+            key.group_id == group_id && key.topic == topic && key.partition == partition
+        })
+    }
+
+    /// Groups with at least one committed offset.
+    pub fn groups(&self) -> Vec<Bytes> {
+        self.lock().committed.keys().cloned().collect()
+    }
+
+    /// Removes offsets, writing tombstones so they stay gone after a restart.
+    pub fn delete(&self, group_id: &Bytes, partitions: &[(Bytes, i32)]) -> Result<(), ErrorCode> {
+        if partitions.is_empty() {
+            return Ok(());
+        }
+
+        let keys: Vec<OffsetCommitKey> = partitions
+            .iter()
+            .map(|(topic, partition)| OffsetCommitKey {
+                group_id: group_id.clone(),
+                topic: topic.clone(),
+                partition: *partition,
+            })
+            .collect();
+        let records = keys
+            .iter()
+            .map(|key| Record::new(Some(key.encode()), None))
+            .collect();
+        self.append(group_id, RecordBatch::new(current_time_ms(), records))?;
+
+        let mut cache = self.lock();
+        for key in keys {
+            cache.apply(key, None);
+        }
+
+        Ok(())
+    }
+
+    /// Drops offsets of groups that have been empty for longer than the retention, counting
+    /// from the later of the commit and the group emptying. Groups left with no offsets
+    /// are forgotten by the group coordinator as well.
+    pub fn expire(&self, groups: &GroupCoordinator, retention_ms: i64) {
+        let now = current_time_ms();
+        let expired: Vec<(Bytes, Vec<(Bytes, i32)>)> = self
+            .groups()
+            .into_iter()
+            .filter_map(|group_id| {
+                let empty_since = groups.empty_since(&group_id)?;
+                let partitions: Vec<(Bytes, i32)> = self
+                    .group_offsets(&group_id)
+                    .into_iter()
+                    .filter(|(_, _, value)| {
+                        now - value.commit_timestamp.max(empty_since) >= retention_ms
+                    })
+                    .map(|(topic, partition, _)| (topic, partition))
+                    .collect();
+                Some((group_id, partitions))
+            })
+            .collect();
+
+        for (group_id, partitions) in expired {
+            if let Err(error_code) = self.delete(&group_id, &partitions) {
+                eprintln!("expiring offsets of group {group_id:?}: {error_code:?}");
+                continue;
+            }
+
+            if self.group_offsets(&group_id).is_empty() {
+                groups.remove_if_empty(&group_id);
+            }
+        }
+    }
+
+    /// Compacts every offsets partition down to the latest commit per key.
+    pub fn compact(&self) {
+        let topic = Bytes::from_static(OFFSETS_TOPIC);
+        for partition in 0..OFFSETS_TOPIC_PARTITIONS {
+            if !self.logs.has_partition(&topic, partition) {
+                continue;
+            }
+
+            if let Err(err) = self.logs.compact(&topic, partition) {
+                eprintln!("compacting offsets partition {partition}: {err:#}");
+            }
+        }
+    }
+
+    fn append(&self, group_id: &Bytes, batch: RecordBatch) -> Result<(), ErrorCode> {
+        let records = batch.encode().map_err(|_| ErrorCode::Unknown)?;
+        self.logs
+            .append(
+                &Bytes::from_static(OFFSETS_TOPIC),
+                partition_for(group_id),
+                records,
+            )
+            .map_err(|err| {
+                eprintln!("appending to offsets log: {err:#}");
+                ErrorCode::CoordinatorNotAvailable
+            })?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,488 @@
+use crate::{
+    metadata::{MetadataWriter, ProducerIdsRecord, RecordBatch as MetadataBatch},
+    raft::RaftQuorum,
+    record::{ControlRecordType, Record, RecordBatchHeader, increment_sequence},
+    request::{ApiType, ErrorCode, TryGet},
+};
+use anyhow::{Context, Result, bail};
+use bytes::{BufMut, BytesMut};
+use thiserror::Error;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+// Kafka keeps the last five batches per producer so in-flight retries can be matched
+const RETAINED_BATCHES: usize = 5;
+pub const PRODUCER_ID_BLOCK_SIZE: i64 = 1000;
+// As in Kafka, the next block is asked for once 90% of the current one is handed out
+const PRODUCER_ID_PREFETCH_THRESHOLD: i64 = PRODUCER_ID_BLOCK_SIZE / 10;
+const ALLOCATE_PRODUCER_IDS_VERSION: i16 = 0;
+
+#[derive(Debug, Error)]
+pub enum SequenceError {
+    #[error("batch duplicates one already written at offset {0}")]
+    Duplicate(i64),
+    #[error("expected sequence {expected}, received {received}")]
+    OutOfOrder { expected: i32, received: i32 },
+    #[error("producer epoch {received} is older than current epoch {current}")]
+    InvalidEpoch { current: i16, received: i16 },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BatchSequence {
+    first_sequence: i32,
+    last_sequence: i32,
+    first_offset: i64,
+}
+
+#[derive(Debug, Clone)]
+struct ProducerEntry {
+    epoch: i16,
+    batches: VecDeque<BatchSequence>,
+    // Offset of the first batch of the producer's open transaction on this partition
+    current_txn_first_offset: Option<i64>,
+}
+
+impl ProducerEntry {
+    fn last_sequence(&self) -> Option<i32> {
+        self.batches.back().map(|batch| batch.last_sequence)
+    }
+}
+
+/// A transaction closed on this partition by a COMMIT or ABORT marker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompletedTxn {
+    pub producer_id: i64,
+    pub first_offset: i64,
+    // Offset of the marker itself
+    pub last_offset: i64,
+    pub aborted: bool,
+}
+
+/// Per-partition idempotence state: the epoch and recent sequence ranges of every producer
+/// that has written to the partition.
+#[derive(Debug, Clone, Default)]
+pub struct ProducerState {
+    producers: HashMap<i64, ProducerEntry>,
+}
+
+impl ProducerState {
+    /// Checks an incoming batch against what the producer last wrote. Batches without a
+    /// producer id are never checked.
+    pub fn check(&self, header: &RecordBatchHeader) -> Result<(), SequenceError> {
+        if !header.has_producer_id() {
+            return Ok(());
+        }
+
+        let entry = self.producers.get(&header.producer_id);
+        if header.is_control() || header.base_sequence < 0 {
+            // Markers and coordinator writes carry no sequence, only an epoch that must not
+            // be stale
+            return match entry {
+                Some(entry) if header.producer_epoch < entry.epoch => {
+                    Err(SequenceError::InvalidEpoch {
+                        current: entry.epoch,
+                        received: header.producer_epoch,
+                    })
+                }
+                _ => Ok(()),
+            };
+        }
+
+        let Some(entry) = entry else {
+            return match header.base_sequence {
+                0 => Ok(()),
+                received => Err(SequenceError::OutOfOrder {
+                    expected: 0,
+                    received,
+                }),
+            };
+        };
+
+        if header.producer_epoch < entry.epoch {
+            return Err(SequenceError::InvalidEpoch {
+                current: entry.epoch,
+                received: header.producer_epoch,
+            });
+        }
+
+        // A bumped epoch restarts the sequence
+        if header.producer_epoch > entry.epoch {
+            return match header.base_sequence {
+                0 => Ok(()),
+                received => Err(SequenceError::OutOfOrder {
+                    expected: 0,
+                    received,
+                }),
+            };
+        }
+
+        if let Some(duplicate) = entry.batches.iter().find(|batch| {
+            batch.first_sequence == header.base_sequence
+                && batch.last_sequence == header.last_sequence()
+        }) {
+            return Err(SequenceError::Duplicate(duplicate.first_offset));
+        }
+
+        let expected = entry
+            .last_sequence()
+            .map(|sequence| increment_sequence(sequence, 1))
+            .unwrap_or(0);
+        if header.base_sequence != expected {
+            return Err(SequenceError::OutOfOrder {
+                expected,
+                received: header.base_sequence,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Records a batch that has been assigned its offsets and written to the log. `marker`
+    /// is the control record type of a control batch, which closes the producer's open
+    /// transaction and is returned as completed.
+    pub fn update(
+        &mut self,
+        header: &RecordBatchHeader,
+        marker: Option<ControlRecordType>,
+    ) -> Option<CompletedTxn> {
+        if !header.has_producer_id() {
+            return None;
+        }
+
+        let entry = self
+            .producers
+            .entry(header.producer_id)
+            .or_insert_with(|| ProducerEntry {
+                epoch: header.producer_epoch,
+                batches: VecDeque::new(),
+                current_txn_first_offset: None,
+            });
+
+        if header.producer_epoch != entry.epoch {
+            entry.epoch = header.producer_epoch;
+            entry.batches.clear();
+        }
+
+        if let Some(marker) = marker {
+            // A marker for a producer with nothing open on this partition closes no range
+            return entry
+                .current_txn_first_offset
+                .take()
+                .map(|first_offset| CompletedTxn {
+                    producer_id: header.producer_id,
+                    first_offset,
+                    last_offset: header.base_offset,
+                    aborted: marker == ControlRecordType::Abort,
+                });
+        }
+
+        if header.is_transactional() && entry.current_txn_first_offset.is_none() {
+            entry.current_txn_first_offset = Some(header.base_offset);
+        }
+
+        if header.is_control() || header.base_sequence < 0 {
+            return None;
+        }
+
+        entry.batches.push_back(BatchSequence {
+            first_sequence: header.base_sequence,
+            last_sequence: header.last_sequence(),
+            first_offset: header.base_offset,
+        });
+
+        if entry.batches.len() > RETAINED_BATCHES {
+            entry.batches.pop_front();
+        }
+
+        None
+    }
+
+    /// Offset of the earliest batch belonging to a still open transaction. Nothing at or
+    /// past it is stable yet.
+    pub fn first_unstable_offset(&self) -> Option<i64> {
+        self.producers
+            .values()
+            .filter_map(|entry| entry.current_txn_first_offset)
+            .min()
+    }
+}
+
+/// Hands out producer ids from blocks reserved in the metadata log, so ids stay unique
+/// across restarts. With the embedded quorum the blocks come from the active controller,
+/// the next one asked for before the current one runs out; until it arrives no id is
+/// handed out.
+#[derive(Debug)]
+pub struct ProducerIdManager {
+    broker_id: i32,
+    writer: MetadataWriter,
+    // Next id to hand out, the exclusive end of the current block and the furthest block
+    // end any broker reserved
+    block: Mutex<(i64, i64, i64)>,
+    // Start and end of the block the controller handed out after the current one
+    prefetched: Mutex<Option<(i64, i64)>>,
+    // Whether a block was asked for and has not arrived yet
+    allocating: AtomicBool,
+}
+
+impl ProducerIdManager {
+    pub fn new(broker_id: i32, next_producer_id: i64, writer: MetadataWriter) -> Self {
+        Self {
+            broker_id,
+            writer,
+            block: Mutex::new((next_producer_id, next_producer_id, next_producer_id)),
+            prefetched: Mutex::new(None),
+            allocating: AtomicBool::new(false),
+        }
+    }
+
+    /// Takes note of the blocks other brokers reserved, so the next one starts past them.
+    pub fn replay(&self, metadata: &[MetadataBatch]) {
+        let reserved = metadata
+            .iter()
+            .filter_map(|batch| batch.next_producer_id())
+            .max();
+        if let Some(reserved) = reserved {
+            let mut block = self.block.lock().expect("producer id lock poisoned");
+            block.2 = block.2.max(reserved);
+        }
+    }
+
+    /// Whether the id was handed out by some broker of the cluster.
+    pub fn is_issued(&self, producer_id: i64) -> bool {
+        let block = self.block.lock().expect("producer id lock poisoned");
+        producer_id >= 0 && producer_id < block.0.max(block.2)
+    }
+
+    pub fn generate(self: &Arc<Self>) -> Result<i64> {
+        let mut block = self.block.lock().expect("producer id lock poisoned");
+        match &self.writer {
+            MetadataWriter::Log(_) if block.0 >= block.1 => {
+                let next = block.0.max(block.2);
+                let end = next + PRODUCER_ID_BLOCK_SIZE;
+                self.reserve_block(end)
+                    .context("reserving producer id block")?;
+                *block = (next, end, block.2.max(end));
+            }
+            MetadataWriter::Log(_) => {}
+            MetadataWriter::Quorum(quorum) => {
+                if block.0 >= block.1 {
+                    let prefetched = self
+                        .prefetched
+                        .lock()
+                        .expect("producer id lock poisoned")
+                        .take();
+                    let Some((next, end)) = prefetched else {
+                        self.allocate_block(quorum);
+                        bail!("waiting for a producer id block from the active controller");
+                    };
+                    *block = (next, end, block.2.max(end));
+                }
+                if block.1 - block.0 <= PRODUCER_ID_PREFETCH_THRESHOLD {
+                    self.allocate_block(quorum);
+                }
+            }
+        }
+        let next = block.0;
+
+        block.0 += 1;
+        Ok(next)
+    }
+
+    fn reserve_block(&self, next_producer_id: i64) -> Result<()> {
+        let record = ProducerIdsRecord {
+            version: 0,
+            broker_id: self.broker_id,
+            broker_epoch: 0,
+            next_producer_id,
+            tags: 0,
+        };
+
+        self.writer
+            .append(vec![Record::new(None, Some(record.encode()))])?;
+
+        Ok(())
+    }
+
+    /// Asks the active controller for the next block, unless one was already asked for or
+    /// is waiting to be used.
+    fn allocate_block(self: &Arc<Self>, quorum: &Arc<RaftQuorum>) {
+        let prefetched = self.prefetched.lock().expect("producer id lock poisoned");
+        if prefetched.is_some() || self.allocating.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        drop(prefetched);
+
+        let producer_ids = Arc::clone(self);
+        let quorum = Arc::clone(quorum);
+        tokio::task::spawn(async move {
+            match allocate_producer_ids(&quorum, producer_ids.broker_id).await {
+                Ok(block) => {
+                    *producer_ids
+                        .prefetched
+                        .lock()
+                        .expect("producer id lock poisoned") = Some(block);
+                }
+                Err(err) => eprintln!("allocating producer id block: {err:#}"),
+            }
+            producer_ids.allocating.store(false, Ordering::Release);
+        });
+    }
+}
+
+/// Asks the active controller for a block of producer ids with AllocateProducerIds,
+/// returning its start and exclusive end.
+async fn allocate_producer_ids(quorum: &RaftQuorum, broker_id: i32) -> Result<(i64, i64)> {
+    let mut body = BytesMut::new();
+    body.put_i32(broker_id);
+    // Broker epoch, which the controller only records
+    body.put_i64(-1);
+    // Tags
+    body.put_i8(0x00);
+
+    let mut response = quorum
+        .call_leader(
+            ApiType::AllocateProducerIds,
+            ALLOCATE_PRODUCER_IDS_VERSION,
+            &body,
+        )
+        .await?;
+    let _throttle_time = response.try_get_i32()?;
+    let error_code = response.try_get_i16()?;
+    let producer_id_start = response.try_get_i64()?;
+    let producer_id_len = response.try_get_i32()?;
+    if error_code != ErrorCode::None as i16 {
+        bail!("controller returned error {error_code}");
+    }
+
+    Ok((
+        producer_id_start,
+        producer_id_start + producer_id_len as i64,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(
+        producer_epoch: i16,
+        base_sequence: i32,
+        records: i32,
+        base_offset: i64,
+    ) -> RecordBatchHeader {
+        RecordBatchHeader {
+            base_offset,
+            batch_length: 0,
+            partition_leader_epoch: 0,
+            magic: 2,
+            crc: 0,
+            attributes: 0,
+            last_offset_delta: records - 1,
+            base_timestamp: 0,
+            max_timestamp: 0,
+            producer_id: 7,
+            producer_epoch,
+            base_sequence,
+            record_count: records,
+        }
+    }
+
+    #[test]
+    fn retried_batches_are_duplicates_of_their_first_write() {
+        let mut state = ProducerState::default();
+        state.update(&batch(0, 0, 3, 10), None);
+        state.update(&batch(0, 3, 2, 13), None);
+
+        assert!(matches!(
+            state.check(&batch(0, 0, 3, 0)),
+            Err(SequenceError::Duplicate(10))
+        ));
+        assert!(matches!(
+            state.check(&batch(0, 3, 2, 0)),
+            Err(SequenceError::Duplicate(13))
+        ));
+        assert!(state.check(&batch(0, 5, 1, 0)).is_ok());
+    }
+
+    #[test]
+    fn gaps_in_the_sequence_are_out_of_order() {
+        let mut state = ProducerState::default();
+        // A producer new to the partition starts at zero
+        assert!(matches!(
+            state.check(&batch(0, 4, 1, 0)),
+            Err(SequenceError::OutOfOrder {
+                expected: 0,
+                received: 4
+            })
+        ));
+
+        state.update(&batch(0, 0, 3, 0), None);
+        assert!(matches!(
+            state.check(&batch(0, 4, 1, 0)),
+            Err(SequenceError::OutOfOrder {
+                expected: 3,
+                received: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn epochs_fence_older_producers_and_restart_sequences() {
+        let mut state = ProducerState::default();
+        state.update(&batch(1, 0, 5, 0), None);
+
+        assert!(matches!(
+            state.check(&batch(0, 5, 1, 0)),
+            Err(SequenceError::InvalidEpoch {
+                current: 1,
+                received: 0
+            })
+        ));
+
+        // A bumped epoch has to start over at zero
+        assert!(matches!(
+            state.check(&batch(2, 5, 1, 0)),
+            Err(SequenceError::OutOfOrder {
+                expected: 0,
+                received: 5
+            })
+        ));
+        assert!(state.check(&batch(2, 0, 1, 0)).is_ok());
+
+        state.update(&batch(2, 0, 1, 5), None);
+        assert!(matches!(
+            state.check(&batch(1, 5, 1, 0)),
+            Err(SequenceError::InvalidEpoch {
+                current: 2,
+                received: 1
+            })
+        ));
+        assert!(state.check(&batch(2, 1, 1, 0)).is_ok());
+    }
+
+    #[test]
+    fn sequences_wrap_around_after_the_maximum() {
+        let mut state = ProducerState::default();
+        state.update(&batch(0, 0, 1, 0), None);
+        // Move the last sequence up to just below the maximum
+        state.update(&batch(0, 1, i32::MAX - 2, 1), None);
+
+        // The next batch runs over the maximum and ends past zero
+        let wrapping = batch(0, i32::MAX - 1, 4, i32::MAX as i64);
+        assert_eq!(wrapping.last_sequence(), 1);
+        assert!(state.check(&wrapping).is_ok());
+        state.update(&wrapping, None);
+
+        assert!(state.check(&batch(0, 2, 1, 0)).is_ok());
+        assert!(matches!(
+            state.check(&wrapping),
+            Err(SequenceError::Duplicate(offset)) if offset == i32::MAX as i64
+        ));
+    }
+}
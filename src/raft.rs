@@ -0,0 +1,1675 @@
+use crate::{
+    config::{BrokerConfig, QuorumVoter, parse_properties},
+    current_time_ms,
+    leader_epoch::UNDEFINED_EPOCH,
+    log::{LogManager, encode_uuid},
+    metadata::{FeatureRecord, METADATA_TOPIC, RecordBatch as MetadataBatch, decode_metadata},
+    record::{Record, RecordBatch},
+    request::{
+        ApiType, ErrorCode, TryGet, read_compact_len, read_compact_nullable_string,
+        read_compact_string, read_uvarint, skip_tagged_fields, write_compact_nullable_string,
+        write_compact_string, write_tagged_fields,
+    },
+    unsigned_varint_encode,
+};
+use anyhow::{Context, Result, bail};
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::Notify,
+};
+use uuid::Uuid;
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Id Kafka gives `__cluster_metadata`, which has no TopicRecord of its own.
+pub const METADATA_TOPIC_ID: Uuid = Uuid::from_u128(1);
+pub const VOTE_VERSION: i16 = 0;
+pub const BEGIN_QUORUM_EPOCH_VERSION: i16 = 1;
+pub const END_QUORUM_EPOCH_VERSION: i16 = 1;
+// Last Fetch version with the replica id in the body rather than a tagged field
+const FETCH_VERSION: i16 = 14;
+const CLIENT_ID: &[u8] = b"raft-client";
+const QUORUM_STATE_FILE: &str = "quorum-state";
+const QUORUM_STATE_VERSION: i32 = 0;
+const META_PROPERTIES: &str = "meta.properties";
+// Fetch request tagged field with the cluster id, and partition response ones with where a
+// follower diverged and who leads the partition
+const CLUSTER_ID_TAG: u32 = 0;
+const DIVERGING_EPOCH_TAG: u32 = 0;
+const CURRENT_LEADER_TAG: u32 = 1;
+// Kafka's controller.quorum.* defaults
+const DEFAULT_ELECTION_TIMEOUT_MS: i64 = 1000;
+const DEFAULT_FETCH_TIMEOUT_MS: i64 = 2000;
+const DEFAULT_REQUEST_TIMEOUT_MS: i64 = 2000;
+const DEFAULT_ELECTION_BACKOFF_MAX_MS: i64 = 1000;
+// How long the leader holds a fetch that finds nothing new, and how much one returns
+pub const FETCH_MAX_WAIT_MS: i32 = 500;
+const FETCH_MAX_BYTES: i32 = 8388608;
+// Pause between rounds of the quorum loop that have nothing to wait on
+const TICK: Duration = Duration::from_millis(50);
+// How long the active controller waits for a write a broker sent it to be committed before
+// giving up
+pub const COMMIT_TIMEOUT: Duration = Duration::from_secs(5);
+// metadata.version a new cluster starts at, 3.8-IV0, the release whose records this broker
+// writes
+const BOOTSTRAP_METADATA_VERSION: i16 = 20;
+
+/// What this node is in the current epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    // Neither a leader known nor a vote cast
+    Unattached,
+    Voted(i32),
+    Candidate,
+    Leader,
+    Follower(i32),
+}
+
+#[derive(Debug)]
+struct QuorumState {
+    epoch: i32,
+    role: Role,
+    // As candidate, the voters that granted their vote, this node included
+    granted: BTreeSet<i32>,
+    // As leader, the end offset each voter last fetched from, this node's own included
+    voter_offsets: HashMap<i32, i64>,
+    // As leader, where its LeaderChange record sits. Nothing counts as committed before it is
+    epoch_start_offset: i64,
+    high_watermark: i64,
+    // Whether this node has the log up to a high watermark of the current quorum
+    caught_up: bool,
+    // When this node stands for election, or, following a leader, when it gives up on it
+    election_deadline: i64,
+    // As leader, when voters that are not fetching yet were last sent BeginQuorumEpoch
+    last_begin_epoch: i64,
+}
+
+/// The `__cluster_metadata` partition of a quorum Fetch, as the leader answers it.
+#[derive(Debug)]
+pub struct QuorumFetch {
+    pub error_code: ErrorCode,
+    pub high_watermark: i64,
+    pub log_start_offset: i64,
+    pub records: Bytes,
+    pub diverging_epoch: Option<(i32, i64)>,
+    // Leader id, -1 when unknown, and epoch
+    pub current_leader: (i32, i32),
+}
+
+/// The embedded KRaft quorum, replicating `__cluster_metadata` between the voters of
+/// `controller.quorum.voters` the way Kafka's Raft client does. Voters elect a leader with
+/// Vote, which announces itself with BeginQuorumEpoch and steps down with EndQuorumEpoch;
+/// everyone else fetches the log from it. Records are committed once a majority of voters
+/// has them, and only the leader, the active controller, appends.
+///
+/// Nodes that are not voters only follow the leader. The election state is kept in the
+/// partition's `quorum-state` file so votes survive restarts.
+#[derive(Debug)]
+pub struct RaftQuorum {
+    node_id: i32,
+    cluster_id: Bytes,
+    voters: Vec<QuorumVoter>,
+    // Controller listener the leader names alongside its endpoint
+    listener_name: Bytes,
+    logs: Arc<LogManager>,
+    state_path: PathBuf,
+    election_timeout_ms: i64,
+    fetch_timeout_ms: i64,
+    request_timeout_ms: i64,
+    election_backoff_max_ms: i64,
+    state: Mutex<QuorumState>,
+    // Notified when the log, the high watermark or the leadership changes
+    changed: Notify,
+}
+
+impl RaftQuorum {
+    pub fn new(config: &BrokerConfig, logs: Arc<LogManager>) -> Result<Self> {
+        let property = |key: &str, default: i64| {
+            config
+                .property(key)
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+
+        let cluster_id = load_cluster_id(config)?;
+        let dir = logs.with_partition(&Bytes::from_static(METADATA_TOPIC), 0, |log| {
+            log.become_follower();
+            Ok(log.segment_path().parent().map(Path::to_path_buf))
+        })?;
+        let state_path = dir
+            .context("metadata log has no directory")?
+            .join(QUORUM_STATE_FILE);
+        let (epoch, leader_id, voted_id) = read_quorum_state(&state_path)?;
+
+        // A leader that restarts cannot pick up where it left off, it voted for itself
+        let role = match (leader_id, voted_id) {
+            (leader_id, _) if leader_id == config.node_id => Role::Voted(leader_id),
+            (leader_id, _) if leader_id >= 0 => Role::Follower(leader_id),
+            (_, voted_id) if voted_id >= 0 => Role::Voted(voted_id),
+            _ => Role::Unattached,
+        };
+        let quorum = Self {
+            node_id: config.node_id,
+            cluster_id,
+            voters: config.quorum_voters.clone(),
+            listener_name: config
+                .controller_listener_names
+                .first()
+                .map(|name| Bytes::from(name.clone()))
+                .unwrap_or_default(),
+            logs,
+            state_path,
+            election_timeout_ms: property(
+                "controller.quorum.election.timeout.ms",
+                DEFAULT_ELECTION_TIMEOUT_MS,
+            ),
+            fetch_timeout_ms: property(
+                "controller.quorum.fetch.timeout.ms",
+                DEFAULT_FETCH_TIMEOUT_MS,
+            ),
+            request_timeout_ms: property(
+                "controller.quorum.request.timeout.ms",
+                DEFAULT_REQUEST_TIMEOUT_MS,
+            ),
+            election_backoff_max_ms: property(
+                "controller.quorum.election.backoff.max.ms",
+                DEFAULT_ELECTION_BACKOFF_MAX_MS,
+            ),
+            state: Mutex::new(QuorumState {
+                epoch,
+                role,
+                granted: BTreeSet::new(),
+                voter_offsets: HashMap::new(),
+                epoch_start_offset: 0,
+                high_watermark: 0,
+                caught_up: false,
+                election_deadline: 0,
+                last_begin_epoch: 0,
+            }),
+            changed: Notify::new(),
+        };
+
+        // A lone voter has nobody to wait for
+        let deadline = match quorum.voters.len() {
+            1 => current_time_ms(),
+            _ => current_time_ms() + quorum.deadline_after(role),
+        };
+        quorum.lock().election_deadline = deadline;
+
+        Ok(quorum)
+    }
+
+    /// Runs elections and follows the leader on a task of its own.
+    pub fn start(self: &Arc<Self>) {
+        let quorum = Arc::clone(self);
+        tokio::task::spawn(async move { quorum.run().await });
+    }
+
+    pub fn node_id(&self) -> i32 {
+        self.node_id
+    }
+
+    pub fn cluster_id(&self) -> &Bytes {
+        &self.cluster_id
+    }
+
+    pub fn voters(&self) -> &[QuorumVoter] {
+        &self.voters
+    }
+
+    pub fn is_voter(&self, node_id: i32) -> bool {
+        self.voters.iter().any(|voter| voter.id == node_id)
+    }
+
+    /// Whether this node is the active controller.
+    pub fn is_leader(&self) -> bool {
+        self.lock().role == Role::Leader
+    }
+
+    /// The leader's id, -1 when none is known, and the current epoch.
+    pub fn current_leader(&self) -> (i32, i32) {
+        let state = self.lock();
+        (self.leader_id(&state), state.epoch)
+    }
+
+    /// Requests from another cluster are turned away. Those without a cluster id are let
+    /// through, as Kafka does.
+    pub fn check_cluster_id(&self, cluster_id: Option<&[u8]>) -> Result<(), ErrorCode> {
+        match cluster_id {
+            Some(cluster_id) if cluster_id != &self.cluster_id[..] => {
+                Err(ErrorCode::InconsistentClusterId)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Appends, as the leader, the batch `records` builds from the offset it will start at,
+    /// returning that offset. It counts as committed once a majority of voters has it.
+    pub fn append_with(&self, records: impl FnOnce(i64) -> Vec<Record>) -> Result<i64> {
+        let mut state = self.lock();
+        if state.role != Role::Leader {
+            bail!("node {} is not the active controller", self.node_id);
+        }
+
+        let (base_offset, log_end_offset) = self.with_log(|log| {
+            let base_offset = log.log_end_offset();
+            let batch = RecordBatch::new(current_time_ms(), records(base_offset));
+            let records = batch.encode().context("encoding metadata records")?;
+            let appended = log.append(records).context("appending to metadata log")?;
+            Ok((base_offset, appended.log_end_offset))
+        })?;
+        state.voter_offsets.insert(self.node_id, log_end_offset);
+        self.update_high_watermark(&mut state);
+        self.changed.notify_waiters();
+
+        Ok(base_offset)
+    }
+
+    /// Like `append_with`, with `records` also handed the metadata already in the log,
+    /// committed or not, for changes that build on it such as the next producer id block.
+    /// The log is read and appended to under the state lock, so concurrent writes cannot
+    /// build on the same metadata. `records` refuses the change with an error code.
+    pub fn append_after(
+        &self,
+        records: impl FnOnce(&[MetadataBatch], i64) -> Result<Vec<Record>, ErrorCode>,
+    ) -> Result<i64, ErrorCode> {
+        let mut state = self.lock();
+        if state.role != Role::Leader {
+            return Err(ErrorCode::NotController);
+        }
+
+        let (metadata, base_offset) = self
+            .with_log(|log| {
+                let records = log.read(log.log_start_offset(), log.log_end_offset(), usize::MAX)?;
+                Ok((decode_metadata(records)?, log.log_end_offset()))
+            })
+            .map_err(|err| {
+                eprintln!("reading metadata log: {err:#}");
+                ErrorCode::KafkaStorageError
+            })?;
+        let records = records(&metadata, base_offset)?;
+
+        let log_end_offset = self
+            .with_log(|log| {
+                let batch = RecordBatch::new(current_time_ms(), records);
+                let records = batch.encode().context("encoding metadata records")?;
+                let appended = log.append(records).context("appending to metadata log")?;
+                Ok(appended.log_end_offset)
+            })
+            .map_err(|err| {
+                eprintln!("appending to metadata log: {err:#}");
+                ErrorCode::KafkaStorageError
+            })?;
+        state.voter_offsets.insert(self.node_id, log_end_offset);
+        self.update_high_watermark(&mut state);
+        self.changed.notify_waiters();
+
+        Ok(base_offset)
+    }
+
+    /// Waits until everything before `offset` is committed, or `deadline` passes.
+    pub async fn wait_for_commit(&self, offset: i64, deadline: tokio::time::Instant) -> bool {
+        loop {
+            // Registered before checking so a change in between is not missed
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+
+            if self.lock().high_watermark >= offset {
+                return true;
+            }
+            if tokio::time::timeout_at(deadline, changed).await.is_err() {
+                return false;
+            }
+        }
+    }
+
+    /// Waits until the high watermark moves past `offset` and returns it.
+    pub async fn wait_for_high_watermark(&self, offset: i64) -> i64 {
+        loop {
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+
+            let high_watermark = self.lock().high_watermark;
+            if high_watermark > offset {
+                return high_watermark;
+            }
+            changed.await;
+        }
+    }
+
+    /// Waits until this node has the log up to a high watermark of the current quorum, so
+    /// the metadata it loads is no older than what the cluster already committed.
+    pub async fn wait_until_caught_up(&self) {
+        loop {
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+
+            if self.lock().caught_up {
+                return;
+            }
+            changed.await;
+        }
+    }
+
+    /// The committed batches from `offset` on, and the high watermark they end at.
+    pub fn read_committed(&self, offset: i64) -> Result<(Bytes, i64)> {
+        let state = self.lock();
+        let high_watermark = state.high_watermark;
+        let records = self.with_log(|log| log.read(offset, high_watermark, usize::MAX))?;
+        Ok((records, high_watermark))
+    }
+
+    /// Sends a request to the active controller over a connection of its own and returns
+    /// the response without its size prefix and header.
+    pub async fn call_leader(&self, api: ApiType, version: i16, body: &[u8]) -> Result<Bytes> {
+        let (leader_id, _) = self.current_leader();
+        let voter = self
+            .voter(leader_id)
+            .context("no active controller is known")?;
+        let request = async {
+            let mut channel = Channel::connect(&voter.address()).await?;
+            channel.call(api, version, body).await
+        };
+        tokio::time::timeout(self.request_timeout(), request)
+            .await
+            .context("request to the active controller timed out")?
+    }
+
+    /// Answers a candidate's Vote. A voter grants one vote per epoch, to a candidate whose
+    /// log is at least as up to date as its own. Returns the error, the leader as known
+    /// here, the epoch and whether the vote was granted.
+    pub fn handle_vote(
+        &self,
+        candidate_epoch: i32,
+        candidate_id: i32,
+        last_offset_epoch: i32,
+        last_offset: i64,
+    ) -> (ErrorCode, i32, i32, bool) {
+        let mut state = self.lock();
+        if !self.is_voter(candidate_id) || !self.is_voter(self.node_id) {
+            return (ErrorCode::InconsistentVoterSet, -1, state.epoch, false);
+        }
+        if candidate_epoch < state.epoch {
+            let leader_id = self.leader_id(&state);
+            return (ErrorCode::FencedLeaderEpoch, leader_id, state.epoch, false);
+        }
+        if candidate_epoch > state.epoch {
+            self.transition(&mut state, candidate_epoch, Role::Unattached);
+        }
+
+        let granted = match state.role {
+            Role::Voted(voted_id) => voted_id == candidate_id,
+            Role::Unattached => match self.last_offset() {
+                Ok(ours) => (last_offset_epoch, last_offset) >= ours,
+                Err(err) => {
+                    eprintln!("reading metadata log: {err:#}");
+                    false
+                }
+            },
+            Role::Candidate | Role::Leader | Role::Follower(_) => false,
+        };
+        if granted && state.role == Role::Unattached {
+            self.transition(&mut state, candidate_epoch, Role::Voted(candidate_id));
+        }
+
+        (
+            ErrorCode::None,
+            self.leader_id(&state),
+            state.epoch,
+            granted,
+        )
+    }
+
+    /// Answers a new leader's BeginQuorumEpoch by following it.
+    pub fn handle_begin_quorum_epoch(
+        &self,
+        leader_id: i32,
+        leader_epoch: i32,
+    ) -> (ErrorCode, i32, i32) {
+        let mut state = self.lock();
+        if !self.is_voter(leader_id) {
+            return (
+                ErrorCode::InconsistentVoterSet,
+                self.leader_id(&state),
+                state.epoch,
+            );
+        }
+        if leader_epoch < state.epoch {
+            return (
+                ErrorCode::FencedLeaderEpoch,
+                self.leader_id(&state),
+                state.epoch,
+            );
+        }
+
+        self.observe_leader(&mut state, leader_id, leader_epoch);
+        (ErrorCode::None, self.leader_id(&state), state.epoch)
+    }
+
+    /// Answers a resigning leader's EndQuorumEpoch. Its followers stand for election, the
+    /// ones it prefers sooner than the rest.
+    pub fn handle_end_quorum_epoch(
+        &self,
+        leader_id: i32,
+        leader_epoch: i32,
+        preferred_successors: &[i32],
+    ) -> (ErrorCode, i32, i32) {
+        let mut state = self.lock();
+        if leader_epoch < state.epoch {
+            return (
+                ErrorCode::FencedLeaderEpoch,
+                self.leader_id(&state),
+                state.epoch,
+            );
+        }
+
+        if leader_epoch > state.epoch || state.role == Role::Follower(leader_id) {
+            self.transition(&mut state, leader_epoch, Role::Unattached);
+            if let Some(position) = preferred_successors
+                .iter()
+                .position(|id| *id == self.node_id)
+            {
+                state.election_deadline = current_time_ms() + self.election_backoff(position);
+            }
+        }
+
+        (ErrorCode::None, self.leader_id(&state), state.epoch)
+    }
+
+    /// Answers a Fetch of the metadata log, as the leader. Voters' fetch offsets count
+    /// towards the high watermark, and a fetcher whose log diverged from the leader's is
+    /// told where to truncate to instead of being sent records.
+    pub fn handle_fetch(
+        &self,
+        replica_id: i32,
+        current_leader_epoch: i32,
+        fetch_offset: i64,
+        last_fetched_epoch: i32,
+        max_bytes: usize,
+    ) -> QuorumFetch {
+        let mut state = self.lock();
+        let mut fetched = QuorumFetch {
+            error_code: ErrorCode::None,
+            high_watermark: state.high_watermark,
+            log_start_offset: 0,
+            records: Bytes::new(),
+            diverging_epoch: None,
+            current_leader: (self.leader_id(&state), state.epoch),
+        };
+        if let Err(error_code) = self.check_leader_epoch(&state, current_leader_epoch) {
+            fetched.error_code = error_code;
+            return fetched;
+        }
+
+        let read = self.with_log(|log| {
+            if last_fetched_epoch >= 0 {
+                let (epoch, end_offset) = log.end_offset_for_epoch(last_fetched_epoch);
+                if epoch != last_fetched_epoch || end_offset < fetch_offset {
+                    return Ok((log.log_start_offset(), None, Some((epoch, end_offset))));
+                }
+            }
+            let records = log.read(fetch_offset, log.log_end_offset(), max_bytes)?;
+            Ok((log.log_start_offset(), Some(records), None))
+        });
+        match read {
+            Ok((log_start_offset, records, diverging_epoch)) => {
+                fetched.log_start_offset = log_start_offset;
+                fetched.records = records.unwrap_or_default();
+                fetched.diverging_epoch = diverging_epoch;
+            }
+            Err(err) => {
+                eprintln!("reading metadata log: {err:#}");
+                fetched.error_code = ErrorCode::KafkaStorageError;
+                return fetched;
+            }
+        }
+
+        if fetched.diverging_epoch.is_none()
+            && replica_id != self.node_id
+            && self.is_voter(replica_id)
+        {
+            state.voter_offsets.insert(replica_id, fetch_offset);
+            self.update_high_watermark(&mut state);
+            fetched.high_watermark = state.high_watermark;
+        }
+
+        fetched
+    }
+
+    /// Answers a Fetch like [`handle_fetch`](Self::handle_fetch), holding it for up to
+    /// `max_wait` until there is something new for the fetcher: records, or a high
+    /// watermark other than the one it was about to be told.
+    pub async fn fetch(
+        &self,
+        replica_id: i32,
+        current_leader_epoch: i32,
+        fetch_offset: i64,
+        last_fetched_epoch: i32,
+        max_bytes: usize,
+        max_wait: Duration,
+    ) -> QuorumFetch {
+        let deadline = tokio::time::Instant::now() + max_wait;
+        let high_watermark = self.lock().high_watermark;
+        loop {
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+
+            let fetched = self.handle_fetch(
+                replica_id,
+                current_leader_epoch,
+                fetch_offset,
+                last_fetched_epoch,
+                max_bytes,
+            );
+            if fetched.error_code != ErrorCode::None
+                || !fetched.records.is_empty()
+                || fetched.diverging_epoch.is_some()
+                || fetched.high_watermark != high_watermark
+            {
+                return fetched;
+            }
+            if tokio::time::timeout_at(deadline, changed).await.is_err() {
+                return fetched;
+            }
+        }
+    }
+
+    /// Reads part of the snapshot ending at `end_offset` in `epoch` from the partition's
+    /// `.checkpoint` files, returning its size and the bytes from `position` on. This node
+    /// never takes snapshots itself, so only ones placed there are served.
+    pub fn read_snapshot(
+        &self,
+        current_leader_epoch: i32,
+        end_offset: i64,
+        epoch: i32,
+        position: i64,
+        max_bytes: usize,
+    ) -> Result<(i64, Bytes), ErrorCode> {
+        let state = self.lock();
+        self.check_leader_epoch(&state, current_leader_epoch)?;
+        drop(state);
+
+        let path = self
+            .state_path
+            .with_file_name(format!("{end_offset:020}-{epoch:010}.checkpoint"));
+        let content = match std::fs::read(&path) {
+            Ok(content) => Bytes::from(content),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Err(ErrorCode::SnapshotNotFound);
+            }
+            Err(err) => {
+                eprintln!("reading snapshot {}: {err:#}", path.display());
+                return Err(ErrorCode::KafkaStorageError);
+            }
+        };
+
+        let size = content.len() as i64;
+        if position < 0 || position > size {
+            return Err(ErrorCode::PositionOutOfRange);
+        }
+        let start = position as usize;
+        let end = content.len().min(start.saturating_add(max_bytes));
+        Ok((size, content.slice(start..end)))
+    }
+
+    /// Steps down as leader, asking the other voters with EndQuorumEpoch to elect a
+    /// successor, those furthest along first.
+    pub async fn resign(&self) {
+        let (epoch, successors) = {
+            let state = self.lock();
+            if state.role != Role::Leader {
+                return;
+            }
+
+            let mut successors = self
+                .voters
+                .iter()
+                .map(|voter| voter.id)
+                .filter(|id| *id != self.node_id)
+                .collect::<Vec<_>>();
+            successors.sort_by_key(|id| {
+                std::cmp::Reverse(state.voter_offsets.get(id).copied().unwrap_or(-1))
+            });
+            (state.epoch, successors)
+        };
+
+        let body = self.end_quorum_epoch_request(epoch, &successors);
+        let requests = successors.iter().map(|id| {
+            self.call_voter(
+                *id,
+                ApiType::EndQuorumEpoch,
+                END_QUORUM_EPOCH_VERSION,
+                &body,
+            )
+        });
+        for (id, result) in successors
+            .iter()
+            .zip(futures_util::future::join_all(requests).await)
+        {
+            if let Err(err) = result {
+                eprintln!("resigning to voter {id}: {err:#}");
+            }
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        let mut channel = None;
+        // The node fetches kept failing from, so an unreachable leader is reported once
+        let mut failing = None;
+        loop {
+            match self.poll() {
+                Step::Elect(epoch) => {
+                    self.request_votes(epoch);
+                    tokio::time::sleep(TICK).await;
+                }
+                Step::BeginEpoch(epoch, voters) => {
+                    self.begin_epoch(epoch, voters);
+                    tokio::time::sleep(TICK).await;
+                }
+                Step::Fetch(target) => match self.fetch_from(target, &mut channel).await {
+                    Ok(()) => failing = None,
+                    Err(err) => {
+                        if failing != Some(target) {
+                            eprintln!("fetching metadata from node {target}: {err:#}");
+                            failing = Some(target);
+                        }
+                        channel = None;
+                        tokio::time::sleep(TICK).await;
+                    }
+                },
+                Step::Wait => tokio::time::sleep(TICK).await,
+            }
+        }
+    }
+
+    /// Decides what the quorum loop does next, starting an election when this voter's
+    /// timer ran out.
+    fn poll(&self) -> Step {
+        let mut state = self.lock();
+        let now = current_time_ms();
+        let voter = self.is_voter(self.node_id);
+
+        if state.role == Role::Leader {
+            let waiting = self
+                .voters
+                .iter()
+                .map(|voter| voter.id)
+                .filter(|id| !state.voter_offsets.contains_key(id))
+                .collect::<Vec<_>>();
+            if waiting.is_empty() || now - state.last_begin_epoch < self.request_timeout_ms {
+                return Step::Wait;
+            }
+            state.last_begin_epoch = now;
+            return Step::BeginEpoch(state.epoch, waiting);
+        }
+
+        if now >= state.election_deadline {
+            if voter {
+                return self.start_election(&mut state);
+            }
+            // An observer that lost its leader looks for the next one
+            if let Role::Follower(_) = state.role {
+                let epoch = state.epoch;
+                self.transition(&mut state, epoch, Role::Unattached);
+            }
+        }
+
+        match state.role {
+            Role::Follower(leader_id) => Step::Fetch(leader_id),
+            // Observers ask the voters in turn, who point them at the leader
+            Role::Unattached if !voter => {
+                let index = (now / TICK.as_millis() as i64) as usize % self.voters.len();
+                Step::Fetch(self.voters[index].id)
+            }
+            _ => Step::Wait,
+        }
+    }
+
+    /// Stands for election in the next epoch, voting for itself. A lone voter wins on the
+    /// spot.
+    fn start_election(&self, state: &mut QuorumState) -> Step {
+        let epoch = state.epoch + 1;
+        self.transition(state, epoch, Role::Candidate);
+        state.granted.insert(self.node_id);
+        eprintln!(
+            "node {} standing for election in epoch {epoch}",
+            self.node_id
+        );
+
+        if self.has_majority(&state.granted) {
+            self.become_leader(state);
+            return Step::Wait;
+        }
+        Step::Elect(epoch)
+    }
+
+    fn request_votes(self: &Arc<Self>, epoch: i32) {
+        let (last_offset_epoch, last_offset) = match self.last_offset() {
+            Ok(last_offset) => last_offset,
+            Err(err) => {
+                eprintln!("reading metadata log: {err:#}");
+                return;
+            }
+        };
+        let body = self.vote_request(epoch, last_offset_epoch, last_offset);
+
+        for voter in self.voters.iter().filter(|voter| voter.id != self.node_id) {
+            let quorum = Arc::clone(self);
+            let body = body.clone();
+            let voter_id = voter.id;
+            tokio::task::spawn(async move {
+                let response = quorum
+                    .call_voter(voter_id, ApiType::Vote, VOTE_VERSION, &body)
+                    .await
+                    .and_then(parse_vote_response);
+                match response {
+                    Ok((leader_id, leader_epoch, granted)) => {
+                        quorum.record_vote(epoch, voter_id, leader_id, leader_epoch, granted);
+                    }
+                    Err(err) => eprintln!("requesting vote from node {voter_id}: {err:#}"),
+                }
+            });
+        }
+    }
+
+    /// Counts a voter's answer to this node's candidacy in `epoch`.
+    fn record_vote(
+        &self,
+        epoch: i32,
+        voter_id: i32,
+        leader_id: i32,
+        leader_epoch: i32,
+        granted: bool,
+    ) {
+        let mut state = self.lock();
+        if leader_epoch > state.epoch || leader_id >= 0 {
+            self.observe_leader(&mut state, leader_id, leader_epoch);
+        }
+        if state.epoch != epoch || state.role != Role::Candidate || !granted {
+            return;
+        }
+
+        state.granted.insert(voter_id);
+        if self.has_majority(&state.granted) {
+            self.become_leader(&mut state);
+        }
+    }
+
+    /// Takes over as leader: starts the epoch in the log with a LeaderChange record, and
+    /// bootstraps `metadata.version` when the log has never had one.
+    fn become_leader(&self, state: &mut QuorumState) {
+        let granted = state.granted.iter().copied().collect::<Vec<_>>();
+        let epoch = state.epoch;
+        self.transition(state, epoch, Role::Leader);
+
+        let voter_ids = self.voters.iter().map(|voter| voter.id).collect::<Vec<_>>();
+        let started = self.with_log(|log| {
+            let bootstrapped = decode_metadata(log.read_all()?)?
+                .iter()
+                .any(|batch| batch.metadata_version().is_some());
+
+            log.become_leader(epoch)?;
+            let epoch_start_offset = log.log_end_offset();
+            let leader_change =
+                RecordBatch::leader_change(self.node_id, &voter_ids, &granted, current_time_ms());
+            log.append(leader_change.encode()?)?;
+
+            if !bootstrapped {
+                let record = FeatureRecord {
+                    version: 0,
+                    name: Bytes::from_static(FeatureRecord::METADATA_VERSION),
+                    feature_level: BOOTSTRAP_METADATA_VERSION,
+                    tags: 0,
+                };
+                let batch = RecordBatch::new(
+                    current_time_ms(),
+                    vec![Record::new(None, Some(record.encode()))],
+                );
+                log.append(batch.encode()?)?;
+            }
+            Ok((epoch_start_offset, log.log_end_offset()))
+        });
+
+        match started {
+            Ok((epoch_start_offset, log_end_offset)) => {
+                eprintln!(
+                    "node {} is the active controller in epoch {epoch}",
+                    self.node_id
+                );
+                state.epoch_start_offset = epoch_start_offset;
+                state.voter_offsets.insert(self.node_id, log_end_offset);
+                self.update_high_watermark(state);
+            }
+            Err(err) => eprintln!("starting epoch {epoch}: {err:#}"),
+        }
+    }
+
+    fn begin_epoch(self: &Arc<Self>, epoch: i32, voters: Vec<i32>) {
+        let body = self.begin_quorum_epoch_request(epoch);
+        for voter_id in voters.into_iter().filter(|id| *id != self.node_id) {
+            let quorum = Arc::clone(self);
+            let body = body.clone();
+            tokio::task::spawn(async move {
+                let response = quorum
+                    .call_voter(
+                        voter_id,
+                        ApiType::BeginQuorumEpoch,
+                        BEGIN_QUORUM_EPOCH_VERSION,
+                        &body,
+                    )
+                    .await
+                    .and_then(parse_epoch_response);
+                match response {
+                    Ok((leader_id, leader_epoch)) => {
+                        let mut state = quorum.lock();
+                        quorum.observe_leader(&mut state, leader_id, leader_epoch);
+                    }
+                    Err(err) => eprintln!("beginning epoch {epoch} on node {voter_id}: {err:#}"),
+                }
+            });
+        }
+    }
+
+    /// Fetches once from `target`, the leader or, for an observer still looking for one,
+    /// a voter. Requests go over one connection, kept while the target stays the same.
+    async fn fetch_from(&self, target: i32, channel: &mut Option<(i32, Channel)>) -> Result<()> {
+        let (epoch, fetch_offset, last_fetched_epoch) = {
+            let state = self.lock();
+            let (fetch_offset, last_fetched_epoch) = self.with_log(|log| {
+                Ok((
+                    log.log_end_offset(),
+                    log.latest_epoch().unwrap_or(UNDEFINED_EPOCH),
+                ))
+            })?;
+            (state.epoch, fetch_offset, last_fetched_epoch)
+        };
+
+        if channel.as_ref().is_none_or(|(id, _)| *id != target) {
+            let voter = self
+                .voter(target)
+                .with_context(|| format!("node {target} is not a voter"))?;
+            let connected =
+                tokio::time::timeout(self.request_timeout(), Channel::connect(&voter.address()))
+                    .await
+                    .context("connecting timed out")??;
+            *channel = Some((target, connected));
+        }
+        let (_, connected) = channel.as_mut().expect("connected above");
+
+        let body = self.fetch_request(epoch, fetch_offset, last_fetched_epoch);
+        let timeout = self.request_timeout() + Duration::from_millis(FETCH_MAX_WAIT_MS as u64);
+        let response = tokio::time::timeout(
+            timeout,
+            connected.call(ApiType::Fetch, FETCH_VERSION, &body),
+        )
+        .await
+        .context("fetch timed out")??;
+        let fetched = FetchedPartition::decode(response)?;
+
+        self.apply_fetch(target, epoch, fetched)
+    }
+
+    /// Applies a fetch response from `target`: learns of a newer leader, truncates where the
+    /// log diverged from the leader's, or appends the records and takes over the high
+    /// watermark.
+    fn apply_fetch(&self, target: i32, epoch: i32, fetched: FetchedPartition) -> Result<()> {
+        let mut state = self.lock();
+        if let Some((leader_id, leader_epoch)) = fetched.current_leader {
+            self.observe_leader(&mut state, leader_id, leader_epoch);
+        }
+        if fetched.error_code != 0 {
+            bail!("node {target} returned error {}", fetched.error_code);
+        }
+        if state.epoch != epoch || state.role != Role::Follower(target) {
+            return Ok(());
+        }
+        state.election_deadline = current_time_ms() + self.fetch_timeout_ms;
+
+        if let Some((diverging_epoch, end_offset)) = fetched.diverging_epoch {
+            return self.with_log(|log| {
+                let offset = match diverging_epoch {
+                    UNDEFINED_EPOCH => log.log_start_offset(),
+                    _ => {
+                        let (_, local_end_offset) = log.end_offset_for_epoch(diverging_epoch);
+                        end_offset.min(local_end_offset).max(log.log_start_offset())
+                    }
+                };
+                eprintln!(
+                    "truncating metadata log from {} to {offset}, diverged from leader at epoch {diverging_epoch}",
+                    log.log_end_offset()
+                );
+                log.truncate_to(offset)
+            });
+        }
+
+        let log_end_offset = self.with_log(|log| {
+            if !fetched.records.is_empty() {
+                log.append_replicated(fetched.records)?;
+            }
+            Ok(log.log_end_offset())
+        })?;
+
+        let high_watermark = fetched.high_watermark.min(log_end_offset);
+        if high_watermark > state.high_watermark {
+            state.high_watermark = high_watermark;
+        }
+        if !state.caught_up && log_end_offset >= fetched.high_watermark {
+            eprintln!(
+                "node {} caught up with metadata leader {target}",
+                self.node_id
+            );
+            state.caught_up = true;
+        }
+        self.changed.notify_waiters();
+
+        Ok(())
+    }
+
+    /// Follows the leader a response or request named, if it is newer than the one known.
+    fn observe_leader(&self, state: &mut QuorumState, leader_id: i32, leader_epoch: i32) {
+        let known = leader_id >= 0 && leader_id != self.node_id;
+        if leader_epoch > state.epoch {
+            let role = if known {
+                Role::Follower(leader_id)
+            } else {
+                Role::Unattached
+            };
+            self.transition(state, leader_epoch, role);
+        } else if leader_epoch == state.epoch
+            && known
+            && !matches!(state.role, Role::Follower(_) | Role::Leader)
+        {
+            self.transition(state, leader_epoch, Role::Follower(leader_id));
+        }
+    }
+
+    /// Moves to `role` in `epoch`, persisting the election state and resetting the timer.
+    fn transition(&self, state: &mut QuorumState, epoch: i32, role: Role) {
+        if state.role == Role::Leader
+            && role != Role::Leader
+            && let Err(err) = self.with_log(|log| {
+                log.become_follower();
+                Ok(())
+            })
+        {
+            eprintln!("stepping down as leader: {err:#}");
+        }
+        if let Role::Follower(leader_id) = role
+            && (state.role != role || state.epoch != epoch)
+        {
+            eprintln!(
+                "node {} following leader {leader_id} in epoch {epoch}",
+                self.node_id
+            );
+        }
+
+        state.epoch = epoch;
+        state.role = role;
+        state.granted.clear();
+        state.voter_offsets.clear();
+        state.last_begin_epoch = 0;
+        state.election_deadline = current_time_ms() + self.deadline_after(role);
+        if let Err(err) = self.write_quorum_state(state) {
+            eprintln!("writing quorum state: {err:#}");
+        }
+        self.changed.notify_waiters();
+    }
+
+    /// Moves the high watermark to the largest offset a majority of voters has, once this
+    /// leader's own epoch has started committing.
+    fn update_high_watermark(&self, state: &mut QuorumState) {
+        let mut offsets = self
+            .voters
+            .iter()
+            .map(|voter| state.voter_offsets.get(&voter.id).copied().unwrap_or(0))
+            .collect::<Vec<_>>();
+        offsets.sort_unstable_by(|a, b| b.cmp(a));
+
+        let committed = offsets[self.voters.len() / 2];
+        if committed <= state.epoch_start_offset || committed <= state.high_watermark {
+            return;
+        }
+        state.high_watermark = committed;
+        state.caught_up = true;
+        self.changed.notify_waiters();
+    }
+
+    fn check_leader_epoch(&self, state: &QuorumState, epoch: i32) -> Result<(), ErrorCode> {
+        if epoch < state.epoch {
+            return Err(ErrorCode::FencedLeaderEpoch);
+        }
+        if epoch > state.epoch {
+            return Err(ErrorCode::UnknownLeaderEpoch);
+        }
+        if state.role != Role::Leader {
+            return Err(ErrorCode::NotLeaderOrFollower);
+        }
+        Ok(())
+    }
+
+    fn has_majority(&self, voters: &BTreeSet<i32>) -> bool {
+        let granted = voters.iter().filter(|id| self.is_voter(**id)).count();
+        granted > self.voters.len() / 2
+    }
+
+    fn leader_id(&self, state: &QuorumState) -> i32 {
+        match state.role {
+            Role::Leader => self.node_id,
+            Role::Follower(leader_id) => leader_id,
+            Role::Unattached | Role::Voted(_) | Role::Candidate => -1,
+        }
+    }
+
+    fn voter(&self, node_id: i32) -> Option<&QuorumVoter> {
+        self.voters.iter().find(|voter| voter.id == node_id)
+    }
+
+    /// The epoch of the log's last batch and its end offset, which candidates compare.
+    fn last_offset(&self) -> Result<(i32, i64)> {
+        self.with_log(|log| Ok((log.latest_epoch().unwrap_or(0), log.log_end_offset())))
+    }
+
+    fn with_log<T>(&self, f: impl FnOnce(&mut crate::log::PartitionLog) -> Result<T>) -> Result<T> {
+        self.logs
+            .with_partition(&Bytes::from_static(METADATA_TOPIC), 0, f)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, QuorumState> {
+        self.state.lock().expect("quorum state lock poisoned")
+    }
+
+    fn request_timeout(&self) -> Duration {
+        Duration::from_millis(self.request_timeout_ms.max(0) as u64)
+    }
+
+    /// How long a node in `role` waits before giving up on the current epoch: followers as
+    /// long as their leader stays silent, everyone else a randomised election timeout so
+    /// candidates rarely collide.
+    fn deadline_after(&self, role: Role) -> i64 {
+        match role {
+            Role::Follower(_) => self.fetch_timeout_ms,
+            Role::Leader => i64::MAX / 2,
+            Role::Unattached | Role::Voted(_) | Role::Candidate => {
+                self.election_timeout_ms + random_below(self.election_timeout_ms)
+            }
+        }
+    }
+
+    /// How long the voter at `position` among a resigning leader's preferred successors
+    /// waits before standing, the first not at all.
+    fn election_backoff(&self, position: usize) -> i64 {
+        match position {
+            0 => 0,
+            _ => random_below(self.election_backoff_max_ms.min(50 << position.min(16))),
+        }
+    }
+
+    async fn call_voter(
+        &self,
+        voter_id: i32,
+        api: ApiType,
+        version: i16,
+        body: &[u8],
+    ) -> Result<Bytes> {
+        let voter = self
+            .voter(voter_id)
+            .with_context(|| format!("node {voter_id} is not a voter"))?;
+        let request = async {
+            let mut channel = Channel::connect(&voter.address()).await?;
+            channel.call(api, version, body).await
+        };
+        tokio::time::timeout(self.request_timeout(), request)
+            .await
+            .context("request timed out")?
+    }
+
+    /// Writes the election state in Kafka's `quorum-state` format, through a temporary file
+    /// so a crash leaves either version.
+    fn write_quorum_state(&self, state: &QuorumState) -> Result<()> {
+        let voted_id = match state.role {
+            Role::Voted(voted_id) => voted_id,
+            Role::Candidate | Role::Leader => self.node_id,
+            Role::Unattached | Role::Follower(_) => -1,
+        };
+        let voters = self
+            .voters
+            .iter()
+            .map(|voter| format!("{{\"voterId\":{}}}", voter.id))
+            .collect::<Vec<_>>()
+            .join(",");
+        let content = format!(
+            "{{\"clusterId\":\"\",\"leaderId\":{},\"leaderEpoch\":{},\"votedId\":{voted_id},\"appliedOffset\":0,\"currentVoters\":[{voters}],\"data_version\":{QUORUM_STATE_VERSION}}}",
+            self.leader_id(state),
+            state.epoch,
+        );
+
+        let tmp = self.state_path.with_extension("tmp");
+        std::fs::write(&tmp, content).context("writing quorum state")?;
+        std::fs::rename(&tmp, &self.state_path).context("replacing quorum state")?;
+        Ok(())
+    }
+
+    fn vote_request(&self, epoch: i32, last_offset_epoch: i32, last_offset: i64) -> BytesMut {
+        let mut buf = BytesMut::new();
+        write_compact_nullable_string(&mut buf, Some(&self.cluster_id));
+        unsigned_varint_encode(&mut buf, 1);
+        write_compact_string(&mut buf, METADATA_TOPIC);
+        unsigned_varint_encode(&mut buf, 1);
+        buf.put_i32(0);
+        buf.put_i32(epoch);
+        buf.put_i32(self.node_id);
+        buf.put_i32(last_offset_epoch);
+        buf.put_i64(last_offset);
+        // Tags
+        buf.put_i8(0x00);
+        // Tags
+        buf.put_i8(0x00);
+        // Tags
+        buf.put_i8(0x00);
+        buf
+    }
+
+    fn begin_quorum_epoch_request(&self, epoch: i32) -> BytesMut {
+        let mut buf = BytesMut::new();
+        write_compact_nullable_string(&mut buf, Some(&self.cluster_id));
+        // Voter id, one request goes to every voter
+        buf.put_i32(-1);
+        unsigned_varint_encode(&mut buf, 1);
+        write_compact_string(&mut buf, METADATA_TOPIC);
+        unsigned_varint_encode(&mut buf, 1);
+        buf.put_i32(0);
+        // Voter directory id
+        buf.put_u128(0);
+        buf.put_i32(self.node_id);
+        buf.put_i32(epoch);
+        // Tags
+        buf.put_i8(0x00);
+        // Tags
+        buf.put_i8(0x00);
+        self.put_leader_endpoints(&mut buf);
+        // Tags
+        buf.put_i8(0x00);
+        buf
+    }
+
+    fn end_quorum_epoch_request(&self, epoch: i32, successors: &[i32]) -> BytesMut {
+        let mut buf = BytesMut::new();
+        write_compact_nullable_string(&mut buf, Some(&self.cluster_id));
+        unsigned_varint_encode(&mut buf, 1);
+        write_compact_string(&mut buf, METADATA_TOPIC);
+        unsigned_varint_encode(&mut buf, 1);
+        buf.put_i32(0);
+        buf.put_i32(self.node_id);
+        buf.put_i32(epoch);
+        // Preferred candidates with their directory ids
+        unsigned_varint_encode(&mut buf, successors.len());
+        for id in successors {
+            buf.put_i32(*id);
+            buf.put_u128(0);
+            // Tags
+            buf.put_i8(0x00);
+        }
+        // Tags
+        buf.put_i8(0x00);
+        // Tags
+        buf.put_i8(0x00);
+        self.put_leader_endpoints(&mut buf);
+        // Tags
+        buf.put_i8(0x00);
+        buf
+    }
+
+    fn put_leader_endpoints(&self, buf: &mut BytesMut) {
+        let Some(voter) = self.voter(self.node_id) else {
+            unsigned_varint_encode(buf, 0);
+            return;
+        };
+        unsigned_varint_encode(buf, 1);
+        write_compact_string(buf, &self.listener_name);
+        write_compact_string(buf, voter.host.as_bytes());
+        buf.put_u16(voter.port);
+        // Tags
+        buf.put_i8(0x00);
+    }
+
+    fn fetch_request(&self, epoch: i32, fetch_offset: i64, last_fetched_epoch: i32) -> BytesMut {
+        let mut buf = BytesMut::new();
+        buf.put_i32(self.node_id);
+        buf.put_i32(FETCH_MAX_WAIT_MS);
+        // Min bytes
+        buf.put_i32(1);
+        buf.put_i32(FETCH_MAX_BYTES);
+        // Isolation level
+        buf.put_i8(0);
+        // Sessionless full fetch
+        buf.put_i32(0);
+        buf.put_i32(-1);
+
+        unsigned_varint_encode(&mut buf, 1);
+        buf.put_u128(METADATA_TOPIC_ID.as_u128());
+        unsigned_varint_encode(&mut buf, 1);
+        buf.put_i32(0);
+        buf.put_i32(epoch);
+        buf.put_i64(fetch_offset);
+        buf.put_i32(last_fetched_epoch);
+        // Log start offset
+        buf.put_i64(0);
+        buf.put_i32(FETCH_MAX_BYTES);
+        // Tags
+        buf.put_i8(0x00);
+        // Tags
+        buf.put_i8(0x00);
+        // Forgotten topics
+        unsigned_varint_encode(&mut buf, 0);
+        // Rack id
+        unsigned_varint_encode(&mut buf, 0);
+
+        let mut cluster_id = BytesMut::new();
+        write_compact_nullable_string(&mut cluster_id, Some(&self.cluster_id));
+        write_tagged_fields(&mut buf, &[(CLUSTER_ID_TAG, cluster_id)]);
+        buf
+    }
+}
+
+#[derive(Debug)]
+enum Step {
+    Wait,
+    // Ask the other voters for their vote in the epoch
+    Elect(i32),
+    // Announce this leader's epoch to the voters not fetching from it yet
+    BeginEpoch(i32, Vec<i32>),
+    Fetch(i32),
+}
+
+/// A connection to another node's controller listener.
+#[derive(Debug)]
+struct Channel {
+    stream: TcpStream,
+    correlation_id: i32,
+}
+
+impl Channel {
+    async fn connect(address: &str) -> Result<Self> {
+        let stream = TcpStream::connect(address)
+            .await
+            .with_context(|| format!("connecting to {address}"))?;
+        Ok(Self {
+            stream,
+            correlation_id: 0,
+        })
+    }
+
+    /// Sends a request with a flexible header and returns the response after its header.
+    async fn call(&mut self, api: ApiType, version: i16, body: &[u8]) -> Result<Bytes> {
+        self.correlation_id = self.correlation_id.wrapping_add(1);
+        let mut request = BytesMut::new();
+        request.put_i16(api as i16);
+        request.put_i16(version);
+        request.put_i32(self.correlation_id);
+        request.put_i16(CLIENT_ID.len() as i16);
+        request.put_slice(CLIENT_ID);
+        // Tags
+        request.put_i8(0x00);
+        request.put_slice(body);
+
+        self.stream.write_i32(request.len() as i32).await?;
+        self.stream.write_all(&request).await?;
+
+        let size = self
+            .stream
+            .read_i32()
+            .await
+            .context("reading response size")?;
+        let mut response = vec![0; size.max(0) as usize];
+        self.stream
+            .read_exact(&mut response)
+            .await
+            .context("reading response")?;
+
+        let mut response = Bytes::from(response);
+        let correlation_id = response.try_get_i32()?;
+        if correlation_id != self.correlation_id {
+            bail!(
+                "response to request {correlation_id}, expected {}",
+                self.correlation_id
+            );
+        }
+        skip_tagged_fields(&mut response)?;
+        Ok(response)
+    }
+}
+
+/// The metadata partition of a Fetch response, as a follower reads it.
+#[derive(Debug)]
+struct FetchedPartition {
+    error_code: i16,
+    high_watermark: i64,
+    records: Bytes,
+    diverging_epoch: Option<(i32, i64)>,
+    current_leader: Option<(i32, i32)>,
+}
+
+impl FetchedPartition {
+    fn decode(mut buf: Bytes) -> Result<Self> {
+        let _throttle_time = buf.try_get_i32()?;
+        let error_code = buf.try_get_i16()?;
+        if error_code != 0 {
+            bail!("fetch failed with error {error_code}");
+        }
+        let _session_id = buf.try_get_i32()?;
+
+        let topics_len = read_compact_len(&mut buf)?;
+        if topics_len != 1 || Uuid::from_u128(buf.try_get_u128()?) != METADATA_TOPIC_ID {
+            bail!("fetch response is not for the metadata log");
+        }
+        let partitions_len = read_compact_len(&mut buf)?;
+        if partitions_len != 1 {
+            bail!("fetch response has {partitions_len} metadata partitions");
+        }
+
+        let _partition = buf.try_get_i32()?;
+        let error_code = buf.try_get_i16()?;
+        let high_watermark = buf.try_get_i64()?;
+        let _last_stable_offset = buf.try_get_i64()?;
+        let _log_start_offset = buf.try_get_i64()?;
+        let aborted_len = read_compact_len(&mut buf)?;
+        for _ in 0..aborted_len {
+            buf.try_advance(8 + 8)?;
+            skip_tagged_fields(&mut buf)?;
+        }
+        let _preferred_read_replica = buf.try_get_i32()?;
+        let records = read_compact_nullable_string(&mut buf)?.unwrap_or_default();
+
+        let mut diverging_epoch = None;
+        let mut current_leader = None;
+        let tags_len = read_uvarint(&mut buf)?;
+        for _ in 0..tags_len {
+            let tag = read_uvarint(&mut buf)?;
+            let size = read_uvarint(&mut buf)?;
+            let mut field = buf.try_split_to(size as usize)?;
+            match tag {
+                DIVERGING_EPOCH_TAG => {
+                    diverging_epoch = Some((field.try_get_i32()?, field.try_get_i64()?))
+                }
+                CURRENT_LEADER_TAG => {
+                    current_leader = Some((field.try_get_i32()?, field.try_get_i32()?))
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            error_code,
+            high_watermark,
+            records,
+            diverging_epoch,
+            current_leader,
+        })
+    }
+}
+
+/// The leader, epoch and grant of a Vote response's single partition.
+fn parse_vote_response(mut buf: Bytes) -> Result<(i32, i32, bool)> {
+    let error_code = buf.try_get_i16()?;
+    if error_code != 0 {
+        bail!("vote failed with error {error_code}");
+    }
+    let (error_code, leader_id, leader_epoch, mut rest) = read_epoch_partition(buf)?;
+    let granted = rest.try_get_u8()? != 0;
+    // A fenced candidate still learns the newer epoch
+    if error_code != 0 && error_code != ErrorCode::FencedLeaderEpoch as i16 {
+        bail!("vote failed with error {error_code}");
+    }
+    Ok((leader_id, leader_epoch, granted))
+}
+
+/// The leader and epoch of a BeginQuorumEpoch or EndQuorumEpoch response.
+fn parse_epoch_response(mut buf: Bytes) -> Result<(i32, i32)> {
+    let error_code = buf.try_get_i16()?;
+    if error_code != 0 {
+        bail!("request failed with error {error_code}");
+    }
+    let (error_code, leader_id, leader_epoch, _) = read_epoch_partition(buf)?;
+    if error_code != 0 && error_code != ErrorCode::FencedLeaderEpoch as i16 {
+        bail!("request failed with error {error_code}");
+    }
+    Ok((leader_id, leader_epoch))
+}
+
+/// Reads up to the leader epoch of the single partition the quorum responses carry,
+/// returning the rest.
+fn read_epoch_partition(mut buf: Bytes) -> Result<(i16, i32, i32, Bytes)> {
+    let topics_len = read_compact_len(&mut buf)?;
+    if topics_len != 1 {
+        bail!("response has {topics_len} topics");
+    }
+    let _topic_name = read_compact_string(&mut buf)?;
+    let partitions_len = read_compact_len(&mut buf)?;
+    if partitions_len != 1 {
+        bail!("response has {partitions_len} partitions");
+    }
+    let _partition = buf.try_get_i32()?;
+    let error_code = buf.try_get_i16()?;
+    let leader_id = buf.try_get_i32()?;
+    let leader_epoch = buf.try_get_i32()?;
+    Ok((error_code, leader_id, leader_epoch, buf))
+}
+
+/// Reads the epoch, leader and vote from a `quorum-state` file, all unset without one.
+fn read_quorum_state(path: &Path) -> Result<(i32, i32, i32)> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok((0, -1, -1)),
+        Err(err) => return Err(err).context("reading quorum state"),
+    };
+
+    let field = |key: &str| -> Result<i32> {
+        let start = content
+            .find(&format!("\"{key}\":"))
+            .with_context(|| format!("quorum state has no {key}"))?
+            + key.len()
+            + 3;
+        let value = &content[start..];
+        let end = value.find([',', '}']).unwrap_or(value.len());
+        value[..end]
+            .trim()
+            .parse()
+            .with_context(|| format!("quorum state has an invalid {key}"))
+    };
+    Ok((field("leaderEpoch")?, field("leaderId")?, field("votedId")?))
+}
+
+/// The cluster id from the metadata log directory's `meta.properties`, written there on
+/// first start from the `cluster.id` property, the way `kafka-storage format` would. A
+/// lone voter may also make one up, but voters of a larger quorum have to agree on it.
+fn load_cluster_id(config: &BrokerConfig) -> Result<Bytes> {
+    let path = config.metadata_log_dir.join(META_PROPERTIES);
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).context("reading meta.properties"),
+    };
+
+    let configured = config.property("cluster.id");
+    if let Some(cluster_id) = parse_properties(&content).remove("cluster.id") {
+        if configured.is_some_and(|configured| configured != cluster_id) {
+            bail!(
+                "cluster.id does not match {cluster_id} in {}",
+                path.display()
+            );
+        }
+        return Ok(Bytes::from(cluster_id));
+    }
+
+    let cluster_id = match configured {
+        Some(cluster_id) => cluster_id.to_string(),
+        None if config.quorum_voters.len() == 1 => encode_uuid(&Uuid::new_v4()),
+        None => bail!("set cluster.id so every voter of the new cluster uses the same one"),
+    };
+    let mut content = match content.is_empty() {
+        true => format!("version=1\nnode.id={}\n", config.node_id),
+        false => content,
+    };
+    if !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&format!("cluster.id={cluster_id}\n"));
+    std::fs::create_dir_all(&config.metadata_log_dir).context("creating metadata log directory")?;
+    std::fs::write(&path, content).context("writing meta.properties")?;
+    eprintln!("formatted {} for cluster {cluster_id}", path.display());
+
+    Ok(Bytes::from(cluster_id))
+}
+
+/// A random duration below `bound` milliseconds.
+fn random_below(bound: i64) -> i64 {
+    match bound {
+        ..=0 => 0,
+        _ => (Uuid::new_v4().as_u128() % bound as u128) as i64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A quorum of `voters` on a fresh metadata log, seen from node `node_id`.
+    fn quorum(name: &str, node_id: i32, voters: &str) -> RaftQuorum {
+        let dir = std::env::temp_dir().join(format!(
+            "raft-test-{name}-{}-{}",
+            std::process::id(),
+            current_time_ms()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let overrides = [
+            format!("node.id={node_id}"),
+            format!("controller.quorum.voters={voters}"),
+            format!("log.dirs={}", dir.display()),
+            "cluster.id=raft-test".to_string(),
+        ];
+        let config = BrokerConfig::from_args(
+            overrides
+                .into_iter()
+                .flat_map(|value| ["--override".to_string(), value]),
+        )
+        .unwrap();
+        let logs = Arc::new(LogManager::new(&config));
+        RaftQuorum::new(&config, logs).unwrap()
+    }
+
+    const THREE_VOTERS: &str = "1@localhost:19093,2@localhost:19094,3@localhost:19095";
+
+    #[test]
+    fn voters_grant_one_vote_per_epoch() {
+        let quorum = quorum("one-vote", 1, THREE_VOTERS);
+
+        let (error_code, _, epoch, granted) = quorum.handle_vote(1, 2, 0, 0);
+        assert_eq!((error_code, epoch, granted), (ErrorCode::None, 1, true));
+        // The same candidate asking again keeps the vote, another one does not get it
+        assert!(quorum.handle_vote(1, 2, 0, 0).3);
+        assert!(!quorum.handle_vote(1, 3, 0, 0).3);
+        // A later epoch frees the vote
+        assert!(quorum.handle_vote(2, 3, 0, 0).3);
+
+        let (error_code, _, epoch, granted) = quorum.handle_vote(1, 2, 0, 0);
+        assert_eq!(
+            (error_code, epoch, granted),
+            (ErrorCode::FencedLeaderEpoch, 2, false)
+        );
+        let (error_code, _, _, granted) = quorum.handle_vote(3, 7, 0, 0);
+        assert_eq!(
+            (error_code, granted),
+            (ErrorCode::InconsistentVoterSet, false)
+        );
+    }
+
+    #[test]
+    fn votes_go_to_candidates_at_least_as_up_to_date() {
+        let quorum = quorum("up-to-date", 1, THREE_VOTERS);
+        quorum
+            .with_log(|log| {
+                log.become_leader(1)?;
+                let batch = RecordBatch::new(
+                    current_time_ms(),
+                    vec![Record::new(None, Some(Bytes::from_static(b"record")))],
+                );
+                log.append(batch.encode()?)?;
+                log.become_follower();
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(quorum.last_offset().unwrap(), (1, 1));
+
+        // An older last epoch loses whatever its offset, a shorter log loses too
+        assert!(!quorum.handle_vote(2, 2, 0, 5).3);
+        assert!(!quorum.handle_vote(2, 2, 1, 0).3);
+        assert!(quorum.handle_vote(2, 3, 1, 1).3);
+    }
+
+    #[test]
+    fn high_watermark_needs_a_majority_past_the_epoch_start() {
+        let quorum = quorum("high-watermark", 1, THREE_VOTERS);
+        let mut state = quorum.lock();
+        state.epoch = 1;
+        state.role = Role::Leader;
+        state.epoch_start_offset = 5;
+
+        state.voter_offsets.insert(1, 10);
+        quorum.update_high_watermark(&mut state);
+        assert_eq!(state.high_watermark, 0);
+
+        // A majority reached the epoch start, which commits nothing yet
+        state.voter_offsets.insert(2, 5);
+        quorum.update_high_watermark(&mut state);
+        assert_eq!(state.high_watermark, 0);
+
+        state.voter_offsets.insert(2, 8);
+        quorum.update_high_watermark(&mut state);
+        assert_eq!(state.high_watermark, 8);
+
+        // The high watermark never moves back
+        state.voter_offsets.insert(2, 6);
+        state.voter_offsets.insert(3, 7);
+        quorum.update_high_watermark(&mut state);
+        assert_eq!(state.high_watermark, 8);
+    }
+
+    #[test]
+    fn majorities_only_count_voters() {
+        let quorum = quorum("majority", 1, THREE_VOTERS);
+        assert!(!quorum.has_majority(&BTreeSet::from([1])));
+        assert!(!quorum.has_majority(&BTreeSet::from([1, 7, 8])));
+        assert!(quorum.has_majority(&BTreeSet::from([1, 3])));
+    }
+
+    #[test]
+    fn lone_voter_elects_itself_and_bootstraps_the_metadata_version() {
+        let quorum = quorum("lone-voter", 1, "1@localhost:19093");
+        let mut state = quorum.lock();
+        assert!(matches!(quorum.start_election(&mut state), Step::Wait));
+        assert_eq!((state.role, state.epoch), (Role::Leader, 1));
+        drop(state);
+
+        // The LeaderChange and metadata.version batches are committed by the leader alone
+        let (records, high_watermark) = quorum.read_committed(0).unwrap();
+        assert_eq!(high_watermark, 2);
+        let metadata = decode_metadata(records).unwrap();
+        assert!(
+            metadata
+                .iter()
+                .any(|batch| batch.metadata_version() == Some(BOOTSTRAP_METADATA_VERSION))
+        );
+    }
+}
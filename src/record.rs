@@ -0,0 +1,871 @@
+use crate::{
+    compression::{COMPRESSION_MASK, Compression, DecompressedTooLarge},
+    unsigned_varint_encode, varint_decode, varint_encode, varlong_decode, varlong_encode,
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use thiserror::Error;
+
+pub const MAGIC: i8 = 2;
+
+// Base offset and batch length, which sit in front of the batch header
+pub const LOG_OVERHEAD: usize = 12;
+// Batch header from the partition leader epoch up to and including the record count
+pub const BATCH_HEADER_LEN: usize = 49;
+// Bytes preceding the CRC-covered region (partition leader epoch, magic and the CRC itself)
+const CRC_OFFSET: usize = 9;
+// Default cap on a batch's decompressed records, as much as the batch length field allows
+const MAX_RECORDS_LEN: usize = i32::MAX as usize;
+const TIMESTAMP_TYPE_MASK: i16 = 0x08;
+const TRANSACTIONAL_MASK: i16 = 0x10;
+const CONTROL_MASK: i16 = 0x20;
+
+/// Key of the single record inside a control batch.
+#[repr(i16)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ControlRecordType {
+    Abort = 0,
+    Commit = 1,
+    // Written by a new KRaft leader at the start of its epoch
+    LeaderChange = 2,
+}
+
+impl ControlRecordType {
+    pub fn decode(mut key: &[u8]) -> Option<Self> {
+        if key.len() < 4 {
+            return None;
+        }
+
+        let _version = key.get_i16();
+        match key.get_i16() {
+            0 => Some(Self::Abort),
+            1 => Some(Self::Commit),
+            2 => Some(Self::LeaderChange),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        // Version
+        buf.put_i16(0);
+        buf.put_i16(*self as i16);
+        buf.freeze()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TimestampType {
+    CreateTime,
+    LogAppendTime,
+}
+
+impl TimestampType {
+    /// Maps a topic's `message.timestamp.type` config onto a timestamp type.
+    pub fn from_config(value: &[u8]) -> Option<Self> {
+        match value {
+            b"CreateTime" => Some(Self::CreateTime),
+            b"LogAppendTime" => Some(Self::LogAppendTime),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RecordError {
+    #[error("record batch is truncated")]
+    Truncated,
+    #[error("unsupported record batch magic: {0}")]
+    UnsupportedMagic(i8),
+    #[error("record batch crc mismatch: stored {stored:#010x}, computed {computed:#010x}")]
+    CrcMismatch { stored: u32, computed: u32 },
+    #[error("invalid records: {0}")]
+    InvalidRecords(&'static str),
+    #[error("record compression: {0:#}")]
+    Compression(anyhow::Error),
+    #[error("records decompress to more than {0} bytes")]
+    TooLarge(usize),
+}
+
+/// The fixed fields in front of a batch's records, readable straight from an encoded batch
+/// without checking the CRC or decompressing anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordBatchHeader {
+    pub base_offset: i64,
+    pub batch_length: i32,
+    pub partition_leader_epoch: i32,
+    pub magic: i8,
+    pub crc: u32,
+    pub attributes: i16,
+    pub last_offset_delta: i32,
+    pub base_timestamp: i64,
+    pub max_timestamp: i64,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub base_sequence: i32,
+    pub record_count: i32,
+}
+
+impl RecordBatchHeader {
+    /// Reads the header at the start of `raw`, or `None` if the header is incomplete.
+    pub fn peek(mut raw: &[u8]) -> Option<Self> {
+        if raw.len() < LOG_OVERHEAD + BATCH_HEADER_LEN {
+            return None;
+        }
+
+        Some(Self {
+            base_offset: raw.get_i64(),
+            batch_length: raw.get_i32(),
+            partition_leader_epoch: raw.get_i32(),
+            magic: raw.get_i8(),
+            crc: raw.get_u32(),
+            attributes: raw.get_i16(),
+            last_offset_delta: raw.get_i32(),
+            base_timestamp: raw.get_i64(),
+            max_timestamp: raw.get_i64(),
+            producer_id: raw.get_i64(),
+            producer_epoch: raw.get_i16(),
+            base_sequence: raw.get_i32(),
+            record_count: raw.get_i32(),
+        })
+    }
+
+    /// Total size of the encoded batch, including the base offset and length prefix.
+    pub fn size(&self) -> usize {
+        LOG_OVERHEAD + self.batch_length.max(0) as usize
+    }
+
+    pub fn last_offset(&self) -> i64 {
+        self.base_offset + self.last_offset_delta as i64
+    }
+
+    pub fn next_offset(&self) -> i64 {
+        self.last_offset() + 1
+    }
+
+    pub fn has_producer_id(&self) -> bool {
+        self.producer_id >= 0
+    }
+
+    pub fn is_transactional(&self) -> bool {
+        self.attributes & TRANSACTIONAL_MASK != 0
+    }
+
+    pub fn is_control(&self) -> bool {
+        self.attributes & CONTROL_MASK != 0
+    }
+
+    /// Sequence of the batch's last record. Sequences wrap back to zero after `i32::MAX`.
+    pub fn last_sequence(&self) -> i32 {
+        increment_sequence(self.base_sequence, self.last_offset_delta)
+    }
+}
+
+pub fn increment_sequence(sequence: i32, increment: i32) -> i32 {
+    if sequence > i32::MAX - increment {
+        increment - (i32::MAX - sequence) - 1
+    } else {
+        sequence + increment
+    }
+}
+
+/// A v2 record batch as it sits on disk and on the wire. Keys, values and headers are
+/// slices into the (decompressed) batch so decoding never copies record data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordBatch {
+    pub base_offset: i64,
+    pub partition_leader_epoch: i32,
+    pub magic: i8,
+    pub crc: u32,
+    pub attributes: i16,
+    pub last_offset_delta: i32,
+    pub base_timestamp: i64,
+    pub max_timestamp: i64,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub base_sequence: i32,
+    pub records: Vec<Record>,
+}
+
+impl RecordBatch {
+    /// Builds an uncompressed, non-idempotent batch, assigning offset deltas in order.
+    pub fn new(base_timestamp: i64, mut records: Vec<Record>) -> Self {
+        for (delta, record) in records.iter_mut().enumerate() {
+            record.offset_delta = delta as i32;
+        }
+
+        let max_delta = records.iter().map(|r| r.timestamp_delta).max().unwrap_or(0);
+        Self {
+            base_offset: 0,
+            partition_leader_epoch: -1,
+            magic: MAGIC,
+            crc: 0,
+            attributes: 0,
+            last_offset_delta: records.len() as i32 - 1,
+            base_timestamp,
+            max_timestamp: base_timestamp + max_delta,
+            producer_id: -1,
+            producer_epoch: -1,
+            base_sequence: -1,
+            records,
+        }
+    }
+
+    /// Builds the COMMIT/ABORT marker a transaction coordinator writes into every partition
+    /// the transaction touched.
+    pub fn end_txn_marker(
+        producer_id: i64,
+        producer_epoch: i16,
+        coordinator_epoch: i32,
+        marker: ControlRecordType,
+        timestamp: i64,
+    ) -> Self {
+        let mut value = BytesMut::new();
+        // Version
+        value.put_i16(0);
+        value.put_i32(coordinator_epoch);
+
+        let record = Record::new(Some(marker.encode()), Some(value.freeze()));
+        let mut batch = Self::new(timestamp, vec![record]);
+        batch.attributes |= TRANSACTIONAL_MASK | CONTROL_MASK;
+        batch.producer_id = producer_id;
+        batch.producer_epoch = producer_epoch;
+        batch
+    }
+
+    /// Builds the `LeaderChangeMessage` a KRaft leader starts its epoch with, naming the
+    /// voters and those that elected it.
+    pub fn leader_change(
+        leader_id: i32,
+        voters: &[i32],
+        granting_voters: &[i32],
+        timestamp: i64,
+    ) -> Self {
+        let mut value = BytesMut::new();
+        // Version
+        value.put_i16(0);
+        value.put_i32(leader_id);
+        for voters in [voters, granting_voters] {
+            unsigned_varint_encode(&mut value, voters.len());
+            for voter_id in voters {
+                value.put_i32(*voter_id);
+                // Tags
+                value.put_i8(0x00);
+            }
+        }
+        // Tags
+        value.put_i8(0x00);
+
+        let key = ControlRecordType::LeaderChange.encode();
+        let mut batch = Self::new(
+            timestamp,
+            vec![Record::new(Some(key), Some(value.freeze()))],
+        );
+        batch.attributes |= CONTROL_MASK;
+        batch
+    }
+
+    pub fn decode(buf: &mut Bytes) -> Result<Self, RecordError> {
+        Self::decode_bounded(buf, MAX_RECORDS_LEN)
+    }
+
+    /// Decodes a batch whose records may take at most `max_records_len` bytes once
+    /// decompressed, for batches from clients that could otherwise expand without limit.
+    pub fn decode_bounded(buf: &mut Bytes, max_records_len: usize) -> Result<Self, RecordError> {
+        if buf.remaining() < LOG_OVERHEAD {
+            return Err(RecordError::Truncated);
+        }
+
+        let base_offset = buf.get_i64();
+        let batch_len = buf.get_i32();
+        if batch_len < BATCH_HEADER_LEN as i32 || batch_len as usize > buf.remaining() {
+            return Err(RecordError::Truncated);
+        }
+
+        let mut batch = buf.split_to(batch_len as usize);
+        let computed = crc32c::crc32c(&batch[CRC_OFFSET..]);
+        let partition_leader_epoch = batch.get_i32();
+        let magic = batch.get_i8();
+        if magic != MAGIC {
+            return Err(RecordError::UnsupportedMagic(magic));
+        }
+
+        let crc = batch.get_u32();
+        if crc != computed {
+            return Err(RecordError::CrcMismatch {
+                stored: crc,
+                computed,
+            });
+        }
+
+        let attributes = batch.get_i16();
+        let last_offset_delta = batch.get_i32();
+        let base_timestamp = batch.get_i64();
+        let max_timestamp = batch.get_i64();
+        let producer_id = batch.get_i64();
+        let producer_epoch = batch.get_i16();
+        let base_sequence = batch.get_i32();
+        let record_count = batch.get_i32();
+        if record_count < 0 {
+            return Err(RecordError::InvalidRecords("negative record count"));
+        }
+
+        let mut content = Compression::from_attributes(attributes)
+            .and_then(|codec| codec.decompress(&batch, max_records_len))
+            .map_err(|err| match err.is::<DecompressedTooLarge>() {
+                true => RecordError::TooLarge(max_records_len),
+                false => RecordError::Compression(err),
+            })?;
+
+        let mut records = Vec::new();
+        for _ in 0..record_count {
+            if !content.has_remaining() {
+                return Err(RecordError::InvalidRecords(
+                    "fewer records than the record count",
+                ));
+            }
+
+            records.push(Record::decode(&mut content)?);
+        }
+
+        if content.has_remaining() {
+            return Err(RecordError::InvalidRecords("trailing bytes after records"));
+        }
+
+        Ok(Self {
+            base_offset,
+            partition_leader_epoch,
+            magic,
+            crc,
+            attributes,
+            last_offset_delta,
+            base_timestamp,
+            max_timestamp,
+            producer_id,
+            producer_epoch,
+            base_sequence,
+            records,
+        })
+    }
+
+    /// Decodes every batch in a buffer, such as a Produce partition's records or a log segment.
+    pub fn decode_all(mut buf: Bytes) -> Result<Vec<Self>, RecordError> {
+        let mut batches = Vec::new();
+        while buf.has_remaining() {
+            batches.push(Self::decode(&mut buf)?);
+        }
+
+        Ok(batches)
+    }
+
+    /// Encodes the batch using the codec in its attributes. The batch length and CRC are
+    /// always recomputed, so the stored `crc` field is ignored.
+    pub fn encode(&self) -> Result<Bytes, RecordError> {
+        let mut records = BytesMut::new();
+        for record in self.records.iter() {
+            record.encode(&mut records);
+        }
+
+        let records = self
+            .compression()?
+            .compress(&records)
+            .map_err(RecordError::Compression)?;
+
+        let mut buf = BytesMut::with_capacity(LOG_OVERHEAD + BATCH_HEADER_LEN + records.len());
+        buf.put_i64(self.base_offset);
+        buf.put_i32((BATCH_HEADER_LEN + records.len()) as i32);
+        buf.put_i32(self.partition_leader_epoch);
+        buf.put_i8(self.magic);
+        // CRC, filled in once the rest of the batch is written
+        buf.put_u32(0);
+        buf.put_i16(self.attributes);
+        buf.put_i32(self.last_offset_delta);
+        buf.put_i64(self.base_timestamp);
+        buf.put_i64(self.max_timestamp);
+        buf.put_i64(self.producer_id);
+        buf.put_i16(self.producer_epoch);
+        buf.put_i32(self.base_sequence);
+        buf.put_i32(self.records.len() as i32);
+        buf.put(records);
+
+        let crc = crc32c::crc32c(&buf[LOG_OVERHEAD + CRC_OFFSET..]);
+        buf[LOG_OVERHEAD + 5..LOG_OVERHEAD + CRC_OFFSET].copy_from_slice(&crc.to_be_bytes());
+
+        Ok(buf.freeze())
+    }
+
+    /// Writes this batch's header over an encoded copy of the same batch and refreshes the
+    /// CRC, letting the broker stamp header fields without recompressing the records.
+    pub fn rewrite_header(&self, raw: &mut [u8]) {
+        let batch_len = (&raw[8..LOG_OVERHEAD]).get_i32();
+        let mut header = &mut raw[..LOG_OVERHEAD + BATCH_HEADER_LEN];
+        header.put_i64(self.base_offset);
+        header.put_i32(batch_len);
+        header.put_i32(self.partition_leader_epoch);
+        header.put_i8(self.magic);
+        // CRC, refreshed below
+        header.put_u32(0);
+        header.put_i16(self.attributes);
+        header.put_i32(self.last_offset_delta);
+        header.put_i64(self.base_timestamp);
+        header.put_i64(self.max_timestamp);
+        header.put_i64(self.producer_id);
+        header.put_i16(self.producer_epoch);
+        header.put_i32(self.base_sequence);
+
+        let crc = crc32c::crc32c(&raw[LOG_OVERHEAD + CRC_OFFSET..]);
+        raw[LOG_OVERHEAD + 5..LOG_OVERHEAD + CRC_OFFSET].copy_from_slice(&crc.to_be_bytes());
+    }
+
+    pub fn is_transactional(&self) -> bool {
+        self.attributes & TRANSACTIONAL_MASK != 0
+    }
+
+    pub fn set_transactional(&mut self) {
+        self.attributes |= TRANSACTIONAL_MASK;
+    }
+
+    pub fn is_control(&self) -> bool {
+        self.attributes & CONTROL_MASK != 0
+    }
+
+    /// The marker carried by a control batch, if this is one.
+    pub fn control_type(&self) -> Option<ControlRecordType> {
+        if !self.is_control() {
+            return None;
+        }
+
+        self.records
+            .first()
+            .and_then(|record| record.key.as_ref())
+            .and_then(|key| ControlRecordType::decode(key))
+    }
+
+    pub fn timestamp_type(&self) -> TimestampType {
+        if self.attributes & TIMESTAMP_TYPE_MASK != 0 {
+            TimestampType::LogAppendTime
+        } else {
+            TimestampType::CreateTime
+        }
+    }
+
+    /// Stamps the batch with the broker's append time, which consumers then report for
+    /// every record in it.
+    pub fn set_log_append_time(&mut self, timestamp: i64) {
+        self.attributes |= TIMESTAMP_TYPE_MASK;
+        self.base_timestamp = timestamp;
+        self.max_timestamp = timestamp;
+    }
+
+    pub fn compression(&self) -> Result<Compression, RecordError> {
+        Compression::from_attributes(self.attributes).map_err(RecordError::Compression)
+    }
+
+    pub fn set_compression(&mut self, codec: Compression) {
+        self.attributes = (self.attributes & !COMPRESSION_MASK) | codec as i16;
+    }
+
+    pub fn last_offset(&self) -> i64 {
+        self.base_offset + self.last_offset_delta as i64
+    }
+
+    pub fn next_offset(&self) -> i64 {
+        self.last_offset() + 1
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Record {
+    pub attributes: i8,
+    pub timestamp_delta: i64,
+    pub offset_delta: i32,
+    pub key: Option<Bytes>,
+    pub value: Option<Bytes>,
+    pub headers: Vec<RecordHeader>,
+}
+
+impl Record {
+    pub fn new(key: Option<Bytes>, value: Option<Bytes>) -> Self {
+        Self {
+            key,
+            value,
+            ..Default::default()
+        }
+    }
+
+    pub fn decode(buf: &mut Bytes) -> Result<Self, RecordError> {
+        let record_len = varint_decode(buf);
+        if record_len <= 0 || record_len as usize > buf.remaining() {
+            return Err(RecordError::Truncated);
+        }
+
+        let mut record = buf.split_to(record_len as usize);
+        let attributes = record.get_i8();
+        let timestamp_delta = varlong_decode(&mut record);
+        let offset_delta = varint_decode(&mut record);
+        let key = read_varint_bytes(&mut record)?;
+        let value = read_varint_bytes(&mut record)?;
+
+        let header_count = varint_decode(&mut record);
+        if header_count < 0 {
+            return Err(RecordError::InvalidRecords("negative header count"));
+        }
+
+        let headers = (0..header_count)
+            .map(|_| RecordHeader::decode(&mut record))
+            .collect::<Result<Vec<RecordHeader>, RecordError>>()?;
+
+        if record.has_remaining() {
+            return Err(RecordError::InvalidRecords("trailing bytes after record"));
+        }
+
+        Ok(Self {
+            attributes,
+            timestamp_delta,
+            offset_delta,
+            key,
+            value,
+            headers,
+        })
+    }
+
+    pub fn encode(&self, buf: &mut BytesMut) {
+        let mut body = BytesMut::new();
+        body.put_i8(self.attributes);
+        varlong_encode(&mut body, self.timestamp_delta);
+        varint_encode(&mut body, self.offset_delta);
+        write_varint_bytes(&mut body, self.key.as_ref());
+        write_varint_bytes(&mut body, self.value.as_ref());
+        varint_encode(&mut body, self.headers.len() as i32);
+        for header in self.headers.iter() {
+            header.encode(&mut body);
+        }
+
+        varint_encode(buf, body.len() as i32);
+        buf.put(body);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordHeader {
+    pub key: Bytes,
+    pub value: Option<Bytes>,
+}
+
+impl RecordHeader {
+    pub fn decode(buf: &mut Bytes) -> Result<Self, RecordError> {
+        let key = read_varint_bytes(buf)?
+            .ok_or(RecordError::InvalidRecords("record header key is null"))?;
+        let value = read_varint_bytes(buf)?;
+
+        Ok(Self { key, value })
+    }
+
+    pub fn encode(&self, buf: &mut BytesMut) {
+        write_varint_bytes(buf, Some(&self.key));
+        write_varint_bytes(buf, self.value.as_ref());
+    }
+}
+
+fn read_varint_bytes(buf: &mut Bytes) -> Result<Option<Bytes>, RecordError> {
+    let len = varint_decode(buf);
+    if len < 0 {
+        return Ok(None);
+    }
+
+    if len as usize > buf.remaining() {
+        return Err(RecordError::Truncated);
+    }
+
+    Ok(Some(buf.split_to(len as usize)))
+}
+
+fn write_varint_bytes(buf: &mut BytesMut, value: Option<&Bytes>) {
+    match value {
+        Some(value) => {
+            varint_encode(buf, value.len() as i32);
+            buf.put_slice(value);
+        }
+        None => varint_encode(buf, -1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{varint_decode, varint_encode, varlong_decode, varlong_encode};
+
+    fn batch() -> RecordBatch {
+        let mut records = vec![
+            Record::new(
+                Some(Bytes::from_static(b"key")),
+                Some(Bytes::from_static(b"a")),
+            ),
+            Record::new(None, Some(Bytes::from_static(b"no key"))),
+            Record::new(Some(Bytes::from_static(b"tombstone")), None),
+        ];
+        records[1].timestamp_delta = 1500;
+
+        let mut batch = RecordBatch::new(1_700_000_000_000, records);
+        batch.base_offset = 42;
+        batch.partition_leader_epoch = 3;
+        batch.producer_id = 7;
+        batch.producer_epoch = 1;
+        batch.base_sequence = 10;
+        batch
+    }
+
+    #[test]
+    fn batches_round_trip_with_every_codec() {
+        for codec in [
+            Compression::None,
+            Compression::Gzip,
+            Compression::Snappy,
+            Compression::Lz4,
+            Compression::Zstd,
+        ] {
+            let mut batch = batch();
+            batch.set_compression(codec);
+
+            let mut encoded = batch.encode().unwrap();
+            let decoded = RecordBatch::decode(&mut encoded).unwrap();
+            assert!(!encoded.has_remaining());
+            assert_eq!(decoded.compression().unwrap(), codec);
+
+            batch.crc = decoded.crc;
+            assert_eq!(decoded, batch);
+        }
+    }
+
+    #[test]
+    fn new_batches_number_records_and_track_the_max_timestamp() {
+        let batch = batch();
+        assert_eq!(batch.last_offset_delta, 2);
+        assert_eq!(batch.max_timestamp, batch.base_timestamp + 1500);
+        assert_eq!(batch.last_offset(), 44);
+        assert_eq!(batch.next_offset(), 45);
+        let deltas = batch
+            .records
+            .iter()
+            .map(|r| r.offset_delta)
+            .collect::<Vec<_>>();
+        assert_eq!(deltas, [0, 1, 2]);
+    }
+
+    #[test]
+    fn crc_covers_everything_after_itself() {
+        let encoded = batch().encode().unwrap();
+        let stored = (&encoded[LOG_OVERHEAD + 5..]).get_u32();
+        assert_eq!(
+            stored,
+            crc32c::crc32c(&encoded[LOG_OVERHEAD + CRC_OFFSET..])
+        );
+
+        // The base offset sits outside the CRC, so the log can assign it freely
+        let mut reassigned = BytesMut::from(&encoded[..]);
+        reassigned[..8].copy_from_slice(&100i64.to_be_bytes());
+        let decoded = RecordBatch::decode(&mut reassigned.freeze()).unwrap();
+        assert_eq!(decoded.base_offset, 100);
+
+        let mut corrupted = BytesMut::from(&encoded[..]);
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 0xFF;
+        assert!(matches!(
+            RecordBatch::decode(&mut corrupted.freeze()),
+            Err(RecordError::CrcMismatch { stored: crc, .. }) if crc == stored
+        ));
+    }
+
+    #[test]
+    fn rewritten_headers_get_a_fresh_crc() {
+        let encoded = batch().encode().unwrap();
+        let mut batch = RecordBatch::decode(&mut encoded.clone()).unwrap();
+        batch.set_log_append_time(1_800_000_000_000);
+
+        let mut raw = BytesMut::from(&encoded[..]);
+        batch.rewrite_header(&mut raw);
+        let decoded = RecordBatch::decode(&mut raw.freeze()).unwrap();
+        assert_eq!(decoded.timestamp_type(), TimestampType::LogAppendTime);
+        assert_eq!(decoded.base_timestamp, 1_800_000_000_000);
+        assert_eq!(decoded.max_timestamp, 1_800_000_000_000);
+        assert_eq!(decoded.records, batch.records);
+    }
+
+    #[test]
+    fn malformed_batches_are_rejected() {
+        let encoded = batch().encode().unwrap();
+
+        let mut truncated = encoded.slice(..encoded.len() - 1);
+        assert!(matches!(
+            RecordBatch::decode(&mut truncated),
+            Err(RecordError::Truncated)
+        ));
+
+        let mut old_magic = BytesMut::from(&encoded[..]);
+        old_magic[LOG_OVERHEAD + 4] = 1;
+        assert!(matches!(
+            RecordBatch::decode(&mut old_magic.freeze()),
+            Err(RecordError::UnsupportedMagic(1))
+        ));
+
+        // Claim one more record than the batch holds, and re-sign it
+        let mut overcounted = BytesMut::from(&encoded[..]);
+        let count_at = LOG_OVERHEAD + BATCH_HEADER_LEN - 4;
+        overcounted[count_at..count_at + 4].copy_from_slice(&4i32.to_be_bytes());
+        let crc = crc32c::crc32c(&overcounted[LOG_OVERHEAD + CRC_OFFSET..]);
+        overcounted[LOG_OVERHEAD + 5..LOG_OVERHEAD + CRC_OFFSET]
+            .copy_from_slice(&crc.to_be_bytes());
+        assert!(matches!(
+            RecordBatch::decode(&mut overcounted.freeze()),
+            Err(RecordError::InvalidRecords(_))
+        ));
+    }
+
+    #[test]
+    fn decode_all_reads_consecutive_batches() {
+        let mut first = batch();
+        first.base_offset = 0;
+        let mut second = batch();
+        second.base_offset = first.next_offset();
+
+        let mut buf = BytesMut::new();
+        buf.put(first.encode().unwrap());
+        buf.put(second.encode().unwrap());
+        let batches = RecordBatch::decode_all(buf.freeze()).unwrap();
+        let offsets = batches.iter().map(|b| b.base_offset).collect::<Vec<_>>();
+        assert_eq!(offsets, [0, 3]);
+    }
+
+    #[test]
+    fn decompressed_records_are_bounded() {
+        let mut batch = RecordBatch::new(0, vec![Record::new(None, Some(vec![0; 4096].into()))]);
+        batch.set_compression(Compression::Zstd);
+        let encoded = batch.encode().unwrap();
+
+        assert!(RecordBatch::decode_bounded(&mut encoded.clone(), 8192).is_ok());
+        assert!(matches!(
+            RecordBatch::decode_bounded(&mut encoded.clone(), 1024),
+            Err(RecordError::TooLarge(1024))
+        ));
+    }
+
+    #[test]
+    fn control_batches_carry_their_marker() {
+        let mut encoded = RecordBatch::end_txn_marker(7, 2, 5, ControlRecordType::Abort, 0)
+            .encode()
+            .unwrap();
+        let decoded = RecordBatch::decode(&mut encoded).unwrap();
+        assert!(decoded.is_control());
+        assert!(decoded.is_transactional());
+        assert_eq!(decoded.control_type(), Some(ControlRecordType::Abort));
+        assert_eq!(batch().control_type(), None);
+    }
+
+    #[test]
+    fn sequences_wrap_past_i32_max() {
+        assert_eq!(increment_sequence(5, 3), 8);
+        assert_eq!(increment_sequence(i32::MAX, 1), 0);
+        assert_eq!(increment_sequence(i32::MAX - 1, 3), 1);
+    }
+
+    #[test]
+    fn varints_zigzag_encode() {
+        for (value, encoded) in [
+            (0, &[0x00][..]),
+            (-1, &[0x01]),
+            (1, &[0x02]),
+            (63, &[0x7E]),
+            (-64, &[0x7F]),
+            (64, &[0x80, 0x01]),
+            (i32::MAX, &[0xFE, 0xFF, 0xFF, 0xFF, 0x0F]),
+            (i32::MIN, &[0xFF, 0xFF, 0xFF, 0xFF, 0x0F]),
+        ] {
+            let mut buf = BytesMut::new();
+            varint_encode(&mut buf, value);
+            assert_eq!(&buf[..], encoded, "{value}");
+            assert_eq!(varint_decode(&mut buf.freeze()), value);
+        }
+    }
+
+    #[test]
+    fn varlongs_zigzag_encode() {
+        for value in [0, -1, 1, i32::MAX as i64 + 1, i64::MAX, i64::MIN] {
+            let mut buf = BytesMut::new();
+            varlong_encode(&mut buf, value);
+            let mut buf = buf.freeze();
+            assert_eq!(varlong_decode(&mut buf), value);
+            assert!(!buf.has_remaining());
+        }
+
+        let mut buf = BytesMut::new();
+        varlong_encode(&mut buf, i64::MIN);
+        assert_eq!(buf.len(), 10);
+    }
+
+    #[test]
+    fn headers_survive_a_round_trip() {
+        let mut record = Record::new(Some(Bytes::from_static(b"key")), None);
+        record.headers = vec![
+            RecordHeader {
+                key: Bytes::from_static(b"traceparent"),
+                value: Some(Bytes::from_static(
+                    b"00-4bf92f3577b34da6-00f067aa0ba902b7-01",
+                )),
+            },
+            // Keys may repeat, and values may be null
+            RecordHeader {
+                key: Bytes::from_static(b"traceparent"),
+                value: None,
+            },
+            RecordHeader {
+                key: Bytes::new(),
+                value: Some(Bytes::new()),
+            },
+        ];
+
+        for codec in [Compression::None, Compression::Lz4] {
+            let mut batch = RecordBatch::new(0, vec![record.clone()]);
+            batch.set_compression(codec);
+            let decoded = RecordBatch::decode(&mut batch.encode().unwrap()).unwrap();
+            assert_eq!(decoded.records, batch.records);
+        }
+    }
+
+    #[test]
+    fn headers_need_a_key() {
+        let mut buf = BytesMut::new();
+        // Null key, then a value
+        varint_encode(&mut buf, -1);
+        varint_encode(&mut buf, 1);
+        buf.put_u8(b'v');
+        assert!(matches!(
+            RecordHeader::decode(&mut buf.freeze()),
+            Err(RecordError::InvalidRecords(_))
+        ));
+
+        let mut body = BytesMut::new();
+        body.put_i8(0);
+        varlong_encode(&mut body, 0);
+        varint_encode(&mut body, 0);
+        varint_encode(&mut body, -1);
+        varint_encode(&mut body, -1);
+        // Header count
+        varint_encode(&mut body, -2);
+        let mut buf = BytesMut::new();
+        varint_encode(&mut buf, body.len() as i32);
+        buf.put(body);
+        assert!(matches!(
+            Record::decode(&mut buf.freeze()),
+            Err(RecordError::InvalidRecords(_))
+        ));
+    }
+
+    #[test]
+    fn null_keys_and_values_survive_a_round_trip() {
+        let record = Record::new(None, None);
+        let mut buf = BytesMut::new();
+        record.encode(&mut buf);
+        // Length, attributes, timestamp and offset deltas, null key and value, no headers
+        assert_eq!(&buf[..], [0x0C, 0x00, 0x00, 0x00, 0x01, 0x01, 0x00]);
+        assert_eq!(Record::decode(&mut buf.freeze()).unwrap(), record);
+    }
+}
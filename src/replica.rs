@@ -0,0 +1,595 @@
+use crate::{
+    config::BrokerConfig,
+    current_time_ms,
+    log::LogManager,
+    metadata::{MetadataWriter, PartitionChangeRecord, RecordBatch as MetadataBatch},
+    raft::RaftQuorum,
+    record::Record,
+    request::{ApiType, ErrorCode, TryGet, read_compact_len},
+    unsigned_varint_encode,
+};
+use anyhow::{Result, bail};
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+// Kafka's replica.lag.time.max.ms default
+const DEFAULT_REPLICA_LAG_TIME_MAX_MS: i64 = 30000;
+const ALTER_PARTITION_VERSION: i16 = 2;
+// How long an ISR change sent to the active controller is waited on before it is sent again
+const ALTER_PARTITION_RETRY_MS: i64 = 5000;
+
+/// A follower's progress as seen by the leader, from its last fetch.
+#[derive(Debug, Clone, Copy)]
+struct FollowerState {
+    // Unknown until the follower first fetches
+    log_end_offset: Option<i64>,
+    last_caught_up_ms: i64,
+}
+
+#[derive(Debug)]
+struct PartitionState {
+    topic_id: Uuid,
+    leader: i32,
+    leader_epoch: i32,
+    partition_epoch: i32,
+    replicas: Box<[i32]>,
+    in_sync_replicas: Vec<i32>,
+    // Every other replica, tracked while this broker leads
+    followers: HashMap<i32, FollowerState>,
+    // When an ISR change was last sent to the active controller, until it is replayed
+    pending_isr_change_ms: Option<i64>,
+}
+
+// A partition's leader epoch and replicas, which decide what this broker does with it
+type Leadership = (i32, Box<[i32]>);
+
+/// A partition this broker follows, to be fetched from its leader.
+#[derive(Debug, Clone)]
+pub struct FollowedPartition {
+    pub topic_name: Bytes,
+    pub topic_id: Uuid,
+    pub partition: i32,
+    pub leader: i32,
+    pub leader_epoch: i32,
+}
+
+/// Leader and follower state of every partition, from the metadata log. For partitions
+/// replicated to more than one broker, leaders track how far each follower has fetched,
+/// commit what the whole ISR has by moving the high watermark, and drop or re-admit
+/// followers to the ISR through `PartitionChangeRecord`s. Partitions with a single replica
+/// commit everything appended to them at once.
+#[derive(Debug)]
+pub struct ReplicaManager {
+    node_id: i32,
+    logs: Arc<LogManager>,
+    writer: MetadataWriter,
+    replica_lag_time_max_ms: i64,
+    partitions: Mutex<HashMap<(Bytes, i32), PartitionState>>,
+    // Woken whenever a high watermark moves, for acks=-1 produces waiting on replication
+    high_watermark_moved: Notify,
+}
+
+impl ReplicaManager {
+    pub fn new(
+        config: &BrokerConfig,
+        metadata: &[MetadataBatch],
+        logs: Arc<LogManager>,
+        writer: MetadataWriter,
+    ) -> Self {
+        let replica_lag_time_max_ms = config
+            .property("replica.lag.time.max.ms")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_REPLICA_LAG_TIME_MAX_MS);
+
+        let replicas = Self {
+            node_id: config.node_id,
+            logs,
+            writer,
+            replica_lag_time_max_ms,
+            partitions: Mutex::new(HashMap::new()),
+            high_watermark_moved: Notify::new(),
+        };
+        replicas.replay(metadata);
+        replicas
+    }
+
+    /// Applies the partition records and changes of metadata batches, then has this broker
+    /// lead or follow every partition of its own that is new or changed leader. Run on the
+    /// metadata loaded at startup, then on every batch added to the metadata log after it.
+    pub fn replay(&self, metadata: &[MetadataBatch]) {
+        let mut partitions = self.partitions.lock().expect("replica lock poisoned");
+        // Leadership of every replayed partition before the batches, none for new ones
+        let mut replayed: HashMap<(Bytes, i32), Option<Leadership>> = HashMap::new();
+        for batch in metadata.iter() {
+            for (topic_name, topic_id, records) in batch.topic_partitions() {
+                for record in records {
+                    let key = (topic_name.clone(), record.partition_id);
+                    let state = PartitionState {
+                        topic_id: *topic_id,
+                        leader: record.leader,
+                        leader_epoch: record.leader_epoch,
+                        partition_epoch: record.partition_epoch,
+                        replicas: record.replication_ids.clone(),
+                        in_sync_replicas: record.in_sync_replica_ids.to_vec(),
+                        followers: HashMap::new(),
+                        pending_isr_change_ms: None,
+                    };
+                    let previous = partitions.insert(key.clone(), state);
+                    replayed
+                        .entry(key)
+                        .or_insert(previous.map(|state| (state.leader_epoch, state.replicas)));
+                }
+            }
+
+            for change in batch.partition_change_records() {
+                let Some((key, state)) = partitions.iter_mut().find(|((_, partition), state)| {
+                    state.topic_id == change.uuid && *partition == change.partition_id
+                }) else {
+                    continue;
+                };
+                replayed
+                    .entry(key.clone())
+                    .or_insert_with(|| Some((state.leader_epoch, state.replicas.clone())));
+
+                if let Some(isr) = &change.in_sync_replica_ids {
+                    state.in_sync_replicas = isr.to_vec();
+                }
+                if let Some(replicas) = &change.replication_ids {
+                    state.replicas = replicas.clone();
+                }
+                if change.leader != PartitionChangeRecord::NO_LEADER_CHANGE {
+                    state.leader = change.leader;
+                    state.leader_epoch += 1;
+                }
+                state.partition_epoch += 1;
+                state.pending_isr_change_ms = None;
+            }
+        }
+
+        for ((topic_name, partition), previous) in replayed {
+            let Some(state) = partitions.get_mut(&(topic_name.clone(), partition)) else {
+                continue;
+            };
+            // ISR changes leave the leadership as it is
+            let unchanged = previous.as_ref().is_some_and(|(leader_epoch, replicas)| {
+                *leader_epoch == state.leader_epoch && *replicas == state.replicas
+            });
+            if unchanged || !state.replicas.contains(&self.node_id) {
+                continue;
+            }
+
+            let known = previous.is_some();
+            let transitioned = self.transition(&topic_name, partition, state, known);
+            if let Err(err) = transitioned {
+                eprintln!("opening replica {topic_name:?}-{partition}: {err:#}");
+            }
+        }
+    }
+
+    /// Makes this broker lead or follow a partition after a leader change, or when it first
+    /// learns of the partition.
+    fn transition(
+        &self,
+        topic_name: &Bytes,
+        partition: i32,
+        state: &mut PartitionState,
+        known: bool,
+    ) -> Result<()> {
+        let leader = state.leader == self.node_id;
+        let replicated = state.replicas.len() > 1;
+        state.followers.clear();
+        if leader && replicated {
+            // Followers get a full lag interval to show up before leaving the ISR
+            let now = current_time_ms();
+            state.followers = state
+                .replicas
+                .iter()
+                .filter(|replica| **replica != self.node_id)
+                .map(|replica| {
+                    let follower = FollowerState {
+                        log_end_offset: None,
+                        last_caught_up_ms: now,
+                    };
+                    (*replica, follower)
+                })
+                .collect();
+        }
+
+        self.logs.with_partition(topic_name, partition, |log| {
+            match leader {
+                true => log.become_leader(state.leader_epoch)?,
+                false => log.become_follower(),
+            }
+            // Nothing is known to be committed until the followers report in, the leader's
+            // for one starting from the log start. A broker that already had the partition
+            // keeps what it knew to be committed
+            if replicated && !known {
+                let high_watermark = log.log_start_offset();
+                log.set_high_watermark(high_watermark);
+            }
+            Ok(())
+        })
+    }
+
+    /// How often followers are checked for falling out of the ISR, as in Kafka half the
+    /// time they are allowed to lag.
+    pub fn isr_expiration_interval(&self) -> Duration {
+        Duration::from_millis((self.replica_lag_time_max_ms / 2).max(1) as u64)
+    }
+
+    /// The partition's in-sync replicas, if it is known.
+    pub fn in_sync_replicas(&self, topic_name: &Bytes, partition: i32) -> Option<Vec<i32>> {
+        let partitions = self.partitions.lock().expect("replica lock poisoned");
+        partitions
+            .get(&(topic_name.clone(), partition))
+            .map(|state| state.in_sync_replicas.clone())
+    }
+
+    /// Whether an acks=-1 write to the partition has to wait for followers.
+    pub fn has_in_sync_followers(&self, topic_name: &Bytes, partition: i32) -> bool {
+        self.in_sync_replicas(topic_name, partition)
+            .is_some_and(|isr| isr.iter().any(|replica| *replica != self.node_id))
+    }
+
+    pub fn leader_epoch(&self, topic_name: &Bytes, partition: i32) -> Option<i32> {
+        self.leader(topic_name, partition).map(|(_, epoch)| epoch)
+    }
+
+    /// The partition's leader and leader epoch, if it is known.
+    pub fn leader(&self, topic_name: &Bytes, partition: i32) -> Option<(i32, i32)> {
+        let partitions = self.partitions.lock().expect("replica lock poisoned");
+        partitions
+            .get(&(topic_name.clone(), partition))
+            .map(|state| (state.leader, state.leader_epoch))
+    }
+
+    /// Checks that this broker leads the partition, the only one to take writes for it or
+    /// serve it to consumers.
+    pub fn check_leader(&self, topic_name: &Bytes, partition: i32) -> Result<(), ErrorCode> {
+        match self.leader(topic_name, partition) {
+            Some((leader, _)) if leader != self.node_id => Err(ErrorCode::NotLeaderOrFollower),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the leader epoch a client or follower believes the partition is in, -1 when
+    /// it does not know. An older one means it missed a leader change, a newer one that
+    /// this broker has yet to learn of it.
+    pub fn check_leader_epoch(
+        &self,
+        topic_name: &Bytes,
+        partition: i32,
+        current_leader_epoch: i32,
+    ) -> Result<(), ErrorCode> {
+        if current_leader_epoch < 0 {
+            return Ok(());
+        }
+
+        match self.leader_epoch(topic_name, partition) {
+            Some(epoch) if current_leader_epoch < epoch => Err(ErrorCode::FencedLeaderEpoch),
+            Some(epoch) if current_leader_epoch > epoch => Err(ErrorCode::UnknownLeaderEpoch),
+            _ => Ok(()),
+        }
+    }
+
+    /// Partitions this broker follows, with the leader to fetch each from.
+    pub fn followed_partitions(&self) -> Vec<FollowedPartition> {
+        let partitions = self.partitions.lock().expect("replica lock poisoned");
+        partitions
+            .iter()
+            .filter(|(_, state)| {
+                state.replicas.contains(&self.node_id)
+                    && state.leader != self.node_id
+                    && state.leader >= 0
+            })
+            .map(|((topic_name, partition), state)| FollowedPartition {
+                topic_name: topic_name.clone(),
+                topic_id: state.topic_id,
+                partition: *partition,
+                leader: state.leader,
+                leader_epoch: state.leader_epoch,
+            })
+            .collect()
+    }
+
+    /// Records a follower's fetch from `fetch_offset`, which tells the leader the follower
+    /// has everything before it. The follower rejoins the ISR once it has caught up to the
+    /// high watermark and to the start of the leader's epoch, so it holds no batches of an
+    /// older leader the current one does not have, and the high watermark moves up to what
+    /// the whole ISR has.
+    pub fn record_follower_fetch(
+        &self,
+        topic_name: &Bytes,
+        partition: i32,
+        replica_id: i32,
+        fetch_offset: i64,
+    ) -> Result<(), ErrorCode> {
+        let key = (topic_name.clone(), partition);
+        let (log_end_offset, high_watermark, epoch_start_offset) = self
+            .logs
+            .with_partition(topic_name, partition, |log| {
+                Ok((
+                    log.log_end_offset(),
+                    log.high_watermark(),
+                    log.leader_epoch_start_offset(),
+                ))
+            })
+            .map_err(|err| {
+                eprintln!("reading {topic_name:?}-{partition}: {err:#}");
+                ErrorCode::KafkaStorageError
+            })?;
+
+        let mut partitions = self.partitions.lock().expect("replica lock poisoned");
+        let state = partitions
+            .get_mut(&key)
+            .filter(|state| state.leader == self.node_id)
+            .ok_or(ErrorCode::NotLeaderOrFollower)?;
+        let follower = state
+            .followers
+            .get_mut(&replica_id)
+            .ok_or(ErrorCode::NotLeaderOrFollower)?;
+
+        follower.log_end_offset = Some(fetch_offset);
+        if fetch_offset >= log_end_offset {
+            follower.last_caught_up_ms = current_time_ms();
+        }
+
+        let caught_up = fetch_offset >= high_watermark
+            && epoch_start_offset.is_some_and(|start_offset| fetch_offset >= start_offset);
+        if !state.in_sync_replicas.contains(&replica_id) && caught_up {
+            let mut isr = state.in_sync_replicas.clone();
+            isr.push(replica_id);
+            isr.sort_unstable();
+            if let Err(err) = self.change_isr(topic_name, partition, state, isr) {
+                eprintln!("expanding ISR of {topic_name:?}-{partition}: {err:#}");
+            }
+        }
+
+        self.update_high_watermark(topic_name, partition, state);
+        Ok(())
+    }
+
+    /// Moves the high watermark of a partition this broker leads after an append, which a
+    /// lone in-sync leader commits right away.
+    pub fn record_append(&self, topic_name: &Bytes, partition: i32) {
+        let partitions = self.partitions.lock().expect("replica lock poisoned");
+        if let Some(state) = partitions
+            .get(&(topic_name.clone(), partition))
+            .filter(|state| state.leader == self.node_id)
+        {
+            self.update_high_watermark(topic_name, partition, state);
+        }
+    }
+
+    /// Takes the high watermark a follower was sent by its leader, capped at its own log.
+    pub fn record_leader_high_watermark(
+        &self,
+        topic_name: &Bytes,
+        partition: i32,
+        high_watermark: i64,
+    ) -> Result<()> {
+        self.logs.with_partition(topic_name, partition, |log| {
+            let high_watermark = high_watermark.min(log.log_end_offset());
+            log.set_high_watermark(high_watermark);
+            Ok(())
+        })
+    }
+
+    /// Drops followers that have not caught up within `replica.lag.time.max.ms` from the
+    /// ISR of every partition this broker leads.
+    pub fn expire_lagging_followers(&self) {
+        let now = current_time_ms();
+        let mut partitions = self.partitions.lock().expect("replica lock poisoned");
+        for ((topic_name, partition), state) in partitions.iter_mut() {
+            if state.leader != self.node_id {
+                continue;
+            }
+
+            let isr = state
+                .in_sync_replicas
+                .iter()
+                .copied()
+                .filter(|replica| {
+                    state.followers.get(replica).is_none_or(|follower| {
+                        now - follower.last_caught_up_ms <= self.replica_lag_time_max_ms
+                    })
+                })
+                .collect::<Vec<_>>();
+            if isr.len() != state.in_sync_replicas.len()
+                && let Err(err) = self.change_isr(topic_name, *partition, state, isr)
+            {
+                eprintln!("shrinking ISR of {topic_name:?}-{partition}: {err:#}");
+            }
+
+            // Also picks up appends that bypassed produce, such as transaction markers
+            self.update_high_watermark(topic_name, *partition, state);
+        }
+    }
+
+    /// Waits until the high watermark reaches `offset`, so every in-sync replica has the
+    /// log up to it. Returns false if it is not there by `deadline`.
+    pub async fn wait_for_high_watermark(
+        &self,
+        topic_name: &Bytes,
+        partition: i32,
+        offset: i64,
+        deadline: tokio::time::Instant,
+    ) -> bool {
+        loop {
+            // Registered before checking so a move in between is not missed
+            let moved = self.high_watermark_moved.notified();
+            tokio::pin!(moved);
+            moved.as_mut().enable();
+
+            let high_watermark = self
+                .logs
+                .with_partition(topic_name, partition, |log| Ok(log.high_watermark()));
+            match high_watermark {
+                Ok(high_watermark) if high_watermark >= offset => return true,
+                Ok(_) => {}
+                Err(err) => {
+                    eprintln!("reading {topic_name:?}-{partition}: {err:#}");
+                    return false;
+                }
+            }
+
+            if tokio::time::timeout_at(deadline, moved).await.is_err() {
+                return false;
+            }
+        }
+    }
+
+    /// Sets the high watermark to the smallest log end offset of the ISR, never moving it
+    /// back.
+    fn update_high_watermark(&self, topic_name: &Bytes, partition: i32, state: &PartitionState) {
+        let moved = self.logs.with_partition(topic_name, partition, |log| {
+            let mut high_watermark = log.log_end_offset();
+            for replica in state.in_sync_replicas.iter() {
+                if let Some(follower) = state.followers.get(replica) {
+                    let log_end_offset = follower.log_end_offset.unwrap_or(i64::MIN);
+                    high_watermark = high_watermark.min(log_end_offset);
+                }
+            }
+
+            let current = log.high_watermark();
+            if high_watermark > current {
+                log.set_high_watermark(high_watermark);
+            }
+            Ok(high_watermark > current)
+        });
+
+        match moved {
+            Ok(true) => self.high_watermark_moved.notify_waiters(),
+            Ok(false) => {}
+            Err(err) => eprintln!("updating high watermark of {topic_name:?}-{partition}: {err:#}"),
+        }
+    }
+
+    /// Writes the new ISR to the metadata log as a `PartitionChangeRecord`, then applies it.
+    /// The partition epoch moves once the record is replayed from the metadata log.
+    ///
+    /// With the embedded quorum the change is sent to the active controller instead, and
+    /// applied once it is committed and replayed. Until then, or until the controller had
+    /// long enough to answer, no other change of the partition's ISR is sent.
+    fn change_isr(
+        &self,
+        topic_name: &Bytes,
+        partition: i32,
+        state: &mut PartitionState,
+        isr: Vec<i32>,
+    ) -> Result<()> {
+        if let MetadataWriter::Quorum(quorum) = &self.writer {
+            let now = current_time_ms();
+            if state
+                .pending_isr_change_ms
+                .is_some_and(|sent_ms| now - sent_ms < ALTER_PARTITION_RETRY_MS)
+            {
+                return Ok(());
+            }
+            state.pending_isr_change_ms = Some(now);
+
+            let quorum = Arc::clone(quorum);
+            let change = AlterPartition {
+                broker_id: self.node_id,
+                topic_id: state.topic_id,
+                partition,
+                leader_epoch: state.leader_epoch,
+                partition_epoch: state.partition_epoch,
+                isr,
+            };
+            let topic_name = topic_name.clone();
+            tokio::task::spawn(async move {
+                if let Err(err) = change.send(&quorum).await {
+                    eprintln!("changing ISR of {topic_name:?}-{partition}: {err:#}");
+                }
+            });
+            return Ok(());
+        }
+
+        let record = PartitionChangeRecord {
+            version: 0,
+            partition_id: partition,
+            uuid: state.topic_id,
+            in_sync_replica_ids: Some(isr.clone().into_boxed_slice()),
+            leader: PartitionChangeRecord::NO_LEADER_CHANGE,
+            replication_ids: None,
+        };
+
+        self.writer
+            .append(vec![Record::new(None, Some(record.encode()))])?;
+
+        eprintln!(
+            "ISR of {}-{partition} changed from {:?} to {isr:?}",
+            String::from_utf8_lossy(topic_name),
+            state.in_sync_replicas
+        );
+        state.in_sync_replicas = isr;
+        Ok(())
+    }
+}
+
+/// An ISR change a partition leader sends the active controller.
+#[derive(Debug)]
+struct AlterPartition {
+    broker_id: i32,
+    topic_id: Uuid,
+    partition: i32,
+    leader_epoch: i32,
+    partition_epoch: i32,
+    isr: Vec<i32>,
+}
+
+impl AlterPartition {
+    /// Sends the change with AlterPartition, returning once the controller committed it.
+    async fn send(&self, quorum: &RaftQuorum) -> Result<()> {
+        let mut body = BytesMut::new();
+        body.put_i32(self.broker_id);
+        // Broker epoch, which the controller does not check
+        body.put_i64(-1);
+        unsigned_varint_encode(&mut body, 1);
+        body.put_u128(self.topic_id.as_u128());
+        unsigned_varint_encode(&mut body, 1);
+        body.put_i32(self.partition);
+        body.put_i32(self.leader_epoch);
+        unsigned_varint_encode(&mut body, self.isr.len());
+        for replica in self.isr.iter() {
+            body.put_i32(*replica);
+        }
+        // Leader recovery state, recovered
+        body.put_i8(0);
+        body.put_i32(self.partition_epoch);
+        // Tags
+        body.put_i8(0x00);
+        body.put_i8(0x00);
+        body.put_i8(0x00);
+
+        let mut response = quorum
+            .call_leader(ApiType::AlterPartition, ALTER_PARTITION_VERSION, &body)
+            .await?;
+        let _throttle_time = response.try_get_i32()?;
+        let mut error_code = response.try_get_i16()?;
+        if error_code == ErrorCode::None as i16 {
+            if read_compact_len(&mut response)? != 1 {
+                bail!("expected the result of one topic");
+            }
+            let _topic_id = response.try_get_u128()?;
+            if read_compact_len(&mut response)? != 1 {
+                bail!("expected the result of one partition");
+            }
+            let _partition = response.try_get_i32()?;
+            error_code = response.try_get_i16()?;
+        }
+        if error_code != ErrorCode::None as i16 {
+            bail!("controller returned error {error_code}");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+use crate::{
+    cluster::BrokerRegistry,
+    config::BrokerConfig,
+    leader_epoch::UNDEFINED_EPOCH,
+    log::LogManager,
+    replica::{FollowedPartition, ReplicaManager},
+    request::{
+        ApiType, TryGet, read_compact_len, read_compact_nullable_string, read_uvarint,
+        skip_tagged_fields,
+    },
+    unsigned_varint_encode,
+};
+use anyhow::{Context, Result, bail};
+use bytes::{BufMut, Bytes, BytesMut};
+use futures_util::future::join_all;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use uuid::Uuid;
+
+use std::{collections::HashMap, sync::Arc};
+
+// Last Fetch version with the replica id in the body rather than a tagged field
+const FETCH_VERSION: i16 = 14;
+const CLIENT_ID: &[u8] = b"replica-fetcher";
+// Partition response tagged field with the leader's end of the epoch the logs diverged in
+const DIVERGING_EPOCH_TAG: u32 = 0;
+// Kafka's replica.fetch.max.bytes and replica.fetch.response.max.bytes defaults
+const DEFAULT_FETCH_MAX_BYTES: i32 = 1048576;
+const DEFAULT_FETCH_RESPONSE_MAX_BYTES: i32 = 10485760;
+// Kafka's replica.fetch.wait.max.ms and replica.fetch.min.bytes defaults
+const DEFAULT_FETCH_WAIT_MAX_MS: i32 = 500;
+const DEFAULT_FETCH_MIN_BYTES: i32 = 1;
+
+/// Copies the partitions this broker follows from their leaders, fetching as replica
+/// `node.id` so the leader counts the fetch offsets towards its high watermark and ISR.
+/// One connection is kept per leader, and leaders are fetched from side by side since each
+/// holds the fetch until it has records.
+#[derive(Debug)]
+pub struct ReplicaFetcher {
+    node_id: i32,
+    brokers: Arc<BrokerRegistry>,
+    logs: Arc<LogManager>,
+    replicas: Arc<ReplicaManager>,
+    fetch_max_bytes: i32,
+    fetch_response_max_bytes: i32,
+    fetch_wait_max_ms: i32,
+    fetch_min_bytes: i32,
+    connections: HashMap<i32, TcpStream>,
+    correlation_id: i32,
+}
+
+impl ReplicaFetcher {
+    pub fn new(
+        config: &BrokerConfig,
+        brokers: Arc<BrokerRegistry>,
+        logs: Arc<LogManager>,
+        replicas: Arc<ReplicaManager>,
+    ) -> Self {
+        let property = |key: &str, default: i32| {
+            config
+                .property(key)
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+
+        Self {
+            node_id: config.node_id,
+            brokers,
+            logs,
+            replicas,
+            fetch_max_bytes: property("replica.fetch.max.bytes", DEFAULT_FETCH_MAX_BYTES),
+            fetch_response_max_bytes: property(
+                "replica.fetch.response.max.bytes",
+                DEFAULT_FETCH_RESPONSE_MAX_BYTES,
+            ),
+            fetch_wait_max_ms: property("replica.fetch.wait.max.ms", DEFAULT_FETCH_WAIT_MAX_MS),
+            fetch_min_bytes: property("replica.fetch.min.bytes", DEFAULT_FETCH_MIN_BYTES),
+            connections: HashMap::new(),
+            correlation_id: 0,
+        }
+    }
+
+    /// Fetches once from every leader this broker follows a partition of.
+    pub async fn fetch_all(&mut self) {
+        let mut by_leader: HashMap<i32, Vec<FollowedPartition>> = HashMap::new();
+        for partition in self.replicas.followed_partitions() {
+            by_leader
+                .entry(partition.leader)
+                .or_default()
+                .push(partition);
+        }
+        self.connections
+            .retain(|leader, _| by_leader.contains_key(leader));
+
+        // Each leader's fetch runs on its own connection, so one request id serves them all
+        self.correlation_id = self.correlation_id.wrapping_add(1);
+        let mut connections = std::mem::take(&mut self.connections);
+        let this = &*self;
+        let fetches = by_leader.into_iter().map(|(leader, partitions)| {
+            let mut connection = connections.remove(&leader);
+            async move {
+                let result = this.fetch_from(leader, &partitions, &mut connection).await;
+                (leader, connection, result)
+            }
+        });
+
+        for (leader, connection, result) in join_all(fetches).await {
+            match (result, connection) {
+                (Ok(()), Some(connection)) => {
+                    self.connections.insert(leader, connection);
+                }
+                (Ok(()), None) => {}
+                // Reconnect on the next round
+                (Err(err), _) => eprintln!("fetching from leader {leader}: {err:#}"),
+            }
+        }
+    }
+
+    async fn fetch_from(
+        &self,
+        leader: i32,
+        partitions: &[FollowedPartition],
+        connection: &mut Option<TcpStream>,
+    ) -> Result<()> {
+        let mut fetch_offsets = HashMap::new();
+        for partition in partitions {
+            let offsets =
+                self.logs
+                    .with_partition(&partition.topic_name, partition.partition, |log| {
+                        let last_fetched_epoch = log.latest_epoch().unwrap_or(UNDEFINED_EPOCH);
+                        Ok((
+                            log.log_end_offset(),
+                            last_fetched_epoch,
+                            log.log_start_offset(),
+                        ))
+                    })?;
+            fetch_offsets.insert((partition.topic_id, partition.partition), offsets);
+        }
+
+        let request = self.fetch_request(partitions, &fetch_offsets);
+        let mut response = self.send(leader, connection, request).await?;
+
+        // Header
+        let correlation_id = response.try_get_i32()?;
+        if correlation_id != self.correlation_id {
+            bail!(
+                "response to request {correlation_id}, expected {}",
+                self.correlation_id
+            );
+        }
+        skip_tagged_fields(&mut response)?;
+
+        let _throttle_time = response.try_get_i32()?;
+        let error_code = response.try_get_i16()?;
+        if error_code != 0 {
+            bail!("fetch failed with error {error_code}");
+        }
+        let _session_id = response.try_get_i32()?;
+
+        let topics_len = read_compact_len(&mut response)?;
+        for _ in 0..topics_len {
+            let topic_id = Uuid::from_u128(response.try_get_u128()?);
+            let partitions_len = read_compact_len(&mut response)?;
+            for _ in 0..partitions_len {
+                let fetched = FetchedPartition::decode(&mut response)?;
+                let Some(partition) = partitions
+                    .iter()
+                    .find(|p| p.topic_id == topic_id && p.partition == fetched.partition)
+                else {
+                    continue;
+                };
+
+                if let Err(err) = self.apply(partition, fetched) {
+                    eprintln!(
+                        "replicating {:?}-{}: {err:#}",
+                        partition.topic_name, partition.partition
+                    );
+                }
+            }
+            skip_tagged_fields(&mut response)?;
+        }
+
+        Ok(())
+    }
+
+    /// Appends what the leader sent and takes over its high watermark.
+    fn apply(&self, partition: &FollowedPartition, fetched: FetchedPartition) -> Result<()> {
+        if fetched.error_code != 0 {
+            bail!("leader returned error {}", fetched.error_code);
+        }
+
+        let topic_name = &partition.topic_name;
+        if let Some((epoch, end_offset)) = fetched.diverging_epoch {
+            return self.truncate(partition, epoch, end_offset);
+        }
+        if let Some(records) = fetched.records.filter(|records| !records.is_empty()) {
+            self.logs
+                .append_replicated(topic_name, partition.partition, records)?;
+        }
+        self.replicas.record_leader_high_watermark(
+            topic_name,
+            partition.partition,
+            fetched.high_watermark,
+        )
+    }
+
+    /// Truncates the log where it stops matching the leader's, given the leader's end offset
+    /// for the latest epoch both might share. The next fetch checks the result again.
+    fn truncate(&self, partition: &FollowedPartition, epoch: i32, end_offset: i64) -> Result<()> {
+        let topic_name = &partition.topic_name;
+        self.logs
+            .with_partition(topic_name, partition.partition, |log| {
+                let offset = match epoch {
+                    // The leader has nothing that old, so none of this log can be trusted
+                    UNDEFINED_EPOCH => log.log_start_offset(),
+                    _ => {
+                        let (_, local_end_offset) = log.end_offset_for_epoch(epoch);
+                        end_offset.min(local_end_offset).max(log.log_start_offset())
+                    }
+                };
+
+                eprintln!(
+                    "truncating {}-{} from {} to {offset}, diverged from leader at epoch {epoch}",
+                    String::from_utf8_lossy(topic_name),
+                    partition.partition,
+                    log.log_end_offset()
+                );
+                log.truncate_to(offset)
+            })
+            .context("truncating diverged log")
+    }
+
+    fn fetch_request(
+        &self,
+        partitions: &[FollowedPartition],
+        fetch_offsets: &HashMap<(Uuid, i32), (i64, i32, i64)>,
+    ) -> BytesMut {
+        let mut topics: Vec<(Uuid, Vec<&FollowedPartition>)> = Vec::new();
+        for partition in partitions {
+            match topics.iter_mut().find(|(id, _)| *id == partition.topic_id) {
+                Some((_, partitions)) => partitions.push(partition),
+                None => topics.push((partition.topic_id, vec![partition])),
+            }
+        }
+
+        let mut buf = BytesMut::new();
+        buf.put_i16(ApiType::Fetch as i16);
+        buf.put_i16(FETCH_VERSION);
+        buf.put_i32(self.correlation_id);
+        buf.put_i16(CLIENT_ID.len() as i16);
+        buf.put_slice(CLIENT_ID);
+        // Tags
+        buf.put_i8(0x00);
+
+        buf.put_i32(self.node_id);
+        buf.put_i32(self.fetch_wait_max_ms);
+        buf.put_i32(self.fetch_min_bytes);
+        buf.put_i32(self.fetch_response_max_bytes);
+        // Isolation level, followers copy everything
+        buf.put_i8(0);
+        // Sessionless full fetch
+        buf.put_i32(0);
+        buf.put_i32(-1);
+
+        unsigned_varint_encode(&mut buf, topics.len());
+        for (topic_id, partitions) in topics {
+            buf.put_u128(topic_id.as_u128());
+            unsigned_varint_encode(&mut buf, partitions.len());
+            for partition in partitions {
+                let (log_end_offset, last_fetched_epoch, log_start_offset) =
+                    fetch_offsets[&(partition.topic_id, partition.partition)];
+                buf.put_i32(partition.partition);
+                buf.put_i32(partition.leader_epoch);
+                buf.put_i64(log_end_offset);
+                buf.put_i32(last_fetched_epoch);
+                buf.put_i64(log_start_offset);
+                buf.put_i32(self.fetch_max_bytes);
+                // Tags
+                buf.put_i8(0x00);
+            }
+            // Tags
+            buf.put_i8(0x00);
+        }
+        // Forgotten topics
+        unsigned_varint_encode(&mut buf, 0);
+        // Rack id
+        unsigned_varint_encode(&mut buf, 0);
+        // Tags
+        buf.put_i8(0x00);
+
+        buf
+    }
+
+    /// Sends a request to the leader over its connection, opening one first if needed, and
+    /// returns the response without its size prefix.
+    async fn send(
+        &self,
+        leader: i32,
+        connection: &mut Option<TcpStream>,
+        request: BytesMut,
+    ) -> Result<Bytes> {
+        let stream = match connection {
+            Some(stream) => stream,
+            None => {
+                let broker = self
+                    .brokers
+                    .brokers(true)
+                    .into_iter()
+                    .find(|broker| broker.id == leader)
+                    .with_context(|| format!("leader {leader} is not registered"))?;
+                let endpoint = broker
+                    .endpoint(self.brokers.listener_name())
+                    .with_context(|| format!("leader {leader} has no matching listener"))?;
+                let address = format!(
+                    "{}:{}",
+                    String::from_utf8_lossy(&endpoint.host),
+                    endpoint.port
+                );
+                let stream = TcpStream::connect(&address)
+                    .await
+                    .with_context(|| format!("connecting to {address}"))?;
+                connection.insert(stream)
+            }
+        };
+
+        stream.write_i32(request.len() as i32).await?;
+        stream.write_all(&request).await?;
+
+        let size = stream.read_i32().await.context("reading response size")?;
+        let mut response = vec![0; size.max(0) as usize];
+        stream
+            .read_exact(&mut response)
+            .await
+            .context("reading response")?;
+
+        Ok(Bytes::from(response))
+    }
+}
+
+#[derive(Debug)]
+struct FetchedPartition {
+    partition: i32,
+    error_code: i16,
+    high_watermark: i64,
+    records: Option<Bytes>,
+    diverging_epoch: Option<(i32, i64)>,
+}
+
+impl FetchedPartition {
+    fn decode(buf: &mut Bytes) -> Result<Self> {
+        let partition = buf.try_get_i32()?;
+        let error_code = buf.try_get_i16()?;
+        let high_watermark = buf.try_get_i64()?;
+        let _last_stable_offset = buf.try_get_i64()?;
+        let _log_start_offset = buf.try_get_i64()?;
+
+        // Aborted transactions, null for read_uncommitted fetches
+        let aborted_len = read_compact_len(buf)?;
+        for _ in 0..aborted_len {
+            buf.try_advance(8 + 8)?;
+            skip_tagged_fields(buf)?;
+        }
+        let _preferred_read_replica = buf.try_get_i32()?;
+        let records = read_compact_nullable_string(buf)?;
+
+        let mut diverging_epoch = None;
+        let tags_len = read_uvarint(buf)?;
+        for _ in 0..tags_len {
+            let tag = read_uvarint(buf)?;
+            let size = read_uvarint(buf)?;
+            let mut field = buf.try_split_to(size as usize)?;
+            if tag == DIVERGING_EPOCH_TAG {
+                diverging_epoch = Some((field.try_get_i32()?, field.try_get_i64()?));
+            }
+        }
+
+        Ok(Self {
+            partition,
+            error_code,
+            high_watermark,
+            records,
+            diverging_epoch,
+        })
+    }
+}
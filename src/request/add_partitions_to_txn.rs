@@ -0,0 +1,164 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    cluster::BrokerRegistry,
+    metadata::RecordBatch,
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_string, skip_tagged_fields, write_compact_string,
+    },
+    txn::{TransactionCoordinator, transaction_partition_for},
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct AddPartitionsToTxnRequest {
+    header: RequestHeader,
+    metadata: Arc<Box<[RecordBatch]>>,
+    transactions: Arc<TransactionCoordinator>,
+    brokers: Arc<BrokerRegistry>,
+    transactional_id: Bytes,
+    producer_id: i64,
+    producer_epoch: i16,
+    topics: Box<[(Bytes, Box<[i32]>)]>,
+}
+
+impl AddPartitionsToTxnRequest {
+    pub fn new(
+        req: Request,
+        metadata: Arc<Box<[RecordBatch]>>,
+        transactions: Arc<TransactionCoordinator>,
+        brokers: Arc<BrokerRegistry>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+        let transactional_id = read_compact_string(&mut payload)?;
+        let producer_id = payload.try_get_i64()?;
+        let producer_epoch = payload.try_get_i16()?;
+
+        let topics_len = read_compact_len(&mut payload)?;
+        let topics = (0..topics_len)
+            .map(|_| {
+                let name = read_compact_string(&mut payload)?;
+                let partitions_len = read_compact_len(&mut payload)?;
+                let partitions = (0..partitions_len)
+                    .map(|_| payload.try_get_i32())
+                    .collect::<Result<Vec<i32>>>()?;
+                skip_tagged_fields(&mut payload)?;
+
+                Ok((name, partitions.into_boxed_slice()))
+            })
+            .collect::<Result<Vec<(Bytes, Box<[i32]>)>>>()?;
+
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            metadata,
+            transactions,
+            brokers,
+            transactional_id,
+            producer_id,
+            producer_epoch,
+            topics: topics.into_boxed_slice(),
+        })
+    }
+
+    fn partition_exists(&self, topic_name: &Bytes, index: i32) -> bool {
+        self.metadata.iter().any(|record| {
+            record
+                .get_topic_uuid(topic_name)
+                .is_some_and(|uuid| record.valid_partition(&uuid, index))
+        })
+    }
+
+    /// Unknown partitions fail the whole request, the rest being reported as
+    /// `OPERATION_NOT_ATTEMPTED` as Kafka does. Every partition fails when this broker does
+    /// not coordinate the transactional id.
+    fn partition_errors(&self) -> Vec<(Bytes, i32, ErrorCode)> {
+        let requested = self.topics.iter().flat_map(|(name, partitions)| {
+            partitions.iter().map(move |index| (name.clone(), *index))
+        });
+
+        if let Err(error_code) = self
+            .brokers
+            .check_coordinator(transaction_partition_for(&self.transactional_id))
+        {
+            return requested
+                .map(|(name, index)| (name, index, error_code))
+                .collect();
+        }
+
+        let unknown = requested
+            .clone()
+            .any(|(name, index)| !self.partition_exists(&name, index));
+        if unknown {
+            return requested
+                .map(|(name, index)| {
+                    let error_code = if self.partition_exists(&name, index) {
+                        ErrorCode::OperationNotAttempted
+                    } else {
+                        ErrorCode::UnknownTopicOrPartition
+                    };
+                    (name, index, error_code)
+                })
+                .collect();
+        }
+
+        let partitions: Vec<(Bytes, i32)> = requested.collect();
+        let error_code = match self.transactions.add_partitions(
+            &self.transactional_id,
+            self.producer_id,
+            self.producer_epoch,
+            &partitions,
+        ) {
+            Ok(()) => ErrorCode::None,
+            Err(error_code) => error_code,
+        };
+
+        partitions
+            .into_iter()
+            .map(|(name, index)| (name, index, error_code))
+            .collect()
+    }
+}
+
+impl IntoResponse for AddPartitionsToTxnRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+        let errors = self.partition_errors();
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+
+        unsigned_varint_encode(&mut content, self.topics.len());
+        for (topic_name, partitions) in self.topics.iter() {
+            write_compact_string(&mut content, topic_name);
+            unsigned_varint_encode(&mut content, partitions.len());
+            for index in partitions.iter() {
+                let error_code = errors
+                    .iter()
+                    .find(|(name, idx, _)| name == topic_name && idx == index)
+                    .map(|(_, _, error_code)| *error_code)
+                    .unwrap_or(ErrorCode::Unknown);
+
+                content.put_i32(*index);
+                content.put_i16(error_code as i16);
+                // Tags
+                content.put_i8(0x00);
+            }
+
+            content.put_i8(0x00);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
@@ -0,0 +1,109 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, BytesMut};
+
+use crate::{
+    metadata::ProducerIdsRecord,
+    producer::PRODUCER_ID_BLOCK_SIZE,
+    raft::{COMMIT_TIMEOUT, RaftQuorum},
+    record::Record,
+    request::{ErrorCode, IntoDelayedResponse, Request, RequestHeader, TryGet, skip_tagged_fields},
+};
+
+use std::sync::Arc;
+
+/// A broker asking the active controller for a block of producer ids. The block starts
+/// where the last one written to the metadata log ended, and is handed out once its
+/// `ProducerIdsRecord` is committed.
+#[derive(Debug)]
+pub struct AllocateProducerIdsRequest {
+    header: RequestHeader,
+    quorum: Arc<RaftQuorum>,
+    broker_id: i32,
+    broker_epoch: i64,
+}
+
+impl AllocateProducerIdsRequest {
+    pub fn new(req: Request, quorum: Arc<RaftQuorum>) -> Result<Self> {
+        let mut payload = req.payload;
+
+        let broker_id = payload.try_get_i32()?;
+        let broker_epoch = payload.try_get_i64()?;
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            quorum,
+            broker_id,
+            broker_epoch,
+        })
+    }
+
+    /// Hands out no block.
+    pub fn error_response(header: &RequestHeader, error_code: ErrorCode) -> BytesMut {
+        allocation_response(header, error_code, -1, 0)
+    }
+
+    /// Writes the next block and waits for it to be committed, returning where it starts.
+    async fn allocate(&self) -> Result<i64, ErrorCode> {
+        let mut start = 0;
+        let appended = self.quorum.append_after(|metadata, _| {
+            start = metadata
+                .iter()
+                .filter_map(|batch| batch.next_producer_id())
+                .max()
+                .unwrap_or(0);
+            let record = ProducerIdsRecord {
+                version: 0,
+                broker_id: self.broker_id,
+                broker_epoch: self.broker_epoch,
+                next_producer_id: start + PRODUCER_ID_BLOCK_SIZE,
+                tags: 0,
+            };
+            Ok(vec![Record::new(None, Some(record.encode()))])
+        })?;
+
+        let deadline = tokio::time::Instant::now() + COMMIT_TIMEOUT;
+        if !self.quorum.wait_for_commit(appended + 1, deadline).await {
+            return Err(ErrorCode::RequestTimedOut);
+        }
+
+        Ok(start)
+    }
+}
+
+impl IntoDelayedResponse for AllocateProducerIdsRequest {
+    async fn response(self) -> BytesMut {
+        match self.allocate().await {
+            Ok(start) => allocation_response(
+                &self.header,
+                ErrorCode::None,
+                start,
+                PRODUCER_ID_BLOCK_SIZE as i32,
+            ),
+            Err(error_code) => Self::error_response(&self.header, error_code),
+        }
+    }
+}
+
+fn allocation_response(
+    header: &RequestHeader,
+    error_code: ErrorCode,
+    producer_id_start: i64,
+    producer_id_len: i32,
+) -> BytesMut {
+    let mut content = BytesMut::new();
+    let throttle_time = 0;
+
+    content.put_i32(header.correlation_id);
+    content.put_i8(0x00);
+    content.put_i32(throttle_time);
+    content.put_i16(error_code as i16);
+    content.put_i64(producer_id_start);
+    content.put_i32(producer_id_len);
+
+    content.put_i8(0x00);
+
+    content
+}
@@ -0,0 +1,134 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    config::{ConfigError, ConfigManager, ConfigOperation},
+    request::{
+        ErrorCode, IntoDelayedResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_nullable_string, read_compact_string, skip_tagged_fields,
+        write_compact_nullable_string, write_compact_string,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+struct Resource {
+    resource_type: i8,
+    resource_name: Bytes,
+    configs: Box<[(Bytes, Option<Bytes>)]>,
+}
+
+/// Replaces every dynamic config of the named resources, those left out going back to
+/// their default.
+#[derive(Debug)]
+pub struct AlterConfigsRequest {
+    header: RequestHeader,
+    configs: Arc<ConfigManager>,
+    resources: Box<[Resource]>,
+    validate_only: bool,
+}
+
+impl AlterConfigsRequest {
+    pub fn new(req: Request, configs: Arc<ConfigManager>) -> Result<Self> {
+        let mut payload = req.payload;
+
+        let resources_len = read_compact_len(&mut payload)?;
+        let resources = (0..resources_len)
+            .map(|_| {
+                let resource_type = payload.try_get_i8()?;
+                let resource_name = read_compact_string(&mut payload)?;
+                let configs_len = read_compact_len(&mut payload)?;
+                let configs = (0..configs_len)
+                    .map(|_| {
+                        let name = read_compact_string(&mut payload)?;
+                        let value = read_compact_nullable_string(&mut payload)?;
+                        skip_tagged_fields(&mut payload)?;
+                        Ok((name, value))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                skip_tagged_fields(&mut payload)?;
+
+                Ok(Resource {
+                    resource_type,
+                    resource_name,
+                    configs: configs.into_boxed_slice(),
+                })
+            })
+            .collect::<Result<Vec<Resource>>>()?;
+        let validate_only = payload.try_get_i8()? != 0;
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            configs,
+            resources: resources.into_boxed_slice(),
+            validate_only,
+        })
+    }
+}
+
+/// Writes the outcome of altering one resource, as both AlterConfigs and
+/// IncrementalAlterConfigs report it.
+pub fn write_alter_result(
+    content: &mut BytesMut,
+    resource_type: i8,
+    resource_name: &[u8],
+    result: Result<(), ConfigError>,
+) {
+    let (error_code, error_message) = match result {
+        Ok(()) => (ErrorCode::None, None),
+        Err(err) => (err.error_code, Some(err.message)),
+    };
+
+    content.put_i16(error_code as i16);
+    write_compact_nullable_string(content, error_message.as_deref().map(str::as_bytes));
+    content.put_i8(resource_type);
+    write_compact_string(content, resource_name);
+    // Tags
+    content.put_i8(0x00);
+}
+
+impl IntoDelayedResponse for AlterConfigsRequest {
+    async fn response(self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+
+        unsigned_varint_encode(&mut content, self.resources.len());
+        for resource in self.resources.iter() {
+            let changes = resource
+                .configs
+                .iter()
+                .map(|(name, value)| (name.clone(), ConfigOperation::Set, value.clone()))
+                .collect::<Vec<_>>();
+            let result = self
+                .configs
+                .alter(
+                    resource.resource_type,
+                    &resource.resource_name,
+                    &changes,
+                    true,
+                    self.validate_only,
+                )
+                .await;
+
+            write_alter_result(
+                &mut content,
+                resource.resource_type,
+                &resource.resource_name,
+                result,
+            );
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
@@ -0,0 +1,255 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, BytesMut};
+use uuid::Uuid;
+
+use crate::{
+    metadata::{PartitionChangeRecord, RecordBatch as MetadataBatch},
+    raft::{COMMIT_TIMEOUT, RaftQuorum},
+    record::Record,
+    request::{
+        ErrorCode, IntoDelayedResponse, Request, RequestHeader, TryGet, read_compact_len,
+        skip_tagged_fields,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+struct PartitionRequest {
+    partition: i32,
+    leader_epoch: i32,
+    new_isr: Box<[i32]>,
+    partition_epoch: i32,
+}
+
+// The partition's leader, leader epoch and partition epoch once the change is committed
+type AlteredPartition = (i32, i32, i32);
+
+/// A partition leader asking the active controller to change the ISR, which it writes as a
+/// `PartitionChangeRecord`. The change is refused when the leader is not the partition's
+/// current one or missed an earlier change, told by the leader and partition epochs.
+#[derive(Debug)]
+pub struct AlterPartitionRequest {
+    header: RequestHeader,
+    quorum: Arc<RaftQuorum>,
+    broker_id: i32,
+    topics: Box<[(Uuid, Box<[PartitionRequest]>)]>,
+}
+
+impl AlterPartitionRequest {
+    pub fn new(req: Request, quorum: Arc<RaftQuorum>) -> Result<Self> {
+        let mut payload = req.payload;
+
+        let broker_id = payload.try_get_i32()?;
+        let _broker_epoch = payload.try_get_i64()?;
+        let topics_len = read_compact_len(&mut payload)?;
+        let topics = (0..topics_len)
+            .map(|_| {
+                let topic_id = Uuid::from_u128(payload.try_get_u128()?);
+                let partitions_len = read_compact_len(&mut payload)?;
+                let partitions = (0..partitions_len)
+                    .map(|_| {
+                        let partition = payload.try_get_i32()?;
+                        let leader_epoch = payload.try_get_i32()?;
+                        let isr_len = read_compact_len(&mut payload)?;
+                        let new_isr = (0..isr_len)
+                            .map(|_| payload.try_get_i32())
+                            .collect::<Result<Vec<_>, _>>()?;
+                        let _leader_recovery_state = payload.try_get_i8()?;
+                        let partition_epoch = payload.try_get_i32()?;
+                        skip_tagged_fields(&mut payload)?;
+                        Ok(PartitionRequest {
+                            partition,
+                            leader_epoch,
+                            new_isr: new_isr.into_boxed_slice(),
+                            partition_epoch,
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                skip_tagged_fields(&mut payload)?;
+                Ok((topic_id, partitions.into_boxed_slice()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            quorum,
+            broker_id,
+            topics: topics.into_boxed_slice(),
+        })
+    }
+
+    /// Refuses every change, with an error for the whole request.
+    pub fn error_response(header: &RequestHeader, error_code: ErrorCode) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        content.put_i32(header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+        content.put_i16(error_code as i16);
+        unsigned_varint_encode(&mut content, 0);
+
+        content.put_i8(0x00);
+
+        content
+    }
+
+    /// Checks the change against the partition as the metadata leaves it, and builds its
+    /// record.
+    fn change(
+        &self,
+        metadata: &[MetadataBatch],
+        topic_id: Uuid,
+        request: &PartitionRequest,
+    ) -> Result<(Vec<Record>, AlteredPartition), ErrorCode> {
+        let (leader, leader_epoch, partition_epoch) =
+            partition_state(metadata, topic_id, request.partition)
+                .ok_or(ErrorCode::UnknownTopicOrPartition)?;
+        if leader != self.broker_id {
+            return Err(ErrorCode::NotLeaderOrFollower);
+        }
+        if request.leader_epoch != leader_epoch {
+            return Err(ErrorCode::FencedLeaderEpoch);
+        }
+        if request.partition_epoch != partition_epoch {
+            return Err(ErrorCode::InvalidUpdateVersion);
+        }
+
+        let record = PartitionChangeRecord {
+            version: 0,
+            partition_id: request.partition,
+            uuid: topic_id,
+            in_sync_replica_ids: Some(request.new_isr.clone()),
+            leader: PartitionChangeRecord::NO_LEADER_CHANGE,
+            replication_ids: None,
+        };
+        let records = vec![Record::new(None, Some(record.encode()))];
+        Ok((records, (leader, leader_epoch, partition_epoch + 1)))
+    }
+
+    /// Writes every change that checks out and waits for them to be committed.
+    async fn alter(&self) -> Vec<Vec<Result<AlteredPartition, ErrorCode>>> {
+        let mut last_offset = None;
+        let mut results = Vec::new();
+        for (topic_id, partitions) in self.topics.iter() {
+            let mut topic = Vec::new();
+            for request in partitions.iter() {
+                let mut altered = None;
+                let appended = self.quorum.append_after(|metadata, _| {
+                    let (records, partition) = self.change(metadata, *topic_id, request)?;
+                    altered = Some(partition);
+                    Ok(records)
+                });
+                topic.push(appended.map(|offset| {
+                    last_offset = Some(offset);
+                    altered.expect("appended changes are checked")
+                }));
+            }
+            results.push(topic);
+        }
+
+        if let Some(offset) = last_offset {
+            let deadline = tokio::time::Instant::now() + COMMIT_TIMEOUT;
+            if !self.quorum.wait_for_commit(offset + 1, deadline).await {
+                for result in results.iter_mut().flatten() {
+                    if result.is_ok() {
+                        *result = Err(ErrorCode::RequestTimedOut);
+                    }
+                }
+            }
+        }
+
+        results
+    }
+}
+
+impl IntoDelayedResponse for AlterPartitionRequest {
+    async fn response(self) -> BytesMut {
+        if !self.quorum.is_leader() {
+            return Self::error_response(&self.header, ErrorCode::NotController);
+        }
+
+        let results = self.alter().await;
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+        content.put_i16(ErrorCode::None as i16);
+        unsigned_varint_encode(&mut content, self.topics.len());
+        for ((topic_id, partitions), results) in self.topics.iter().zip(results) {
+            content.put_u128(topic_id.as_u128());
+            unsigned_varint_encode(&mut content, partitions.len());
+            for (request, result) in partitions.iter().zip(results) {
+                let (error_code, (leader, leader_epoch, partition_epoch)) = match result {
+                    Ok(altered) => (ErrorCode::None, altered),
+                    Err(error_code) => (error_code, (-1, -1, -1)),
+                };
+                content.put_i32(request.partition);
+                content.put_i16(error_code as i16);
+                content.put_i32(leader);
+                content.put_i32(leader_epoch);
+                match error_code {
+                    ErrorCode::None => {
+                        unsigned_varint_encode(&mut content, request.new_isr.len());
+                        for replica in request.new_isr.iter() {
+                            content.put_i32(*replica);
+                        }
+                    }
+                    _ => unsigned_varint_encode(&mut content, 0),
+                }
+                // Leader recovery state, recovered
+                content.put_i8(0);
+                content.put_i32(partition_epoch);
+                content.put_i8(0x00);
+            }
+            content.put_i8(0x00);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
+
+/// The partition's leader, leader epoch and partition epoch after replaying its records
+/// and changes, the way brokers do.
+fn partition_state(
+    metadata: &[MetadataBatch],
+    topic_id: Uuid,
+    partition: i32,
+) -> Option<(i32, i32, i32)> {
+    let mut state = None;
+    for batch in metadata.iter() {
+        let records = batch
+            .topic_partitions()
+            .filter(|(_, id, _)| **id == topic_id)
+            .flat_map(|(_, _, records)| records);
+        for record in records.filter(|record| record.partition_id == partition) {
+            state = Some((record.leader, record.leader_epoch, record.partition_epoch));
+        }
+
+        let changes = batch
+            .partition_change_records()
+            .iter()
+            .filter(|change| change.uuid == topic_id && change.partition_id == partition);
+        for change in changes {
+            let Some((leader, leader_epoch, partition_epoch)) = state.as_mut() else {
+                continue;
+            };
+            if change.leader != PartitionChangeRecord::NO_LEADER_CHANGE {
+                *leader = change.leader;
+                *leader_epoch += 1;
+            }
+            *partition_epoch += 1;
+        }
+    }
+
+    state
+}
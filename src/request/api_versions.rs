@@ -2,14 +2,39 @@ use bytes::{BufMut, BytesMut};
 
 use crate::request::{ApiType, IntoResponse, Request, RequestHeader};
 
+// What the controller listener serves, the quorum's own requests and the metadata writes
+// brokers forward to the active controller
+const CONTROLLER_APIS: &[ApiType] = &[
+    ApiType::ApiVersions,
+    ApiType::Vote,
+    ApiType::BeginQuorumEpoch,
+    ApiType::EndQuorumEpoch,
+    ApiType::FetchSnapshot,
+    ApiType::Fetch,
+    ApiType::BrokerRegistration,
+    ApiType::IncrementalAlterConfigs,
+    ApiType::AlterPartition,
+    ApiType::AllocateProducerIds,
+];
+
 pub struct ApiVersionsRequest {
     header: RequestHeader,
+    controller: bool,
 }
 
 impl ApiVersionsRequest {
     pub fn new(request: Request) -> Self {
         Self {
             header: request.header,
+            controller: false,
+        }
+    }
+
+    /// ApiVersions on the controller listener, listing only what it serves.
+    pub fn for_controller(request: Request) -> Self {
+        Self {
+            header: request.header,
+            controller: true,
         }
     }
 }
@@ -21,12 +46,42 @@ impl IntoResponse for ApiVersionsRequest {
         let error_code = self.header.version_supported();
         let thottle: i32 = 0;
 
-        let supported_apis = vec![
+        let broker_apis = [
             ApiType::ApiVersions,
             ApiType::DescribeTopicPartitions,
             ApiType::Fetch,
             ApiType::Produce,
+            ApiType::InitProducerId,
+            ApiType::AddPartitionsToTxn,
+            ApiType::AddOffsetsToTxn,
+            ApiType::EndTxn,
+            ApiType::WriteTxnMarkers,
+            ApiType::TxnOffsetCommit,
+            ApiType::FindCoordinator,
+            ApiType::JoinGroup,
+            ApiType::Heartbeat,
+            ApiType::LeaveGroup,
+            ApiType::SyncGroup,
+            ApiType::OffsetCommit,
+            ApiType::OffsetFetch,
+            ApiType::DescribeGroups,
+            ApiType::ListGroups,
+            ApiType::DeleteGroups,
+            ApiType::OffsetDelete,
+            ApiType::ConsumerGroupHeartbeat,
+            ApiType::ConsumerGroupDescribe,
+            ApiType::DescribeLogDirs,
+            ApiType::DescribeConfigs,
+            ApiType::AlterConfigs,
+            ApiType::IncrementalAlterConfigs,
+            ApiType::Metadata,
+            ApiType::DescribeCluster,
+            ApiType::OffsetForLeaderEpoch,
         ];
+        let supported_apis = match self.controller {
+            true => CONTROLLER_APIS,
+            false => &broker_apis[..],
+        };
 
         let api_items = supported_apis.len() + 1; // TODO: varint encode
 
@@ -0,0 +1,144 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    metadata::METADATA_TOPIC,
+    raft::RaftQuorum,
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_nullable_string, read_compact_string, skip_tagged_fields,
+        write_compact_string,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+struct EpochPartition {
+    partition: i32,
+    leader_id: i32,
+    leader_epoch: i32,
+}
+
+#[derive(Debug)]
+pub struct BeginQuorumEpochRequest {
+    header: RequestHeader,
+    quorum: Arc<RaftQuorum>,
+    cluster_id: Option<Bytes>,
+    // The voter the request is meant for, -1 when sent to any
+    voter_id: i32,
+    topics: Box<[(Bytes, Box<[EpochPartition]>)]>,
+}
+
+impl BeginQuorumEpochRequest {
+    pub fn new(req: Request, quorum: Arc<RaftQuorum>) -> Result<Self> {
+        let mut payload = req.payload;
+
+        let cluster_id = read_compact_nullable_string(&mut payload)?;
+        let voter_id = payload.try_get_i32()?;
+        let topics_len = read_compact_len(&mut payload)?;
+        let topics = (0..topics_len)
+            .map(|_| {
+                let topic_name = read_compact_string(&mut payload)?;
+                let partitions_len = read_compact_len(&mut payload)?;
+                let partitions = (0..partitions_len)
+                    .map(|_| {
+                        let partition = payload.try_get_i32()?;
+                        let _voter_directory_id = payload.try_get_u128()?;
+                        let partition = EpochPartition {
+                            partition,
+                            leader_id: payload.try_get_i32()?,
+                            leader_epoch: payload.try_get_i32()?,
+                        };
+                        skip_tagged_fields(&mut payload)?;
+                        Ok(partition)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                skip_tagged_fields(&mut payload)?;
+
+                Ok((topic_name, partitions.into_boxed_slice()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // Leader endpoints, the voter list already says where the leader is
+        let endpoints_len = read_compact_len(&mut payload)?;
+        for _ in 0..endpoints_len {
+            let _name = read_compact_string(&mut payload)?;
+            let _host = read_compact_string(&mut payload)?;
+            let _port = payload.try_get_u16()?;
+            skip_tagged_fields(&mut payload)?;
+        }
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            quorum,
+            cluster_id,
+            voter_id,
+            topics: topics.into_boxed_slice(),
+        })
+    }
+
+    /// Refuses the whole request, answering no partition.
+    pub fn error_response(header: &RequestHeader, error_code: ErrorCode) -> BytesMut {
+        let mut content = BytesMut::new();
+
+        content.put_i32(header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i16(error_code as i16);
+        unsigned_varint_encode(&mut content, 0);
+        content.put_i8(0x00);
+
+        content
+    }
+}
+
+impl IntoResponse for BeginQuorumEpochRequest {
+    fn response(&self) -> BytesMut {
+        let error_code = match self.quorum.check_cluster_id(self.cluster_id.as_deref()) {
+            Ok(()) if self.voter_id >= 0 && self.voter_id != self.quorum.node_id() => {
+                Err(ErrorCode::InconsistentVoterSet)
+            }
+            checked => checked,
+        };
+        if let Err(error_code) = error_code {
+            return Self::error_response(&self.header, error_code);
+        }
+
+        let mut content = BytesMut::new();
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i16(ErrorCode::None as i16);
+
+        unsigned_varint_encode(&mut content, self.topics.len());
+        for (topic_name, partitions) in self.topics.iter() {
+            write_compact_string(&mut content, topic_name);
+            unsigned_varint_encode(&mut content, partitions.len());
+            for partition in partitions.iter() {
+                let (error_code, leader_id, leader_epoch) =
+                    match &topic_name[..] == METADATA_TOPIC && partition.partition == 0 {
+                        true => self
+                            .quorum
+                            .handle_begin_quorum_epoch(partition.leader_id, partition.leader_epoch),
+                        false => (ErrorCode::UnknownTopicOrPartition, -1, -1),
+                    };
+
+                content.put_i32(partition.partition);
+                content.put_i16(error_code as i16);
+                content.put_i32(leader_id);
+                content.put_i32(leader_epoch);
+                // Tags
+                content.put_i8(0x00);
+            }
+            // Tags
+            content.put_i8(0x00);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
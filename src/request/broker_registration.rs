@@ -0,0 +1,170 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+use uuid::Uuid;
+
+use crate::{
+    metadata::{BrokerEndpoint, RegisterBrokerRecord},
+    raft::{COMMIT_TIMEOUT, RaftQuorum},
+    record::Record,
+    request::{
+        ErrorCode, IntoDelayedResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_nullable_string, read_compact_string, skip_tagged_fields,
+    },
+};
+
+use std::sync::Arc;
+
+/// A broker registering with the active controller, which writes its
+/// `RegisterBrokerRecord` to the quorum. As in Kafka, the broker epoch is the offset of
+/// that record. Without heartbeats to unfence it later, the broker is registered unfenced.
+#[derive(Debug)]
+pub struct BrokerRegistrationRequest {
+    header: RequestHeader,
+    quorum: Arc<RaftQuorum>,
+    cluster_id: Bytes,
+    record: RegisterBrokerRecord,
+}
+
+impl BrokerRegistrationRequest {
+    pub fn new(req: Request, quorum: Arc<RaftQuorum>) -> Result<Self> {
+        let mut payload = req.payload;
+        let version = req.header.api_version;
+
+        let broker_id = payload.try_get_i32()?;
+        let cluster_id = read_compact_string(&mut payload)?;
+        let incarnation_id = Uuid::from_u128(payload.try_get_u128()?);
+        let endpoints_len = read_compact_len(&mut payload)?;
+        let endpoints = (0..endpoints_len)
+            .map(|_| {
+                let endpoint = BrokerEndpoint {
+                    name: read_compact_string(&mut payload)?,
+                    host: read_compact_string(&mut payload)?,
+                    port: payload.try_get_u16()?,
+                    security_protocol: payload.try_get_i16()?,
+                };
+                skip_tagged_fields(&mut payload)?;
+                Ok(endpoint)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let features_len = read_compact_len(&mut payload)?;
+        let features = (0..features_len)
+            .map(|_| {
+                let feature = (
+                    read_compact_string(&mut payload)?,
+                    payload.try_get_i16()?,
+                    payload.try_get_i16()?,
+                );
+                skip_tagged_fields(&mut payload)?;
+                Ok(feature)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let rack = read_compact_nullable_string(&mut payload)?;
+        let is_migrating_zk_broker = version >= 1 && payload.try_get_i8()? != 0;
+        let log_dirs = match version >= 2 {
+            true => {
+                let log_dirs_len = read_compact_len(&mut payload)?;
+                (0..log_dirs_len)
+                    .map(|_| payload.try_get_u128().map(Uuid::from_u128))
+                    .collect::<Result<Vec<_>>>()?
+            }
+            false => Vec::new(),
+        };
+        if version >= 3 {
+            let _previous_broker_epoch = payload.try_get_i64()?;
+        }
+        skip_tagged_fields(&mut payload)?;
+
+        let record = RegisterBrokerRecord {
+            version: RegisterBrokerRecord::VERSION,
+            broker_id,
+            is_migrating_zk_broker,
+            incarnation_id,
+            broker_epoch: -1,
+            endpoints: endpoints.into_boxed_slice(),
+            features: features.into_boxed_slice(),
+            rack,
+            fenced: false,
+            in_controlled_shutdown: false,
+            log_dirs: log_dirs.into_boxed_slice(),
+            tags: 0,
+        };
+
+        Ok(Self {
+            header: req.header,
+            quorum,
+            cluster_id,
+            record,
+        })
+    }
+
+    /// Refuses the registration, leaving the broker without an epoch.
+    pub fn error_response(header: &RequestHeader, error_code: ErrorCode) -> BytesMut {
+        registration_response(header, error_code, -1)
+    }
+
+    /// Writes the registration and waits for it to be committed, returning the broker epoch.
+    async fn register(mut self) -> Result<i64, ErrorCode> {
+        self.quorum.check_cluster_id(Some(&self.cluster_id))?;
+        if !self.quorum.is_leader() {
+            return Err(ErrorCode::NotController);
+        }
+
+        let appended = self.quorum.append_with(|offset| {
+            self.record.broker_epoch = offset;
+            vec![Record::new(None, Some(self.record.encode()))]
+        });
+        let broker_epoch = match appended {
+            Ok(offset) => offset,
+            Err(err) => {
+                eprintln!("registering broker {}: {err:#}", self.record.broker_id);
+                return Err(ErrorCode::NotController);
+            }
+        };
+
+        let deadline = tokio::time::Instant::now() + COMMIT_TIMEOUT;
+        if !self
+            .quorum
+            .wait_for_commit(broker_epoch + 1, deadline)
+            .await
+        {
+            return Err(ErrorCode::RequestTimedOut);
+        }
+        eprintln!(
+            "registered broker {} with epoch {broker_epoch}",
+            self.record.broker_id
+        );
+
+        Ok(broker_epoch)
+    }
+}
+
+impl IntoDelayedResponse for BrokerRegistrationRequest {
+    async fn response(self) -> BytesMut {
+        let header = self.header.clone();
+        match self.register().await {
+            Ok(broker_epoch) => registration_response(&header, ErrorCode::None, broker_epoch),
+            Err(error_code) => Self::error_response(&header, error_code),
+        }
+    }
+}
+
+fn registration_response(
+    header: &RequestHeader,
+    error_code: ErrorCode,
+    broker_epoch: i64,
+) -> BytesMut {
+    let mut content = BytesMut::new();
+    let throttle_time = 0;
+
+    content.put_i32(header.correlation_id);
+    content.put_i8(0x00);
+    content.put_i32(throttle_time);
+    content.put_i16(error_code as i16);
+    content.put_i64(broker_epoch);
+
+    content.put_i8(0x00);
+
+    content
+}
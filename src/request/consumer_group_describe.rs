@@ -0,0 +1,184 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    cluster::BrokerRegistry,
+    consumer_group::Assignment,
+    group::GroupCoordinator,
+    metadata::RecordBatch,
+    offsets::partition_for,
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_string, skip_tagged_fields, write_compact_nullable_string,
+        write_compact_string,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+// Authorized operations were not requested
+const OPERATIONS_NOT_REQUESTED: i32 = i32::MIN;
+
+#[derive(Debug)]
+pub struct ConsumerGroupDescribeRequest {
+    header: RequestHeader,
+    groups: Arc<GroupCoordinator>,
+    metadata: Arc<Box<[RecordBatch]>>,
+    brokers: Arc<BrokerRegistry>,
+    group_ids: Box<[Bytes]>,
+    include_authorized_operations: bool,
+}
+
+impl ConsumerGroupDescribeRequest {
+    pub fn new(
+        req: Request,
+        groups: Arc<GroupCoordinator>,
+        metadata: Arc<Box<[RecordBatch]>>,
+        brokers: Arc<BrokerRegistry>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+        let groups_len = read_compact_len(&mut payload)?;
+        let group_ids = (0..groups_len)
+            .map(|_| read_compact_string(&mut payload))
+            .collect::<Result<Vec<Bytes>>>()?;
+        let include_authorized_operations = payload.try_get_u8()? != 0;
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            groups,
+            metadata,
+            brokers,
+            group_ids: group_ids.into_boxed_slice(),
+            include_authorized_operations,
+        })
+    }
+
+    fn write_assignment(&self, content: &mut BytesMut, assignment: &Assignment) {
+        unsigned_varint_encode(content, assignment.len());
+        for (topic_id, partitions) in assignment {
+            let topic_name = self
+                .metadata
+                .iter()
+                .find_map(|batch| batch.get_topic_name(topic_id))
+                .unwrap_or_default();
+
+            content.put_u128(topic_id.as_u128());
+            write_compact_string(content, &topic_name);
+            unsigned_varint_encode(content, partitions.len());
+            for partition in partitions {
+                content.put_i32(*partition);
+            }
+            // Tags
+            content.put_i8(0x00);
+        }
+        // Tags
+        content.put_i8(0x00);
+    }
+
+    fn describe_group(&self, content: &mut BytesMut, group_id: &Bytes) {
+        if let Err(error_code) = self.brokers.check_coordinator(partition_for(group_id)) {
+            write_missing_group(content, group_id, error_code, None);
+            return;
+        }
+
+        let consumer_groups = self.groups.consumer_lock();
+        let Some(group) = consumer_groups.get(group_id) else {
+            let message = format!(
+                "Group {} not found or is not a consumer group.",
+                String::from_utf8_lossy(group_id)
+            );
+            write_missing_group(
+                content,
+                group_id,
+                ErrorCode::GroupIdNotFound,
+                Some(message.as_bytes()),
+            );
+            return;
+        };
+
+        content.put_i16(ErrorCode::None as i16);
+        write_compact_nullable_string(content, None);
+        write_compact_string(content, group_id);
+        write_compact_string(content, group.state.name().as_bytes());
+        content.put_i32(group.group_epoch);
+        content.put_i32(group.assignment_epoch);
+        write_compact_string(content, &group.assignor());
+
+        unsigned_varint_encode(content, group.members.len());
+        for member in group.members.values() {
+            write_compact_string(content, &member.member_id);
+            write_compact_nullable_string(content, member.instance_id.as_deref());
+            write_compact_nullable_string(content, member.rack_id.as_deref());
+            content.put_i32(member.member_epoch);
+            write_compact_string(content, &member.client_id);
+            write_compact_string(content, &member.client_host);
+            unsigned_varint_encode(content, member.subscribed_topic_names.len());
+            for topic_name in member.subscribed_topic_names.iter() {
+                write_compact_string(content, topic_name);
+            }
+            // Subscribed topic regex
+            write_compact_nullable_string(content, None);
+            self.write_assignment(content, &member.assigned);
+            self.write_assignment(
+                content,
+                &group
+                    .target_assignment
+                    .get(&member.member_id)
+                    .cloned()
+                    .unwrap_or_default(),
+            );
+            // Tags
+            content.put_i8(0x00);
+        }
+
+        content.put_i32(OPERATIONS_NOT_REQUESTED);
+        content.put_i8(0x00);
+    }
+}
+
+impl IntoResponse for ConsumerGroupDescribeRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+
+        unsigned_varint_encode(&mut content, self.group_ids.len());
+        for group_id in self.group_ids.iter() {
+            self.describe_group(&mut content, group_id);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
+
+/// Describes a group this broker cannot, as a dead group with no members.
+fn write_missing_group(
+    content: &mut BytesMut,
+    group_id: &Bytes,
+    error_code: ErrorCode,
+    message: Option<&[u8]>,
+) {
+    content.put_i16(error_code as i16);
+    write_compact_nullable_string(content, message);
+    write_compact_string(content, group_id);
+    // Group state
+    write_compact_string(content, b"Dead");
+    // Group epoch and assignment epoch
+    content.put_i32(-1);
+    content.put_i32(-1);
+    // Assignor name
+    write_compact_string(content, b"");
+    // Members
+    unsigned_varint_encode(content, 0);
+    content.put_i32(OPERATIONS_NOT_REQUESTED);
+    content.put_i8(0x00);
+}
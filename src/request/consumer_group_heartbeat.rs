@@ -0,0 +1,149 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+use uuid::Uuid;
+
+use crate::{
+    cluster::BrokerRegistry,
+    consumer_group::{
+        Assignment, ConsumerGroupHeartbeatParams, ConsumerGroupHeartbeatResult,
+        HEARTBEAT_INTERVAL_MS,
+    },
+    group::GroupCoordinator,
+    metadata::RecordBatch,
+    offsets::partition_for,
+    request::{
+        IntoResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_nullable_string, read_compact_string, read_uvarint, skip_tagged_fields,
+        write_compact_nullable_string,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct ConsumerGroupHeartbeatRequest {
+    header: RequestHeader,
+    groups: Arc<GroupCoordinator>,
+    metadata: Arc<Box<[RecordBatch]>>,
+    brokers: Arc<BrokerRegistry>,
+    params: ConsumerGroupHeartbeatParams,
+}
+
+impl ConsumerGroupHeartbeatRequest {
+    pub fn new(
+        req: Request,
+        groups: Arc<GroupCoordinator>,
+        metadata: Arc<Box<[RecordBatch]>>,
+        brokers: Arc<BrokerRegistry>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+        let group_id = read_compact_string(&mut payload)?;
+        let member_id = read_compact_string(&mut payload)?;
+        let member_epoch = payload.try_get_i32()?;
+        let instance_id = read_compact_nullable_string(&mut payload)?;
+        let rack_id = read_compact_nullable_string(&mut payload)?;
+        let rebalance_timeout_ms = payload.try_get_i32()?;
+
+        // Null arrays mean the field did not change since the last heartbeat
+        let subscribed_topic_names = match read_uvarint(&mut payload)? {
+            0 => None,
+            len => Some(
+                (0..len - 1)
+                    .map(|_| read_compact_string(&mut payload))
+                    .collect::<Result<Vec<Bytes>>>()?,
+            ),
+        };
+        let server_assignor = read_compact_nullable_string(&mut payload)?;
+        let owned_partitions = match read_uvarint(&mut payload)? {
+            0 => None,
+            len => Some(
+                (0..len - 1)
+                    .map(|_| {
+                        let topic_id = Uuid::from_u128(payload.try_get_u128()?);
+                        let partitions_len = read_compact_len(&mut payload)?;
+                        let partitions = (0..partitions_len)
+                            .map(|_| payload.try_get_i32())
+                            .collect::<Result<_>>()?;
+                        skip_tagged_fields(&mut payload)?;
+                        Ok((topic_id, partitions))
+                    })
+                    .collect::<Result<Assignment>>()?,
+            ),
+        };
+        skip_tagged_fields(&mut payload)?;
+
+        let params = ConsumerGroupHeartbeatParams {
+            group_id,
+            member_id,
+            member_epoch,
+            instance_id,
+            rack_id,
+            rebalance_timeout_ms,
+            subscribed_topic_names,
+            server_assignor,
+            owned_partitions,
+            client_id: req.header.client_id.clone(),
+            client_host: req.client_host,
+        };
+
+        Ok(Self {
+            header: req.header,
+            groups,
+            metadata,
+            brokers,
+            params,
+        })
+    }
+}
+
+impl IntoResponse for ConsumerGroupHeartbeatRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+        let result = match self
+            .brokers
+            .check_coordinator(partition_for(&self.params.group_id))
+        {
+            Ok(()) => self
+                .groups
+                .consumer_group_heartbeat(self.params.clone(), &self.metadata),
+            Err(error_code) => ConsumerGroupHeartbeatResult::error(error_code),
+        };
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+        content.put_i16(result.error_code as i16);
+        // Error message
+        write_compact_nullable_string(&mut content, None);
+        write_compact_nullable_string(&mut content, result.member_id.as_deref());
+        content.put_i32(result.member_epoch);
+        content.put_i32(HEARTBEAT_INTERVAL_MS);
+
+        // Nullable struct, prefixed by -1 when absent and 1 when present
+        match result.assignment {
+            Some(assignment) => {
+                content.put_i8(1);
+                unsigned_varint_encode(&mut content, assignment.len());
+                for (topic_id, partitions) in assignment {
+                    content.put_u128(topic_id.as_u128());
+                    unsigned_varint_encode(&mut content, partitions.len());
+                    for partition in partitions {
+                        content.put_i32(partition);
+                    }
+                    // Tags
+                    content.put_i8(0x00);
+                }
+                content.put_i8(0x00);
+            }
+            None => content.put_i8(-1),
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
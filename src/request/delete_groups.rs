@@ -0,0 +1,78 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    cluster::BrokerRegistry,
+    group::GroupCoordinator,
+    offsets::{OffsetManager, partition_for},
+    request::{
+        IntoResponse, Request, RequestHeader, read_compact_len, read_compact_string,
+        skip_tagged_fields, write_compact_string,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct DeleteGroupsRequest {
+    header: RequestHeader,
+    groups: Arc<GroupCoordinator>,
+    offsets: Arc<OffsetManager>,
+    brokers: Arc<BrokerRegistry>,
+    group_ids: Box<[Bytes]>,
+}
+
+impl DeleteGroupsRequest {
+    pub fn new(
+        req: Request,
+        groups: Arc<GroupCoordinator>,
+        offsets: Arc<OffsetManager>,
+        brokers: Arc<BrokerRegistry>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+        let groups_len = read_compact_len(&mut payload)?;
+        let group_ids = (0..groups_len)
+            .map(|_| read_compact_string(&mut payload))
+            .collect::<Result<Vec<Bytes>>>()?;
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            groups,
+            offsets,
+            brokers,
+            group_ids: group_ids.into_boxed_slice(),
+        })
+    }
+}
+
+impl IntoResponse for DeleteGroupsRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+
+        unsigned_varint_encode(&mut content, self.group_ids.len());
+        for group_id in self.group_ids.iter() {
+            let error_code = match self.brokers.check_coordinator(partition_for(group_id)) {
+                Ok(()) => self.groups.delete_group(group_id, &self.offsets),
+                Err(error_code) => error_code,
+            };
+
+            write_compact_string(&mut content, group_id);
+            content.put_i16(error_code as i16);
+            // Tags
+            content.put_i8(0x00);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
@@ -0,0 +1,106 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, BytesMut};
+
+use crate::{
+    cluster::BrokerRegistry,
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, skip_tagged_fields,
+        write_compact_nullable_string, write_compact_string,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+const BROKER_ENDPOINT_TYPE: i8 = 1;
+
+#[derive(Debug)]
+pub struct DescribeClusterRequest {
+    header: RequestHeader,
+    brokers: Arc<BrokerRegistry>,
+    include_cluster_authorized_operations: bool,
+    endpoint_type: i8,
+    include_fenced_brokers: bool,
+}
+
+impl DescribeClusterRequest {
+    pub fn new(req: Request, brokers: Arc<BrokerRegistry>) -> Result<Self> {
+        let mut payload = req.payload;
+        let version = req.header.api_version;
+
+        let include_cluster_authorized_operations = payload.try_get_i8()? != 0;
+        let endpoint_type = match version >= 1 {
+            true => payload.try_get_i8()?,
+            false => BROKER_ENDPOINT_TYPE,
+        };
+        let include_fenced_brokers = version >= 2 && payload.try_get_i8()? != 0;
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            brokers,
+            include_cluster_authorized_operations,
+            endpoint_type,
+            include_fenced_brokers,
+        })
+    }
+}
+
+impl IntoResponse for DescribeClusterRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+        let version = self.header.api_version;
+        // Controllers are not reachable through this broker's listeners
+        let error_code = match self.endpoint_type {
+            BROKER_ENDPOINT_TYPE => ErrorCode::None,
+            _ => ErrorCode::MismatchedEndpointType,
+        };
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+        content.put_i16(error_code as i16);
+        write_compact_nullable_string(&mut content, None);
+        if version >= 1 {
+            content.put_i8(self.endpoint_type);
+        }
+        let cluster_id = self.brokers.cluster_id().cloned().unwrap_or_default();
+        write_compact_string(&mut content, &cluster_id);
+
+        let (controller_id, brokers) = match error_code {
+            ErrorCode::None => (
+                self.brokers.controller_id(),
+                self.brokers.brokers(self.include_fenced_brokers),
+            ),
+            _ => (-1, Vec::new()),
+        };
+        content.put_i32(controller_id);
+
+        let listener_name = self.brokers.listener_name();
+        let endpoints = brokers
+            .iter()
+            .filter_map(|broker| Some((broker, broker.endpoint(listener_name)?)))
+            .collect::<Vec<_>>();
+        unsigned_varint_encode(&mut content, endpoints.len());
+        for (broker, endpoint) in endpoints {
+            content.put_i32(broker.id);
+            write_compact_string(&mut content, &endpoint.host);
+            content.put_i32(endpoint.port as i32);
+            write_compact_nullable_string(&mut content, broker.rack.as_deref());
+            if version >= 2 {
+                content.put_i8(broker.fenced as i8);
+            }
+            // Tags
+            content.put_i8(0x00);
+        }
+
+        // Cluster authorized operations, left unset as no authorizer is configured
+        content.put_i32(i32::MIN);
+        content.put_i8(0x00);
+
+        content
+    }
+}
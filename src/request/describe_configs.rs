@@ -0,0 +1,147 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    config::{ConfigEntry, ConfigManager},
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_string, skip_tagged_fields, write_compact_nullable_string,
+        write_compact_string,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+struct Resource {
+    resource_type: i8,
+    resource_name: Bytes,
+    // None asks for every config of the resource
+    keys: Option<Box<[Bytes]>>,
+}
+
+#[derive(Debug)]
+pub struct DescribeConfigsRequest {
+    header: RequestHeader,
+    configs: Arc<ConfigManager>,
+    resources: Box<[Resource]>,
+    include_synonyms: bool,
+    include_documentation: bool,
+}
+
+impl DescribeConfigsRequest {
+    pub fn new(req: Request, configs: Arc<ConfigManager>) -> Result<Self> {
+        let mut payload = req.payload;
+
+        let resources_len = read_compact_len(&mut payload)?;
+        let resources = (0..resources_len)
+            .map(|_| {
+                let resource_type = payload.try_get_i8()?;
+                let resource_name = read_compact_string(&mut payload)?;
+
+                // Compact nullable array, a zero length prefix marks null
+                let keys = if payload[0] == 0x00 {
+                    payload.try_advance(1)?;
+                    None
+                } else {
+                    let keys_len = read_compact_len(&mut payload)?;
+                    let keys = (0..keys_len)
+                        .map(|_| read_compact_string(&mut payload))
+                        .collect::<Result<Vec<Bytes>>>()?;
+                    Some(keys.into_boxed_slice())
+                };
+                skip_tagged_fields(&mut payload)?;
+
+                Ok(Resource {
+                    resource_type,
+                    resource_name,
+                    keys,
+                })
+            })
+            .collect::<Result<Vec<Resource>>>()?;
+        let include_synonyms = payload.try_get_i8()? != 0;
+        let include_documentation = payload.try_get_i8()? != 0;
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            configs,
+            resources: resources.into_boxed_slice(),
+            include_synonyms,
+            include_documentation,
+        })
+    }
+
+    fn write_entry(&self, content: &mut BytesMut, entry: &ConfigEntry) {
+        write_compact_string(content, entry.name.as_bytes());
+        write_compact_nullable_string(content, entry.value().map(str::as_bytes));
+        content.put_i8(entry.read_only as i8);
+        content.put_i8(entry.source() as i8);
+        content.put_i8(entry.is_sensitive as i8);
+
+        let synonyms = match self.include_synonyms {
+            true => &entry.synonyms[..],
+            false => &[],
+        };
+        unsigned_varint_encode(content, synonyms.len());
+        for synonym in synonyms {
+            let value = synonym.value.as_deref().filter(|_| !entry.is_sensitive);
+            write_compact_string(content, synonym.name.as_bytes());
+            write_compact_nullable_string(content, value.map(str::as_bytes));
+            content.put_i8(synonym.source as i8);
+            // Tags
+            content.put_i8(0x00);
+        }
+
+        content.put_i8(entry.config_type as i8);
+        // Documentation
+        write_compact_nullable_string(content, None);
+        // Tags
+        content.put_i8(0x00);
+    }
+}
+
+impl IntoResponse for DescribeConfigsRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+
+        unsigned_varint_encode(&mut content, self.resources.len());
+        for resource in self.resources.iter() {
+            let described = self.configs.describe(
+                resource.resource_type,
+                &resource.resource_name,
+                resource.keys.as_deref(),
+            );
+            let (error_code, error_message, entries) = match described {
+                Ok(entries) => (ErrorCode::None, None, entries),
+                Err(err) => (err.error_code, Some(err.message), Vec::new()),
+            };
+
+            content.put_i16(error_code as i16);
+            write_compact_nullable_string(
+                &mut content,
+                error_message.as_deref().map(str::as_bytes),
+            );
+            content.put_i8(resource.resource_type);
+            write_compact_string(&mut content, &resource.resource_name);
+            unsigned_varint_encode(&mut content, entries.len());
+            for entry in entries.iter() {
+                self.write_entry(&mut content, entry);
+            }
+            // Tags
+            content.put_i8(0x00);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
@@ -0,0 +1,147 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    cluster::BrokerRegistry,
+    group::{GroupCoordinator, GroupState},
+    offsets::partition_for,
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_string, skip_tagged_fields, write_compact_nullable_string,
+        write_compact_string,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+// Authorized operations were not requested
+const OPERATIONS_NOT_REQUESTED: i32 = i32::MIN;
+
+#[derive(Debug)]
+pub struct DescribeGroupsRequest {
+    header: RequestHeader,
+    groups: Arc<GroupCoordinator>,
+    brokers: Arc<BrokerRegistry>,
+    group_ids: Box<[Bytes]>,
+    include_authorized_operations: bool,
+}
+
+impl DescribeGroupsRequest {
+    pub fn new(
+        req: Request,
+        groups: Arc<GroupCoordinator>,
+        brokers: Arc<BrokerRegistry>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+        let groups_len = read_compact_len(&mut payload)?;
+        let group_ids = (0..groups_len)
+            .map(|_| read_compact_string(&mut payload))
+            .collect::<Result<Vec<Bytes>>>()?;
+        let include_authorized_operations = payload.try_get_u8()? != 0;
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            groups,
+            brokers,
+            group_ids: group_ids.into_boxed_slice(),
+            include_authorized_operations,
+        })
+    }
+
+    /// Describes a group this broker cannot, as a dead group with no members.
+    fn write_missing_group(&self, content: &mut BytesMut, group_id: &Bytes, error_code: ErrorCode) {
+        content.put_i16(error_code as i16);
+        if self.header.api_version >= 6 {
+            write_compact_nullable_string(content, None);
+        }
+        write_compact_string(content, group_id);
+        write_compact_string(content, GroupState::Dead.name().as_bytes());
+        // Protocol type and protocol data
+        write_compact_string(content, b"");
+        write_compact_string(content, b"");
+        // Members
+        unsigned_varint_encode(content, 0);
+        content.put_i32(OPERATIONS_NOT_REQUESTED);
+        content.put_i8(0x00);
+    }
+
+    fn describe_group(&self, content: &mut BytesMut, group_id: &Bytes) {
+        if let Err(error_code) = self.brokers.check_coordinator(partition_for(group_id)) {
+            self.write_missing_group(content, group_id, error_code);
+            return;
+        }
+
+        let groups = self.groups.lock();
+        let Some(group) = groups.get(group_id) else {
+            // Unknown groups were reported as dead before v6 got a proper error
+            let error_code = if self.header.api_version >= 6 {
+                ErrorCode::GroupIdNotFound
+            } else {
+                ErrorCode::None
+            };
+            self.write_missing_group(content, group_id, error_code);
+            return;
+        };
+
+        // Member metadata and assignments are only settled once the group is stable
+        let stable = group.state == GroupState::Stable;
+        let protocol_name = group
+            .protocol_name
+            .clone()
+            .filter(|_| stable)
+            .unwrap_or_default();
+
+        content.put_i16(ErrorCode::None as i16);
+        if self.header.api_version >= 6 {
+            write_compact_nullable_string(content, None);
+        }
+        write_compact_string(content, group_id);
+        write_compact_string(content, group.state.name().as_bytes());
+        write_compact_string(content, &group.protocol_type.clone().unwrap_or_default());
+        write_compact_string(content, &protocol_name);
+
+        unsigned_varint_encode(content, group.members.len());
+        for member in group.members.values() {
+            write_compact_string(content, &member.member_id);
+            write_compact_nullable_string(content, member.group_instance_id.as_deref());
+            write_compact_string(content, &member.client_id);
+            write_compact_string(content, &member.client_host);
+            if stable {
+                write_compact_string(content, &member.metadata(&protocol_name));
+                write_compact_string(content, &member.assignment);
+            } else {
+                write_compact_string(content, b"");
+                write_compact_string(content, b"");
+            }
+            // Tags
+            content.put_i8(0x00);
+        }
+
+        content.put_i32(OPERATIONS_NOT_REQUESTED);
+        content.put_i8(0x00);
+    }
+}
+
+impl IntoResponse for DescribeGroupsRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+
+        unsigned_varint_encode(&mut content, self.group_ids.len());
+        for group_id in self.group_ids.iter() {
+            self.describe_group(&mut content, group_id);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
@@ -0,0 +1,153 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    log::{LogDir, LogManager, PartitionUsage},
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_string, read_uvarint, skip_tagged_fields, write_compact_string,
+    },
+    unsigned_varint_encode,
+};
+
+use std::{collections::BTreeMap, sync::Arc};
+
+// Requested partition indexes per topic name
+type TopicPartitions = Box<[(Bytes, Box<[i32]>)]>;
+
+#[derive(Debug)]
+pub struct DescribeLogDirsRequest {
+    header: RequestHeader,
+    logs: Arc<LogManager>,
+    // None asks for every partition
+    topics: Option<TopicPartitions>,
+}
+
+impl DescribeLogDirsRequest {
+    pub fn new(req: Request, logs: Arc<LogManager>) -> Result<Self> {
+        let mut payload = req.payload;
+
+        // Compact nullable array, a zero length prefix marks null
+        let topics = match read_uvarint(&mut payload)? {
+            0 => None,
+            len => {
+                let topics = (0..len - 1)
+                    .map(|_| {
+                        let topic_name = read_compact_string(&mut payload)?;
+                        let partitions_len = read_compact_len(&mut payload)?;
+                        let partitions = (0..partitions_len)
+                            .map(|_| payload.try_get_i32())
+                            .collect::<Result<Vec<i32>>>()?;
+                        skip_tagged_fields(&mut payload)?;
+
+                        Ok((topic_name, partitions.into_boxed_slice()))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Some(topics.into_boxed_slice())
+            }
+        };
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            logs,
+            topics,
+        })
+    }
+
+    fn requested(&self, usage: &PartitionUsage) -> bool {
+        self.topics.as_ref().is_none_or(|topics| {
+            topics.iter().any(|(topic_name, partitions)| {
+                *topic_name == usage.topic_name && partitions.contains(&usage.partition)
+            })
+        })
+    }
+
+    /// The requested partitions of a directory grouped by topic, or why it cannot be read.
+    fn describe(&self, dir: &LogDir) -> Result<BTreeMap<Bytes, Vec<PartitionUsage>>, ErrorCode> {
+        if !dir.is_online() {
+            return Err(ErrorCode::KafkaStorageError);
+        }
+
+        let usage = self.logs.dir_usage(dir).map_err(|err| {
+            eprintln!("describing log directory {}: {err:#}", dir.path.display());
+            ErrorCode::KafkaStorageError
+        })?;
+
+        let mut topics: BTreeMap<Bytes, Vec<PartitionUsage>> = BTreeMap::new();
+        for partition in usage.into_iter().filter(|usage| self.requested(usage)) {
+            topics
+                .entry(partition.topic_name.clone())
+                .or_default()
+                .push(partition);
+        }
+        for partitions in topics.values_mut() {
+            partitions.sort_by_key(|usage| usage.partition);
+        }
+
+        Ok(topics)
+    }
+}
+
+impl IntoResponse for DescribeLogDirsRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+        let version = self.header.api_version;
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+        if version >= 3 {
+            content.put_i16(ErrorCode::None as i16);
+        }
+
+        let dirs = self.logs.log_dirs();
+        unsigned_varint_encode(&mut content, dirs.len());
+        for dir in dirs {
+            let (error_code, topics) = match self.describe(dir) {
+                Ok(topics) => (ErrorCode::None, topics),
+                Err(error_code) => (error_code, BTreeMap::new()),
+            };
+
+            content.put_i16(error_code as i16);
+            write_compact_string(&mut content, dir.path.to_string_lossy().as_bytes());
+            unsigned_varint_encode(&mut content, topics.len());
+            for (topic_name, partitions) in topics.iter() {
+                write_compact_string(&mut content, topic_name);
+                unsigned_varint_encode(&mut content, partitions.len());
+                for partition in partitions {
+                    content.put_i32(partition.partition);
+                    content.put_i64(partition.size);
+                    content.put_i64(partition.offset_lag);
+                    // Is future key, this broker never moves replicas between directories
+                    content.put_i8(0);
+                    // Tags
+                    content.put_i8(0x00);
+                }
+                // Tags
+                content.put_i8(0x00);
+            }
+
+            if version >= 4 {
+                let (total_bytes, usable_bytes) = match error_code {
+                    ErrorCode::None => dir.space().unwrap_or_else(|err| {
+                        eprintln!("reading space of {}: {err:#}", dir.path.display());
+                        (-1, -1)
+                    }),
+                    _ => (-1, -1),
+                };
+                content.put_i64(total_bytes);
+                content.put_i64(usable_bytes);
+            }
+            // Tags
+            content.put_i8(0x00);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
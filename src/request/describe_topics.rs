@@ -1,9 +1,12 @@
-use bytes::{Buf, BufMut, Bytes, BytesMut};
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
 
 use crate::{
     metadata::RecordBatch,
-    request::{ErrorCode, IntoResponse, Request, RequestHeader},
-    unsigned_varint_decode,
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_string, skip_tagged_fields,
+    },
 };
 
 use std::sync::Arc;
@@ -18,37 +21,33 @@ pub struct DescribeTopicsRequest {
 }
 
 impl DescribeTopicsRequest {
-    pub fn new(request: Request, metadata: Arc<Box<[RecordBatch]>>) -> Self {
+    pub fn new(request: Request, metadata: Arc<Box<[RecordBatch]>>) -> Result<Self> {
         let Request {
             header,
             mut payload,
             ..
         } = request;
 
-        let len = unsigned_varint_decode(&mut payload);
-        let mut topic_names = Vec::with_capacity(len as usize);
+        let len = read_compact_len(&mut payload)?;
+        let mut topic_names = Vec::new();
         for _ in 0..len {
-            let topic_len = unsigned_varint_decode(&mut payload);
-            let topic_len = topic_len as usize;
-            topic_names.push(Bytes::from_iter(payload[..topic_len].to_vec()));
-            payload.advance(topic_len);
-            let tags = payload.get_i8();
-            assert_eq!(tags, 0x00);
+            topic_names.push(read_compact_string(&mut payload)?);
+            skip_tagged_fields(&mut payload)?;
         }
         topic_names.sort();
 
-        let partition_limit = payload.get_i32();
-        let cursor = payload.get_u8();
-        let tags = payload.get_i8();
+        let partition_limit = payload.try_get_i32()?;
+        let cursor = payload.try_get_u8()?;
+        let tags = payload.try_get_i8()?;
 
-        Self {
+        Ok(Self {
             header,
             topic_names,
             partition_limit,
             cursor,
             tags,
             metadata,
-        }
+        })
     }
 
     pub fn topic_response(&self, topic_name: &Bytes) -> BytesMut {
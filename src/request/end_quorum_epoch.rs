@@ -0,0 +1,149 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    metadata::METADATA_TOPIC,
+    raft::RaftQuorum,
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_nullable_string, read_compact_string, skip_tagged_fields,
+        write_compact_string,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+struct EpochPartition {
+    partition: i32,
+    leader_id: i32,
+    leader_epoch: i32,
+    // Voters the resigning leader would have succeed it, most preferred first
+    preferred_candidates: Box<[i32]>,
+}
+
+#[derive(Debug)]
+pub struct EndQuorumEpochRequest {
+    header: RequestHeader,
+    quorum: Arc<RaftQuorum>,
+    cluster_id: Option<Bytes>,
+    topics: Box<[(Bytes, Box<[EpochPartition]>)]>,
+}
+
+impl EndQuorumEpochRequest {
+    pub fn new(req: Request, quorum: Arc<RaftQuorum>) -> Result<Self> {
+        let mut payload = req.payload;
+
+        let cluster_id = read_compact_nullable_string(&mut payload)?;
+        let topics_len = read_compact_len(&mut payload)?;
+        let topics = (0..topics_len)
+            .map(|_| {
+                let topic_name = read_compact_string(&mut payload)?;
+                let partitions_len = read_compact_len(&mut payload)?;
+                let partitions = (0..partitions_len)
+                    .map(|_| {
+                        let partition = payload.try_get_i32()?;
+                        let leader_id = payload.try_get_i32()?;
+                        let leader_epoch = payload.try_get_i32()?;
+                        let candidates_len = read_compact_len(&mut payload)?;
+                        let preferred_candidates = (0..candidates_len)
+                            .map(|_| {
+                                let candidate_id = payload.try_get_i32()?;
+                                let _candidate_directory_id = payload.try_get_u128()?;
+                                skip_tagged_fields(&mut payload)?;
+                                Ok(candidate_id)
+                            })
+                            .collect::<Result<Vec<_>>>()?;
+                        skip_tagged_fields(&mut payload)?;
+
+                        Ok(EpochPartition {
+                            partition,
+                            leader_id,
+                            leader_epoch,
+                            preferred_candidates: preferred_candidates.into_boxed_slice(),
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                skip_tagged_fields(&mut payload)?;
+
+                Ok((topic_name, partitions.into_boxed_slice()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // Leader endpoints, the voter list already says where the leader is
+        let endpoints_len = read_compact_len(&mut payload)?;
+        for _ in 0..endpoints_len {
+            let _name = read_compact_string(&mut payload)?;
+            let _host = read_compact_string(&mut payload)?;
+            let _port = payload.try_get_u16()?;
+            skip_tagged_fields(&mut payload)?;
+        }
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            quorum,
+            cluster_id,
+            topics: topics.into_boxed_slice(),
+        })
+    }
+
+    /// Refuses the whole request, answering no partition.
+    pub fn error_response(header: &RequestHeader, error_code: ErrorCode) -> BytesMut {
+        let mut content = BytesMut::new();
+
+        content.put_i32(header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i16(error_code as i16);
+        unsigned_varint_encode(&mut content, 0);
+        content.put_i8(0x00);
+
+        content
+    }
+}
+
+impl IntoResponse for EndQuorumEpochRequest {
+    fn response(&self) -> BytesMut {
+        if let Err(error_code) = self.quorum.check_cluster_id(self.cluster_id.as_deref()) {
+            return Self::error_response(&self.header, error_code);
+        }
+
+        let mut content = BytesMut::new();
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i16(ErrorCode::None as i16);
+
+        unsigned_varint_encode(&mut content, self.topics.len());
+        for (topic_name, partitions) in self.topics.iter() {
+            write_compact_string(&mut content, topic_name);
+            unsigned_varint_encode(&mut content, partitions.len());
+            for partition in partitions.iter() {
+                let (error_code, leader_id, leader_epoch) =
+                    match &topic_name[..] == METADATA_TOPIC && partition.partition == 0 {
+                        true => self.quorum.handle_end_quorum_epoch(
+                            partition.leader_id,
+                            partition.leader_epoch,
+                            &partition.preferred_candidates,
+                        ),
+                        false => (ErrorCode::UnknownTopicOrPartition, -1, -1),
+                    };
+
+                content.put_i32(partition.partition);
+                content.put_i16(error_code as i16);
+                content.put_i32(leader_id);
+                content.put_i32(leader_epoch);
+                // Tags
+                content.put_i8(0x00);
+            }
+            // Tags
+            content.put_i8(0x00);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
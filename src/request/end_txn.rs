@@ -0,0 +1,82 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    cluster::BrokerRegistry,
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, read_compact_string,
+        skip_tagged_fields,
+    },
+    txn::{TransactionCoordinator, transaction_partition_for},
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct EndTxnRequest {
+    header: RequestHeader,
+    transactions: Arc<TransactionCoordinator>,
+    brokers: Arc<BrokerRegistry>,
+    transactional_id: Bytes,
+    producer_id: i64,
+    producer_epoch: i16,
+    committed: bool,
+}
+
+impl EndTxnRequest {
+    pub fn new(
+        req: Request,
+        transactions: Arc<TransactionCoordinator>,
+        brokers: Arc<BrokerRegistry>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+        let transactional_id = read_compact_string(&mut payload)?;
+        let producer_id = payload.try_get_i64()?;
+        let producer_epoch = payload.try_get_i16()?;
+        let committed = payload.try_get_u8()? != 0;
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            transactions,
+            brokers,
+            transactional_id,
+            producer_id,
+            producer_epoch,
+            committed,
+        })
+    }
+}
+
+impl IntoResponse for EndTxnRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+        let ended = self
+            .brokers
+            .check_coordinator(transaction_partition_for(&self.transactional_id))
+            .and_then(|()| {
+                self.transactions.end_transaction(
+                    &self.transactional_id,
+                    self.producer_id,
+                    self.producer_epoch,
+                    self.committed,
+                )
+            });
+        let error_code = match ended {
+            Ok(()) => ErrorCode::None,
+            Err(error_code) => error_code,
+        };
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+        content.put_i16(error_code as i16);
+        // Tags
+        content.put_i8(0x00);
+
+        content
+    }
+}
@@ -1,19 +1,45 @@
 #![allow(dead_code)]
-use std::sync::Arc;
+use std::{collections::BTreeSet, sync::Arc, time::Duration};
 
-use bytes::{Buf, BufMut, Bytes, BytesMut};
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
 use uuid::Uuid;
 
 use crate::{
+    cluster::BrokerRegistry,
+    fetch_session::{FetchContext, FetchSessionCache, INVALID_SESSION_ID},
+    log::{AbortedTxn, LogManager},
     metadata::RecordBatch,
-    request::{ErrorCode, IntoResponse, Request, RequestHeader},
-    unsigned_varint_decode, unsigned_varint_encode,
+    replica::ReplicaManager,
+    request::{
+        ErrorCode, IntoDelayedResponse, Request, RequestHeader, TryGet, encode_current_leader,
+        encode_node_endpoints, read_compact_len, read_uvarint, skip_tagged_fields,
+        write_tagged_fields,
+    },
+    unsigned_varint_encode,
 };
 
+// Isolation level of consumers that only see committed transactional records
+const READ_COMMITTED: i8 = 1;
+// Tagged field carrying the fetching replica from v15 on
+const REPLICA_STATE_TAG: u32 = 1;
+// Partition response tagged fields telling a follower where its log diverged, and a client
+// that asked the wrong broker which one leads the partition
+const DIVERGING_EPOCH_TAG: u32 = 0;
+const CURRENT_LEADER_TAG: u32 = 1;
+// Response tagged field with the endpoints of those leaders, from v16 on
+const NODE_ENDPOINTS_TAG: u32 = 0;
+
 #[derive(Debug)]
 pub struct FetchRequest {
     header: RequestHeader,
     metadata: Arc<Box<[RecordBatch]>>,
+    logs: Arc<LogManager>,
+    sessions: Arc<FetchSessionCache>,
+    replicas: Arc<ReplicaManager>,
+    brokers: Arc<BrokerRegistry>,
+    // A follower's node id, -1 for consumers
+    replica_id: i32,
     max_wait: i32,
     min_bytes: i32,
     max_bytes: i32,
@@ -26,50 +52,85 @@ pub struct FetchRequest {
 }
 
 impl FetchRequest {
-    pub fn new(req: Request, metadata: Arc<Box<[RecordBatch]>>) -> Self {
+    pub fn new(
+        req: Request,
+        metadata: Arc<Box<[RecordBatch]>>,
+        logs: Arc<LogManager>,
+        sessions: Arc<FetchSessionCache>,
+        replicas: Arc<ReplicaManager>,
+        brokers: Arc<BrokerRegistry>,
+    ) -> Result<Self> {
         let mut payload = req.payload;
-        let max_wait = payload.get_i32();
-        let min_bytes = payload.get_i32();
-        let max_bytes = payload.get_i32();
-        let isolation_level = payload.get_i8();
-        let session_id = payload.get_i32();
-        let session_epoch = payload.get_i32();
-        let topics_len = unsigned_varint_decode(&mut payload);
+        // Moved into a tagged field from v15 on
+        let mut replica_id = if req.header.api_version < 15 {
+            payload.try_get_i32()?
+        } else {
+            -1
+        };
+        let max_wait = payload.try_get_i32()?;
+        let min_bytes = payload.try_get_i32()?;
+        let max_bytes = payload.try_get_i32()?;
+        let isolation_level = payload.try_get_i8()?;
+        let session_id = payload.try_get_i32()?;
+        let session_epoch = payload.try_get_i32()?;
+        let topics_len = read_compact_len(&mut payload)?;
         let topics = (0..topics_len as usize)
-            .into_iter()
             .map(|_| {
-                let uuid = Uuid::from_u128(payload.get_u128());
-                let partition_len = unsigned_varint_decode(&mut payload);
+                let uuid = Uuid::from_u128(payload.try_get_u128()?);
+                let partition_len = read_compact_len(&mut payload)?;
                 let partitions = (0..partition_len as usize)
-                    .map(|_| PartitionRequest {
-                        partition_id: payload.get_i32(),
-                        current_leader_epoch: payload.get_i32(),
-                        fetch_offset: payload.get_i64(),
-                        last_fetched_epoch: payload.get_i32(),
-                        log_start_offset: payload.get_i64(),
-                        partition_max_bytes: payload.get_i32(),
+                    .map(|_| {
+                        let partition = PartitionRequest {
+                            partition_id: payload.try_get_i32()?,
+                            current_leader_epoch: payload.try_get_i32()?,
+                            fetch_offset: payload.try_get_i64()?,
+                            last_fetched_epoch: payload.try_get_i32()?,
+                            log_start_offset: payload.try_get_i64()?,
+                            partition_max_bytes: payload.try_get_i32()?,
+                        };
+                        skip_tagged_fields(&mut payload)?;
+                        Ok(partition)
                     })
-                    .collect::<Vec<PartitionRequest>>();
+                    .collect::<Result<Vec<PartitionRequest>>>()?;
+                skip_tagged_fields(&mut payload)?;
 
-                (uuid, partitions.into_boxed_slice())
+                Ok((uuid, partitions.into_boxed_slice()))
             })
-            .collect::<Vec<(Uuid, Box<[PartitionRequest]>)>>();
+            .collect::<Result<Vec<(Uuid, Box<[PartitionRequest]>)>>>()?;
 
-        let forgotten_topics_len = unsigned_varint_decode(&mut payload);
-        let forgotten_topics = (0..forgotten_topics_len as usize)
-            .map(|_| {
-                let topic_id = Uuid::from_u128(payload.get_u128());
-                let partitions = payload.get_i32();
-                (topic_id, partitions)
-            })
-            .collect::<Vec<(Uuid, i32)>>();
-        let rack_id_len = unsigned_varint_decode(&mut payload);
+        // Partitions an incremental fetch drops from its session
+        let forgotten_topics_len = read_compact_len(&mut payload)?;
+        let mut forgotten_topics = Vec::new();
+        for _ in 0..forgotten_topics_len {
+            let topic_id = Uuid::from_u128(payload.try_get_u128()?);
+            let partitions_len = read_compact_len(&mut payload)?;
+            for _ in 0..partitions_len {
+                forgotten_topics.push((topic_id, payload.try_get_i32()?));
+            }
+            skip_tagged_fields(&mut payload)?;
+        }
+        let rack_id_len = read_compact_len(&mut payload)?;
         let rack_id = Bytes::copy_from_slice(&payload[..rack_id_len as usize]);
-        payload.advance(rack_id_len as usize);
+        payload.try_advance(rack_id_len as usize)?;
 
-        Self {
+        let tags_len = read_uvarint(&mut payload)?;
+        for _ in 0..tags_len {
+            let tag = read_uvarint(&mut payload)?;
+            let size = read_uvarint(&mut payload)?;
+            let mut field = payload.try_split_to(size as usize)?;
+            if tag == REPLICA_STATE_TAG {
+                replica_id = field.try_get_i32()?;
+            }
+        }
+
+        Ok(Self {
             header: req.header,
             metadata,
+            logs,
+            sessions,
+            replicas,
+            brokers,
+            replica_id,
             max_wait,
             min_bytes,
             max_bytes,
@@ -79,26 +140,10 @@ impl FetchRequest {
             topics: topics.into_boxed_slice(),
             forgotten_topics: forgotten_topics.into_boxed_slice(),
             rack_id,
-        }
+        })
     }
 
-    pub fn no_topic_response(&self, content: &mut BytesMut) {
-        let throttle_time = 0;
-        let error_code = ErrorCode::None as i16;
-        let responses_len = 1;
-
-        content.put_i32(self.header.correlation_id);
-        content.put_i8(0x00);
-        content.put_i32(throttle_time);
-        content.put_i16(error_code);
-        content.put_i32(self.session_id);
-        content.put_i8(responses_len);
-        content.put_i8(0x00);
-    }
-
-    pub fn unknown_topics_response(&self, content: &mut BytesMut) {
-        // Partitions Array length
-        unsigned_varint_encode(content, 1);
+    pub fn unknown_topic_response(&self, content: &mut BytesMut) {
         content.put_i32(0);
         content.put_i16(ErrorCode::UnknownTopicId as i16);
 
@@ -117,9 +162,280 @@ impl FetchRequest {
 
         content.put_i8(0x00);
     }
+
+    /// Writes a partition's response, unless the fetch is incremental and nothing changed
+    /// since the session's last response. Returns whether it was written.
+    fn partition_response(
+        &self,
+        content: &mut BytesMut,
+        context: &FetchContext,
+        uuid: &Uuid,
+        partition: &PartitionRequest,
+        fetched: Result<FetchedPartition, ErrorCode>,
+        leaders: &mut BTreeSet<i32>,
+    ) -> bool {
+        let id = partition.partition_id;
+
+        let changed = context.session_id == INVALID_SESSION_ID || {
+            let offsets = fetched.as_ref().ok().map(|fetched| {
+                (
+                    fetched.high_watermark,
+                    fetched.last_stable_offset,
+                    fetched.log_start_offset,
+                )
+            });
+            let has_records = fetched.as_ref().is_ok_and(|fetched| {
+                !fetched.records.is_empty() || fetched.diverging_epoch.is_some()
+            });
+            self.sessions
+                .update_partition(context.session_id, uuid, id, offsets, has_records)
+        };
+        if context.incremental && !changed {
+            return false;
+        }
+
+        let fetched = match fetched {
+            Ok(fetched) => fetched,
+            Err(error_code) => {
+                let current_leader = match error_code {
+                    ErrorCode::NotLeaderOrFollower | ErrorCode::FencedLeaderEpoch => {
+                        self.current_leader(uuid, id)
+                    }
+                    _ => None,
+                };
+                leaders.extend(current_leader.map(|(leader_id, _)| leader_id));
+                self.partition_error(content, id, error_code, current_leader);
+                return true;
+            }
+        };
+
+        content.put_i32(id);
+        content.put_i16(ErrorCode::None as i16);
+        // High Watermark
+        content.put_i64(fetched.high_watermark);
+        // Last Stable Offset
+        content.put_i64(fetched.last_stable_offset);
+        // Log start offset
+        content.put_i64(fetched.log_start_offset);
+        // Aborted Txns, null for read_uncommitted consumers
+        match fetched.aborted {
+            Some(aborted) => {
+                unsigned_varint_encode(content, aborted.len());
+                for txn in aborted {
+                    content.put_i64(txn.producer_id);
+                    content.put_i64(txn.first_offset);
+                    content.put_i8(0x00);
+                }
+            }
+            None => content.put_i8(0x00),
+        }
+        // Prefered Read Replica
+        content.put_i32(-1);
+        // Compact Records
+        unsigned_varint_encode(content, fetched.records.len());
+        content.put(fetched.records);
+
+        // Tags
+        match fetched.diverging_epoch {
+            Some((epoch, end_offset)) => {
+                let mut field = BytesMut::new();
+                field.put_i32(epoch);
+                field.put_i64(end_offset);
+                // Tags
+                field.put_i8(0x00);
+                write_tagged_fields(content, &[(DIVERGING_EPOCH_TAG, field)]);
+            }
+            None => content.put_i8(0x00),
+        }
+        true
+    }
+
+    fn fetch_partition(
+        &self,
+        uuid: &Uuid,
+        partition: &PartitionRequest,
+    ) -> Result<FetchedPartition, ErrorCode> {
+        let id = partition.partition_id;
+        let topic_name = self
+            .metadata
+            .iter()
+            .find_map(|record| record.get_topic_name(uuid))
+            .filter(|_| {
+                self.metadata
+                    .iter()
+                    .any(|record| record.valid_partition(uuid, id))
+            });
+
+        let Some(topic_name) = topic_name else {
+            return Err(ErrorCode::UnknownTopicOrPartition);
+        };
+        self.replicas
+            .check_leader_epoch(&topic_name, id, partition.current_leader_epoch)?;
+        // Consumers read from the leader, followers' own fetches are checked below
+        if self.replica_id < 0 {
+            self.replicas.check_leader(&topic_name, id)?;
+        }
+
+        // A follower's fetch offset is how far it has replicated the log
+        if self.replica_id >= 0 {
+            self.replicas.record_follower_fetch(
+                &topic_name,
+                id,
+                self.replica_id,
+                partition.fetch_offset,
+            )?;
+        }
+
+        let fetched = self.logs.with_partition(&topic_name, id, |log| {
+            let fetch_offset = partition.fetch_offset;
+
+            // Where the fetcher's last epoch ends here tells whether its log still matches,
+            // otherwise it has to truncate before fetching on
+            if partition.last_fetched_epoch >= 0 {
+                let (epoch, end_offset) = log.end_offset_for_epoch(partition.last_fetched_epoch);
+                if epoch != partition.last_fetched_epoch || end_offset < fetch_offset {
+                    return Ok(Ok(FetchedPartition {
+                        high_watermark: log.high_watermark(),
+                        last_stable_offset: log.last_stable_offset(),
+                        log_start_offset: log.log_start_offset(),
+                        aborted: None,
+                        records: Bytes::new(),
+                        diverging_epoch: Some((epoch, end_offset)),
+                    }));
+                }
+            }
+
+            if fetch_offset < log.log_start_offset() || fetch_offset > log.log_end_offset() {
+                return Ok(Err(ErrorCode::OffsetOutOfRange));
+            }
+
+            // Followers copy everything, committed or not. read_committed consumers stop at
+            // the last stable offset and are told which transactions below it were aborted
+            let (max_offset, aborted) = if self.replica_id >= 0 {
+                (log.log_end_offset(), None)
+            } else if self.isolation_level == READ_COMMITTED {
+                let last_stable_offset = log.last_stable_offset();
+                let aborted = log.aborted_transactions(fetch_offset, last_stable_offset);
+                (last_stable_offset, Some(aborted))
+            } else {
+                (log.high_watermark(), None)
+            };
+
+            let records = log.read(
+                fetch_offset,
+                max_offset,
+                partition.partition_max_bytes.max(0) as usize,
+            )?;
+
+            Ok(Ok(FetchedPartition {
+                high_watermark: log.high_watermark(),
+                last_stable_offset: log.last_stable_offset(),
+                log_start_offset: log.log_start_offset(),
+                aborted,
+                records,
+                diverging_epoch: None,
+            }))
+        });
+
+        match fetched {
+            Ok(result) => result,
+            Err(err) => {
+                eprintln!("reading {topic_name:?}-{id}: {err:#}");
+                Err(ErrorCode::KafkaStorageError)
+            }
+        }
+    }
+
+    /// Reads every partition of the session, `None` standing for those of unknown topics.
+    fn fetch_all(
+        &self,
+        context: &FetchContext,
+    ) -> Vec<Option<Result<FetchedPartition, ErrorCode>>> {
+        context
+            .partitions
+            .iter()
+            .map(|(uuid, partition)| {
+                let contains_topic = self.metadata.iter().any(|record| record.has_topic(uuid));
+                contains_topic.then(|| self.fetch_partition(uuid, partition))
+            })
+            .collect()
+    }
+
+    /// Whether the fetch can be answered without waiting for more records: there are at
+    /// least `min_bytes` of them, or something the fetcher has to act on.
+    fn satisfied(&self, fetched: &[Option<Result<FetchedPartition, ErrorCode>>]) -> bool {
+        let mut bytes = 0;
+        for fetched in fetched {
+            match fetched {
+                Some(Ok(fetched)) if fetched.diverging_epoch.is_none() => {
+                    bytes += fetched.records.len();
+                }
+                _ => return true,
+            }
+        }
+
+        bytes >= self.min_bytes.max(0) as usize
+    }
+
+    /// The partition's leader, for clients to be redirected to.
+    fn current_leader(&self, uuid: &Uuid, id: i32) -> Option<(i32, i32)> {
+        let topic_name = self
+            .metadata
+            .iter()
+            .find_map(|record| record.get_topic_name(uuid))?;
+        self.replicas
+            .leader(&topic_name, id)
+            .filter(|(leader_id, _)| *leader_id >= 0)
+    }
+
+    fn partition_error(
+        &self,
+        content: &mut BytesMut,
+        id: i32,
+        error_code: ErrorCode,
+        current_leader: Option<(i32, i32)>,
+    ) {
+        content.put_i32(id);
+        content.put_i16(error_code as i16);
+        // High Watermark
+        content.put_i64(-1);
+        // Last Stable Offset
+        content.put_i64(-1);
+        // Log start offset
+        content.put_i64(-1);
+        // Aborted Txns length
+        unsigned_varint_encode(content, 0);
+        // Prefered Read Replica
+        content.put_i32(-1);
+        // Records length
+        unsigned_varint_encode(content, 0);
+
+        // Tags
+        match current_leader {
+            Some((leader_id, leader_epoch)) => write_tagged_fields(
+                content,
+                &[(
+                    CURRENT_LEADER_TAG,
+                    encode_current_leader(leader_id, leader_epoch),
+                )],
+            ),
+            None => content.put_i8(0x00),
+        }
+    }
 }
 
 #[derive(Debug)]
+struct FetchedPartition {
+    high_watermark: i64,
+    last_stable_offset: i64,
+    log_start_offset: i64,
+    aborted: Option<Vec<AbortedTxn>>,
+    records: Bytes,
+    // Epoch and end offset of the leader's log, where a follower's log diverged from it
+    diverging_epoch: Option<(i32, i64)>,
+}
+
+#[derive(Debug, Clone)]
 pub struct PartitionRequest {
     pub partition_id: i32,
     pub current_leader_epoch: i32,
@@ -129,77 +445,119 @@ pub struct PartitionRequest {
     pub partition_max_bytes: i32,
 }
 
-impl IntoResponse for FetchRequest {
-    fn response(&self) -> BytesMut {
-        let mut content = BytesMut::new();
-        if self.topics.is_empty() {
-            self.no_topic_response(&mut content);
-            return content;
-        }
-
+/// Fetches are held for up to `max_wait_ms` until `min_bytes` of records are there to send
+/// back, rather than have consumers and followers poll an idle log.
+impl IntoDelayedResponse for FetchRequest {
+    async fn response(self) -> BytesMut {
         let mut content = BytesMut::new();
         let throttle_time = 0;
-        let error_code = ErrorCode::None as i16;
+        let partitions = self
+            .topics
+            .iter()
+            .flat_map(|(uuid, partitions)| partitions.iter().map(|p| (*uuid, p.clone())))
+            .collect();
+        let context = self.sessions.new_context(
+            self.session_id,
+            self.session_epoch,
+            partitions,
+            &self.forgotten_topics,
+        );
 
         content.put_i32(self.header.correlation_id);
         content.put_i8(0x00);
         content.put_i32(throttle_time);
-        content.put_i16(error_code);
-        content.put_i32(self.session_id);
 
-        // Responses Length
-        unsigned_varint_encode(&mut content, self.topics.len());
+        let context = match context {
+            Ok(context) => context,
+            Err(error_code) => {
+                content.put_i16(error_code as i16);
+                content.put_i32(INVALID_SESSION_ID);
+                // Responses Length
+                unsigned_varint_encode(&mut content, 0);
+                content.put_i8(0x00);
+                return content;
+            }
+        };
 
-        for topic in self.topics.iter() {
-            let (uuid, req_partition) = topic;
-            content.put_u128(uuid.as_u128());
+        let deadline =
+            tokio::time::Instant::now() + Duration::from_millis(self.max_wait.max(0) as u64);
+        let fetched = loop {
+            // Registered before reading so an append in between is not missed
+            let moved = self.logs.offsets_moved();
+            tokio::pin!(moved);
+            moved.as_mut().enable();
 
-            let contains_topic = self.metadata.iter().any(|record| record.has_topic(&uuid));
-            if !contains_topic {
-                self.unknown_topics_response(&mut content);
-            } else {
-                // Partitions Array length
-                unsigned_varint_encode(&mut content, req_partition.len());
-                for partition in req_partition.iter() {
-                    let id = partition.partition_id;
-                    content.put_i32(id);
-                    content.put_i16(ErrorCode::None as i16);
-
-                    // High Watermark
-                    content.put_i64(0);
-                    // Last Stable Offset
-                    content.put_i64(0);
-                    // Log start offset
-                    content.put_i64(0);
-                    // Aborted Txns length
-                    unsigned_varint_encode(&mut content, 0);
-                    // Prefered Read Replica
-                    content.put_i32(0);
-
-                    let log_records = self
-                        .metadata
-                        .iter()
-                        .filter_map(|record| record.read_log_file(uuid, id))
-                        .fold(BytesMut::new(), |mut acc, log_file| {
-                            acc.put(log_file);
-                            acc
-                        })
-                        .freeze();
-
-                    // Compact Records
-                    unsigned_varint_encode(&mut content, log_records.len());
-                    content.put(log_records);
+            let fetched = self.fetch_all(&context);
+            if self.satisfied(&fetched) || tokio::time::timeout_at(deadline, moved).await.is_err() {
+                break fetched;
+            }
+        };
 
-                    content.put_i8(0x00);
+        content.put_i16(ErrorCode::None as i16);
+        content.put_i32(context.session_id);
+
+        // Leaders the client is redirected to, whose endpoints go along
+        let mut leaders = BTreeSet::new();
+        // Topic id, partition count and encoded partitions, in the order topics are fetched
+        let mut responses: Vec<(Uuid, usize, BytesMut)> = Vec::new();
+        for ((uuid, partition), fetched) in context.partitions.iter().zip(fetched) {
+            let index = match responses.iter().position(|(id, ..)| id == uuid) {
+                Some(index) => index,
+                None => {
+                    responses.push((*uuid, 0, BytesMut::new()));
+                    responses.len() - 1
+                }
+            };
+            let (_, count, partitions) = &mut responses[index];
+
+            match fetched {
+                // Unknown topics get a single error entry, whatever partitions were asked for
+                None => {
+                    if *count == 0 {
+                        self.unknown_topic_response(partitions);
+                        *count += 1;
+                    }
+                }
+                Some(fetched) => {
+                    if self.partition_response(
+                        partitions,
+                        &context,
+                        uuid,
+                        partition,
+                        fetched,
+                        &mut leaders,
+                    ) {
+                        *count += 1;
+                    }
                 }
             }
+        }
+        responses.retain(|(_, count, _)| *count > 0);
+
+        // Responses Length
+        unsigned_varint_encode(&mut content, responses.len());
+        for (uuid, count, partitions) in responses {
+            content.put_u128(uuid.as_u128());
+            // Partitions Array length
+            unsigned_varint_encode(&mut content, count);
+            content.put(partitions);
 
             // Final tags to add
             content.put_i8(0x00);
         }
 
         // Tags again
-        content.put_i8(0x00);
+        if self.header.api_version >= 16 && !leaders.is_empty() {
+            write_tagged_fields(
+                &mut content,
+                &[(
+                    NODE_ENDPOINTS_TAG,
+                    encode_node_endpoints(&self.brokers, leaders),
+                )],
+            );
+        } else {
+            content.put_i8(0x00);
+        }
 
         content
     }
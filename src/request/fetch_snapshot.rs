@@ -0,0 +1,178 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    metadata::METADATA_TOPIC,
+    raft::RaftQuorum,
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, encode_current_leader,
+        read_compact_len, read_compact_nullable_string, read_compact_string, read_uvarint,
+        skip_tagged_fields, write_compact_string, write_tagged_fields,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+// Request tagged field with the cluster id, and partition response one with the leader
+const CLUSTER_ID_TAG: u32 = 0;
+const CURRENT_LEADER_TAG: u32 = 0;
+
+#[derive(Debug)]
+struct SnapshotPartition {
+    partition: i32,
+    current_leader_epoch: i32,
+    end_offset: i64,
+    epoch: i32,
+    position: i64,
+}
+
+#[derive(Debug)]
+pub struct FetchSnapshotRequest {
+    header: RequestHeader,
+    quorum: Arc<RaftQuorum>,
+    cluster_id: Option<Bytes>,
+    max_bytes: i32,
+    topics: Box<[(Bytes, Box<[SnapshotPartition]>)]>,
+}
+
+impl FetchSnapshotRequest {
+    pub fn new(req: Request, quorum: Arc<RaftQuorum>) -> Result<Self> {
+        let mut payload = req.payload;
+
+        let _replica_id = payload.try_get_i32()?;
+        let max_bytes = payload.try_get_i32()?;
+        let topics_len = read_compact_len(&mut payload)?;
+        let topics = (0..topics_len)
+            .map(|_| {
+                let topic_name = read_compact_string(&mut payload)?;
+                let partitions_len = read_compact_len(&mut payload)?;
+                let partitions = (0..partitions_len)
+                    .map(|_| {
+                        let partition = payload.try_get_i32()?;
+                        let current_leader_epoch = payload.try_get_i32()?;
+                        let end_offset = payload.try_get_i64()?;
+                        let epoch = payload.try_get_i32()?;
+                        skip_tagged_fields(&mut payload)?;
+                        let partition = SnapshotPartition {
+                            partition,
+                            current_leader_epoch,
+                            end_offset,
+                            epoch,
+                            position: payload.try_get_i64()?,
+                        };
+                        skip_tagged_fields(&mut payload)?;
+                        Ok(partition)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                skip_tagged_fields(&mut payload)?;
+
+                Ok((topic_name, partitions.into_boxed_slice()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut cluster_id = None;
+        let tags_len = read_uvarint(&mut payload)?;
+        for _ in 0..tags_len {
+            let tag = read_uvarint(&mut payload)?;
+            let size = read_uvarint(&mut payload)?;
+            let mut field = payload.try_split_to(size as usize)?;
+            if tag == CLUSTER_ID_TAG {
+                cluster_id = read_compact_nullable_string(&mut field)?;
+            }
+        }
+
+        Ok(Self {
+            header: req.header,
+            quorum,
+            cluster_id,
+            max_bytes,
+            topics: topics.into_boxed_slice(),
+        })
+    }
+
+    /// Refuses the whole request, answering no partition.
+    pub fn error_response(header: &RequestHeader, error_code: ErrorCode) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        content.put_i32(header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+        content.put_i16(error_code as i16);
+        unsigned_varint_encode(&mut content, 0);
+        content.put_i8(0x00);
+
+        content
+    }
+}
+
+impl IntoResponse for FetchSnapshotRequest {
+    fn response(&self) -> BytesMut {
+        if let Err(error_code) = self.quorum.check_cluster_id(self.cluster_id.as_deref()) {
+            return Self::error_response(&self.header, error_code);
+        }
+
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+        content.put_i16(ErrorCode::None as i16);
+
+        let mut max_bytes = self.max_bytes.max(0) as usize;
+        unsigned_varint_encode(&mut content, self.topics.len());
+        for (topic_name, partitions) in self.topics.iter() {
+            write_compact_string(&mut content, topic_name);
+            unsigned_varint_encode(&mut content, partitions.len());
+            for partition in partitions.iter() {
+                let read = match &topic_name[..] == METADATA_TOPIC && partition.partition == 0 {
+                    true => self.quorum.read_snapshot(
+                        partition.current_leader_epoch,
+                        partition.end_offset,
+                        partition.epoch,
+                        partition.position,
+                        max_bytes,
+                    ),
+                    false => Err(ErrorCode::UnknownTopicOrPartition),
+                };
+                let (error_code, size, records) = match read {
+                    Ok((size, records)) => (ErrorCode::None, size, records),
+                    Err(error_code) => (error_code, -1, Bytes::new()),
+                };
+                max_bytes -= records.len();
+
+                content.put_i32(partition.partition);
+                content.put_i16(error_code as i16);
+                // Snapshot id
+                content.put_i64(partition.end_offset);
+                content.put_i32(partition.epoch);
+                // Tags
+                content.put_i8(0x00);
+                content.put_i64(size);
+                content.put_i64(partition.position);
+                unsigned_varint_encode(&mut content, records.len());
+                content.put(records);
+
+                // Tags
+                let (leader_id, leader_epoch) = self.quorum.current_leader();
+                write_tagged_fields(
+                    &mut content,
+                    &[(
+                        CURRENT_LEADER_TAG,
+                        encode_current_leader(leader_id, leader_epoch),
+                    )],
+                );
+            }
+            // Tags
+            content.put_i8(0x00);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
@@ -0,0 +1,132 @@
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use std::sync::Arc;
+
+use crate::{
+    cluster::BrokerRegistry,
+    config::BrokerConfig,
+    offsets::partition_for,
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_string, skip_tagged_fields, write_compact_nullable_string,
+        write_compact_string,
+    },
+    txn::transaction_partition_for,
+    unsigned_varint_encode,
+};
+
+const GROUP_KEY_TYPE: i8 = 0;
+const TRANSACTION_KEY_TYPE: i8 = 1;
+
+#[derive(Debug)]
+pub struct FindCoordinatorRequest {
+    header: RequestHeader,
+    config: Arc<BrokerConfig>,
+    brokers: Arc<BrokerRegistry>,
+    key_type: i8,
+    coordinator_keys: Box<[Bytes]>,
+}
+
+impl FindCoordinatorRequest {
+    pub fn new(
+        req: Request,
+        config: Arc<BrokerConfig>,
+        brokers: Arc<BrokerRegistry>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+
+        // Up to v3 a single key is looked up, later versions batch them
+        let (key_type, coordinator_keys) = if req.header.api_version < 4 {
+            let key = read_compact_string(&mut payload)?;
+            (payload.try_get_i8()?, vec![key])
+        } else {
+            let key_type = payload.try_get_i8()?;
+            let keys_len = read_compact_len(&mut payload)?;
+            let keys = (0..keys_len)
+                .map(|_| read_compact_string(&mut payload))
+                .collect::<Result<Vec<Bytes>>>()?;
+            (key_type, keys)
+        };
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            config,
+            brokers,
+            key_type,
+            coordinator_keys: coordinator_keys.into_boxed_slice(),
+        })
+    }
+
+    /// The broker leading the internal topic partition the key hashes to, with its
+    /// endpoint on the listener clients are given.
+    fn coordinator(&self, key: &[u8]) -> (ErrorCode, i32, Bytes, i32) {
+        let partition = match self.key_type {
+            GROUP_KEY_TYPE => partition_for(key),
+            TRANSACTION_KEY_TYPE => transaction_partition_for(key),
+            _ => return (ErrorCode::InvalidRequest, -1, Bytes::new(), -1),
+        };
+
+        let node_id = self.brokers.coordinator(partition);
+        if node_id == self.config.node_id {
+            let (host, port) = self.config.advertised_endpoint();
+            return (ErrorCode::None, node_id, Bytes::from(host), port);
+        }
+
+        let endpoint = self
+            .brokers
+            .brokers(false)
+            .into_iter()
+            .find(|broker| broker.id == node_id)
+            .and_then(|broker| broker.endpoint(self.brokers.listener_name()).cloned());
+        match endpoint {
+            Some(endpoint) => (
+                ErrorCode::None,
+                node_id,
+                endpoint.host,
+                endpoint.port as i32,
+            ),
+            None => (ErrorCode::CoordinatorNotAvailable, -1, Bytes::new(), -1),
+        }
+    }
+}
+
+impl IntoResponse for FindCoordinatorRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+
+        if self.header.api_version < 4 {
+            let (error_code, node_id, host, port) = self.coordinator(&self.coordinator_keys[0]);
+            content.put_i16(error_code as i16);
+            // Error message
+            write_compact_nullable_string(&mut content, None);
+            content.put_i32(node_id);
+            write_compact_string(&mut content, &host);
+            content.put_i32(port);
+        } else {
+            unsigned_varint_encode(&mut content, self.coordinator_keys.len());
+            for key in self.coordinator_keys.iter() {
+                let (error_code, node_id, host, port) = self.coordinator(key);
+                write_compact_string(&mut content, key);
+                content.put_i32(node_id);
+                write_compact_string(&mut content, &host);
+                content.put_i32(port);
+                content.put_i16(error_code as i16);
+                // Error message
+                write_compact_nullable_string(&mut content, None);
+                // Tags
+                content.put_i8(0x00);
+            }
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
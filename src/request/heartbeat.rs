@@ -0,0 +1,80 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    cluster::BrokerRegistry,
+    group::GroupCoordinator,
+    offsets::partition_for,
+    request::{
+        IntoResponse, Request, RequestHeader, TryGet, read_compact_nullable_string,
+        read_compact_string, skip_tagged_fields,
+    },
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct HeartbeatRequest {
+    header: RequestHeader,
+    groups: Arc<GroupCoordinator>,
+    brokers: Arc<BrokerRegistry>,
+    group_id: Bytes,
+    generation_id: i32,
+    member_id: Bytes,
+    group_instance_id: Option<Bytes>,
+}
+
+impl HeartbeatRequest {
+    pub fn new(
+        req: Request,
+        groups: Arc<GroupCoordinator>,
+        brokers: Arc<BrokerRegistry>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+        let group_id = read_compact_string(&mut payload)?;
+        let generation_id = payload.try_get_i32()?;
+        let member_id = read_compact_string(&mut payload)?;
+        let group_instance_id = read_compact_nullable_string(&mut payload)?;
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            groups,
+            brokers,
+            group_id,
+            generation_id,
+            member_id,
+            group_instance_id,
+        })
+    }
+}
+
+impl IntoResponse for HeartbeatRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+        let error_code = match self
+            .brokers
+            .check_coordinator(partition_for(&self.group_id))
+        {
+            Ok(()) => self.groups.heartbeat(
+                &self.group_id,
+                self.generation_id,
+                &self.member_id,
+                self.group_instance_id.as_ref(),
+            ),
+            Err(error_code) => error_code,
+        };
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+        content.put_i16(error_code as i16);
+        // Tags
+        content.put_i8(0x00);
+
+        content
+    }
+}
@@ -0,0 +1,124 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    config::{ConfigError, ConfigManager, ConfigOperation},
+    request::{
+        IntoDelayedResponse, Request, RequestHeader, TryGet, alter_configs::write_alter_result,
+        read_compact_len, read_compact_nullable_string, read_compact_string, skip_tagged_fields,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+struct Resource {
+    resource_type: i8,
+    resource_name: Bytes,
+    // Operations as sent, an unknown one failing the whole resource
+    configs: Box<[(Bytes, i8, Option<Bytes>)]>,
+}
+
+/// Sets, deletes, appends to or subtracts from individual configs, leaving the others
+/// untouched.
+#[derive(Debug)]
+pub struct IncrementalAlterConfigsRequest {
+    header: RequestHeader,
+    configs: Arc<ConfigManager>,
+    resources: Box<[Resource]>,
+    validate_only: bool,
+}
+
+impl IncrementalAlterConfigsRequest {
+    pub fn new(req: Request, configs: Arc<ConfigManager>) -> Result<Self> {
+        let mut payload = req.payload;
+
+        let resources_len = read_compact_len(&mut payload)?;
+        let resources = (0..resources_len)
+            .map(|_| {
+                let resource_type = payload.try_get_i8()?;
+                let resource_name = read_compact_string(&mut payload)?;
+                let configs_len = read_compact_len(&mut payload)?;
+                let configs = (0..configs_len)
+                    .map(|_| {
+                        let name = read_compact_string(&mut payload)?;
+                        let operation = payload.try_get_i8()?;
+                        let value = read_compact_nullable_string(&mut payload)?;
+                        skip_tagged_fields(&mut payload)?;
+                        Ok((name, operation, value))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                skip_tagged_fields(&mut payload)?;
+
+                Ok(Resource {
+                    resource_type,
+                    resource_name,
+                    configs: configs.into_boxed_slice(),
+                })
+            })
+            .collect::<Result<Vec<Resource>>>()?;
+        let validate_only = payload.try_get_i8()? != 0;
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            configs,
+            resources: resources.into_boxed_slice(),
+            validate_only,
+        })
+    }
+
+    async fn alter(&self, resource: &Resource) -> Result<(), ConfigError> {
+        let changes = resource
+            .configs
+            .iter()
+            .map(|(name, operation, value)| {
+                let operation =
+                    ConfigOperation::try_from(*operation).map_err(|error_code| ConfigError {
+                        error_code,
+                        message: format!("Unknown config operation {operation}"),
+                    })?;
+                Ok((name.clone(), operation, value.clone()))
+            })
+            .collect::<Result<Vec<_>, ConfigError>>()?;
+
+        self.configs
+            .alter(
+                resource.resource_type,
+                &resource.resource_name,
+                &changes,
+                false,
+                self.validate_only,
+            )
+            .await
+    }
+}
+
+impl IntoDelayedResponse for IncrementalAlterConfigsRequest {
+    async fn response(self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+
+        unsigned_varint_encode(&mut content, self.resources.len());
+        for resource in self.resources.iter() {
+            let result = self.alter(resource).await;
+            write_alter_result(
+                &mut content,
+                resource.resource_type,
+                &resource.resource_name,
+                result,
+            );
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
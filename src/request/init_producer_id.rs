@@ -0,0 +1,126 @@
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    cluster::BrokerRegistry,
+    producer::ProducerIdManager,
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, read_compact_nullable_string,
+    },
+    txn::{TransactionCoordinator, transaction_partition_for},
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct InitProducerIdRequest {
+    header: RequestHeader,
+    producer_ids: Arc<ProducerIdManager>,
+    transactions: Arc<TransactionCoordinator>,
+    brokers: Arc<BrokerRegistry>,
+    transactional_id: Option<Bytes>,
+    transaction_timeout: i32,
+    producer_id: i64,
+    producer_epoch: i16,
+}
+
+impl InitProducerIdRequest {
+    pub fn new(
+        req: Request,
+        producer_ids: Arc<ProducerIdManager>,
+        transactions: Arc<TransactionCoordinator>,
+        brokers: Arc<BrokerRegistry>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+
+        // Null for a producer that is only idempotent
+        let transactional_id = read_compact_nullable_string(&mut payload)?;
+        let transaction_timeout = payload.try_get_i32()?;
+
+        let (producer_id, producer_epoch) = if req.header.api_version >= 3 {
+            (payload.try_get_i64()?, payload.try_get_i16()?)
+        } else {
+            (-1, -1)
+        };
+
+        // Skip tag buffer
+        payload.try_get_i8()?;
+
+        Ok(Self {
+            header: req.header,
+            producer_ids,
+            transactions,
+            brokers,
+            transactional_id,
+            transaction_timeout,
+            producer_id,
+            producer_epoch,
+        })
+    }
+
+    /// The id and epoch a restarting producer held before (KIP-360), sent from v3 on.
+    fn current_producer(&self) -> Option<(i64, i16)> {
+        (self.producer_id >= 0).then_some((self.producer_id, self.producer_epoch))
+    }
+
+    /// Hands an idempotent producer a new id, or bumps the epoch of the one it already
+    /// holds, so its sequences restart without losing the id. An exhausted epoch moves
+    /// the producer to a new id.
+    fn init_idempotent_producer(&self) -> Result<(i64, i16), ErrorCode> {
+        match self.current_producer() {
+            Some((producer_id, producer_epoch))
+                if producer_epoch < 0 || !self.producer_ids.is_issued(producer_id) =>
+            {
+                Err(ErrorCode::InvalidProducerEpoch)
+            }
+            Some((producer_id, producer_epoch)) if producer_epoch < i16::MAX => {
+                Ok((producer_id, producer_epoch + 1))
+            }
+            _ => match self.producer_ids.generate() {
+                Ok(producer_id) => Ok((producer_id, 0)),
+                Err(err) => {
+                    eprintln!("allocating producer id: {err:#}");
+                    Err(ErrorCode::CoordinatorNotAvailable)
+                }
+            },
+        }
+    }
+}
+
+impl IntoResponse for InitProducerIdRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        // Transactional producers are tracked by the coordinator, which bumps the epoch of
+        // an existing id instead of handing out a new one
+        let initialized = match &self.transactional_id {
+            Some(transactional_id) => self
+                .brokers
+                .check_coordinator(transaction_partition_for(transactional_id))
+                .and_then(|()| {
+                    self.transactions.init_producer_id(
+                        transactional_id,
+                        self.transaction_timeout,
+                        self.current_producer(),
+                    )
+                }),
+            None => self.init_idempotent_producer(),
+        };
+        let (error_code, producer_id, producer_epoch) = match initialized {
+            Ok((producer_id, producer_epoch)) => (ErrorCode::None, producer_id, producer_epoch),
+            Err(error_code) => (error_code, -1, -1),
+        };
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+        content.put_i16(error_code as i16);
+        content.put_i64(producer_id);
+        content.put_i16(producer_epoch);
+        // Tags
+        content.put_i8(0x00);
+
+        content
+    }
+}
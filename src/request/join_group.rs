@@ -0,0 +1,137 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    cluster::BrokerRegistry,
+    group::{GroupCoordinator, JoinGroupParams, JoinGroupResult},
+    offsets::partition_for,
+    request::{
+        ErrorCode, IntoDelayedResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_nullable_string, read_compact_string, skip_tagged_fields,
+        write_compact_nullable_string, write_compact_string,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct JoinGroupRequest {
+    header: RequestHeader,
+    groups: Arc<GroupCoordinator>,
+    brokers: Arc<BrokerRegistry>,
+    params: JoinGroupParams,
+}
+
+impl JoinGroupRequest {
+    pub fn new(
+        req: Request,
+        groups: Arc<GroupCoordinator>,
+        brokers: Arc<BrokerRegistry>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+        let group_id = read_compact_string(&mut payload)?;
+        let session_timeout_ms = payload.try_get_i32()?;
+        let rebalance_timeout_ms = payload.try_get_i32()?;
+        let member_id = read_compact_string(&mut payload)?;
+        let group_instance_id = read_compact_nullable_string(&mut payload)?;
+        let protocol_type = read_compact_string(&mut payload)?;
+
+        let protocols_len = read_compact_len(&mut payload)?;
+        let protocols = (0..protocols_len)
+            .map(|_| {
+                let name = read_compact_string(&mut payload)?;
+                let metadata = read_compact_string(&mut payload)?;
+                skip_tagged_fields(&mut payload)?;
+
+                Ok((name, metadata))
+            })
+            .collect::<Result<Vec<(Bytes, Bytes)>>>()?;
+
+        if req.header.api_version >= 8 {
+            // Reason
+            read_compact_nullable_string(&mut payload)?;
+        }
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            params: JoinGroupParams {
+                group_id,
+                member_id,
+                group_instance_id,
+                client_id: req.header.client_id.clone(),
+                client_host: req.client_host,
+                session_timeout_ms,
+                rebalance_timeout_ms,
+                protocol_type,
+                protocols,
+                require_known_member_id: true,
+            },
+            header: req.header,
+            groups,
+            brokers,
+        })
+    }
+}
+
+impl IntoDelayedResponse for JoinGroupRequest {
+    async fn response(self) -> BytesMut {
+        let member_id = self.params.member_id.clone();
+        let rejected = |error_code| JoinGroupResult {
+            error_code,
+            generation_id: -1,
+            protocol_type: None,
+            protocol_name: None,
+            leader: Bytes::new(),
+            member_id,
+            members: Vec::new(),
+        };
+        let result = match self
+            .brokers
+            .check_coordinator(partition_for(&self.params.group_id))
+        {
+            Ok(()) => self
+                .groups
+                .join_group(self.params)
+                .await
+                .unwrap_or_else(|_| rejected(ErrorCode::UnknownMemberId)),
+            Err(error_code) => rejected(error_code),
+        };
+
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+        content.put_i16(result.error_code as i16);
+        content.put_i32(result.generation_id);
+        if self.header.api_version >= 7 {
+            write_compact_nullable_string(&mut content, result.protocol_type.as_deref());
+            write_compact_nullable_string(&mut content, result.protocol_name.as_deref());
+        } else {
+            write_compact_string(&mut content, &result.protocol_name.unwrap_or_default());
+        }
+        write_compact_string(&mut content, &result.leader);
+        if self.header.api_version >= 9 {
+            // Skip assignment
+            content.put_u8(0);
+        }
+        write_compact_string(&mut content, &result.member_id);
+
+        unsigned_varint_encode(&mut content, result.members.len());
+        for (member_id, group_instance_id, metadata) in result.members.iter() {
+            write_compact_string(&mut content, member_id);
+            write_compact_nullable_string(&mut content, group_instance_id.as_deref());
+            write_compact_string(&mut content, metadata);
+            // Tags
+            content.put_i8(0x00);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
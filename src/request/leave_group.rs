@@ -0,0 +1,98 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    cluster::BrokerRegistry,
+    group::GroupCoordinator,
+    offsets::partition_for,
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, read_compact_len,
+        read_compact_nullable_string, read_compact_string, skip_tagged_fields,
+        write_compact_nullable_string, write_compact_string,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct LeaveGroupRequest {
+    header: RequestHeader,
+    groups: Arc<GroupCoordinator>,
+    brokers: Arc<BrokerRegistry>,
+    group_id: Bytes,
+    members: Box<[(Bytes, Option<Bytes>)]>,
+}
+
+impl LeaveGroupRequest {
+    pub fn new(
+        req: Request,
+        groups: Arc<GroupCoordinator>,
+        brokers: Arc<BrokerRegistry>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+        let group_id = read_compact_string(&mut payload)?;
+
+        let members_len = read_compact_len(&mut payload)?;
+        let members = (0..members_len)
+            .map(|_| {
+                let member_id = read_compact_string(&mut payload)?;
+                let group_instance_id = read_compact_nullable_string(&mut payload)?;
+                if req.header.api_version >= 5 {
+                    // Reason
+                    read_compact_nullable_string(&mut payload)?;
+                }
+                skip_tagged_fields(&mut payload)?;
+
+                Ok((member_id, group_instance_id))
+            })
+            .collect::<Result<Vec<(Bytes, Option<Bytes>)>>>()?;
+
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            groups,
+            brokers,
+            group_id,
+            members: members.into_boxed_slice(),
+        })
+    }
+}
+
+impl IntoResponse for LeaveGroupRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+        let left = self
+            .brokers
+            .check_coordinator(partition_for(&self.group_id))
+            .and_then(|()| self.groups.leave_group(&self.group_id, &self.members));
+        let (error_code, member_errors) = match left {
+            Ok(member_errors) => (ErrorCode::None, member_errors),
+            Err(error_code) => (error_code, Vec::new()),
+        };
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+        content.put_i16(error_code as i16);
+
+        unsigned_varint_encode(&mut content, member_errors.len());
+        for ((member_id, group_instance_id), error_code) in
+            self.members.iter().zip(member_errors.iter())
+        {
+            write_compact_string(&mut content, member_id);
+            write_compact_nullable_string(&mut content, group_instance_id.as_deref());
+            content.put_i16(*error_code as i16);
+            // Tags
+            content.put_i8(0x00);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
@@ -0,0 +1,124 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    group::{CONSUMER_PROTOCOL_TYPE, GroupCoordinator},
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, read_compact_len, read_compact_string,
+        skip_tagged_fields, write_compact_string,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+const CLASSIC_GROUP_TYPE: &[u8] = b"classic";
+const CONSUMER_GROUP_TYPE: &[u8] = b"consumer";
+
+#[derive(Debug)]
+pub struct ListGroupsRequest {
+    header: RequestHeader,
+    groups: Arc<GroupCoordinator>,
+    states_filter: Box<[Bytes]>,
+    types_filter: Box<[Bytes]>,
+}
+
+impl ListGroupsRequest {
+    pub fn new(req: Request, groups: Arc<GroupCoordinator>) -> Result<Self> {
+        let mut payload = req.payload;
+        let read_filter = |payload: &mut Bytes| {
+            let len = read_compact_len(payload)?;
+            (0..len)
+                .map(|_| read_compact_string(payload))
+                .collect::<Result<Box<[Bytes]>>>()
+        };
+
+        let states_filter = if req.header.api_version >= 4 {
+            read_filter(&mut payload)?
+        } else {
+            Box::default()
+        };
+        let types_filter = if req.header.api_version >= 5 {
+            read_filter(&mut payload)?
+        } else {
+            Box::default()
+        };
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            groups,
+            states_filter,
+            types_filter,
+        })
+    }
+
+    /// An empty filter matches everything. States and types compare case-insensitively,
+    /// as the Kafka tools send them in whatever case the user typed.
+    fn matches(filter: &[Bytes], value: &[u8]) -> bool {
+        filter.is_empty()
+            || filter
+                .iter()
+                .any(|wanted| wanted.eq_ignore_ascii_case(value))
+    }
+}
+
+impl IntoResponse for ListGroupsRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        // Group id, protocol type, state and group type of every group
+        let mut listed: Vec<(Bytes, Bytes, &[u8], &[u8])> = Vec::new();
+        {
+            let groups = self.groups.lock();
+            listed.extend(groups.values().map(|group| {
+                (
+                    group.group_id.clone(),
+                    group.protocol_type.clone().unwrap_or_default(),
+                    group.state.name().as_bytes(),
+                    CLASSIC_GROUP_TYPE,
+                )
+            }));
+
+            listed.extend(self.groups.consumer_lock().values().map(|group| {
+                (
+                    group.group_id.clone(),
+                    Bytes::from_static(CONSUMER_PROTOCOL_TYPE),
+                    group.state.name().as_bytes(),
+                    CONSUMER_GROUP_TYPE,
+                )
+            }));
+        }
+        listed.retain(|(_, _, state, group_type)| {
+            Self::matches(&self.states_filter, state)
+                && Self::matches(&self.types_filter, group_type)
+        });
+        listed.sort();
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+        content.put_i16(ErrorCode::None as i16);
+
+        unsigned_varint_encode(&mut content, listed.len());
+        for (group_id, protocol_type, state, group_type) in listed {
+            write_compact_string(&mut content, &group_id);
+            write_compact_string(&mut content, &protocol_type);
+            if self.header.api_version >= 4 {
+                write_compact_string(&mut content, state);
+            }
+            if self.header.api_version >= 5 {
+                write_compact_string(&mut content, group_type);
+            }
+            // Tags
+            content.put_i8(0x00);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
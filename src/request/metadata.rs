@@ -0,0 +1,204 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+use uuid::Uuid;
+
+use crate::{
+    cluster::BrokerRegistry,
+    metadata::{PartitionRecord, RecordBatch},
+    replica::ReplicaManager,
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_nullable_string, skip_tagged_fields, write_compact_nullable_string,
+        write_compact_string,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+// Each requested topic is named or, with a null name, given by id
+type RequestedTopics = Box<[(Uuid, Option<Bytes>)]>;
+
+#[derive(Debug)]
+pub struct MetadataRequest {
+    header: RequestHeader,
+    brokers: Arc<BrokerRegistry>,
+    metadata: Arc<Box<[RecordBatch]>>,
+    replicas: Arc<ReplicaManager>,
+    // None asks for every topic
+    topics: Option<RequestedTopics>,
+    allow_auto_topic_creation: bool,
+    include_topic_authorized_operations: bool,
+}
+
+impl MetadataRequest {
+    pub fn new(
+        req: Request,
+        brokers: Arc<BrokerRegistry>,
+        metadata: Arc<Box<[RecordBatch]>>,
+        replicas: Arc<ReplicaManager>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+
+        // Compact nullable array, a zero length prefix marks null
+        let topics = if payload[0] == 0x00 {
+            payload.try_advance(1)?;
+            None
+        } else {
+            let topics_len = read_compact_len(&mut payload)?;
+            let topics = (0..topics_len)
+                .map(|_| {
+                    let topic_id = Uuid::from_u128(payload.try_get_u128()?);
+                    let name = read_compact_nullable_string(&mut payload)?;
+                    skip_tagged_fields(&mut payload)?;
+                    Ok((topic_id, name))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Some(topics.into_boxed_slice())
+        };
+        let allow_auto_topic_creation = payload.try_get_i8()? != 0;
+        let include_topic_authorized_operations = payload.try_get_i8()? != 0;
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            brokers,
+            metadata,
+            replicas,
+            topics,
+            allow_auto_topic_creation,
+            include_topic_authorized_operations,
+        })
+    }
+
+    /// The topic's name, id and partitions, from whichever it was requested by.
+    fn find_topic(
+        &self,
+        topic_id: &Uuid,
+        name: Option<&Bytes>,
+    ) -> Option<(Bytes, Uuid, &[PartitionRecord])> {
+        self.metadata.iter().find_map(|batch| {
+            let (name, topic_id) = match name {
+                Some(name) => (name.clone(), batch.get_topic_uuid(name)?),
+                None => (batch.get_topic_name(topic_id)?, *topic_id),
+            };
+            let partitions = batch.get_topic_partitions_from_uuid(&topic_id)?;
+            Some((name, topic_id, partitions))
+        })
+    }
+
+    fn write_topic(&self, content: &mut BytesMut, topic_id: &Uuid, name: Option<&Bytes>) {
+        let Some((name, topic_id, partitions)) = self.find_topic(topic_id, name) else {
+            let error_code = match name {
+                Some(_) => ErrorCode::UnknownTopicOrPartition,
+                None => ErrorCode::UnknownTopicId,
+            };
+            content.put_i16(error_code as i16);
+            write_compact_nullable_string(content, name.map(|name| &name[..]));
+            content.put_u128(topic_id.as_u128());
+            // Is internal
+            content.put_i8(0);
+            // Partitions
+            unsigned_varint_encode(content, 0);
+            content.put_i32(i32::MIN);
+            // Tags
+            content.put_i8(0x00);
+            return;
+        };
+
+        content.put_i16(ErrorCode::None as i16);
+        write_compact_string(content, &name);
+        content.put_u128(topic_id.as_u128());
+        content.put_i8(name.starts_with(b"__") as i8);
+
+        let mut partitions = partitions.iter().collect::<Vec<_>>();
+        partitions.sort_by_key(|partition| partition.partition_id);
+        unsigned_varint_encode(content, partitions.len());
+        for partition in partitions {
+            content.put_i16(ErrorCode::None as i16);
+            content.put_i32(partition.partition_id);
+            content.put_i32(partition.leader);
+            content.put_i32(partition.leader_epoch);
+            unsigned_varint_encode(content, partition.replication_ids.len());
+            for replica in partition.replication_ids.iter() {
+                content.put_i32(*replica);
+            }
+            // The leader's view of the ISR is ahead of the partition record
+            let in_sync_replicas = self
+                .replicas
+                .in_sync_replicas(&name, partition.partition_id)
+                .unwrap_or_else(|| partition.in_sync_replica_ids.to_vec());
+            unsigned_varint_encode(content, in_sync_replicas.len());
+            for replica in in_sync_replicas {
+                content.put_i32(replica);
+            }
+            // Offline replicas
+            unsigned_varint_encode(content, 0);
+            // Tags
+            content.put_i8(0x00);
+        }
+
+        // Topic authorized operations, left unset as no authorizer is configured
+        content.put_i32(i32::MIN);
+        // Tags
+        content.put_i8(0x00);
+    }
+}
+
+impl IntoResponse for MetadataRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+
+        let listener_name = self.brokers.listener_name();
+        let brokers = self.brokers.brokers(false);
+        let endpoints = brokers
+            .iter()
+            .filter_map(|broker| Some((broker, broker.endpoint(listener_name)?)))
+            .collect::<Vec<_>>();
+        unsigned_varint_encode(&mut content, endpoints.len());
+        for (broker, endpoint) in endpoints {
+            content.put_i32(broker.id);
+            write_compact_string(&mut content, &endpoint.host);
+            content.put_i32(endpoint.port as i32);
+            write_compact_nullable_string(&mut content, broker.rack.as_deref());
+            // Tags
+            content.put_i8(0x00);
+        }
+
+        write_compact_nullable_string(&mut content, self.brokers.cluster_id().map(|id| &id[..]));
+        content.put_i32(self.brokers.controller_id());
+
+        match &self.topics {
+            Some(topics) => {
+                unsigned_varint_encode(&mut content, topics.len());
+                for (topic_id, name) in topics.iter() {
+                    self.write_topic(&mut content, topic_id, name.as_ref());
+                }
+            }
+            None => {
+                let mut names = self
+                    .metadata
+                    .iter()
+                    .flat_map(|batch| batch.topic_names())
+                    .collect::<Vec<_>>();
+                names.sort();
+                names.dedup();
+                unsigned_varint_encode(&mut content, names.len());
+                for name in names {
+                    self.write_topic(&mut content, &Uuid::nil(), Some(name));
+                }
+            }
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
@@ -1,13 +1,248 @@
+pub mod add_offsets_to_txn;
+pub mod add_partitions_to_txn;
+pub mod allocate_producer_ids;
+pub mod alter_configs;
+pub mod alter_partition;
 pub mod api_versions;
+pub mod begin_quorum_epoch;
+pub mod broker_registration;
+pub mod consumer_group_describe;
+pub mod consumer_group_heartbeat;
+pub mod delete_groups;
+pub mod describe_cluster;
+pub mod describe_configs;
+pub mod describe_groups;
+pub mod describe_log_dirs;
 pub mod describe_topics;
+pub mod end_quorum_epoch;
+pub mod end_txn;
 pub mod fetch;
+pub mod fetch_snapshot;
+pub mod find_coordinator;
+pub mod heartbeat;
+pub mod incremental_alter_configs;
+pub mod init_producer_id;
+pub mod join_group;
+pub mod leave_group;
+pub mod list_groups;
+pub mod metadata;
+pub mod offset_commit;
+pub mod offset_delete;
+pub mod offset_fetch;
+pub mod offset_for_leader_epoch;
 pub mod produce;
+pub mod quorum_alter_configs;
+pub mod quorum_fetch;
+pub mod sync_group;
+pub mod txn_offset_commit;
+pub mod vote;
+pub mod write_txn_markers;
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 
+use crate::{
+    cluster::BrokerRegistry, producer::SequenceError, record::RecordError, unsigned_varint_encode,
+    uvarint_encode,
+};
+
 pub trait IntoResponse {
     fn response(&self) -> BytesMut;
+
+    /// Whether the client waits for the response. When it does not, the request is still
+    /// handled but nothing is sent back.
+    fn expects_response(&self) -> bool {
+        true
+    }
+}
+
+/// Requests whose response waits on other clients, such as a JoinGroup held until the rest
+/// of the group has rejoined.
+pub trait IntoDelayedResponse {
+    fn response(self) -> impl Future<Output = BytesMut> + Send;
+}
+
+/// Non-compact string with an i16 length prefix, as the internal topics' keys and values
+/// use. `None` when the buffer is too short to hold it.
+pub fn read_string(buf: &mut Bytes) -> Option<Bytes> {
+    if buf.remaining() < 2 {
+        return None;
+    }
+
+    let len = buf.get_i16().max(0) as usize;
+    (buf.remaining() >= len).then(|| buf.split_to(len))
+}
+
+pub fn write_string(buf: &mut BytesMut, value: &[u8]) {
+    buf.put_i16(value.len() as i16);
+    buf.put_slice(value);
+}
+
+/// Checked reads of a payload sent by a client or peer, which may be cut short. Named
+/// after the `try_get_*` methods later releases of `bytes` add to `Buf`.
+pub trait TryGet {
+    fn try_get_u8(&mut self) -> Result<u8>;
+    fn try_get_i8(&mut self) -> Result<i8>;
+    fn try_get_i16(&mut self) -> Result<i16>;
+    fn try_get_u16(&mut self) -> Result<u16>;
+    fn try_get_i32(&mut self) -> Result<i32>;
+    fn try_get_i64(&mut self) -> Result<i64>;
+    fn try_get_u128(&mut self) -> Result<u128>;
+    fn try_split_to(&mut self, len: usize) -> Result<Bytes>;
+    fn try_advance(&mut self, len: usize) -> Result<()>;
+}
+
+impl TryGet for Bytes {
+    fn try_get_u8(&mut self) -> Result<u8> {
+        take(self, 1, |buf| buf.get_u8())
+    }
+
+    fn try_get_i8(&mut self) -> Result<i8> {
+        take(self, 1, |buf| buf.get_i8())
+    }
+
+    fn try_get_i16(&mut self) -> Result<i16> {
+        take(self, 2, |buf| buf.get_i16())
+    }
+
+    fn try_get_u16(&mut self) -> Result<u16> {
+        take(self, 2, |buf| buf.get_u16())
+    }
+
+    fn try_get_i32(&mut self) -> Result<i32> {
+        take(self, 4, |buf| buf.get_i32())
+    }
+
+    fn try_get_i64(&mut self) -> Result<i64> {
+        take(self, 8, |buf| buf.get_i64())
+    }
+
+    fn try_get_u128(&mut self) -> Result<u128> {
+        take(self, 16, |buf| buf.get_u128())
+    }
+
+    fn try_split_to(&mut self, len: usize) -> Result<Bytes> {
+        take(self, len, |buf| buf.split_to(len))
+    }
+
+    fn try_advance(&mut self, len: usize) -> Result<()> {
+        take(self, len, |buf| buf.advance(len))
+    }
+}
+
+fn take<T>(buf: &mut Bytes, size: usize, get: impl FnOnce(&mut Bytes) -> T) -> Result<T> {
+    if buf.remaining() < size {
+        bail!("{size} bytes expected, {} left", buf.remaining());
+    }
+    Ok(get(buf))
+}
+
+/// Unsigned varint of at most five bytes, as tagged fields and compact lengths use.
+pub fn read_uvarint(buf: &mut Bytes) -> Result<u32> {
+    let mut value = 0;
+    for shift in (0..32).step_by(7) {
+        let byte = buf.try_get_u8()?;
+        value |= ((byte & 0x7F) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("varint longer than five bytes")
+}
+
+/// Length of a compact string or array, sent plus one so that zero can mark null. A null
+/// array reads as empty.
+pub fn read_compact_len(buf: &mut Bytes) -> Result<u32> {
+    read_uvarint(buf).map(|len| len.saturating_sub(1))
+}
+
+pub fn read_compact_string(buf: &mut Bytes) -> Result<Bytes> {
+    let len = read_compact_len(buf)?;
+    buf.try_split_to(len as usize)
+}
+
+/// Compact nullable string, a zero length prefix marks null.
+pub fn read_compact_nullable_string(buf: &mut Bytes) -> Result<Option<Bytes>> {
+    if buf.first() == Some(&0x00) {
+        buf.advance(1);
+        return Ok(None);
+    }
+
+    read_compact_string(buf).map(Some)
+}
+
+pub fn write_compact_string(buf: &mut BytesMut, value: &[u8]) {
+    unsigned_varint_encode(buf, value.len());
+    buf.put_slice(value);
+}
+
+pub fn write_compact_nullable_string(buf: &mut BytesMut, value: Option<&[u8]>) {
+    match value {
+        Some(value) => write_compact_string(buf, value),
+        None => buf.put_u8(0x00),
+    }
+}
+
+/// Skips a tagged field section, whatever fields the client chose to send.
+pub fn skip_tagged_fields(buf: &mut Bytes) -> Result<()> {
+    let count = read_uvarint(buf)?;
+    for _ in 0..count {
+        let _tag = read_uvarint(buf)?;
+        let size = read_uvarint(buf)?;
+        buf.try_advance(size as usize)?;
+    }
+    Ok(())
+}
+
+/// Writes a tagged field section holding the given fields, in the order given, which has to
+/// be ascending by tag.
+pub fn write_tagged_fields(buf: &mut BytesMut, fields: &[(u32, BytesMut)]) {
+    uvarint_encode(buf, fields.len() as u32);
+    for (tag, field) in fields {
+        uvarint_encode(buf, *tag);
+        uvarint_encode(buf, field.len() as u32);
+        buf.put_slice(field);
+    }
+}
+
+/// The CurrentLeader tagged field of Produce and Fetch partitions, pointing a client that
+/// asked the wrong broker, or with a stale epoch, at the partition's leader.
+pub fn encode_current_leader(leader_id: i32, leader_epoch: i32) -> BytesMut {
+    let mut field = BytesMut::new();
+    field.put_i32(leader_id);
+    field.put_i32(leader_epoch);
+    // Tags
+    field.put_i8(0x00);
+    field
+}
+
+/// The NodeEndpoints tagged field of Produce and Fetch responses, with where to reach the
+/// leaders named in CurrentLeader fields.
+pub fn encode_node_endpoints(
+    brokers: &BrokerRegistry,
+    node_ids: impl IntoIterator<Item = i32>,
+) -> BytesMut {
+    let listener_name = brokers.listener_name();
+    let registered = brokers.brokers(false);
+    let endpoints = node_ids
+        .into_iter()
+        .filter_map(|node_id| {
+            let broker = registered.iter().find(|broker| broker.id == node_id)?;
+            Some((broker, broker.endpoint(listener_name)?))
+        })
+        .collect::<Vec<_>>();
+
+    let mut field = BytesMut::new();
+    unsigned_varint_encode(&mut field, endpoints.len());
+    for (broker, endpoint) in endpoints {
+        field.put_i32(broker.id);
+        write_compact_string(&mut field, &endpoint.host);
+        field.put_i32(endpoint.port as i32);
+        write_compact_nullable_string(&mut field, broker.rack.as_deref());
+        // Tags
+        field.put_i8(0x00);
+    }
+    field
 }
 
 #[repr(u16)]
@@ -15,7 +250,40 @@ pub trait IntoResponse {
 pub enum ApiType {
     Produce = 0,
     Fetch = 1,
+    Metadata = 3,
+    OffsetCommit = 8,
+    OffsetFetch = 9,
+    FindCoordinator = 10,
+    JoinGroup = 11,
+    Heartbeat = 12,
+    LeaveGroup = 13,
+    SyncGroup = 14,
+    DescribeGroups = 15,
+    ListGroups = 16,
     ApiVersions = 18,
+    InitProducerId = 22,
+    OffsetForLeaderEpoch = 23,
+    AddPartitionsToTxn = 24,
+    AddOffsetsToTxn = 25,
+    EndTxn = 26,
+    WriteTxnMarkers = 27,
+    TxnOffsetCommit = 28,
+    DescribeConfigs = 32,
+    AlterConfigs = 33,
+    DescribeLogDirs = 35,
+    DeleteGroups = 42,
+    IncrementalAlterConfigs = 44,
+    OffsetDelete = 47,
+    Vote = 52,
+    BeginQuorumEpoch = 53,
+    EndQuorumEpoch = 54,
+    AlterPartition = 56,
+    FetchSnapshot = 59,
+    DescribeCluster = 60,
+    BrokerRegistration = 62,
+    AllocateProducerIds = 67,
+    ConsumerGroupHeartbeat = 68,
+    ConsumerGroupDescribe = 69,
     DescribeTopicPartitions = 75,
 }
 
@@ -24,11 +292,50 @@ impl ApiType {
         match self {
             Self::Produce => (0, 11),
             Self::Fetch => (0, 16),
+            Self::Metadata => (12, 12),
+            Self::OffsetCommit => (8, 9),
+            Self::OffsetFetch => (6, 9),
+            Self::FindCoordinator => (3, 6),
+            Self::JoinGroup => (6, 9),
+            Self::Heartbeat => (4, 4),
+            Self::LeaveGroup => (4, 5),
+            Self::SyncGroup => (4, 5),
+            Self::DescribeGroups => (5, 6),
+            Self::ListGroups => (3, 5),
             Self::ApiVersions => (0, 4),
+            Self::InitProducerId => (2, 5),
+            Self::OffsetForLeaderEpoch => (4, 4),
+            Self::AddPartitionsToTxn => (3, 3),
+            Self::AddOffsetsToTxn => (3, 4),
+            Self::EndTxn => (3, 4),
+            Self::WriteTxnMarkers => (1, 1),
+            Self::TxnOffsetCommit => (3, 4),
+            Self::DescribeConfigs => (4, 4),
+            Self::AlterConfigs => (2, 2),
+            Self::DescribeLogDirs => (2, 4),
+            Self::DeleteGroups => (2, 2),
+            Self::IncrementalAlterConfigs => (1, 1),
+            Self::OffsetDelete => (0, 0),
+            Self::Vote => (0, 0),
+            Self::BeginQuorumEpoch => (1, 1),
+            Self::EndQuorumEpoch => (1, 1),
+            Self::AlterPartition => (2, 2),
+            Self::FetchSnapshot => (0, 0),
+            Self::DescribeCluster => (0, 2),
+            Self::BrokerRegistration => (0, 3),
+            Self::AllocateProducerIds => (0, 0),
+            Self::ConsumerGroupHeartbeat => (0, 0),
+            Self::ConsumerGroupDescribe => (0, 0),
             Self::DescribeTopicPartitions => (0, 0),
         }
     }
 
+    /// Whether the version uses the flexible encoding, with compact types and tag buffers.
+    /// OffsetDelete has no flexible version at all.
+    pub fn flexible(&self, version: i16) -> bool {
+        !matches!(self, Self::OffsetDelete) || version > 0
+    }
+
     pub fn metadata(&self, buf: &mut BytesMut) {
         let (min, max) = self.supported_versions();
 
@@ -52,7 +359,40 @@ impl TryFrom<i16> for ApiType {
         match value {
             0 => Ok(Self::Produce),
             1 => Ok(Self::Fetch),
+            3 => Ok(Self::Metadata),
+            8 => Ok(Self::OffsetCommit),
+            9 => Ok(Self::OffsetFetch),
+            10 => Ok(Self::FindCoordinator),
+            11 => Ok(Self::JoinGroup),
+            12 => Ok(Self::Heartbeat),
+            13 => Ok(Self::LeaveGroup),
+            14 => Ok(Self::SyncGroup),
+            15 => Ok(Self::DescribeGroups),
+            16 => Ok(Self::ListGroups),
             18 => Ok(Self::ApiVersions),
+            22 => Ok(Self::InitProducerId),
+            23 => Ok(Self::OffsetForLeaderEpoch),
+            24 => Ok(Self::AddPartitionsToTxn),
+            25 => Ok(Self::AddOffsetsToTxn),
+            26 => Ok(Self::EndTxn),
+            27 => Ok(Self::WriteTxnMarkers),
+            28 => Ok(Self::TxnOffsetCommit),
+            32 => Ok(Self::DescribeConfigs),
+            33 => Ok(Self::AlterConfigs),
+            35 => Ok(Self::DescribeLogDirs),
+            42 => Ok(Self::DeleteGroups),
+            44 => Ok(Self::IncrementalAlterConfigs),
+            47 => Ok(Self::OffsetDelete),
+            52 => Ok(Self::Vote),
+            53 => Ok(Self::BeginQuorumEpoch),
+            54 => Ok(Self::EndQuorumEpoch),
+            56 => Ok(Self::AlterPartition),
+            59 => Ok(Self::FetchSnapshot),
+            60 => Ok(Self::DescribeCluster),
+            62 => Ok(Self::BrokerRegistration),
+            67 => Ok(Self::AllocateProducerIds),
+            68 => Ok(Self::ConsumerGroupHeartbeat),
+            69 => Ok(Self::ConsumerGroupDescribe),
             75 => Ok(Self::DescribeTopicPartitions),
             _ => Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
@@ -67,9 +407,82 @@ impl TryFrom<i16> for ApiType {
 pub enum ErrorCode {
     Unknown = -1,
     None = 0,
+    OffsetOutOfRange = 1,
+    CorruptMessage = 2,
     UnknownTopicOrPartition = 3,
+    NotLeaderOrFollower = 6,
+    RequestTimedOut = 7,
+    MessageTooLarge = 10,
+    CoordinatorNotAvailable = 15,
+    NotCoordinator = 16,
+    NotEnoughReplicas = 19,
+    InvalidRequiredAcks = 21,
+    IllegalGeneration = 22,
+    InconsistentGroupProtocol = 23,
+    InvalidGroupId = 24,
+    UnknownMemberId = 25,
+    InvalidSessionTimeout = 26,
+    RebalanceInProgress = 27,
+    InvalidTimestamp = 32,
     UnsupportedVersion = 35,
+    InvalidConfig = 40,
+    NotController = 41,
+    InvalidRequest = 42,
+    OutOfOrderSequenceNumber = 45,
+    DuplicateSequenceNumber = 46,
+    InvalidProducerEpoch = 47,
+    InvalidTxnState = 48,
+    InvalidProducerIdMapping = 49,
+    InvalidTransactionTimeout = 50,
+    ConcurrentTransactions = 51,
+    OperationNotAttempted = 55,
+    KafkaStorageError = 56,
+    NonEmptyGroup = 68,
+    GroupIdNotFound = 69,
+    FetchSessionIdNotFound = 70,
+    InvalidFetchSessionEpoch = 71,
+    FencedLeaderEpoch = 74,
+    UnknownLeaderEpoch = 75,
+    MemberIdRequired = 79,
+    FencedInstanceId = 82,
+    GroupSubscribedToTopic = 86,
+    InvalidRecord = 87,
+    UnstableOffsetCommit = 88,
+    ProducerFenced = 90,
+    InconsistentVoterSet = 94,
+    InvalidUpdateVersion = 95,
+    SnapshotNotFound = 98,
+    PositionOutOfRange = 99,
     UnknownTopicId = 100,
+    InconsistentClusterId = 104,
+    FencedMemberEpoch = 110,
+    UnreleasedInstanceId = 111,
+    UnsupportedAssignor = 112,
+    StaleMemberEpoch = 113,
+    MismatchedEndpointType = 114,
+}
+
+impl From<RecordError> for ErrorCode {
+    fn from(err: RecordError) -> Self {
+        match err {
+            RecordError::InvalidRecords(_) => Self::InvalidRecord,
+            RecordError::TooLarge(_) => Self::MessageTooLarge,
+            RecordError::Truncated
+            | RecordError::UnsupportedMagic(_)
+            | RecordError::CrcMismatch { .. }
+            | RecordError::Compression(_) => Self::CorruptMessage,
+        }
+    }
+}
+
+impl From<&SequenceError> for ErrorCode {
+    fn from(err: &SequenceError) -> Self {
+        match err {
+            SequenceError::Duplicate(_) => Self::DuplicateSequenceNumber,
+            SequenceError::OutOfOrder { .. } => Self::OutOfOrderSequenceNumber,
+            SequenceError::InvalidEpoch { .. } => Self::InvalidProducerEpoch,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -77,18 +490,21 @@ pub struct Request {
     pub message_size: i32,
     pub header: RequestHeader,
     pub payload: Bytes,
+    // Address of the connection the request came in on, as reported for group members
+    pub client_host: Bytes,
 }
 
 impl Request {
-    pub fn parse(mut buf: BytesMut) -> Result<Self> {
-        let message_size = buf.get_i32();
+    pub fn parse(buf: BytesMut) -> Result<Self> {
+        let mut buf = buf.freeze();
+        let message_size = buf.try_get_i32()?;
         let header = RequestHeader::parse(&mut buf).context("parse request header")?;
-        let payload = Bytes::from(buf.clone());
 
         Ok(Self {
             message_size,
             header,
-            payload,
+            payload: buf,
+            client_host: Bytes::new(),
         })
     }
 }
@@ -99,35 +515,31 @@ pub struct RequestHeader {
     pub api_version: i16,
     pub correlation_id: i32,
     pub client_id: Bytes,
-    pub tag_buffer: i8,
 }
 
 impl RequestHeader {
-    fn parse(buf: &mut BytesMut) -> Result<Self> {
-        let api_key = ApiType::try_from(buf.get_i16()).context("parsing api key type")?;
-        let api_version = buf.get_i16();
-        let correlation_id = buf.get_i32();
-
-        let id_len = buf.get_i16();
-        let client_id = if id_len != -1 {
-            let mut c_id = vec![0; id_len as usize];
-            c_id.copy_from_slice(&buf[..id_len as usize]);
-            let c_id = Bytes::from(c_id);
-            buf.advance(id_len as usize);
-            c_id
-        } else {
-            Bytes::new()
+    fn parse(buf: &mut Bytes) -> Result<Self> {
+        let api_key = ApiType::try_from(buf.try_get_i16()?).context("parsing api key type")?;
+        let api_version = buf.try_get_i16()?;
+        let correlation_id = buf.try_get_i32()?;
+
+        // The client id is a nullable string with a plain length prefix, -1 for null
+        let id_len = buf.try_get_i16()?;
+        let client_id = match id_len {
+            -1 => Bytes::new(),
+            len => buf.try_split_to(len.max(0) as usize)?,
         };
 
-        let tag_buffer = buf.get_i8();
-        assert_eq!(tag_buffer, 0x00);
+        // Only flexible versions carry a tag buffer in the header
+        if api_key.flexible(api_version) {
+            skip_tagged_fields(buf)?;
+        }
 
         Ok(Self {
             api_key,
             api_version,
             correlation_id,
             client_id,
-            tag_buffer,
         })
     }
 
@@ -140,3 +552,38 @@ impl RequestHeader {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_fields_are_errors() {
+        // A compact string claiming five bytes with only two sent
+        let mut buf = Bytes::from_static(&[0x06, b'a', b'b']);
+        assert!(read_compact_string(&mut buf).is_err());
+
+        // A tagged field longer than what is left
+        let mut buf = Bytes::from_static(&[0x01, 0x00, 0x09, 0x00]);
+        assert!(skip_tagged_fields(&mut buf).is_err());
+
+        let mut buf = Bytes::from_static(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]);
+        assert!(read_uvarint(&mut buf).is_err());
+
+        let mut buf = Bytes::new();
+        assert!(read_compact_nullable_string(&mut buf).is_err());
+        assert!(buf.try_get_i32().is_err());
+    }
+
+    #[test]
+    fn truncated_headers_are_errors() {
+        let mut buf = BytesMut::new();
+        buf.put_i32(12);
+        buf.put_u16(ApiType::Metadata as u16);
+        buf.put_i16(12);
+        buf.put_i32(7);
+        // Client id length with nothing after it
+        buf.put_i16(4);
+        assert!(Request::parse(buf).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    cluster::BrokerRegistry,
+    current_time_ms,
+    group::GroupCoordinator,
+    offsets::{OffsetCommitKey, OffsetCommitValue, OffsetManager, partition_for},
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_nullable_string, read_compact_string, skip_tagged_fields,
+        write_compact_string,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct OffsetCommitRequest {
+    header: RequestHeader,
+    groups: Arc<GroupCoordinator>,
+    offsets: Arc<OffsetManager>,
+    brokers: Arc<BrokerRegistry>,
+    group_id: Bytes,
+    generation_id: i32,
+    member_id: Bytes,
+    group_instance_id: Option<Bytes>,
+    topics: Box<[(Bytes, Box<[PartitionOffset]>)]>,
+}
+
+#[derive(Debug)]
+struct PartitionOffset {
+    index: i32,
+    committed_offset: i64,
+    committed_leader_epoch: i32,
+    committed_metadata: Option<Bytes>,
+}
+
+impl OffsetCommitRequest {
+    pub fn new(
+        req: Request,
+        groups: Arc<GroupCoordinator>,
+        offsets: Arc<OffsetManager>,
+        brokers: Arc<BrokerRegistry>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+        let group_id = read_compact_string(&mut payload)?;
+        let generation_id = payload.try_get_i32()?;
+        let member_id = read_compact_string(&mut payload)?;
+        let group_instance_id = read_compact_nullable_string(&mut payload)?;
+
+        let topics_len = read_compact_len(&mut payload)?;
+        let topics = (0..topics_len)
+            .map(|_| {
+                let name = read_compact_string(&mut payload)?;
+                let partitions_len = read_compact_len(&mut payload)?;
+                let partitions = (0..partitions_len)
+                    .map(|_| {
+                        let index = payload.try_get_i32()?;
+                        let committed_offset = payload.try_get_i64()?;
+                        let committed_leader_epoch = payload.try_get_i32()?;
+                        let committed_metadata = read_compact_nullable_string(&mut payload)?;
+                        skip_tagged_fields(&mut payload)?;
+
+                        Ok(PartitionOffset {
+                            index,
+                            committed_offset,
+                            committed_leader_epoch,
+                            committed_metadata,
+                        })
+                    })
+                    .collect::<Result<Vec<PartitionOffset>>>()?;
+                skip_tagged_fields(&mut payload)?;
+
+                Ok((name, partitions.into_boxed_slice()))
+            })
+            .collect::<Result<Vec<(Bytes, Box<[PartitionOffset]>)>>>()?;
+
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            groups,
+            offsets,
+            brokers,
+            group_id,
+            generation_id,
+            member_id,
+            group_instance_id,
+            topics: topics.into_boxed_slice(),
+        })
+    }
+
+    fn commit(&self) -> ErrorCode {
+        let validated = self
+            .brokers
+            .check_coordinator(partition_for(&self.group_id))
+            .and_then(|()| {
+                self.groups.validate_offset_commit(
+                    &self.group_id,
+                    self.generation_id,
+                    &self.member_id,
+                    self.group_instance_id.as_ref(),
+                )
+            });
+        if let Err(error_code) = validated {
+            return error_code;
+        }
+
+        let commit_timestamp = current_time_ms();
+        let offsets = self
+            .topics
+            .iter()
+            .flat_map(|(topic_name, partitions)| {
+                partitions.iter().map(move |partition| {
+                    let key = OffsetCommitKey {
+                        group_id: self.group_id.clone(),
+                        topic: topic_name.clone(),
+                        partition: partition.index,
+                    };
+                    let value = OffsetCommitValue {
+                        offset: partition.committed_offset,
+                        leader_epoch: partition.committed_leader_epoch,
+                        metadata: partition.committed_metadata.clone().unwrap_or_default(),
+                        commit_timestamp,
+                    };
+                    (key, value)
+                })
+            })
+            .collect();
+
+        match self.offsets.commit(&self.group_id, offsets) {
+            Ok(()) => ErrorCode::None,
+            Err(error_code) => error_code,
+        }
+    }
+}
+
+impl IntoResponse for OffsetCommitRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+        let error_code = self.commit();
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+
+        unsigned_varint_encode(&mut content, self.topics.len());
+        for (topic_name, partitions) in self.topics.iter() {
+            write_compact_string(&mut content, topic_name);
+            unsigned_varint_encode(&mut content, partitions.len());
+            for partition in partitions.iter() {
+                content.put_i32(partition.index);
+                content.put_i16(error_code as i16);
+                // Tags
+                content.put_i8(0x00);
+            }
+
+            content.put_i8(0x00);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
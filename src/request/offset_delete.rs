@@ -0,0 +1,152 @@
+#![allow(dead_code)]
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    cluster::BrokerRegistry,
+    group::{GroupCoordinator, GroupState},
+    offsets::{OffsetManager, partition_for},
+    request::{ErrorCode, IntoResponse, Request, RequestHeader, TryGet},
+};
+use anyhow::Result;
+
+use std::sync::Arc;
+
+/// OffsetDelete only exists in a non-flexible version, so strings and arrays carry plain
+/// length prefixes and neither header has a tag buffer.
+#[derive(Debug)]
+pub struct OffsetDeleteRequest {
+    header: RequestHeader,
+    groups: Arc<GroupCoordinator>,
+    offsets: Arc<OffsetManager>,
+    brokers: Arc<BrokerRegistry>,
+    group_id: Bytes,
+    topics: Box<[(Bytes, Box<[i32]>)]>,
+}
+
+fn read_string(payload: &mut Bytes) -> Result<Bytes> {
+    let len = payload.try_get_i16()?;
+    payload.try_split_to(len.max(0) as usize)
+}
+
+fn write_string(content: &mut BytesMut, value: &[u8]) {
+    content.put_i16(value.len() as i16);
+    content.put_slice(value);
+}
+
+impl OffsetDeleteRequest {
+    pub fn new(
+        req: Request,
+        groups: Arc<GroupCoordinator>,
+        offsets: Arc<OffsetManager>,
+        brokers: Arc<BrokerRegistry>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+        let group_id = read_string(&mut payload)?;
+
+        let topics_len = payload.try_get_i32()?;
+        let topics = (0..topics_len.max(0))
+            .map(|_| {
+                let name = read_string(&mut payload)?;
+                let partitions_len = payload.try_get_i32()?;
+                let partitions = (0..partitions_len.max(0))
+                    .map(|_| payload.try_get_i32())
+                    .collect::<Result<Vec<i32>>>()?;
+
+                Ok((name, partitions.into_boxed_slice()))
+            })
+            .collect::<Result<Vec<(Bytes, Box<[i32]>)>>>()?;
+
+        Ok(Self {
+            header: req.header,
+            groups,
+            offsets,
+            brokers,
+            group_id,
+            topics: topics.into_boxed_slice(),
+        })
+    }
+
+    /// Deletes the requested offsets, except for topics the group's members are still
+    /// subscribed to. Returns the group level error and one error per partition.
+    fn delete(&self) -> (ErrorCode, Vec<ErrorCode>) {
+        if let Err(error_code) = self
+            .brokers
+            .check_coordinator(partition_for(&self.group_id))
+        {
+            return (error_code, Vec::new());
+        }
+
+        let subscribed = {
+            let groups = self.groups.lock();
+            if let Some(group) = self.groups.consumer_lock().get(&self.group_id) {
+                group.subscribed_topics().into_iter().collect()
+            } else {
+                let Some(group) = groups.get(&self.group_id) else {
+                    return (ErrorCode::GroupIdNotFound, Vec::new());
+                };
+
+                match (group.state, group.subscribed_topics()) {
+                    (GroupState::Empty, _) => Default::default(),
+                    (_, Some(topics)) => topics,
+                    // Members of non-consumer groups may be using any offset
+                    (_, None) => return (ErrorCode::NonEmptyGroup, Vec::new()),
+                }
+            }
+        };
+
+        let mut deleted = Vec::new();
+        let errors: Vec<ErrorCode> = self
+            .topics
+            .iter()
+            .flat_map(|(topic_name, partitions)| {
+                partitions
+                    .iter()
+                    .map(move |partition| (topic_name.clone(), *partition))
+            })
+            .map(|(topic_name, partition)| {
+                if subscribed.contains(&topic_name) {
+                    ErrorCode::GroupSubscribedToTopic
+                } else {
+                    deleted.push((topic_name, partition));
+                    ErrorCode::None
+                }
+            })
+            .collect();
+
+        match self.offsets.delete(&self.group_id, &deleted) {
+            Ok(()) => (ErrorCode::None, errors),
+            Err(error_code) => (error_code, Vec::new()),
+        }
+    }
+}
+
+impl IntoResponse for OffsetDeleteRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+        let (error_code, errors) = self.delete();
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i16(error_code as i16);
+        content.put_i32(throttle_time);
+
+        if errors.is_empty() {
+            content.put_i32(0);
+            return content;
+        }
+
+        let mut errors = errors.into_iter();
+        content.put_i32(self.topics.len() as i32);
+        for (topic_name, partitions) in self.topics.iter() {
+            write_string(&mut content, topic_name);
+            content.put_i32(partitions.len() as i32);
+            for index in partitions.iter() {
+                content.put_i32(*index);
+                content.put_i16(errors.next().unwrap_or(ErrorCode::Unknown) as i16);
+            }
+        }
+
+        content
+    }
+}
@@ -0,0 +1,291 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    cluster::BrokerRegistry,
+    group::GroupCoordinator,
+    offsets::{OffsetManager, partition_for},
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_nullable_string, read_compact_string, read_uvarint, skip_tagged_fields,
+        write_compact_string,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+// Topics and partition indexes asked for, a null list meaning every committed offset
+type RequestedTopics = Option<Box<[(Bytes, Box<[i32]>)]>>;
+
+#[derive(Debug)]
+struct GroupRequest {
+    group_id: Bytes,
+    // Member id and epoch, only checked by consumer protocol groups
+    member_id: Option<Bytes>,
+    member_epoch: i32,
+    topics: RequestedTopics,
+}
+
+#[derive(Debug)]
+pub struct OffsetFetchRequest {
+    header: RequestHeader,
+    groups: Arc<GroupCoordinator>,
+    offsets: Arc<OffsetManager>,
+    brokers: Arc<BrokerRegistry>,
+    requests: Box<[GroupRequest]>,
+    require_stable: bool,
+}
+
+impl OffsetFetchRequest {
+    pub fn new(
+        req: Request,
+        groups: Arc<GroupCoordinator>,
+        offsets: Arc<OffsetManager>,
+        brokers: Arc<BrokerRegistry>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+        let version = req.header.api_version;
+
+        // Up to v7 a single group is fetched, later versions batch them
+        let requests = if version < 8 {
+            let group_id = read_compact_string(&mut payload)?;
+            let topics = read_topics(&mut payload)?;
+            vec![GroupRequest {
+                group_id,
+                member_id: None,
+                member_epoch: -1,
+                topics,
+            }]
+        } else {
+            let groups_len = read_compact_len(&mut payload)?;
+            (0..groups_len)
+                .map(|_| {
+                    let group_id = read_compact_string(&mut payload)?;
+                    let (member_id, member_epoch) = if version >= 9 {
+                        (
+                            read_compact_nullable_string(&mut payload)?,
+                            payload.try_get_i32()?,
+                        )
+                    } else {
+                        (None, -1)
+                    };
+                    let topics = read_topics(&mut payload)?;
+                    skip_tagged_fields(&mut payload)?;
+
+                    Ok(GroupRequest {
+                        group_id,
+                        member_id,
+                        member_epoch,
+                        topics,
+                    })
+                })
+                .collect::<Result<_>>()?
+        };
+
+        let require_stable = version >= 7 && payload.try_get_u8()? != 0;
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            groups,
+            offsets,
+            brokers,
+            requests: requests.into_boxed_slice(),
+            require_stable,
+        })
+    }
+
+    /// Offsets of the requested partitions, or of all the group's partitions.
+    fn group_offsets(
+        &self,
+        group_id: &Bytes,
+        topics: &RequestedTopics,
+    ) -> Vec<(Bytes, Vec<PartitionOffset>)> {
+        let Some(topics) = topics else {
+            let mut grouped: Vec<(Bytes, Vec<PartitionOffset>)> = Vec::new();
+            for (topic_name, index, value) in self.offsets.group_offsets(group_id) {
+                let partition = PartitionOffset {
+                    index,
+                    committed_offset: value.offset,
+                    committed_leader_epoch: value.leader_epoch,
+                    metadata: value.metadata,
+                    error_code: ErrorCode::None,
+                };
+                match grouped.last_mut() {
+                    Some((name, partitions)) if *name == topic_name => partitions.push(partition),
+                    _ => grouped.push((topic_name, vec![partition])),
+                }
+            }
+            return grouped;
+        };
+
+        topics
+            .iter()
+            .map(|(topic_name, partitions)| {
+                let partitions = partitions
+                    .iter()
+                    .map(|index| {
+                        // Offsets a transaction has yet to settle cannot be handed out to
+                        // consumers asking for stable ones
+                        if self.require_stable
+                            && self.offsets.has_pending(group_id, topic_name, *index)
+                        {
+                            return PartitionOffset::missing(
+                                *index,
+                                ErrorCode::UnstableOffsetCommit,
+                            );
+                        }
+
+                        match self.offsets.committed(group_id, topic_name, *index) {
+                            Some(value) => PartitionOffset {
+                                index: *index,
+                                committed_offset: value.offset,
+                                committed_leader_epoch: value.leader_epoch,
+                                metadata: value.metadata,
+                                error_code: ErrorCode::None,
+                            },
+                            None => PartitionOffset::missing(*index, ErrorCode::None),
+                        }
+                    })
+                    .collect();
+
+                (topic_name.clone(), partitions)
+            })
+            .collect()
+    }
+}
+
+fn read_topics(payload: &mut Bytes) -> Result<RequestedTopics> {
+    // A zero length prefix marks a null array
+    let topics_len = match read_uvarint(payload)? {
+        0 => return Ok(None),
+        len => len - 1,
+    };
+
+    let topics = (0..topics_len)
+        .map(|_| {
+            let name = read_compact_string(payload)?;
+            let partitions_len = read_compact_len(payload)?;
+            let partitions = (0..partitions_len)
+                .map(|_| payload.try_get_i32())
+                .collect::<Result<Vec<i32>>>()?;
+            skip_tagged_fields(payload)?;
+
+            Ok((name, partitions.into_boxed_slice()))
+        })
+        .collect::<Result<Vec<(Bytes, Box<[i32]>)>>>()?;
+
+    Ok(Some(topics.into_boxed_slice()))
+}
+
+#[derive(Debug)]
+struct PartitionOffset {
+    index: i32,
+    committed_offset: i64,
+    committed_leader_epoch: i32,
+    metadata: Bytes,
+    error_code: ErrorCode,
+}
+
+impl PartitionOffset {
+    fn missing(index: i32, error_code: ErrorCode) -> Self {
+        Self {
+            index,
+            committed_offset: -1,
+            committed_leader_epoch: -1,
+            metadata: Bytes::new(),
+            error_code,
+        }
+    }
+
+    fn encode(&self, content: &mut BytesMut) {
+        content.put_i32(self.index);
+        content.put_i64(self.committed_offset);
+        content.put_i32(self.committed_leader_epoch);
+        write_compact_string(content, &self.metadata);
+        content.put_i16(self.error_code as i16);
+        // Tags
+        content.put_i8(0x00);
+    }
+}
+
+fn write_topics(content: &mut BytesMut, topics: &[(Bytes, Vec<PartitionOffset>)]) {
+    unsigned_varint_encode(content, topics.len());
+    for (topic_name, partitions) in topics.iter() {
+        write_compact_string(content, topic_name);
+        unsigned_varint_encode(content, partitions.len());
+        for partition in partitions.iter() {
+            partition.encode(content);
+        }
+
+        content.put_i8(0x00);
+    }
+}
+
+impl IntoResponse for OffsetFetchRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+
+        if self.header.api_version < 8 {
+            let request = &self.requests[0];
+            match self
+                .brokers
+                .check_coordinator(partition_for(&request.group_id))
+            {
+                Ok(()) => {
+                    write_topics(
+                        &mut content,
+                        &self.group_offsets(&request.group_id, &request.topics),
+                    );
+                    content.put_i16(ErrorCode::None as i16);
+                }
+                Err(error_code) => {
+                    write_topics(&mut content, &[]);
+                    content.put_i16(error_code as i16);
+                }
+            }
+        } else {
+            unsigned_varint_encode(&mut content, self.requests.len());
+            for request in self.requests.iter() {
+                write_compact_string(&mut content, &request.group_id);
+                let validated = self
+                    .brokers
+                    .check_coordinator(partition_for(&request.group_id))
+                    .and_then(|()| {
+                        self.groups.validate_offset_fetch(
+                            &request.group_id,
+                            request.member_id.as_ref(),
+                            request.member_epoch,
+                        )
+                    });
+                match validated {
+                    Ok(()) => {
+                        write_topics(
+                            &mut content,
+                            &self.group_offsets(&request.group_id, &request.topics),
+                        );
+                        content.put_i16(ErrorCode::None as i16);
+                    }
+                    Err(error_code) => {
+                        write_topics(&mut content, &[]);
+                        content.put_i16(error_code as i16);
+                    }
+                }
+                // Tags
+                content.put_i8(0x00);
+            }
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
@@ -0,0 +1,154 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    leader_epoch::{UNDEFINED_EPOCH, UNDEFINED_OFFSET},
+    log::LogManager,
+    metadata::RecordBatch,
+    replica::ReplicaManager,
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_string, skip_tagged_fields, write_compact_string,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+// Replica id of debugging tools, the only ones allowed to ask a broker other than the leader
+const DEBUGGING_REPLICA_ID: i32 = -2;
+
+#[derive(Debug)]
+struct PartitionEpoch {
+    partition: i32,
+    current_leader_epoch: i32,
+    leader_epoch: i32,
+}
+
+#[derive(Debug)]
+pub struct OffsetForLeaderEpochRequest {
+    header: RequestHeader,
+    metadata: Arc<Box<[RecordBatch]>>,
+    logs: Arc<LogManager>,
+    replicas: Arc<ReplicaManager>,
+    // A follower's node id, -1 for consumers
+    replica_id: i32,
+    topics: Box<[(Bytes, Box<[PartitionEpoch]>)]>,
+}
+
+impl OffsetForLeaderEpochRequest {
+    pub fn new(
+        req: Request,
+        metadata: Arc<Box<[RecordBatch]>>,
+        logs: Arc<LogManager>,
+        replicas: Arc<ReplicaManager>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+
+        let replica_id = payload.try_get_i32()?;
+        let topics_len = read_compact_len(&mut payload)?;
+        let topics = (0..topics_len)
+            .map(|_| {
+                let topic_name = read_compact_string(&mut payload)?;
+                let partitions_len = read_compact_len(&mut payload)?;
+                let partitions = (0..partitions_len)
+                    .map(|_| {
+                        let partition = PartitionEpoch {
+                            partition: payload.try_get_i32()?,
+                            current_leader_epoch: payload.try_get_i32()?,
+                            leader_epoch: payload.try_get_i32()?,
+                        };
+                        skip_tagged_fields(&mut payload)?;
+                        Ok(partition)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                skip_tagged_fields(&mut payload)?;
+
+                Ok((topic_name, partitions.into_boxed_slice()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            metadata,
+            logs,
+            replicas,
+            replica_id,
+            topics: topics.into_boxed_slice(),
+        })
+    }
+
+    /// The largest epoch up to the requested one and where it ends in the partition's log.
+    fn end_offset(
+        &self,
+        topic_name: &Bytes,
+        partition: &PartitionEpoch,
+    ) -> Result<(i32, i64), ErrorCode> {
+        let exists = self.metadata.iter().any(|record| {
+            record
+                .get_topic_uuid(topic_name)
+                .is_some_and(|uuid| record.valid_partition(&uuid, partition.partition))
+        });
+        if !exists {
+            return Err(ErrorCode::UnknownTopicOrPartition);
+        }
+        self.replicas.check_leader_epoch(
+            topic_name,
+            partition.partition,
+            partition.current_leader_epoch,
+        )?;
+        if self.replica_id != DEBUGGING_REPLICA_ID {
+            self.replicas
+                .check_leader(topic_name, partition.partition)?;
+        }
+
+        self.logs
+            .with_partition(topic_name, partition.partition, |log| {
+                Ok(log.end_offset_for_epoch(partition.leader_epoch))
+            })
+            .map_err(|err| {
+                eprintln!("reading {topic_name:?}-{}: {err:#}", partition.partition);
+                ErrorCode::KafkaStorageError
+            })
+    }
+}
+
+impl IntoResponse for OffsetForLeaderEpochRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+
+        unsigned_varint_encode(&mut content, self.topics.len());
+        for (topic_name, partitions) in self.topics.iter() {
+            write_compact_string(&mut content, topic_name);
+            unsigned_varint_encode(&mut content, partitions.len());
+            for partition in partitions.iter() {
+                let (error_code, (leader_epoch, end_offset)) =
+                    match self.end_offset(topic_name, partition) {
+                        Ok(end_offset) => (ErrorCode::None, end_offset),
+                        Err(error_code) => (error_code, (UNDEFINED_EPOCH, UNDEFINED_OFFSET)),
+                    };
+
+                content.put_i16(error_code as i16);
+                content.put_i32(partition.partition);
+                content.put_i32(leader_epoch);
+                content.put_i64(end_offset);
+                // Tags
+                content.put_i8(0x00);
+            }
+            // Tags
+            content.put_i8(0x00);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
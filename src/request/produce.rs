@@ -1,20 +1,45 @@
 #![allow(dead_code)]
 
 use crate::{
+    cluster::BrokerRegistry,
+    compression::Compression,
+    config::ConfigManager,
+    current_time_ms,
+    log::{AppendInfo, LogManager},
     metadata::RecordBatch,
-    request::{ErrorCode, IntoResponse, Request, RequestHeader},
-    unsigned_varint_decode, unsigned_varint_encode,
+    producer::SequenceError,
+    record::{self, TimestampType},
+    replica::ReplicaManager,
+    request::{
+        ErrorCode, IntoDelayedResponse, IntoResponse, Request, RequestHeader, TryGet,
+        encode_current_leader, encode_node_endpoints, read_compact_len,
+        read_compact_nullable_string, read_compact_string, skip_tagged_fields,
+        write_compact_nullable_string, write_tagged_fields,
+    },
+    txn::TransactionCoordinator,
+    unsigned_varint_encode,
 };
+use anyhow::Result;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 
-use std::{path::PathBuf, sync::Arc};
+use std::{collections::BTreeSet, sync::Arc, time::Duration};
 
-const LOG_DIR: &str = "/tmp/kraft-combined-logs";
+// Kafka's min.insync.replicas default
+const DEFAULT_MIN_INSYNC_REPLICAS: usize = 1;
+// Partition response tagged field naming the partition's leader
+const CURRENT_LEADER_TAG: u32 = 0;
+// Response tagged field with the endpoints of the leaders named in partition responses
+const NODE_ENDPOINTS_TAG: u32 = 0;
 
 #[derive(Debug)]
 pub struct ProduceRequest {
     header: RequestHeader,
+    configs: Arc<ConfigManager>,
     metadata: Arc<Box<[RecordBatch]>>,
+    logs: Arc<LogManager>,
+    transactions: Arc<TransactionCoordinator>,
+    replicas: Arc<ReplicaManager>,
+    brokers: Arc<BrokerRegistry>,
     transactional_id: Bytes,
     required_acknowledgements: i16,
     timeout: i32,
@@ -22,61 +47,71 @@ pub struct ProduceRequest {
 }
 
 impl ProduceRequest {
-    pub fn new(req: Request, metadata: Arc<Box<[RecordBatch]>>) -> Self {
+    pub fn new(
+        req: Request,
+        configs: Arc<ConfigManager>,
+        metadata: Arc<Box<[RecordBatch]>>,
+        logs: Arc<LogManager>,
+        transactions: Arc<TransactionCoordinator>,
+        replicas: Arc<ReplicaManager>,
+        brokers: Arc<BrokerRegistry>,
+    ) -> Result<Self> {
         let mut payload = req.payload;
-        let txn_id_len = unsigned_varint_decode(&mut payload);
-        let transactional_id = Bytes::copy_from_slice(&payload[..txn_id_len as usize]);
-        payload.advance(txn_id_len as usize);
-        let required_acks = payload.get_i16();
-        let timeout = payload.get_i32();
-
-        let topics_len = unsigned_varint_decode(&mut payload);
-        let topics: Vec<(Bytes, Box<[Partition]>)> = (0..topics_len)
+        // Null for a producer that is not transactional
+        let transactional_id = read_compact_nullable_string(&mut payload)?.unwrap_or_default();
+        let required_acks = payload.try_get_i16()?;
+        let timeout = payload.try_get_i32()?;
+
+        let topics_len = read_compact_len(&mut payload)?;
+        let topics = (0..topics_len)
             .map(|_| {
-                let topic_name_len = unsigned_varint_decode(&mut payload);
-                let topic_name = Bytes::copy_from_slice(&payload[..topic_name_len as usize]);
-                payload.advance(topic_name_len as usize);
+                let topic_name = read_compact_string(&mut payload)?;
 
-                let partitions_arr_len = unsigned_varint_decode(&mut payload);
-                let partitions: Vec<Partition> = (0..partitions_arr_len)
+                let partitions_arr_len = read_compact_len(&mut payload)?;
+                let partitions = (0..partitions_arr_len)
                     .map(|_| {
-                        let index = payload.get_i32();
-                        let records_len = unsigned_varint_decode(&mut payload);
-                        let records = Bytes::copy_from_slice(&payload[..records_len as usize]);
-                        payload.advance(records_len as usize);
-                        // Skip TAG buffer
-                        payload.get_i8();
-
-                        Partition {
+                        let index = payload.try_get_i32()?;
+                        let records = read_compact_nullable_string(&mut payload)?;
+                        skip_tagged_fields(&mut payload)?;
+
+                        Ok(Partition {
                             index,
-                            record_batches: records,
-                        }
+                            record_batches: records.unwrap_or_default(),
+                        })
                     })
-                    .collect();
-
-                // Skip tag buffer
-                payload.get_i8();
+                    .collect::<Result<Vec<Partition>>>()?;
+                skip_tagged_fields(&mut payload)?;
 
-                (topic_name, partitions.into_boxed_slice())
+                Ok((topic_name, partitions.into_boxed_slice()))
             })
-            .collect();
+            .collect::<Result<Vec<(Bytes, Box<[Partition]>)>>>()?;
+        skip_tagged_fields(&mut payload)?;
 
-        // Skip tag buffer
-        payload.get_i8();
-
-        Self {
+        Ok(Self {
             header: req.header,
+            configs,
             metadata,
+            logs,
+            transactions,
+            replicas,
+            brokers,
             transactional_id,
             required_acknowledgements: required_acks,
             timeout,
             topics: topics.into_boxed_slice(),
-        }
+        })
     }
 
-    pub fn invalid_topic(&self, content: &mut BytesMut, idx: i32) {
+    pub fn partition_error(
+        &self,
+        content: &mut BytesMut,
+        idx: i32,
+        error: impl Into<PartitionError>,
+        current_leader: Option<(i32, i32)>,
+    ) {
+        let error = error.into();
         content.put_i32(idx);
-        content.put_i16(ErrorCode::UnknownTopicOrPartition as i16);
+        content.put_i16(error.error_code as i16);
         // // Base offset
         content.put_i64(-1);
         // // Log append time
@@ -84,23 +119,298 @@ impl ProduceRequest {
         // // Log start offset
         content.put_i64(-1);
         // // Record errors array
-        unsigned_varint_encode(content, 0);
+        unsigned_varint_encode(content, error.record_errors.len());
+        for (batch_index, message) in error.record_errors.iter() {
+            content.put_i32(*batch_index);
+            write_compact_nullable_string(content, Some(message.as_bytes()));
+            // Tags
+            content.put_i8(0x00);
+        }
         // // Error Message
-        content.put_i8(0x00);
+        write_compact_nullable_string(content, error.error_message.as_deref().map(str::as_bytes));
         // // Tags
-        content.put_i8(0x00);
+        match current_leader {
+            Some((leader_id, leader_epoch)) => write_tagged_fields(
+                content,
+                &[(
+                    CURRENT_LEADER_TAG,
+                    encode_current_leader(leader_id, leader_epoch),
+                )],
+            ),
+            None => content.put_i8(0x00),
+        }
+    }
+
+    fn topic_config(&self, topic_name: &Bytes, key: &str) -> Option<Bytes> {
+        self.configs.topic_config(topic_name, key)
+    }
+
+    fn numeric_topic_config(&self, topic_name: &Bytes, key: &str) -> Option<i64> {
+        let value = self.topic_config(topic_name, key)?;
+        std::str::from_utf8(&value).ok()?.trim().parse().ok()
+    }
+
+    /// Checks every record of a batch against the topic's rules: compacted topics need a key
+    /// to compact on, and `CreateTime` timestamps have to be close enough to the broker's
+    /// clock. Returns the index of each rejected record, counted from the partition's first
+    /// record, with the reason and error.
+    fn validate_records(
+        &self,
+        topic_name: &Bytes,
+        partition: &Partition,
+        batch: &record::RecordBatch,
+        first_index: i32,
+        check_timestamps: bool,
+    ) -> Vec<(i32, String, ErrorCode)> {
+        let compacted = self
+            .topic_config(topic_name, "cleanup.policy")
+            .is_some_and(|policy| {
+                policy
+                    .split(|byte| *byte == b',')
+                    .any(|item| item.trim_ascii() == b"compact")
+            });
+
+        let now = current_time_ms();
+        let difference = self
+            .numeric_topic_config(topic_name, "message.timestamp.difference.max.ms")
+            .unwrap_or(i64::MAX);
+        let before = self
+            .numeric_topic_config(topic_name, "message.timestamp.before.max.ms")
+            .unwrap_or(i64::MAX)
+            .min(difference);
+        let after = self
+            .numeric_topic_config(topic_name, "message.timestamp.after.max.ms")
+            .unwrap_or(i64::MAX)
+            .min(difference);
+        let earliest = now.saturating_sub(before);
+        let latest = now.saturating_add(after);
+
+        let mut errors = Vec::new();
+        for (index, record) in batch.records.iter().enumerate() {
+            let index = first_index + index as i32;
+            if compacted && record.key.is_none() {
+                errors.push((
+                    index,
+                    format!(
+                        "Compacted topic cannot accept message without key in topic partition {}-{}.",
+                        String::from_utf8_lossy(topic_name),
+                        partition.index
+                    ),
+                    ErrorCode::InvalidRecord,
+                ));
+                continue;
+            }
+
+            let timestamp = batch.base_timestamp + record.timestamp_delta;
+            if check_timestamps && !(earliest..=latest).contains(&timestamp) {
+                errors.push((
+                    index,
+                    format!(
+                        "Timestamp {timestamp} of message with offset {} is out of range. The timestamp should be within [{earliest}, {latest}]",
+                        record.offset_delta
+                    ),
+                    ErrorCode::InvalidTimestamp,
+                ));
+            }
+        }
+
+        errors
+    }
+
+    /// acks=-1 producers need the write on every in-sync replica, and refuse to write at
+    /// all while fewer than `min.insync.replicas` of them are left.
+    fn check_in_sync_replicas(&self, topic_name: &Bytes, index: i32) -> Result<(), ErrorCode> {
+        if self.required_acknowledgements != -1 {
+            return Ok(());
+        }
+
+        let min_insync_replicas = self
+            .topic_config(topic_name, "min.insync.replicas")
+            .and_then(|value| std::str::from_utf8(&value).ok()?.parse().ok())
+            .unwrap_or(DEFAULT_MIN_INSYNC_REPLICAS);
+        let in_sync_replicas = match self.replicas.in_sync_replicas(topic_name, index) {
+            Some(isr) => isr.len(),
+            None => self
+                .metadata
+                .iter()
+                .rev()
+                .filter_map(|record| record.get_topic_partitions_from_name(topic_name))
+                .find_map(|partitions| partitions.iter().find(|p| p.partition_id == index))
+                .map_or(0, |partition| partition.in_sync_replica_ids.len()),
+        };
+
+        if in_sync_replicas < min_insync_replicas {
+            return Err(ErrorCode::NotEnoughReplicas);
+        }
+
+        Ok(())
+    }
+
+    /// Decodes and verifies every batch sent for a partition before anything touches the
+    /// disk, recompressing it to the topic's `compression.type` when one is configured and
+    /// stamping it when the topic uses `LogAppendTime`. Transactional batches are only
+    /// accepted once the producer has added the partition to its ongoing transaction.
+    /// Batches that need no rewriting are stored exactly as the producer sent them.
+    fn prepare_record_batches(
+        &self,
+        topic_name: &Bytes,
+        partition: &Partition,
+        log_append_time: Option<i64>,
+    ) -> Result<Bytes, PartitionError> {
+        let target = self
+            .topic_config(topic_name, "compression.type")
+            .and_then(|value| Compression::from_config(&value));
+        let max_message_bytes = self
+            .numeric_topic_config(topic_name, "max.message.bytes")
+            .unwrap_or(i64::MAX);
+        let leader_epoch = self.replicas.leader_epoch(topic_name, partition.index);
+
+        let mut records = partition.record_batches.clone();
+        let mut out = BytesMut::with_capacity(records.len());
+        let mut record_count = 0;
+        let mut record_errors = Vec::new();
+        while records.has_remaining() {
+            let raw = records.clone();
+            let mut batch = record::RecordBatch::decode_bounded(
+                &mut records,
+                max_message_bytes.clamp(0, i32::MAX as i64) as usize,
+            )?;
+            let raw = raw.slice(..raw.len() - records.len());
+
+            // Producers leave the epoch unset or stale, and the leader stamps its own over
+            // it. One this broker has not reached yet means its metadata is behind
+            if leader_epoch.is_some_and(|epoch| batch.partition_leader_epoch > epoch) {
+                return Err(ErrorCode::UnknownLeaderEpoch.into());
+            }
+
+            if raw.len() as i64 > max_message_bytes {
+                return Err(PartitionError {
+                    error_code: ErrorCode::MessageTooLarge,
+                    error_message: Some(format!(
+                        "The record batch of {} bytes is larger than max.message.bytes ({max_message_bytes})",
+                        raw.len()
+                    )),
+                    record_errors: Vec::new(),
+                });
+            }
+
+            record_errors.extend(self.validate_records(
+                topic_name,
+                partition,
+                &batch,
+                record_count,
+                log_append_time.is_none(),
+            ));
+            record_count += batch.records.len() as i32;
+
+            let consecutive = batch
+                .records
+                .iter()
+                .enumerate()
+                .all(|(delta, record)| record.offset_delta == delta as i32);
+            if batch.records.len() as i32 != batch.last_offset_delta + 1 || !consecutive {
+                return Err(ErrorCode::InvalidRecord.into());
+            }
+
+            if batch.is_transactional() {
+                self.transactions.verify_partition(
+                    &self.transactional_id,
+                    batch.producer_id,
+                    batch.producer_epoch,
+                    topic_name,
+                    partition.index,
+                )?;
+            }
+
+            if let Some(timestamp) = log_append_time {
+                batch.set_log_append_time(timestamp);
+            }
+
+            if !record_errors.is_empty() {
+                continue;
+            }
+
+            match target {
+                Some(codec) if batch.compression()? != codec => {
+                    batch.set_compression(codec);
+                    out.put(batch.encode()?);
+                }
+                _ if log_append_time.is_some() => {
+                    let mut raw = BytesMut::from(&raw[..]);
+                    batch.rewrite_header(&mut raw);
+                    out.put(raw);
+                }
+                _ => out.put(raw),
+            }
+        }
+
+        if !record_errors.is_empty() {
+            return Err(PartitionError::rejected_records(record_errors));
+        }
+
+        Ok(out.freeze())
     }
+}
 
-    fn write_record_batch(&self, topic_name: &Bytes, partition: &Partition) {
-        let root = PathBuf::from(LOG_DIR);
-        let name = String::from_utf8(topic_name.to_vec()).expect("guaranteed to be utf-8");
-        let dir = root.join(format!("{name}-{}", partition.index));
-        if !dir.exists() {
-            std::fs::create_dir_all(&dir).expect("directory creation");
+/// Why a partition's batches were not appended, down to the records at fault.
+#[derive(Debug)]
+pub struct PartitionError {
+    error_code: ErrorCode,
+    error_message: Option<String>,
+    record_errors: Vec<(i32, String)>,
+}
+
+impl PartitionError {
+    /// Rejects the batches over invalid records, reported as an invalid timestamp if any
+    /// of them has one, the way Kafka does.
+    fn rejected_records(errors: Vec<(i32, String, ErrorCode)>) -> Self {
+        let (error_code, error_message) = if errors
+            .iter()
+            .any(|(_, _, error_code)| *error_code == ErrorCode::InvalidTimestamp)
+        {
+            (
+                ErrorCode::InvalidTimestamp,
+                "One or more records have been rejected due to invalid timestamp".to_string(),
+            )
+        } else {
+            let first = errors
+                .iter()
+                .take(3)
+                .map(|(index, message, _)| format!("{index}: {message}"))
+                .collect::<Vec<_>>();
+            (
+                ErrorCode::InvalidRecord,
+                format!(
+                    "One or more records have been rejected due to {} record errors in total, and only showing the first three errors at most: {first:?}",
+                    errors.len()
+                ),
+            )
+        };
+
+        Self {
+            error_code,
+            error_message: Some(error_message),
+            record_errors: errors
+                .into_iter()
+                .map(|(index, message, _)| (index, message))
+                .collect(),
+        }
+    }
+}
+
+impl From<ErrorCode> for PartitionError {
+    fn from(error_code: ErrorCode) -> Self {
+        Self {
+            error_code,
+            error_message: None,
+            record_errors: Vec::new(),
         }
+    }
+}
 
-        let path = dir.join("00000000000000000000.log");
-        std::fs::write(path, partition.record_batches.clone()).expect("should be valid");
+impl From<record::RecordError> for PartitionError {
+    fn from(err: record::RecordError) -> Self {
+        ErrorCode::from(err).into()
     }
 }
 
@@ -110,62 +420,389 @@ pub struct Partition {
     record_batches: Bytes,
 }
 
-impl IntoResponse for ProduceRequest {
-    fn response(&self) -> BytesMut {
+impl ProduceRequest {
+    /// Whether the response waits, for followers when acks=-1 or for the flusher when a
+    /// topic has a `flush.ms` policy.
+    pub fn is_delayed(&self) -> bool {
+        self.required_acknowledgements == -1
+            || (self.required_acknowledgements == 1
+                && self
+                    .topics
+                    .iter()
+                    .any(|(topic_name, _)| self.waits_for_flush(topic_name)))
+    }
+
+    /// acks=1 and acks=-1 writes to topics with a `flush.ms` policy are acknowledged once
+    /// the flusher put them on disk. `flush.messages` flushes as part of the append.
+    fn waits_for_flush(&self, topic_name: &Bytes) -> bool {
+        self.logs.flush_policy(topic_name).ms.is_some()
+    }
+
+    fn append_partition(
+        &self,
+        topic_name: &Bytes,
+        partition: &Partition,
+    ) -> Result<Appended, PartitionError> {
+        if !matches!(self.required_acknowledgements, -1..=1) {
+            return Err(ErrorCode::InvalidRequiredAcks.into());
+        }
+
+        let exists = self.metadata.iter().any(|record| {
+            record
+                .get_topic_uuid(topic_name)
+                .is_some_and(|uuid| record.valid_partition(&uuid, partition.index))
+        });
+        if !exists {
+            return Err(ErrorCode::UnknownTopicOrPartition.into());
+        }
+        self.replicas.check_leader(topic_name, partition.index)?;
+
+        let log_append_time = self
+            .topic_config(topic_name, "message.timestamp.type")
+            .and_then(|value| TimestampType::from_config(&value))
+            .filter(|timestamp_type| *timestamp_type == TimestampType::LogAppendTime)
+            .map(|_| current_time_ms());
+
+        let records = self.prepare_record_batches(topic_name, partition, log_append_time)?;
+        self.check_in_sync_replicas(topic_name, partition.index)?;
+
+        let info = self
+            .logs
+            .append(topic_name, partition.index, records)
+            .map_err(|err| match err.downcast_ref::<SequenceError>() {
+                Some(sequence_err) => ErrorCode::from(sequence_err),
+                None => {
+                    eprintln!("appending to {topic_name:?}-{}: {err:#}", partition.index);
+                    ErrorCode::KafkaStorageError
+                }
+            })?;
+
+        // When this broker is the only in-sync replica, an acks=-1 write is only
+        // acknowledged once it reached the disk, if the flush policy did not put it there
+        // already. That happens before the high watermark moves, so consumers never see
+        // records that could still be lost
+        if self.required_acknowledgements == -1
+            && !info.flushed
+            && !self
+                .replicas
+                .has_in_sync_followers(topic_name, partition.index)
+            && let Err(err) = self.logs.flush(topic_name, partition.index)
+        {
+            eprintln!("flushing {topic_name:?}-{}: {err:#}", partition.index);
+            return Err(ErrorCode::KafkaStorageError.into());
+        }
+        self.replicas.record_append(topic_name, partition.index);
+
+        Ok(Appended {
+            info,
+            log_append_time: log_append_time.unwrap_or(-1),
+        })
+    }
+
+    /// Appends every partition's batches, in the order they were sent.
+    fn append_all(&self) -> Vec<Vec<(i32, Result<Appended, PartitionError>)>> {
+        self.topics
+            .iter()
+            .map(|(topic_name, partitions)| {
+                partitions
+                    .iter()
+                    .map(|partition| {
+                        let result = self.append_partition(topic_name, partition);
+                        (partition.index, result)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn write_response(
+        &self,
+        results: Vec<Vec<(i32, Result<Appended, PartitionError>)>>,
+    ) -> BytesMut {
         let mut content = BytesMut::new();
         let throttle_time = 0;
         content.put_i32(self.header.correlation_id);
         content.put_i8(0x00);
 
+        // Leaders the producer is redirected to, whose endpoints go along
+        let mut leaders = BTreeSet::new();
         unsigned_varint_encode(&mut content, self.topics.len());
-        for topic in self.topics.iter() {
-            let (topic_name, partitions) = topic;
+        for ((topic_name, _), partitions) in self.topics.iter().zip(results) {
             unsigned_varint_encode(&mut content, topic_name.len());
             content.put(topic_name.clone());
             unsigned_varint_encode(&mut content, partitions.len());
-            for partition in partitions.iter() {
-                let matches: Vec<&RecordBatch> = self
-                    .metadata
-                    .iter()
-                    .filter_map(|record| match record.get_topic_uuid(topic_name) {
-                        Some(uuid) => {
-                            if record.valid_partition(&uuid, partition.index) {
-                                Some(record)
-                            } else {
-                                None
-                            }
-                        }
-                        None => None,
-                    })
-                    .collect();
-
-                if matches.is_empty() {
-                    self.invalid_topic(&mut content, partition.index);
-                } else {
-                    self.write_record_batch(topic_name, partition);
-                    content.put_i32(partition.index);
-                    content.put_i16(ErrorCode::None as i16);
-                    // // Base offset
-                    content.put_i64(0);
-                    // // Log append time
-                    content.put_i64(-1);
-                    // // Log start offset
-                    content.put_i64(0);
-                    // // Record errors array
-                    unsigned_varint_encode(&mut content, 0);
-                    // // Error Message
-                    content.put_i8(0x00);
-                    // // Tags
-                    content.put_i8(0x00);
-                }
+            for (index, result) in partitions {
+                let appended = match result {
+                    Ok(appended) => appended,
+                    Err(error) => {
+                        let current_leader = match error.error_code {
+                            ErrorCode::NotLeaderOrFollower | ErrorCode::FencedLeaderEpoch => self
+                                .replicas
+                                .leader(topic_name, index)
+                                .filter(|(leader_id, _)| *leader_id >= 0),
+                            _ => None,
+                        };
+                        leaders.extend(current_leader.map(|(leader_id, _)| leader_id));
+                        self.partition_error(&mut content, index, error, current_leader);
+                        continue;
+                    }
+                };
+
+                content.put_i32(index);
+                content.put_i16(ErrorCode::None as i16);
+                // // Base offset
+                content.put_i64(appended.info.base_offset);
+                // // Log append time
+                content.put_i64(appended.log_append_time);
+                // // Log start offset
+                content.put_i64(appended.info.log_start_offset);
+                // // Record errors array
+                unsigned_varint_encode(&mut content, 0);
+                // // Error Message
+                content.put_i8(0x00);
+                // // Tags
+                content.put_i8(0x00);
             }
 
             content.put_i8(0x00);
         }
 
         content.put_i32(throttle_time);
-        content.put_i8(0x00);
+        match leaders.is_empty() {
+            true => content.put_i8(0x00),
+            false => write_tagged_fields(
+                &mut content,
+                &[(
+                    NODE_ENDPOINTS_TAG,
+                    encode_node_endpoints(&self.brokers, leaders),
+                )],
+            ),
+        }
 
         content
     }
 }
+
+#[derive(Debug)]
+struct Appended {
+    info: AppendInfo,
+    // -1 unless the topic uses LogAppendTime
+    log_append_time: i64,
+}
+
+impl IntoResponse for ProduceRequest {
+    fn response(&self) -> BytesMut {
+        self.write_response(self.append_all())
+    }
+
+    /// acks=0 producers never read a response.
+    fn expects_response(&self) -> bool {
+        self.required_acknowledgements != 0
+    }
+}
+
+/// acks=-1 produces are answered once every in-sync replica has the batches, and produces
+/// to topics with a `flush.ms` policy once their batches are flushed, or with
+/// `REQUEST_TIMED_OUT` for the partitions that did not get there within the request timeout.
+impl IntoDelayedResponse for ProduceRequest {
+    async fn response(self) -> BytesMut {
+        let mut results = self.append_all();
+        let deadline =
+            tokio::time::Instant::now() + Duration::from_millis(self.timeout.max(0) as u64);
+        for ((topic_name, _), partitions) in self.topics.iter().zip(results.iter_mut()) {
+            for (index, result) in partitions.iter_mut() {
+                let Ok(appended) = result else {
+                    continue;
+                };
+                let offset = appended.info.log_end_offset;
+
+                let replicated = self.required_acknowledgements != -1
+                    || self
+                        .replicas
+                        .wait_for_high_watermark(topic_name, *index, offset, deadline)
+                        .await;
+                let flushed = appended.info.flushed
+                    || !self.waits_for_flush(topic_name)
+                    || self
+                        .logs
+                        .wait_for_flush(topic_name, *index, offset, deadline)
+                        .await;
+                if !replicated || !flushed {
+                    *result = Err(ErrorCode::RequestTimedOut.into());
+                }
+            }
+        }
+
+        self.write_response(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::BrokerConfig,
+        metadata::{MetadataCache, MetadataWriter},
+        offsets::OffsetManager,
+        producer::ProducerIdManager,
+        record::Record,
+        request::ApiType,
+    };
+
+    const TOPIC: &[u8] = b"events";
+
+    /// A produce request for `events-0` over fresh logs, with the given broker properties
+    /// standing in for the topic's configs.
+    fn produce_request(name: &str, properties: &[&str]) -> ProduceRequest {
+        let dir = std::env::temp_dir().join(format!(
+            "produce-test-{name}-{}-{}",
+            std::process::id(),
+            current_time_ms()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut args = vec![
+            "--override".to_string(),
+            format!("log.dirs={}", dir.display()),
+        ];
+        for property in properties {
+            args.extend(["--override".to_string(), property.to_string()]);
+        }
+        let config = Arc::new(BrokerConfig::from_args(args).unwrap());
+
+        let logs = Arc::new(LogManager::new(&config));
+        let writer = MetadataWriter::Log(Arc::clone(&logs));
+        let metadata = Arc::new(MetadataCache::new(Box::new([])));
+        let producer_ids = Arc::new(ProducerIdManager::new(config.node_id, 0, writer.clone()));
+        let offsets = Arc::new(OffsetManager::new(Arc::clone(&logs)).unwrap());
+        let transactions =
+            TransactionCoordinator::new(Arc::clone(&logs), producer_ids, offsets).unwrap();
+
+        ProduceRequest {
+            header: RequestHeader {
+                api_key: ApiType::Produce,
+                api_version: 11,
+                correlation_id: 1,
+                client_id: Bytes::new(),
+            },
+            configs: Arc::new(ConfigManager::new(
+                Arc::clone(&config),
+                Arc::clone(&metadata),
+                Arc::clone(&logs),
+                writer.clone(),
+            )),
+            metadata: metadata.snapshot(),
+            replicas: Arc::new(ReplicaManager::new(
+                &config,
+                &[],
+                Arc::clone(&logs),
+                writer.clone(),
+            )),
+            brokers: Arc::new(BrokerRegistry::new(&config, &[], &logs, &writer)),
+            logs,
+            transactions: Arc::new(transactions),
+            transactional_id: Bytes::new(),
+            required_acknowledgements: 1,
+            timeout: 30000,
+            topics: Box::new([]),
+        }
+    }
+
+    fn partition(batches: &[record::RecordBatch]) -> Partition {
+        let mut record_batches = BytesMut::new();
+        for batch in batches {
+            record_batches.put(batch.encode().unwrap());
+        }
+        Partition {
+            index: 0,
+            record_batches: record_batches.freeze(),
+        }
+    }
+
+    fn prepare(
+        request: &ProduceRequest,
+        batches: &[record::RecordBatch],
+    ) -> Result<Bytes, PartitionError> {
+        let topic_name = Bytes::from_static(TOPIC);
+        let log_append_time = request
+            .topic_config(&topic_name, "message.timestamp.type")
+            .and_then(|value| TimestampType::from_config(&value))
+            .filter(|timestamp_type| *timestamp_type == TimestampType::LogAppendTime)
+            .map(|_| 1_000);
+        request.prepare_record_batches(&topic_name, &partition(batches), log_append_time)
+    }
+
+    fn value(size: usize) -> Record {
+        Record::new(
+            Some(Bytes::from_static(b"key")),
+            Some(Bytes::from(vec![0; size])),
+        )
+    }
+
+    #[test]
+    fn batches_over_max_message_bytes_are_too_large() {
+        let request = produce_request("too-large", &["message.max.bytes=200"]);
+        let small = record::RecordBatch::new(current_time_ms(), vec![value(10)]);
+        assert!(prepare(&request, std::slice::from_ref(&small)).is_ok());
+
+        let large = record::RecordBatch::new(current_time_ms(), vec![value(300)]);
+        let err = prepare(&request, &[small, large]).unwrap_err();
+        assert_eq!(err.error_code, ErrorCode::MessageTooLarge);
+    }
+
+    #[test]
+    fn compacted_topics_reject_records_without_a_key() {
+        let request = produce_request("null-key", &["log.cleanup.policy=compact,delete"]);
+        let batch = record::RecordBatch::new(
+            current_time_ms(),
+            vec![
+                value(1),
+                Record::new(None, Some(Bytes::from_static(b"value"))),
+            ],
+        );
+
+        let err = prepare(&request, &[batch]).unwrap_err();
+        assert_eq!(err.error_code, ErrorCode::InvalidRecord);
+        assert_eq!(err.record_errors.len(), 1);
+        assert_eq!(err.record_errors[0].0, 1);
+    }
+
+    #[test]
+    fn timestamps_outside_the_allowed_range_are_invalid() {
+        let request = produce_request(
+            "timestamp",
+            &[
+                "log.message.timestamp.before.max.ms=60000",
+                "log.message.timestamp.after.max.ms=60000",
+            ],
+        );
+        let now = current_time_ms();
+        assert!(prepare(&request, &[record::RecordBatch::new(now, vec![value(1)])]).is_ok());
+
+        for timestamp in [now - 3_600_000, now + 3_600_000] {
+            let batch = record::RecordBatch::new(timestamp, vec![value(1)]);
+            let err = prepare(&request, &[batch]).unwrap_err();
+            assert_eq!(err.error_code, ErrorCode::InvalidTimestamp);
+            assert_eq!(err.record_errors.len(), 1);
+        }
+    }
+
+    #[test]
+    fn log_append_time_topics_stamp_batches_with_the_broker_time() {
+        let request = produce_request(
+            "log-append-time",
+            &[
+                "log.message.timestamp.type=LogAppendTime",
+                // The producer's own timestamps are not checked against the clock
+                "log.message.timestamp.before.max.ms=60000",
+            ],
+        );
+        let batch = record::RecordBatch::new(0, vec![value(1), value(2)]);
+
+        let mut written = prepare(&request, &[batch]).unwrap();
+        let written = record::RecordBatch::decode(&mut written).unwrap();
+        assert_eq!(written.timestamp_type(), TimestampType::LogAppendTime);
+        assert_eq!(written.base_timestamp, 1_000);
+        assert_eq!(written.max_timestamp, 1_000);
+        assert_eq!(written.records.len(), 2);
+    }
+}
@@ -0,0 +1,157 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    config::{ConfigError, ConfigOperation, ConfigRecord},
+    raft::{COMMIT_TIMEOUT, RaftQuorum},
+    record::Record,
+    request::{
+        ErrorCode, IntoDelayedResponse, Request, RequestHeader, TryGet,
+        alter_configs::write_alter_result, read_compact_len, read_compact_nullable_string,
+        read_compact_string, skip_tagged_fields,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+struct Resource {
+    resource_type: i8,
+    resource_name: Bytes,
+    configs: Box<[(Bytes, i8, Option<Bytes>)]>,
+}
+
+/// An IncrementalAlterConfigs on the controller listener, a broker forwarding config changes
+/// it already validated to the active controller. Only sets and deletes are taken, which
+/// the controller writes as `ConfigRecord`s as they are.
+#[derive(Debug)]
+pub struct QuorumAlterConfigsRequest {
+    header: RequestHeader,
+    quorum: Arc<RaftQuorum>,
+    resources: Box<[Resource]>,
+}
+
+impl QuorumAlterConfigsRequest {
+    pub fn new(req: Request, quorum: Arc<RaftQuorum>) -> Result<Self> {
+        let mut payload = req.payload;
+
+        let resources_len = read_compact_len(&mut payload)?;
+        let resources = (0..resources_len)
+            .map(|_| {
+                let resource_type = payload.try_get_i8()?;
+                let resource_name = read_compact_string(&mut payload)?;
+                let configs_len = read_compact_len(&mut payload)?;
+                let configs = (0..configs_len)
+                    .map(|_| {
+                        let name = read_compact_string(&mut payload)?;
+                        let operation = payload.try_get_i8()?;
+                        let value = read_compact_nullable_string(&mut payload)?;
+                        skip_tagged_fields(&mut payload)?;
+                        Ok((name, operation, value))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                skip_tagged_fields(&mut payload)?;
+
+                Ok(Resource {
+                    resource_type,
+                    resource_name,
+                    configs: configs.into_boxed_slice(),
+                })
+            })
+            .collect::<Result<Vec<Resource>>>()?;
+        let _validate_only = payload.try_get_i8()? != 0;
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            quorum,
+            resources: resources.into_boxed_slice(),
+        })
+    }
+
+    /// The resource's changes as config records, a value setting the config and none
+    /// removing it.
+    fn records(resource: &Resource) -> Result<Vec<Record>, ConfigError> {
+        resource
+            .configs
+            .iter()
+            .map(|(name, operation, value)| {
+                let value = match (ConfigOperation::try_from(*operation), value) {
+                    (Ok(ConfigOperation::Set), Some(value)) => Some(value.clone()),
+                    (Ok(ConfigOperation::Delete), _) => None,
+                    _ => {
+                        return Err(ConfigError {
+                            error_code: ErrorCode::InvalidRequest,
+                            message: format!("Unexpected config operation {operation}"),
+                        });
+                    }
+                };
+                let record = ConfigRecord {
+                    version: 0,
+                    resource_type: resource.resource_type,
+                    resource_name: resource.resource_name.clone(),
+                    name: name.clone(),
+                    value,
+                    tags: 0,
+                };
+                Ok(Record::new(None, Some(record.encode())))
+            })
+            .collect()
+    }
+
+    /// Writes the changes of a resource and waits for them to be committed.
+    async fn alter(&self, resource: &Resource) -> Result<(), ConfigError> {
+        let error = |error_code: ErrorCode| ConfigError {
+            error_code,
+            message: format!("{error_code:?}"),
+        };
+
+        let records = Self::records(resource)?;
+        if records.is_empty() {
+            return Ok(());
+        }
+        if !self.quorum.is_leader() {
+            return Err(error(ErrorCode::NotController));
+        }
+
+        let offset = self.quorum.append_with(|_| records).map_err(|err| {
+            eprintln!("altering configs: {err:#}");
+            error(ErrorCode::NotController)
+        })?;
+        let deadline = tokio::time::Instant::now() + COMMIT_TIMEOUT;
+        if !self.quorum.wait_for_commit(offset + 1, deadline).await {
+            return Err(error(ErrorCode::RequestTimedOut));
+        }
+
+        Ok(())
+    }
+}
+
+impl IntoDelayedResponse for QuorumAlterConfigsRequest {
+    async fn response(self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+
+        unsigned_varint_encode(&mut content, self.resources.len());
+        for resource in self.resources.iter() {
+            let result = self.alter(resource).await;
+            write_alter_result(
+                &mut content,
+                resource.resource_type,
+                &resource.resource_name,
+                result,
+            );
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
@@ -0,0 +1,237 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+use uuid::Uuid;
+
+use crate::{
+    raft::{FETCH_MAX_WAIT_MS, METADATA_TOPIC_ID, QuorumFetch, RaftQuorum},
+    request::{
+        ErrorCode, IntoDelayedResponse, Request, RequestHeader, TryGet, encode_current_leader,
+        read_compact_len, read_compact_nullable_string, read_uvarint, skip_tagged_fields,
+        write_tagged_fields,
+    },
+    unsigned_varint_encode,
+};
+
+use std::{sync::Arc, time::Duration};
+
+// First Fetch version naming topics by id, as the metadata log has no name to the quorum
+const MIN_QUORUM_FETCH_VERSION: i16 = 13;
+// Request tagged fields with the cluster id and, from v15 on, the fetching replica
+const CLUSTER_ID_TAG: u32 = 0;
+const REPLICA_STATE_TAG: u32 = 1;
+// Partition response tagged fields telling a follower where its log diverged, and who
+// leads the quorum
+const DIVERGING_EPOCH_TAG: u32 = 0;
+const CURRENT_LEADER_TAG: u32 = 1;
+
+#[derive(Debug)]
+struct PartitionRequest {
+    partition: i32,
+    current_leader_epoch: i32,
+    fetch_offset: i64,
+    last_fetched_epoch: i32,
+    partition_max_bytes: i32,
+}
+
+/// A Fetch of `__cluster_metadata` on the controller listener, from voters replicating the
+/// quorum's log or observers following it. Unlike a broker Fetch it has no session, and it
+/// is held until there is something new or the request's max wait passes.
+#[derive(Debug)]
+pub struct QuorumFetchRequest {
+    header: RequestHeader,
+    quorum: Arc<RaftQuorum>,
+    cluster_id: Option<Bytes>,
+    replica_id: i32,
+    max_wait: i32,
+    topics: Box<[(Uuid, Box<[PartitionRequest]>)]>,
+}
+
+impl QuorumFetchRequest {
+    pub fn new(req: Request, quorum: Arc<RaftQuorum>) -> Result<Self> {
+        let mut payload = req.payload;
+        let version = req.header.api_version;
+        if version < MIN_QUORUM_FETCH_VERSION {
+            return Ok(Self {
+                header: req.header,
+                quorum,
+                cluster_id: None,
+                replica_id: -1,
+                max_wait: 0,
+                topics: Box::new([]),
+            });
+        }
+
+        let mut replica_id = if version < 15 {
+            payload.try_get_i32()?
+        } else {
+            -1
+        };
+        let max_wait = payload.try_get_i32()?;
+        let _min_bytes = payload.try_get_i32()?;
+        let _max_bytes = payload.try_get_i32()?;
+        let _isolation_level = payload.try_get_i8()?;
+        let _session_id = payload.try_get_i32()?;
+        let _session_epoch = payload.try_get_i32()?;
+        let topics_len = read_compact_len(&mut payload)?;
+        let topics = (0..topics_len)
+            .map(|_| {
+                let topic_id = Uuid::from_u128(payload.try_get_u128()?);
+                let partitions_len = read_compact_len(&mut payload)?;
+                let partitions = (0..partitions_len)
+                    .map(|_| {
+                        let partition = payload.try_get_i32()?;
+                        let current_leader_epoch = payload.try_get_i32()?;
+                        let fetch_offset = payload.try_get_i64()?;
+                        let last_fetched_epoch = payload.try_get_i32()?;
+                        let _log_start_offset = payload.try_get_i64()?;
+                        let partition = PartitionRequest {
+                            partition,
+                            current_leader_epoch,
+                            fetch_offset,
+                            last_fetched_epoch,
+                            partition_max_bytes: payload.try_get_i32()?,
+                        };
+                        skip_tagged_fields(&mut payload)?;
+                        Ok(partition)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                skip_tagged_fields(&mut payload)?;
+
+                Ok((topic_id, partitions.into_boxed_slice()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // Forgotten topics, there is no session to drop them from
+        let forgotten_len = read_compact_len(&mut payload)?;
+        for _ in 0..forgotten_len {
+            payload.try_advance(16)?;
+            let partitions_len = read_compact_len(&mut payload)?;
+            payload.try_advance(4 * partitions_len as usize)?;
+            skip_tagged_fields(&mut payload)?;
+        }
+        let _rack_id = read_compact_nullable_string(&mut payload)?;
+
+        let mut cluster_id = None;
+        let tags_len = read_uvarint(&mut payload)?;
+        for _ in 0..tags_len {
+            let tag = read_uvarint(&mut payload)?;
+            let size = read_uvarint(&mut payload)?;
+            let mut field = payload.try_split_to(size as usize)?;
+            match tag {
+                CLUSTER_ID_TAG => cluster_id = read_compact_nullable_string(&mut field)?,
+                REPLICA_STATE_TAG => replica_id = field.try_get_i32()?,
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            header: req.header,
+            quorum,
+            cluster_id,
+            replica_id,
+            max_wait,
+            topics: topics.into_boxed_slice(),
+        })
+    }
+
+    async fn fetch_partition(&self, topic_id: &Uuid, partition: &PartitionRequest) -> QuorumFetch {
+        if *topic_id != METADATA_TOPIC_ID || partition.partition != 0 {
+            return QuorumFetch {
+                error_code: ErrorCode::UnknownTopicId,
+                high_watermark: -1,
+                log_start_offset: -1,
+                records: Bytes::new(),
+                diverging_epoch: None,
+                current_leader: self.quorum.current_leader(),
+            };
+        }
+
+        let max_wait = self.max_wait.clamp(0, FETCH_MAX_WAIT_MS);
+        self.quorum
+            .fetch(
+                self.replica_id,
+                partition.current_leader_epoch,
+                partition.fetch_offset,
+                partition.last_fetched_epoch,
+                partition.partition_max_bytes.max(0) as usize,
+                Duration::from_millis(max_wait as u64),
+            )
+            .await
+    }
+}
+
+impl IntoDelayedResponse for QuorumFetchRequest {
+    async fn response(self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+
+        let error_code = match self.header.api_version < MIN_QUORUM_FETCH_VERSION {
+            true => Err(ErrorCode::UnsupportedVersion),
+            false => self.quorum.check_cluster_id(self.cluster_id.as_deref()),
+        };
+        if let Err(error_code) = error_code {
+            content.put_i16(error_code as i16);
+            // Session id
+            content.put_i32(0);
+            unsigned_varint_encode(&mut content, 0);
+            content.put_i8(0x00);
+            return content;
+        }
+        content.put_i16(ErrorCode::None as i16);
+        // Session id
+        content.put_i32(0);
+
+        unsigned_varint_encode(&mut content, self.topics.len());
+        for (topic_id, partitions) in self.topics.iter() {
+            content.put_u128(topic_id.as_u128());
+            unsigned_varint_encode(&mut content, partitions.len());
+            for partition in partitions.iter() {
+                let fetched = self.fetch_partition(topic_id, partition).await;
+
+                content.put_i32(partition.partition);
+                content.put_i16(fetched.error_code as i16);
+                // High Watermark
+                content.put_i64(fetched.high_watermark);
+                // Last Stable Offset
+                content.put_i64(fetched.high_watermark);
+                // Log start offset
+                content.put_i64(fetched.log_start_offset);
+                // Aborted Txns, the metadata log has no transactions
+                content.put_i8(0x00);
+                // Prefered Read Replica
+                content.put_i32(-1);
+                // Compact Records
+                unsigned_varint_encode(&mut content, fetched.records.len());
+                content.put(fetched.records);
+
+                // Tags
+                let mut fields = Vec::new();
+                if let Some((epoch, end_offset)) = fetched.diverging_epoch {
+                    let mut field = BytesMut::new();
+                    field.put_i32(epoch);
+                    field.put_i64(end_offset);
+                    // Tags
+                    field.put_i8(0x00);
+                    fields.push((DIVERGING_EPOCH_TAG, field));
+                }
+                let (leader_id, leader_epoch) = fetched.current_leader;
+                fields.push((
+                    CURRENT_LEADER_TAG,
+                    encode_current_leader(leader_id, leader_epoch),
+                ));
+                write_tagged_fields(&mut content, &fields);
+            }
+            // Tags
+            content.put_i8(0x00);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
@@ -0,0 +1,138 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    cluster::BrokerRegistry,
+    group::{GroupCoordinator, SyncGroupResult},
+    offsets::partition_for,
+    request::{
+        ErrorCode, IntoDelayedResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_nullable_string, read_compact_string, skip_tagged_fields,
+        write_compact_nullable_string, write_compact_string,
+    },
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct SyncGroupRequest {
+    header: RequestHeader,
+    groups: Arc<GroupCoordinator>,
+    brokers: Arc<BrokerRegistry>,
+    group_id: Bytes,
+    generation_id: i32,
+    member_id: Bytes,
+    group_instance_id: Option<Bytes>,
+    protocol_type: Option<Bytes>,
+    protocol_name: Option<Bytes>,
+    assignments: Vec<(Bytes, Bytes)>,
+}
+
+impl SyncGroupRequest {
+    pub fn new(
+        req: Request,
+        groups: Arc<GroupCoordinator>,
+        brokers: Arc<BrokerRegistry>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+        let group_id = read_compact_string(&mut payload)?;
+        let generation_id = payload.try_get_i32()?;
+        let member_id = read_compact_string(&mut payload)?;
+        let group_instance_id = read_compact_nullable_string(&mut payload)?;
+        let (protocol_type, protocol_name) = if req.header.api_version >= 5 {
+            (
+                read_compact_nullable_string(&mut payload)?,
+                read_compact_nullable_string(&mut payload)?,
+            )
+        } else {
+            (None, None)
+        };
+
+        let assignments_len = read_compact_len(&mut payload)?;
+        let assignments = (0..assignments_len)
+            .map(|_| {
+                let member_id = read_compact_string(&mut payload)?;
+                let assignment = read_compact_string(&mut payload)?;
+                skip_tagged_fields(&mut payload)?;
+
+                Ok((member_id, assignment))
+            })
+            .collect::<Result<Vec<(Bytes, Bytes)>>>()?;
+
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            groups,
+            brokers,
+            group_id,
+            generation_id,
+            member_id,
+            group_instance_id,
+            protocol_type,
+            protocol_name,
+            assignments,
+        })
+    }
+}
+
+impl IntoDelayedResponse for SyncGroupRequest {
+    async fn response(self) -> BytesMut {
+        let rejected = |error_code| SyncGroupResult {
+            error_code,
+            protocol_type: None,
+            protocol_name: None,
+            assignment: Bytes::new(),
+        };
+        let result = match self
+            .brokers
+            .check_coordinator(partition_for(&self.group_id))
+        {
+            Ok(()) => {
+                let receiver = self.groups.sync_group(
+                    &self.group_id,
+                    self.generation_id,
+                    &self.member_id,
+                    self.group_instance_id.as_ref(),
+                    self.assignments,
+                );
+                receiver
+                    .await
+                    .unwrap_or_else(|_| rejected(ErrorCode::RebalanceInProgress))
+            }
+            Err(error_code) => rejected(error_code),
+        };
+
+        // Members must agree with the group on the protocol they were assigned under
+        let mismatch = |requested: &Option<Bytes>, current: &Option<Bytes>| {
+            requested.is_some() && current.is_some() && requested != current
+        };
+        let error_code = if result.error_code == ErrorCode::None
+            && (mismatch(&self.protocol_type, &result.protocol_type)
+                || mismatch(&self.protocol_name, &result.protocol_name))
+        {
+            ErrorCode::InconsistentGroupProtocol
+        } else {
+            result.error_code
+        };
+
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+        content.put_i16(error_code as i16);
+        if self.header.api_version >= 5 {
+            write_compact_nullable_string(&mut content, result.protocol_type.as_deref());
+            write_compact_nullable_string(&mut content, result.protocol_name.as_deref());
+        }
+        write_compact_string(&mut content, &result.assignment);
+        // Tags
+        content.put_i8(0x00);
+
+        content
+    }
+}
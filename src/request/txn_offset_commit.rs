@@ -0,0 +1,175 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    cluster::BrokerRegistry,
+    current_time_ms,
+    offsets::{OffsetCommitKey, OffsetCommitValue, partition_for},
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_nullable_string, read_compact_string, skip_tagged_fields,
+        write_compact_string,
+    },
+    txn::TransactionCoordinator,
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct TxnOffsetCommitRequest {
+    header: RequestHeader,
+    transactions: Arc<TransactionCoordinator>,
+    brokers: Arc<BrokerRegistry>,
+    transactional_id: Bytes,
+    group_id: Bytes,
+    producer_id: i64,
+    producer_epoch: i16,
+    generation_id: i32,
+    member_id: Bytes,
+    group_instance_id: Option<Bytes>,
+    topics: Box<[(Bytes, Box<[PartitionOffset]>)]>,
+}
+
+#[derive(Debug)]
+struct PartitionOffset {
+    index: i32,
+    committed_offset: i64,
+    committed_leader_epoch: i32,
+    committed_metadata: Option<Bytes>,
+}
+
+impl TxnOffsetCommitRequest {
+    pub fn new(
+        req: Request,
+        transactions: Arc<TransactionCoordinator>,
+        brokers: Arc<BrokerRegistry>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+        let transactional_id = read_compact_string(&mut payload)?;
+        let group_id = read_compact_string(&mut payload)?;
+        let producer_id = payload.try_get_i64()?;
+        let producer_epoch = payload.try_get_i16()?;
+        let generation_id = payload.try_get_i32()?;
+        let member_id = read_compact_string(&mut payload)?;
+        let group_instance_id = read_compact_nullable_string(&mut payload)?;
+
+        let topics_len = read_compact_len(&mut payload)?;
+        let topics = (0..topics_len)
+            .map(|_| {
+                let name = read_compact_string(&mut payload)?;
+                let partitions_len = read_compact_len(&mut payload)?;
+                let partitions = (0..partitions_len)
+                    .map(|_| {
+                        let index = payload.try_get_i32()?;
+                        let committed_offset = payload.try_get_i64()?;
+                        let committed_leader_epoch = payload.try_get_i32()?;
+                        let committed_metadata = read_compact_nullable_string(&mut payload)?;
+                        skip_tagged_fields(&mut payload)?;
+
+                        Ok(PartitionOffset {
+                            index,
+                            committed_offset,
+                            committed_leader_epoch,
+                            committed_metadata,
+                        })
+                    })
+                    .collect::<Result<Vec<PartitionOffset>>>()?;
+                skip_tagged_fields(&mut payload)?;
+
+                Ok((name, partitions.into_boxed_slice()))
+            })
+            .collect::<Result<Vec<(Bytes, Box<[PartitionOffset]>)>>>()?;
+
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            transactions,
+            brokers,
+            transactional_id,
+            group_id,
+            producer_id,
+            producer_epoch,
+            generation_id,
+            member_id,
+            group_instance_id,
+            topics: topics.into_boxed_slice(),
+        })
+    }
+
+    fn commit(&self) -> ErrorCode {
+        // Sent to the group's coordinator, which keeps its offsets
+        if let Err(error_code) = self
+            .brokers
+            .check_coordinator(partition_for(&self.group_id))
+        {
+            return error_code;
+        }
+
+        let commit_timestamp = current_time_ms();
+        let offsets = self
+            .topics
+            .iter()
+            .flat_map(|(topic_name, partitions)| {
+                partitions.iter().map(move |partition| {
+                    let key = OffsetCommitKey {
+                        group_id: self.group_id.clone(),
+                        topic: topic_name.clone(),
+                        partition: partition.index,
+                    };
+                    let value = OffsetCommitValue {
+                        offset: partition.committed_offset,
+                        leader_epoch: partition.committed_leader_epoch,
+                        metadata: partition.committed_metadata.clone().unwrap_or_default(),
+                        commit_timestamp,
+                    };
+                    (key, value)
+                })
+            })
+            .collect();
+
+        match self.transactions.txn_offset_commit(
+            &self.transactional_id,
+            self.producer_id,
+            self.producer_epoch,
+            &self.group_id,
+            offsets,
+        ) {
+            Ok(()) => ErrorCode::None,
+            Err(error_code) => error_code,
+        }
+    }
+}
+
+impl IntoResponse for TxnOffsetCommitRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+        let throttle_time = 0;
+        let error_code = self.commit();
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i32(throttle_time);
+
+        unsigned_varint_encode(&mut content, self.topics.len());
+        for (topic_name, partitions) in self.topics.iter() {
+            write_compact_string(&mut content, topic_name);
+            unsigned_varint_encode(&mut content, partitions.len());
+            for partition in partitions.iter() {
+                content.put_i32(partition.index);
+                content.put_i16(error_code as i16);
+                // Tags
+                content.put_i8(0x00);
+            }
+
+            content.put_i8(0x00);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
@@ -0,0 +1,132 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    metadata::METADATA_TOPIC,
+    raft::RaftQuorum,
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_nullable_string, read_compact_string, skip_tagged_fields,
+        write_compact_string,
+    },
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+struct VotePartition {
+    partition: i32,
+    candidate_epoch: i32,
+    candidate_id: i32,
+    last_offset_epoch: i32,
+    last_offset: i64,
+}
+
+#[derive(Debug)]
+pub struct VoteRequest {
+    header: RequestHeader,
+    quorum: Arc<RaftQuorum>,
+    cluster_id: Option<Bytes>,
+    topics: Box<[(Bytes, Box<[VotePartition]>)]>,
+}
+
+impl VoteRequest {
+    pub fn new(req: Request, quorum: Arc<RaftQuorum>) -> Result<Self> {
+        let mut payload = req.payload;
+
+        let cluster_id = read_compact_nullable_string(&mut payload)?;
+        let topics_len = read_compact_len(&mut payload)?;
+        let topics = (0..topics_len)
+            .map(|_| {
+                let topic_name = read_compact_string(&mut payload)?;
+                let partitions_len = read_compact_len(&mut payload)?;
+                let partitions = (0..partitions_len)
+                    .map(|_| {
+                        let partition = VotePartition {
+                            partition: payload.try_get_i32()?,
+                            candidate_epoch: payload.try_get_i32()?,
+                            candidate_id: payload.try_get_i32()?,
+                            last_offset_epoch: payload.try_get_i32()?,
+                            last_offset: payload.try_get_i64()?,
+                        };
+                        skip_tagged_fields(&mut payload)?;
+                        Ok(partition)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                skip_tagged_fields(&mut payload)?;
+
+                Ok((topic_name, partitions.into_boxed_slice()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            quorum,
+            cluster_id,
+            topics: topics.into_boxed_slice(),
+        })
+    }
+
+    /// Refuses the whole request, answering no partition.
+    pub fn error_response(header: &RequestHeader, error_code: ErrorCode) -> BytesMut {
+        let mut content = BytesMut::new();
+
+        content.put_i32(header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i16(error_code as i16);
+        unsigned_varint_encode(&mut content, 0);
+        content.put_i8(0x00);
+
+        content
+    }
+}
+
+impl IntoResponse for VoteRequest {
+    fn response(&self) -> BytesMut {
+        if let Err(error_code) = self.quorum.check_cluster_id(self.cluster_id.as_deref()) {
+            return Self::error_response(&self.header, error_code);
+        }
+
+        let mut content = BytesMut::new();
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+        content.put_i16(ErrorCode::None as i16);
+
+        unsigned_varint_encode(&mut content, self.topics.len());
+        for (topic_name, partitions) in self.topics.iter() {
+            write_compact_string(&mut content, topic_name);
+            unsigned_varint_encode(&mut content, partitions.len());
+            for partition in partitions.iter() {
+                let (error_code, leader_id, leader_epoch, granted) =
+                    match &topic_name[..] == METADATA_TOPIC && partition.partition == 0 {
+                        true => self.quorum.handle_vote(
+                            partition.candidate_epoch,
+                            partition.candidate_id,
+                            partition.last_offset_epoch,
+                            partition.last_offset,
+                        ),
+                        false => (ErrorCode::UnknownTopicOrPartition, -1, -1, false),
+                    };
+
+                content.put_i32(partition.partition);
+                content.put_i16(error_code as i16);
+                content.put_i32(leader_id);
+                content.put_i32(leader_epoch);
+                content.put_u8(granted as u8);
+                // Tags
+                content.put_i8(0x00);
+            }
+            // Tags
+            content.put_i8(0x00);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
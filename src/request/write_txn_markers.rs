@@ -0,0 +1,179 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::{
+    log::LogManager,
+    metadata::RecordBatch,
+    offsets::{OFFSETS_TOPIC, OFFSETS_TOPIC_PARTITIONS, OffsetManager},
+    record::ControlRecordType,
+    request::{
+        ErrorCode, IntoResponse, Request, RequestHeader, TryGet, read_compact_len,
+        read_compact_string, skip_tagged_fields, write_compact_string,
+    },
+    txn::write_txn_marker,
+    unsigned_varint_encode,
+};
+
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct WriteTxnMarkersRequest {
+    header: RequestHeader,
+    metadata: Arc<Box<[RecordBatch]>>,
+    logs: Arc<LogManager>,
+    offsets: Arc<OffsetManager>,
+    markers: Box<[TxnMarker]>,
+}
+
+#[derive(Debug)]
+struct TxnMarker {
+    producer_id: i64,
+    producer_epoch: i16,
+    committed: bool,
+    topics: Box<[(Bytes, Box<[i32]>)]>,
+    coordinator_epoch: i32,
+}
+
+impl WriteTxnMarkersRequest {
+    pub fn new(
+        req: Request,
+        metadata: Arc<Box<[RecordBatch]>>,
+        logs: Arc<LogManager>,
+        offsets: Arc<OffsetManager>,
+    ) -> Result<Self> {
+        let mut payload = req.payload;
+
+        let markers_len = read_compact_len(&mut payload)?;
+        let markers = (0..markers_len)
+            .map(|_| {
+                let producer_id = payload.try_get_i64()?;
+                let producer_epoch = payload.try_get_i16()?;
+                let committed = payload.try_get_u8()? != 0;
+
+                let topics_len = read_compact_len(&mut payload)?;
+                let topics = (0..topics_len)
+                    .map(|_| {
+                        let name = read_compact_string(&mut payload)?;
+                        let partitions_len = read_compact_len(&mut payload)?;
+                        let partitions = (0..partitions_len)
+                            .map(|_| payload.try_get_i32())
+                            .collect::<Result<Vec<i32>>>()?;
+                        skip_tagged_fields(&mut payload)?;
+
+                        Ok((name, partitions.into_boxed_slice()))
+                    })
+                    .collect::<Result<Vec<(Bytes, Box<[i32]>)>>>()?;
+
+                let coordinator_epoch = payload.try_get_i32()?;
+                skip_tagged_fields(&mut payload)?;
+
+                Ok(TxnMarker {
+                    producer_id,
+                    producer_epoch,
+                    committed,
+                    topics: topics.into_boxed_slice(),
+                    coordinator_epoch,
+                })
+            })
+            .collect::<Result<Vec<TxnMarker>>>()?;
+
+        skip_tagged_fields(&mut payload)?;
+
+        Ok(Self {
+            header: req.header,
+            metadata,
+            logs,
+            offsets,
+            markers: markers.into_boxed_slice(),
+        })
+    }
+
+    fn partition_exists(&self, topic_name: &Bytes, index: i32) -> bool {
+        // The offsets topic is internal and has no metadata records
+        if topic_name == OFFSETS_TOPIC {
+            return (0..OFFSETS_TOPIC_PARTITIONS).contains(&index);
+        }
+
+        self.metadata.iter().any(|record| {
+            record
+                .get_topic_uuid(topic_name)
+                .is_some_and(|uuid| record.valid_partition(&uuid, index))
+        })
+    }
+
+    fn write_marker(&self, marker: &TxnMarker, topic_name: &Bytes, index: i32) -> ErrorCode {
+        if !self.partition_exists(topic_name, index) {
+            return ErrorCode::UnknownTopicOrPartition;
+        }
+
+        let control_type = if marker.committed {
+            ControlRecordType::Commit
+        } else {
+            ControlRecordType::Abort
+        };
+
+        match write_txn_marker(
+            &self.logs,
+            topic_name,
+            index,
+            marker.producer_id,
+            marker.producer_epoch,
+            marker.coordinator_epoch,
+            control_type,
+        ) {
+            Ok(()) => {
+                // Transactional offset commits take effect, or are dropped, with the marker
+                if topic_name == OFFSETS_TOPIC {
+                    self.offsets
+                        .complete_transaction(index, marker.producer_id, marker.committed);
+                }
+                ErrorCode::None
+            }
+            Err(err) => match err.downcast_ref::<crate::producer::SequenceError>() {
+                Some(err) => err.into(),
+                None => {
+                    eprintln!("writing transaction marker: {err:#}");
+                    ErrorCode::KafkaStorageError
+                }
+            },
+        }
+    }
+}
+
+impl IntoResponse for WriteTxnMarkersRequest {
+    fn response(&self) -> BytesMut {
+        let mut content = BytesMut::new();
+
+        content.put_i32(self.header.correlation_id);
+        content.put_i8(0x00);
+
+        unsigned_varint_encode(&mut content, self.markers.len());
+        for marker in self.markers.iter() {
+            content.put_i64(marker.producer_id);
+
+            unsigned_varint_encode(&mut content, marker.topics.len());
+            for (topic_name, partitions) in marker.topics.iter() {
+                write_compact_string(&mut content, topic_name);
+                unsigned_varint_encode(&mut content, partitions.len());
+                for index in partitions.iter() {
+                    let error_code = self.write_marker(marker, topic_name, *index);
+
+                    content.put_i32(*index);
+                    content.put_i16(error_code as i16);
+                    // Tags
+                    content.put_i8(0x00);
+                }
+
+                content.put_i8(0x00);
+            }
+
+            content.put_i8(0x00);
+        }
+
+        content.put_i8(0x00);
+
+        content
+    }
+}
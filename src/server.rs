@@ -1,61 +1,139 @@
 use crate::{
-    metadata::{RecordBatch, parse_metadata},
+    cluster::BrokerRegistry,
+    config::{BrokerConfig, ConfigManager},
+    fetch_session::FetchSessionCache,
+    group::GroupCoordinator,
+    log::LogManager,
+    metadata::{MetadataCache, MetadataWriter, decode_metadata},
+    offsets::{OFFSETS_RETENTION_MS, OffsetManager},
+    producer::ProducerIdManager,
+    raft::RaftQuorum,
+    replica::ReplicaManager,
+    replica_fetcher::ReplicaFetcher,
     request::{
-        ApiType, IntoResponse, api_versions::ApiVersionsRequest,
-        describe_topics::DescribeTopicsRequest, fetch::FetchRequest, produce::ProduceRequest,
+        ApiType, ErrorCode, IntoDelayedResponse, IntoResponse, RequestHeader,
+        add_offsets_to_txn::AddOffsetsToTxnRequest,
+        add_partitions_to_txn::AddPartitionsToTxnRequest,
+        allocate_producer_ids::AllocateProducerIdsRequest, alter_configs::AlterConfigsRequest,
+        alter_partition::AlterPartitionRequest, api_versions::ApiVersionsRequest,
+        begin_quorum_epoch::BeginQuorumEpochRequest,
+        broker_registration::BrokerRegistrationRequest,
+        consumer_group_describe::ConsumerGroupDescribeRequest,
+        consumer_group_heartbeat::ConsumerGroupHeartbeatRequest,
+        delete_groups::DeleteGroupsRequest, describe_cluster::DescribeClusterRequest,
+        describe_configs::DescribeConfigsRequest, describe_groups::DescribeGroupsRequest,
+        describe_log_dirs::DescribeLogDirsRequest, describe_topics::DescribeTopicsRequest,
+        end_quorum_epoch::EndQuorumEpochRequest, end_txn::EndTxnRequest, fetch::FetchRequest,
+        fetch_snapshot::FetchSnapshotRequest, find_coordinator::FindCoordinatorRequest,
+        heartbeat::HeartbeatRequest, incremental_alter_configs::IncrementalAlterConfigsRequest,
+        init_producer_id::InitProducerIdRequest, join_group::JoinGroupRequest,
+        leave_group::LeaveGroupRequest, list_groups::ListGroupsRequest, metadata::MetadataRequest,
+        offset_commit::OffsetCommitRequest, offset_delete::OffsetDeleteRequest,
+        offset_fetch::OffsetFetchRequest, offset_for_leader_epoch::OffsetForLeaderEpochRequest,
+        produce::ProduceRequest, quorum_alter_configs::QuorumAlterConfigsRequest,
+        quorum_fetch::QuorumFetchRequest, sync_group::SyncGroupRequest,
+        txn_offset_commit::TxnOffsetCommitRequest, vote::VoteRequest,
+        write_txn_markers::WriteTxnMarkersRequest,
     },
+    txn::TransactionCoordinator,
 };
 
 use super::request::Request;
-use anyhow::{Context, Result};
-use bytes::{BufMut, BytesMut};
-use kanal::{AsyncReceiver, AsyncSender, unbounded_async};
-use std::{collections::HashMap, sync::Arc};
+use anyhow::{Context, Result, bail};
+use bytes::{BufMut, Bytes, BytesMut};
+use kanal::{AsyncReceiver, AsyncSender, ReceiveError, unbounded_async};
+use std::{collections::HashMap, io::ErrorKind, sync::Arc, time::Duration};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
     task::JoinHandle,
 };
 
-const WORKER_COUNT: usize = 10;
+const TRANSACTION_ABORT_INTERVAL: Duration = Duration::from_secs(1);
+const GROUP_TICK_INTERVAL: Duration = Duration::from_millis(100);
+// How often logs are checked for appends older than their topic's flush.ms
+const LOG_FLUSH_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+// How often followers fetch from their leaders, which answer without waiting for new records
+const REPLICA_FETCH_INTERVAL: Duration = Duration::from_millis(50);
+// Kafka's offsets.retention.check.interval.ms default
+const OFFSETS_RETENTION_CHECK_INTERVAL: Duration = Duration::from_secs(600);
+// How long a broker waits before retrying its registration with the controller
+const BROKER_REGISTRATION_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+// How often a metadata log written outside the quorum is checked for new batches
+const METADATA_LOG_POLL_INTERVAL: Duration = Duration::from_millis(500);
 pub type ServerRequest = (Request, AsyncSender<BytesMut>);
 
 pub struct ConnectionHandler {
     stream: TcpStream,
+    client_host: Bytes,
     msg_sender: AsyncSender<ServerRequest>,
+    // socket.request.max.bytes
+    max_request_size: usize,
 }
 
 impl ConnectionHandler {
-    pub fn new(stream: TcpStream, msg_sender: AsyncSender<ServerRequest>) -> Self {
-        Self { stream, msg_sender }
+    pub fn new(
+        stream: TcpStream,
+        msg_sender: AsyncSender<ServerRequest>,
+        max_request_size: usize,
+    ) -> Self {
+        // Reported the way Kafka does, as the peer's address with a leading slash
+        let client_host = stream
+            .peer_addr()
+            .map(|addr| Bytes::from(format!("/{}", addr.ip())))
+            .unwrap_or_default();
+
+        Self {
+            stream,
+            client_host,
+            msg_sender,
+            max_request_size,
+        }
     }
 
     pub async fn handle_connection(&mut self) -> Result<()> {
         loop {
-            let mut buf = BytesMut::from_iter(vec![0; 4096].into_iter());
-            let n = self
-                .stream
-                .read(&mut buf)
-                .await
-                .context("reading client request")?;
+            let message_size = match self.stream.read_i32().await {
+                Ok(message_size) => message_size,
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err).context("reading client request size"),
+            };
 
-            if n == 0 {
-                break;
+            // Like Kafka, the connection is dropped rather than buffering an oversized request
+            let size = usize::try_from(message_size).unwrap_or(usize::MAX);
+            if size > self.max_request_size {
+                bail!(
+                    "request of {message_size} bytes is larger than socket.request.max.bytes ({})",
+                    self.max_request_size
+                );
             }
 
-            buf.truncate(n);
+            let mut buf = BytesMut::with_capacity(4 + size);
+            buf.put_i32(message_size);
+            buf.resize(4 + size, 0);
+            self.stream
+                .read_exact(&mut buf[4..])
+                .await
+                .context("reading client request")?;
+
             let (tx, rx) = unbounded_async();
-            let request = Request::parse(buf).context("parsing incoming request")?;
+            let mut request = Request::parse(buf).context("parsing incoming request")?;
+            request.client_host = self.client_host.clone();
             self.msg_sender
                 .send((request, tx))
                 .await
                 .context("sending request to server")?;
 
-            if let Ok(response) = rx.recv().await {
-                self.stream
-                    .write(&response[..])
+            match rx.recv().await {
+                Ok(response) => self
+                    .stream
+                    .write_all(&response[..])
                     .await
-                    .context("sending response back to client")?;
+                    .context("sending response back to client")?,
+                // The listener refused the request or could not parse it
+                Err(ReceiveError::Closed) => bail!("request refused by the listener"),
+                // Nothing to send back
+                Err(ReceiveError::SendClosed) => {}
             }
         }
 
@@ -63,18 +141,126 @@ impl ConnectionHandler {
     }
 }
 
+/// State shared by every worker, each handler picking the parts it needs.
+#[derive(Debug, Clone)]
+pub struct BrokerContext {
+    pub config: Arc<BrokerConfig>,
+    pub configs: Arc<ConfigManager>,
+    pub brokers: Arc<BrokerRegistry>,
+    pub metadata: Arc<MetadataCache>,
+    pub logs: Arc<LogManager>,
+    pub producer_ids: Arc<ProducerIdManager>,
+    pub transactions: Arc<TransactionCoordinator>,
+    pub groups: Arc<GroupCoordinator>,
+    pub offsets: Arc<OffsetManager>,
+    pub fetch_sessions: Arc<FetchSessionCache>,
+    pub replicas: Arc<ReplicaManager>,
+}
+
+impl BrokerContext {
+    /// Hands batches read past what was loaded to everything built from the metadata log.
+    /// Directories are assigned first, so partitions this broker takes on open in place.
+    fn replay(&self, records: Bytes) -> Result<()> {
+        let metadata = decode_metadata(records)?;
+        self.logs
+            .load_directory_assignments(&metadata, self.config.node_id);
+        self.metadata.append(&metadata);
+        self.configs.replay(&metadata);
+        self.brokers.replay(&metadata);
+        self.producer_ids.replay(&metadata);
+        self.replicas.replay(&metadata);
+        Ok(())
+    }
+}
+
 pub struct Server {
     worker_count: usize,
-    metadata: Arc<Box<[RecordBatch]>>,
+    context: BrokerContext,
+    // The embedded quorum, when the metadata log comes from it
+    quorum: Option<Arc<RaftQuorum>>,
+    // Offset up to which the metadata log was loaded, later batches are replayed
+    metadata_offset: i64,
     pool: HashMap<usize, JoinHandle<Result<(), anyhow::Error>>>,
 }
 
 impl Server {
-    pub fn new() -> Self {
-        let metadata = parse_metadata();
+    pub fn new(
+        config: Arc<BrokerConfig>,
+        logs: Arc<LogManager>,
+        quorum: Option<Arc<RaftQuorum>>,
+    ) -> Self {
+        let ((records, metadata_offset), writer) = match &quorum {
+            Some(quorum) => (
+                quorum
+                    .read_committed(0)
+                    .expect("reading committed metadata"),
+                MetadataWriter::Quorum(Arc::clone(quorum)),
+            ),
+            None => (
+                logs.read_metadata(0).expect("reading metadata log"),
+                MetadataWriter::Log(Arc::clone(&logs)),
+            ),
+        };
+        let metadata = decode_metadata(records).expect("decoding metadata log");
+        logs.load_directory_assignments(&metadata, config.node_id);
+        let metadata = Arc::new(MetadataCache::new(metadata));
+        let configs = Arc::new(ConfigManager::new(
+            Arc::clone(&config),
+            Arc::clone(&metadata),
+            Arc::clone(&logs),
+            writer.clone(),
+        ));
+        let brokers = Arc::new(BrokerRegistry::new(
+            &config,
+            &metadata.snapshot(),
+            &logs,
+            &writer,
+        ));
+        let replicas = Arc::new(ReplicaManager::new(
+            &config,
+            &metadata.snapshot(),
+            Arc::clone(&logs),
+            writer.clone(),
+        ));
+        let next_producer_id = metadata
+            .snapshot()
+            .iter()
+            .filter_map(|record| record.next_producer_id())
+            .max()
+            .unwrap_or(0);
+        let producer_ids = Arc::new(ProducerIdManager::new(
+            config.node_id,
+            next_producer_id,
+            writer,
+        ));
+        let offsets =
+            Arc::new(OffsetManager::new(Arc::clone(&logs)).expect("loading committed offsets"));
+        let transactions = TransactionCoordinator::new(
+            Arc::clone(&logs),
+            Arc::clone(&producer_ids),
+            Arc::clone(&offsets),
+        )
+        .expect("loading transaction state");
+        let groups = GroupCoordinator::new();
+        groups.load_groups(offsets.groups());
+
         Self {
-            worker_count: WORKER_COUNT,
-            metadata: Arc::new(metadata),
+            worker_count: config.worker_count,
+            context: BrokerContext {
+                config,
+                configs,
+                brokers,
+                metadata,
+                logs,
+                producer_ids,
+                transactions: Arc::new(transactions),
+                groups: Arc::new(groups),
+                offsets,
+                fetch_sessions: Arc::new(FetchSessionCache::new()),
+                replicas,
+            },
+            quorum,
+            metadata_offset,
             pool: HashMap::new(),
         }
     }
@@ -82,49 +268,788 @@ impl Server {
     pub fn start(&mut self, receiver: AsyncReceiver<ServerRequest>) {
         for i in 0..self.worker_count {
             let rx = receiver.clone();
-            let metadata = Arc::clone(&self.metadata);
-            let mut worker = ServerWorker::new(rx, metadata);
+            let mut worker = ServerWorker::new(rx, self.context.clone());
             let handle = tokio::task::spawn(async move { worker.start().await });
             self.pool.insert(i, handle);
         }
+
+        let transactions = Arc::clone(&self.context.transactions);
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(TRANSACTION_ABORT_INTERVAL);
+            loop {
+                interval.tick().await;
+                transactions.abort_timed_out();
+            }
+        });
+
+        let logs = Arc::clone(&self.context.logs);
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(LOG_FLUSH_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                let logs = Arc::clone(&logs);
+                if let Err(err) = tokio::task::spawn_blocking(move || logs.flush_due()).await {
+                    eprintln!("flushing logs: {err:#}");
+                }
+            }
+        });
+
+        let replicas = Arc::clone(&self.context.replicas);
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(replicas.isr_expiration_interval());
+            loop {
+                interval.tick().await;
+                replicas.expire_lagging_followers();
+            }
+        });
+
+        let mut fetcher = ReplicaFetcher::new(
+            &self.context.config,
+            Arc::clone(&self.context.brokers),
+            Arc::clone(&self.context.logs),
+            Arc::clone(&self.context.replicas),
+        );
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(REPLICA_FETCH_INTERVAL);
+            loop {
+                interval.tick().await;
+                fetcher.fetch_all().await;
+            }
+        });
+
+        let groups = Arc::clone(&self.context.groups);
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(GROUP_TICK_INTERVAL);
+            loop {
+                interval.tick().await;
+                groups.tick();
+            }
+        });
+
+        let groups = Arc::clone(&self.context.groups);
+        let offsets = Arc::clone(&self.context.offsets);
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(OFFSETS_RETENTION_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                offsets.expire(&groups, OFFSETS_RETENTION_MS);
+                let offsets = Arc::clone(&offsets);
+                if let Err(err) = tokio::task::spawn_blocking(move || offsets.compact()).await {
+                    eprintln!("compacting offsets: {err:#}");
+                }
+            }
+        });
+
+        match &self.quorum {
+            Some(quorum) => self.start_quorum_tasks(Arc::clone(quorum)),
+            None => self.start_metadata_log_tasks(),
+        }
+    }
+
+    /// Replays the batches other writers add to the metadata log, when it is not kept by
+    /// the quorum.
+    fn start_metadata_log_tasks(&self) {
+        let context = self.context.clone();
+        let mut offset = self.metadata_offset;
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(METADATA_LOG_POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                let replayed =
+                    context
+                        .logs
+                        .read_metadata(offset)
+                        .and_then(|(records, log_end_offset)| {
+                            context.replay(records)?;
+                            Ok(log_end_offset)
+                        });
+                match replayed {
+                    Ok(log_end_offset) => offset = log_end_offset,
+                    Err(err) => eprintln!("replaying metadata log: {err:#}"),
+                }
+            }
+        });
+    }
+
+    /// Replays what the quorum commits, and registers this broker with the active
+    /// controller.
+    fn start_quorum_tasks(&self, quorum: Arc<RaftQuorum>) {
+        let context = self.context.clone();
+        let replayed = Arc::clone(&quorum);
+        let mut offset = self.metadata_offset;
+        tokio::task::spawn(async move {
+            loop {
+                replayed.wait_for_high_watermark(offset).await;
+                let committed =
+                    replayed
+                        .read_committed(offset)
+                        .and_then(|(records, high_watermark)| {
+                            context.replay(records)?;
+                            Ok(high_watermark)
+                        });
+                match committed {
+                    Ok(high_watermark) => offset = high_watermark,
+                    Err(err) => {
+                        eprintln!("replaying committed metadata: {err:#}");
+                        tokio::time::sleep(METADATA_LOG_POLL_INTERVAL).await;
+                    }
+                }
+            }
+        });
+
+        let config = Arc::clone(&self.context.config);
+        let brokers = Arc::clone(&self.context.brokers);
+        let logs = Arc::clone(&self.context.logs);
+        tokio::task::spawn(async move {
+            loop {
+                match brokers
+                    .register_with_controller(&config, &logs, &quorum)
+                    .await
+                {
+                    Ok(broker_epoch) => {
+                        eprintln!(
+                            "broker {} registered with epoch {broker_epoch}",
+                            config.node_id
+                        );
+                        break;
+                    }
+                    Err(err) => {
+                        eprintln!("registering broker {}: {err:#}", config.node_id);
+                        tokio::time::sleep(BROKER_REGISTRATION_RETRY_INTERVAL).await;
+                    }
+                }
+            }
+        });
     }
 }
 
 pub struct ServerWorker {
-    metadata: Arc<Box<[RecordBatch]>>,
+    context: BrokerContext,
     receiver: AsyncReceiver<ServerRequest>,
 }
 
 impl ServerWorker {
-    pub fn new(rx: AsyncReceiver<ServerRequest>, metadata: Arc<Box<[RecordBatch]>>) -> Self {
+    pub fn new(rx: AsyncReceiver<ServerRequest>, context: BrokerContext) -> Self {
         Self {
             receiver: rx,
-            metadata,
+            context,
         }
     }
 
     pub async fn start(&mut self) -> Result<()> {
         while let Ok((request, responder)) = self.receiver.recv().await {
-            let request: &dyn IntoResponse = match request.header.api_key {
-                ApiType::ApiVersions => &ApiVersionsRequest::new(request),
-                ApiType::DescribeTopicPartitions => {
-                    &DescribeTopicsRequest::new(request, Arc::clone(&self.metadata))
+            let api_key = request.header.api_key;
+            if let Err(err) = self.serve(request, &responder).await {
+                // Like Kafka, the connection is dropped rather than answering a request it
+                // could not make sense of
+                eprintln!("serving {api_key:?} request: {err:#}");
+                let _ = responder.close();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parses and answers a request, leaving it unanswered on error.
+    async fn serve(&self, request: Request, responder: &AsyncSender<BytesMut>) -> Result<()> {
+        let context = &self.context;
+        // Produce requests are answered right away unless they wait for replication or a
+        // flush
+        let produce;
+        let request: &dyn IntoResponse = match request.header.api_key {
+            ApiType::ApiVersions => &ApiVersionsRequest::new(request),
+            ApiType::DescribeTopicPartitions => {
+                &DescribeTopicsRequest::new(request, context.metadata.snapshot())?
+            }
+            ApiType::Fetch => {
+                let request = FetchRequest::new(
+                    request,
+                    context.metadata.snapshot(),
+                    Arc::clone(&context.logs),
+                    Arc::clone(&context.fetch_sessions),
+                    Arc::clone(&context.replicas),
+                    Arc::clone(&context.brokers),
+                )?;
+                respond_later(request, responder.clone());
+                return Ok(());
+            }
+            ApiType::Produce => {
+                produce = ProduceRequest::new(
+                    request,
+                    Arc::clone(&context.configs),
+                    context.metadata.snapshot(),
+                    Arc::clone(&context.logs),
+                    Arc::clone(&context.transactions),
+                    Arc::clone(&context.replicas),
+                    Arc::clone(&context.brokers),
+                )?;
+                if produce.is_delayed() {
+                    respond_later(produce, responder.clone());
+                    return Ok(());
                 }
-                ApiType::Fetch => &FetchRequest::new(request, Arc::clone(&self.metadata)),
-                ApiType::Produce => &ProduceRequest::new(request, Arc::clone(&self.metadata)),
+                &produce
+            }
+            ApiType::InitProducerId => &InitProducerIdRequest::new(
+                request,
+                Arc::clone(&context.producer_ids),
+                Arc::clone(&context.transactions),
+                Arc::clone(&context.brokers),
+            )?,
+            ApiType::AddPartitionsToTxn => &AddPartitionsToTxnRequest::new(
+                request,
+                context.metadata.snapshot(),
+                Arc::clone(&context.transactions),
+                Arc::clone(&context.brokers),
+            )?,
+            ApiType::AddOffsetsToTxn => &AddOffsetsToTxnRequest::new(
+                request,
+                Arc::clone(&context.transactions),
+                Arc::clone(&context.brokers),
+            )?,
+            ApiType::EndTxn => &EndTxnRequest::new(
+                request,
+                Arc::clone(&context.transactions),
+                Arc::clone(&context.brokers),
+            )?,
+            ApiType::WriteTxnMarkers => &WriteTxnMarkersRequest::new(
+                request,
+                context.metadata.snapshot(),
+                Arc::clone(&context.logs),
+                Arc::clone(&context.offsets),
+            )?,
+            ApiType::TxnOffsetCommit => &TxnOffsetCommitRequest::new(
+                request,
+                Arc::clone(&context.transactions),
+                Arc::clone(&context.brokers),
+            )?,
+            ApiType::FindCoordinator => &FindCoordinatorRequest::new(
+                request,
+                Arc::clone(&context.config),
+                Arc::clone(&context.brokers),
+            )?,
+            ApiType::JoinGroup => {
+                let request = JoinGroupRequest::new(
+                    request,
+                    Arc::clone(&context.groups),
+                    Arc::clone(&context.brokers),
+                )?;
+                respond_later(request, responder.clone());
+                return Ok(());
+            }
+            ApiType::Heartbeat => &HeartbeatRequest::new(
+                request,
+                Arc::clone(&context.groups),
+                Arc::clone(&context.brokers),
+            )?,
+            ApiType::LeaveGroup => &LeaveGroupRequest::new(
+                request,
+                Arc::clone(&context.groups),
+                Arc::clone(&context.brokers),
+            )?,
+            ApiType::OffsetCommit => &OffsetCommitRequest::new(
+                request,
+                Arc::clone(&context.groups),
+                Arc::clone(&context.offsets),
+                Arc::clone(&context.brokers),
+            )?,
+            ApiType::OffsetFetch => &OffsetFetchRequest::new(
+                request,
+                Arc::clone(&context.groups),
+                Arc::clone(&context.offsets),
+                Arc::clone(&context.brokers),
+            )?,
+            ApiType::ConsumerGroupHeartbeat => &ConsumerGroupHeartbeatRequest::new(
+                request,
+                Arc::clone(&context.groups),
+                context.metadata.snapshot(),
+                Arc::clone(&context.brokers),
+            )?,
+            ApiType::ConsumerGroupDescribe => &ConsumerGroupDescribeRequest::new(
+                request,
+                Arc::clone(&context.groups),
+                context.metadata.snapshot(),
+                Arc::clone(&context.brokers),
+            )?,
+            ApiType::DescribeGroups => &DescribeGroupsRequest::new(
+                request,
+                Arc::clone(&context.groups),
+                Arc::clone(&context.brokers),
+            )?,
+            ApiType::ListGroups => &ListGroupsRequest::new(request, Arc::clone(&context.groups))?,
+            ApiType::DescribeConfigs => {
+                &DescribeConfigsRequest::new(request, Arc::clone(&context.configs))?
+            }
+            ApiType::AlterConfigs => {
+                let request = AlterConfigsRequest::new(request, Arc::clone(&context.configs))?;
+                respond_later(request, responder.clone());
+                return Ok(());
+            }
+            ApiType::IncrementalAlterConfigs => {
+                let request =
+                    IncrementalAlterConfigsRequest::new(request, Arc::clone(&context.configs))?;
+                respond_later(request, responder.clone());
+                return Ok(());
+            }
+            ApiType::Metadata => &MetadataRequest::new(
+                request,
+                Arc::clone(&context.brokers),
+                context.metadata.snapshot(),
+                Arc::clone(&context.replicas),
+            )?,
+            ApiType::DescribeCluster => {
+                &DescribeClusterRequest::new(request, Arc::clone(&context.brokers))?
+            }
+            ApiType::OffsetForLeaderEpoch => &OffsetForLeaderEpochRequest::new(
+                request,
+                context.metadata.snapshot(),
+                Arc::clone(&context.logs),
+                Arc::clone(&context.replicas),
+            )?,
+            ApiType::DescribeLogDirs => {
+                &DescribeLogDirsRequest::new(request, Arc::clone(&context.logs))?
+            }
+            ApiType::DeleteGroups => &DeleteGroupsRequest::new(
+                request,
+                Arc::clone(&context.groups),
+                Arc::clone(&context.offsets),
+                Arc::clone(&context.brokers),
+            )?,
+            ApiType::OffsetDelete => &OffsetDeleteRequest::new(
+                request,
+                Arc::clone(&context.groups),
+                Arc::clone(&context.offsets),
+                Arc::clone(&context.brokers),
+            )?,
+            ApiType::SyncGroup => {
+                let request = SyncGroupRequest::new(
+                    request,
+                    Arc::clone(&context.groups),
+                    Arc::clone(&context.brokers),
+                )?;
+                respond_later(request, responder.clone());
+                return Ok(());
+            }
+            // Served on the controller listener only
+            ApiType::Vote => {
+                refuse(&request.header, responder, VoteRequest::error_response).await?;
+                return Ok(());
+            }
+            ApiType::BeginQuorumEpoch => {
+                refuse(
+                    &request.header,
+                    responder,
+                    BeginQuorumEpochRequest::error_response,
+                )
+                .await?;
+                return Ok(());
+            }
+            ApiType::EndQuorumEpoch => {
+                refuse(
+                    &request.header,
+                    responder,
+                    EndQuorumEpochRequest::error_response,
+                )
+                .await?;
+                return Ok(());
+            }
+            ApiType::FetchSnapshot => {
+                refuse(
+                    &request.header,
+                    responder,
+                    FetchSnapshotRequest::error_response,
+                )
+                .await?;
+                return Ok(());
+            }
+            ApiType::BrokerRegistration => {
+                refuse(
+                    &request.header,
+                    responder,
+                    BrokerRegistrationRequest::error_response,
+                )
+                .await?;
+                return Ok(());
+            }
+            ApiType::AlterPartition => {
+                refuse(
+                    &request.header,
+                    responder,
+                    AlterPartitionRequest::error_response,
+                )
+                .await?;
+                return Ok(());
+            }
+            ApiType::AllocateProducerIds => {
+                refuse(
+                    &request.header,
+                    responder,
+                    AllocateProducerIdsRequest::error_response,
+                )
+                .await?;
+                return Ok(());
+            }
+        };
+
+        let response = request.response();
+        if !request.expects_response() {
+            // Dropping the responder lets the connection move on to its next request
+            return Ok(());
+        }
+
+        responder
+            .send(frame(response))
+            .await
+            .context("sending response to client")?;
+
+        Ok(())
+    }
+}
+
+/// Serves the controller listener: the quorum's own requests from the other voters and
+/// observers, and the registrations, ISR changes, producer id blocks and config changes
+/// brokers send the active controller.
+pub struct ControllerServer {
+    worker_count: usize,
+    quorum: Arc<RaftQuorum>,
+    pool: HashMap<usize, JoinHandle<Result<(), anyhow::Error>>>,
+}
+
+impl ControllerServer {
+    pub fn new(config: &BrokerConfig, quorum: Arc<RaftQuorum>) -> Self {
+        Self {
+            worker_count: config.worker_count,
+            quorum,
+            pool: HashMap::new(),
+        }
+    }
+
+    pub fn start(&mut self, receiver: AsyncReceiver<ServerRequest>) {
+        for i in 0..self.worker_count {
+            let mut worker = ControllerWorker {
+                receiver: receiver.clone(),
+                quorum: Arc::clone(&self.quorum),
             };
+            let handle = tokio::task::spawn(async move { worker.start().await });
+            self.pool.insert(i, handle);
+        }
+    }
+}
 
-            let mut response = BytesMut::new();
-            let content = request.response();
-            response.put_i32(content.len() as i32);
-            response.extend_from_slice(&content[..]);
+pub struct ControllerWorker {
+    quorum: Arc<RaftQuorum>,
+    receiver: AsyncReceiver<ServerRequest>,
+}
 
-            responder
-                .send(response)
-                .await
-                .context("sending response to client")?;
+impl ControllerWorker {
+    pub async fn start(&mut self) -> Result<()> {
+        while let Ok((request, responder)) = self.receiver.recv().await {
+            let api_key = request.header.api_key;
+            if let Err(err) = self.serve(request, &responder).await {
+                eprintln!("serving {api_key:?} request: {err:#}");
+                let _ = responder.close();
+            }
         }
 
         Ok(())
     }
+
+    /// Parses and answers a request, leaving it unanswered on error.
+    async fn serve(&self, request: Request, responder: &AsyncSender<BytesMut>) -> Result<()> {
+        let quorum = Arc::clone(&self.quorum);
+        let request: &dyn IntoResponse = match request.header.api_key {
+            ApiType::ApiVersions => &ApiVersionsRequest::for_controller(request),
+            ApiType::Vote => &VoteRequest::new(request, quorum)?,
+            ApiType::BeginQuorumEpoch => &BeginQuorumEpochRequest::new(request, quorum)?,
+            ApiType::EndQuorumEpoch => &EndQuorumEpochRequest::new(request, quorum)?,
+            ApiType::FetchSnapshot => &FetchSnapshotRequest::new(request, quorum)?,
+            ApiType::Fetch => {
+                respond_later(QuorumFetchRequest::new(request, quorum)?, responder.clone());
+                return Ok(());
+            }
+            ApiType::BrokerRegistration => {
+                respond_later(
+                    BrokerRegistrationRequest::new(request, quorum)?,
+                    responder.clone(),
+                );
+                return Ok(());
+            }
+            ApiType::IncrementalAlterConfigs => {
+                respond_later(
+                    QuorumAlterConfigsRequest::new(request, quorum)?,
+                    responder.clone(),
+                );
+                return Ok(());
+            }
+            ApiType::AlterPartition => {
+                respond_later(
+                    AlterPartitionRequest::new(request, quorum)?,
+                    responder.clone(),
+                );
+                return Ok(());
+            }
+            ApiType::AllocateProducerIds => {
+                respond_later(
+                    AllocateProducerIdsRequest::new(request, quorum)?,
+                    responder.clone(),
+                );
+                return Ok(());
+            }
+            api_key => {
+                // Like Kafka, the connection is dropped rather than answering an API the
+                // listener does not expose
+                eprintln!("{api_key:?} is not served on controller listeners");
+                let _ = responder.close();
+                return Ok(());
+            }
+        };
+
+        responder
+            .send(frame(request.response()))
+            .await
+            .context("sending response to client")?;
+
+        Ok(())
+    }
+}
+
+/// Answers a request for an API served on the controller listener only, in the response
+/// shape of that API.
+async fn refuse(
+    header: &RequestHeader,
+    responder: &AsyncSender<BytesMut>,
+    error_response: fn(&RequestHeader, ErrorCode) -> BytesMut,
+) -> Result<()> {
+    let response = error_response(header, ErrorCode::UnsupportedVersion);
+    responder
+        .send(frame(response))
+        .await
+        .context("sending response to client")
+}
+
+/// Prefixes a response with its size.
+fn frame(content: BytesMut) -> BytesMut {
+    let mut response = BytesMut::new();
+    response.put_i32(content.len() as i32);
+    response.extend_from_slice(&content[..]);
+    response
+}
+
+/// Answers a request on its own task so the worker can keep serving others while it waits.
+fn respond_later(
+    request: impl IntoDelayedResponse + Send + 'static,
+    responder: AsyncSender<BytesMut>,
+) {
+    tokio::task::spawn(async move {
+        let response = frame(request.response().await);
+        if let Err(err) = responder.send(response).await {
+            eprintln!("sending delayed response to client: {err:#}");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{ConfigOperation, ConfigRecord},
+        current_time_ms,
+        metadata::RecordBatch as MetadataBatch,
+        record::Record,
+        unsigned_varint_encode,
+    };
+    use tokio::net::TcpListener;
+    use uuid::Uuid;
+
+    const TOPIC: &[u8] = b"events";
+    const WAIT: Duration = Duration::from_secs(10);
+
+    /// Node `node_id` of a quorum of `voters`, voting and following the leader with its
+    /// controller listener served on `listener`.
+    async fn voter(
+        name: &str,
+        node_id: i32,
+        voters: &str,
+        listener: TcpListener,
+    ) -> (Arc<BrokerConfig>, Arc<LogManager>, Arc<RaftQuorum>) {
+        let dir = std::env::temp_dir().join(format!(
+            "server-test-{name}-{node_id}-{}-{}",
+            std::process::id(),
+            current_time_ms()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let port = listener.local_addr().unwrap().port();
+        let overrides = [
+            format!("node.id={node_id}"),
+            "process.roles=broker,controller".to_string(),
+            format!("listeners=PLAINTEXT://127.0.0.1:0,CONTROLLER://127.0.0.1:{port}"),
+            "controller.listener.names=CONTROLLER".to_string(),
+            format!("controller.quorum.voters={voters}"),
+            format!("log.dirs={}", dir.display()),
+            "cluster.id=server-test".to_string(),
+            "replica.lag.time.max.ms=200".to_string(),
+        ];
+        let config = BrokerConfig::from_args(
+            overrides
+                .into_iter()
+                .flat_map(|value| ["--override".to_string(), value]),
+        )
+        .unwrap();
+        let config = Arc::new(config);
+        let logs = Arc::new(LogManager::new(&config));
+        let quorum = Arc::new(RaftQuorum::new(&config, Arc::clone(&logs)).unwrap());
+
+        let mut controller = ControllerServer::new(&config, Arc::clone(&quorum));
+        let (tx, rx) = unbounded_async();
+        controller.start(rx);
+        tokio::task::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut handler = ConnectionHandler::new(stream, tx.clone(), usize::MAX);
+                tokio::task::spawn(async move { handler.handle_connection().await });
+            }
+        });
+        quorum.start();
+
+        (config, logs, quorum)
+    }
+
+    /// A topic with one partition led by `leader`, replicated to `replicas`, as the records
+    /// a controller writes when creating it.
+    fn topic_records(topic_id: Uuid, leader: i32, replicas: &[i32]) -> Vec<Record> {
+        let mut topic = BytesMut::new();
+        // Frame version, record type and version
+        topic.put_slice(&[1, 2, 0]);
+        unsigned_varint_encode(&mut topic, TOPIC.len());
+        topic.put_slice(TOPIC);
+        topic.put_u128(topic_id.as_u128());
+        topic.put_i8(0x00);
+
+        let mut partition = BytesMut::new();
+        partition.put_slice(&[1, 3, 0]);
+        partition.put_i32(0);
+        partition.put_u128(topic_id.as_u128());
+        for ids in [replicas, replicas, &[], &[]] {
+            unsigned_varint_encode(&mut partition, ids.len());
+            for id in ids {
+                partition.put_i32(*id);
+            }
+        }
+        partition.put_i32(leader);
+        // Leader and partition epochs
+        partition.put_i32(0);
+        partition.put_i32(0);
+        unsigned_varint_encode(&mut partition, 0);
+        partition.put_i8(0x00);
+
+        vec![
+            Record::new(None, Some(topic.freeze())),
+            Record::new(None, Some(partition.freeze())),
+        ]
+    }
+
+    /// Polls `check` until it holds, failing the test if it does not within `WAIT`.
+    async fn eventually(what: &str, mut check: impl FnMut() -> bool) {
+        let deadline = tokio::time::Instant::now() + WAIT;
+        while !check() {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "timed out on {what}"
+            );
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn non_leader_voters_forward_metadata_writes_to_the_active_controller() {
+        let listeners = [
+            TcpListener::bind("127.0.0.1:0").await.unwrap(),
+            TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        ];
+        let voters = format!(
+            "1@127.0.0.1:{},2@127.0.0.1:{}",
+            listeners[0].local_addr().unwrap().port(),
+            listeners[1].local_addr().unwrap().port()
+        );
+        let [first, second] = listeners;
+        let nodes = [
+            voter("forward", 1, &voters, first).await,
+            voter("forward", 2, &voters, second).await,
+        ];
+
+        eventually("a leader", || {
+            nodes.iter().any(|(_, _, quorum)| quorum.is_leader())
+        })
+        .await;
+        let (leader, voter) = match nodes[0].2.is_leader() {
+            true => (&nodes[0], &nodes[1]),
+            false => (&nodes[1], &nodes[0]),
+        };
+        let (config, logs, quorum) = voter.clone();
+        quorum.wait_until_caught_up().await;
+
+        // The non-leader leads the topic's partition, with the leader as its follower
+        let topic_id = Uuid::new_v4();
+        let replicas = [config.node_id, leader.0.node_id];
+        let offset = leader
+            .2
+            .append_with(|_| topic_records(topic_id, config.node_id, &replicas))
+            .unwrap();
+        let deadline = tokio::time::Instant::now() + WAIT;
+        assert!(leader.2.wait_for_commit(offset + 1, deadline).await);
+
+        let mut server = Server::new(Arc::clone(&config), logs, Some(Arc::clone(&quorum)));
+        let (_tx, rx) = unbounded_async();
+        server.start(rx);
+        let context = server.context.clone();
+        let topic = Bytes::from_static(TOPIC);
+        eventually("the topic to be replayed", || {
+            context.replicas.leader(&topic, 0) == Some((config.node_id, 0))
+        })
+        .await;
+
+        // The first id waits on a block from the active controller
+        let mut producer_id = context.producer_ids.generate();
+        eventually("a producer id block", || {
+            producer_id = context.producer_ids.generate();
+            producer_id.is_ok()
+        })
+        .await;
+        assert_eq!(producer_id.unwrap(), 0);
+        eventually("the block to be replayed", || {
+            context
+                .metadata
+                .snapshot()
+                .iter()
+                .any(|batch: &MetadataBatch| batch.next_producer_id().is_some())
+        })
+        .await;
+
+        let changes = [(
+            Bytes::from_static(b"log.cleanup.policy"),
+            ConfigOperation::Set,
+            Some(Bytes::from_static(b"compact")),
+        )];
+        let altered = context
+            .configs
+            .alter(
+                ConfigRecord::BROKER_RESOURCE,
+                &Bytes::new(),
+                &changes,
+                false,
+                false,
+            )
+            .await;
+        assert_eq!(altered, Ok(()));
+        eventually("the config to be replayed", || {
+            context.configs.topic_config(&topic, "cleanup.policy")
+                == Some(Bytes::from_static(b"compact"))
+        })
+        .await;
+
+        // The leader never fetches, and leaves the ISR once the change is committed
+        eventually("the ISR to shrink", || {
+            context.replicas.in_sync_replicas(&topic, 0) == Some(vec![config.node_id])
+        })
+        .await;
+    }
 }
@@ -0,0 +1,803 @@
+use crate::{
+    current_time_ms,
+    log::LogManager,
+    offsets::{
+        OFFSETS_TOPIC, OffsetCommitKey, OffsetCommitValue, OffsetManager, key_partition,
+        partition_for,
+    },
+    producer::ProducerIdManager,
+    record::{ControlRecordType, Record, RecordBatch},
+    request::{ErrorCode, read_string, write_string},
+};
+use anyhow::{Context, Result, bail};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+};
+
+pub const TRANSACTION_STATE_TOPIC: &[u8] = b"__transaction_state";
+// Kafka's transaction.state.log.num.partitions default
+pub const TRANSACTION_STATE_PARTITIONS: i32 = 50;
+// The leader epoch of the coordinator's `__transaction_state` partition, which fences
+// markers from an older coordinator. The partitions are not replicated, so it stays put
+pub const COORDINATOR_EPOCH: i32 = 0;
+const MAX_TRANSACTION_TIMEOUT_MS: i32 = 900_000;
+
+#[repr(i8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TxnState {
+    Empty = 0,
+    Ongoing = 1,
+    PrepareCommit = 2,
+    PrepareAbort = 3,
+    CompleteCommit = 4,
+    CompleteAbort = 5,
+    Dead = 6,
+    PrepareEpochFence = 7,
+}
+
+impl TryFrom<i8> for TxnState {
+    type Error = anyhow::Error;
+
+    fn try_from(value: i8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Empty),
+            1 => Ok(Self::Ongoing),
+            2 => Ok(Self::PrepareCommit),
+            3 => Ok(Self::PrepareAbort),
+            4 => Ok(Self::CompleteCommit),
+            5 => Ok(Self::CompleteAbort),
+            6 => Ok(Self::Dead),
+            7 => Ok(Self::PrepareEpochFence),
+            _ => anyhow::bail!("invalid transaction state: {value}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TransactionMetadata {
+    pub transactional_id: Bytes,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub timeout_ms: i32,
+    pub state: TxnState,
+    pub partitions: BTreeSet<(Bytes, i32)>,
+    pub last_update_timestamp: i64,
+    pub start_timestamp: i64,
+}
+
+impl TransactionMetadata {
+    const VERSION: i16 = 0;
+
+    fn encode_key(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        buf.put_i16(Self::VERSION);
+        write_string(&mut buf, &self.transactional_id);
+        buf.freeze()
+    }
+
+    fn encode_value(&self) -> Bytes {
+        let mut topics: HashMap<&Bytes, Vec<i32>> = HashMap::new();
+        for (topic, partition) in self.partitions.iter() {
+            topics.entry(topic).or_default().push(*partition);
+        }
+
+        let mut buf = BytesMut::new();
+        buf.put_i16(Self::VERSION);
+        buf.put_i64(self.producer_id);
+        buf.put_i16(self.producer_epoch);
+        buf.put_i32(self.timeout_ms);
+        buf.put_i8(self.state as i8);
+        buf.put_i32(topics.len() as i32);
+        for (topic, partitions) in topics {
+            write_string(&mut buf, topic);
+            buf.put_i32(partitions.len() as i32);
+            for partition in partitions {
+                buf.put_i32(partition);
+            }
+        }
+        buf.put_i64(self.last_update_timestamp);
+        buf.put_i64(self.start_timestamp);
+        buf.freeze()
+    }
+
+    fn decode(key: Bytes, mut value: Bytes) -> Result<Self> {
+        let transactional_id = decode_key(key)?;
+
+        let _version = take(&mut value, 2, |buf| buf.get_i16())?;
+        let producer_id = take(&mut value, 8, |buf| buf.get_i64())?;
+        let producer_epoch = take(&mut value, 2, |buf| buf.get_i16())?;
+        let timeout_ms = take(&mut value, 4, |buf| buf.get_i32())?;
+        let state = TxnState::try_from(take(&mut value, 1, |buf| buf.get_i8())?)?;
+        let mut partitions = BTreeSet::new();
+        let topics_len = take(&mut value, 4, |buf| buf.get_i32())?;
+        for _ in 0..topics_len.max(0) {
+            let topic = read_string(&mut value).context("truncated topic name")?;
+            let partitions_len = take(&mut value, 4, |buf| buf.get_i32())?;
+            for _ in 0..partitions_len.max(0) {
+                partitions.insert((topic.clone(), take(&mut value, 4, |buf| buf.get_i32())?));
+            }
+        }
+        let last_update_timestamp = take(&mut value, 8, |buf| buf.get_i64())?;
+        let start_timestamp = take(&mut value, 8, |buf| buf.get_i64())?;
+
+        Ok(Self {
+            transactional_id,
+            producer_id,
+            producer_epoch,
+            timeout_ms,
+            state,
+            partitions,
+            last_update_timestamp,
+            start_timestamp,
+        })
+    }
+
+    /// Checks a request's producer id and epoch against the transaction's.
+    fn validate(&self, producer_id: i64, producer_epoch: i16) -> Result<(), ErrorCode> {
+        if self.producer_id != producer_id {
+            return Err(ErrorCode::InvalidProducerIdMapping);
+        }
+
+        if self.producer_epoch != producer_epoch {
+            return Err(ErrorCode::ProducerFenced);
+        }
+
+        Ok(())
+    }
+}
+
+/// The transactional id a `__transaction_state` key is for.
+fn decode_key(mut key: Bytes) -> Result<Bytes> {
+    let _version = take(&mut key, 2, |buf| buf.get_i16())?;
+    read_string(&mut key).context("truncated transactional id")
+}
+
+/// Partition of `__transaction_state` owning a transactional id, hashed the way groups are
+/// onto `__consumer_offsets`.
+pub fn transaction_partition_for(transactional_id: &[u8]) -> i32 {
+    key_partition(transactional_id, TRANSACTION_STATE_PARTITIONS)
+}
+
+/// Reads a `size` byte field of a `__transaction_state` record, which may be cut short.
+fn take<T>(buf: &mut Bytes, size: usize, get: impl FnOnce(&mut Bytes) -> T) -> Result<T> {
+    if buf.remaining() < size {
+        bail!("truncated transaction state record");
+    }
+    Ok(get(buf))
+}
+
+/// Tracks every transactional producer, persisting each state change to the
+/// `__transaction_state` log and writing COMMIT/ABORT markers when transactions end.
+#[derive(Debug)]
+pub struct TransactionCoordinator {
+    logs: Arc<LogManager>,
+    producer_ids: Arc<ProducerIdManager>,
+    offsets: Arc<OffsetManager>,
+    transactions: Mutex<HashMap<Bytes, TransactionMetadata>>,
+}
+
+impl TransactionCoordinator {
+    /// Rebuilds the coordinator from `__transaction_state`, finishing any transaction that
+    /// was left half way through writing its markers.
+    pub fn new(
+        logs: Arc<LogManager>,
+        producer_ids: Arc<ProducerIdManager>,
+        offsets: Arc<OffsetManager>,
+    ) -> Result<Self> {
+        let topic = Bytes::from_static(TRANSACTION_STATE_TOPIC);
+        let mut batches = Vec::new();
+        for partition in 0..TRANSACTION_STATE_PARTITIONS {
+            if !logs.has_partition(&topic, partition) {
+                continue;
+            }
+
+            let content = logs
+                .with_partition(&topic, partition, |log| log.read_all())
+                .context("reading transaction state log")?;
+            batches.extend(RecordBatch::decode_all(content).context("decoding transaction state")?);
+        }
+
+        let mut transactions = HashMap::new();
+        for batch in batches {
+            for record in batch.records {
+                let Some(key) = record.key else { continue };
+                // A damaged record is left out rather than keeping the broker from starting
+                let loaded = match record.value {
+                    Some(value) => TransactionMetadata::decode(key, value).map(|metadata| {
+                        transactions.insert(metadata.transactional_id.clone(), metadata);
+                    }),
+                    None => decode_key(key).map(|transactional_id| {
+                        transactions.remove(&transactional_id);
+                    }),
+                };
+                if let Err(err) = loaded {
+                    eprintln!(
+                        "skipping transaction state at offset {}: {err:#}",
+                        batch.base_offset + record.offset_delta as i64
+                    );
+                }
+            }
+        }
+
+        let coordinator = Self {
+            logs,
+            producer_ids,
+            offsets,
+            transactions: Mutex::new(HashMap::new()),
+        };
+
+        for (id, mut metadata) in transactions {
+            match metadata.state {
+                TxnState::PrepareCommit => coordinator.complete(&mut metadata, true)?,
+                TxnState::PrepareAbort => coordinator.complete(&mut metadata, false)?,
+                _ => {}
+            }
+
+            coordinator.lock().insert(id, metadata);
+        }
+
+        Ok(coordinator)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Bytes, TransactionMetadata>> {
+        self.transactions
+            .lock()
+            .expect("transaction coordinator lock poisoned")
+    }
+
+    pub fn init_producer_id(
+        &self,
+        transactional_id: &Bytes,
+        timeout_ms: i32,
+        current_producer: Option<(i64, i16)>,
+    ) -> Result<(i64, i16), ErrorCode> {
+        if timeout_ms <= 0 || timeout_ms > MAX_TRANSACTION_TIMEOUT_MS {
+            return Err(ErrorCode::InvalidTransactionTimeout);
+        }
+
+        let mut transactions = self.lock();
+        let now = current_time_ms();
+        if !transactions.contains_key(transactional_id) {
+            let producer_id = self
+                .producer_ids
+                .generate()
+                .map_err(|_| ErrorCode::CoordinatorNotAvailable)?;
+
+            let metadata = TransactionMetadata {
+                transactional_id: transactional_id.clone(),
+                producer_id,
+                producer_epoch: 0,
+                timeout_ms,
+                state: TxnState::Empty,
+                partitions: BTreeSet::new(),
+                last_update_timestamp: now,
+                start_timestamp: -1,
+            };
+            self.persist(&metadata)?;
+            transactions.insert(transactional_id.clone(), metadata);
+            return Ok((producer_id, 0));
+        }
+
+        let metadata = transactions
+            .get_mut(transactional_id)
+            .expect("checked above");
+        // A producer that says which id and epoch it held has to hold the current ones
+        if current_producer
+            .is_some_and(|current| current != (metadata.producer_id, metadata.producer_epoch))
+        {
+            return Err(ErrorCode::InvalidProducerEpoch);
+        }
+        match metadata.state {
+            TxnState::PrepareCommit | TxnState::PrepareAbort => {
+                return Err(ErrorCode::ConcurrentTransactions);
+            }
+            // A new instance of the producer fences the old one and aborts its work
+            TxnState::Ongoing => self
+                .fence_and_abort(metadata)
+                .map_err(|_| ErrorCode::CoordinatorNotAvailable)?,
+            _ => self.bump_epoch(metadata)?,
+        }
+
+        metadata.timeout_ms = timeout_ms;
+        metadata.state = TxnState::Empty;
+        metadata.last_update_timestamp = now;
+        self.persist(metadata)?;
+        Ok((metadata.producer_id, metadata.producer_epoch))
+    }
+
+    pub fn add_partitions(
+        &self,
+        transactional_id: &Bytes,
+        producer_id: i64,
+        producer_epoch: i16,
+        partitions: &[(Bytes, i32)],
+    ) -> Result<(), ErrorCode> {
+        let mut transactions = self.lock();
+        let metadata = transactions
+            .get_mut(transactional_id)
+            .ok_or(ErrorCode::InvalidProducerIdMapping)?;
+        metadata.validate(producer_id, producer_epoch)?;
+
+        match metadata.state {
+            TxnState::PrepareCommit | TxnState::PrepareAbort => {
+                return Err(ErrorCode::ConcurrentTransactions);
+            }
+            TxnState::Ongoing => {}
+            _ => {
+                metadata.state = TxnState::Ongoing;
+                metadata.start_timestamp = current_time_ms();
+            }
+        }
+
+        metadata.partitions.extend(partitions.iter().cloned());
+        metadata.last_update_timestamp = current_time_ms();
+        self.persist(metadata)
+    }
+
+    /// Adds the group's `__consumer_offsets` partition so its offsets commit or abort
+    /// together with the rest of the transaction.
+    pub fn add_offsets(
+        &self,
+        transactional_id: &Bytes,
+        producer_id: i64,
+        producer_epoch: i16,
+        group_id: &Bytes,
+    ) -> Result<(), ErrorCode> {
+        let partition = (Bytes::from_static(OFFSETS_TOPIC), partition_for(group_id));
+        self.add_partitions(transactional_id, producer_id, producer_epoch, &[partition])
+    }
+
+    pub fn end_transaction(
+        &self,
+        transactional_id: &Bytes,
+        producer_id: i64,
+        producer_epoch: i16,
+        commit: bool,
+    ) -> Result<(), ErrorCode> {
+        let mut transactions = self.lock();
+        let metadata = transactions
+            .get_mut(transactional_id)
+            .ok_or(ErrorCode::InvalidProducerIdMapping)?;
+        metadata.validate(producer_id, producer_epoch)?;
+
+        match (metadata.state, commit) {
+            (TxnState::Ongoing, _) => self
+                .complete(metadata, commit)
+                .map_err(|_| ErrorCode::CoordinatorNotAvailable),
+            // Retries of an EndTxn that already went through
+            (TxnState::CompleteCommit, true) | (TxnState::CompleteAbort, false) => Ok(()),
+            (TxnState::PrepareCommit | TxnState::PrepareAbort, _) => {
+                Err(ErrorCode::ConcurrentTransactions)
+            }
+            _ => Err(ErrorCode::InvalidTxnState),
+        }
+    }
+
+    /// Whether a transactional producer may write to the partition, i.e. it added the
+    /// partition to its ongoing transaction first.
+    pub fn verify_partition(
+        &self,
+        transactional_id: &Bytes,
+        producer_id: i64,
+        producer_epoch: i16,
+        topic: &Bytes,
+        partition: i32,
+    ) -> Result<(), ErrorCode> {
+        let transactions = self.lock();
+        let metadata = transactions
+            .get(transactional_id)
+            .ok_or(ErrorCode::InvalidProducerIdMapping)?;
+        metadata.validate(producer_id, producer_epoch)?;
+
+        if metadata.state != TxnState::Ongoing
+            || !metadata.partitions.contains(&(topic.clone(), partition))
+        {
+            return Err(ErrorCode::InvalidTxnState);
+        }
+
+        Ok(())
+    }
+
+    /// Writes the offsets as transactional records into the group's `__consumer_offsets`
+    /// partition. They only become visible once the transaction's COMMIT marker lands.
+    pub fn txn_offset_commit(
+        &self,
+        transactional_id: &Bytes,
+        producer_id: i64,
+        producer_epoch: i16,
+        group_id: &Bytes,
+        offsets: Vec<(OffsetCommitKey, OffsetCommitValue)>,
+    ) -> Result<(), ErrorCode> {
+        self.verify_partition(
+            transactional_id,
+            producer_id,
+            producer_epoch,
+            &Bytes::from_static(OFFSETS_TOPIC),
+            partition_for(group_id),
+        )?;
+
+        self.offsets
+            .commit_transactional(producer_id, producer_epoch, group_id, offsets)
+    }
+
+    /// Aborts transactions that outlived their timeout, fencing the producer so it cannot
+    /// keep writing into the aborted transaction.
+    pub fn abort_timed_out(&self) {
+        let now = current_time_ms();
+        let mut transactions = self.lock();
+        for metadata in transactions.values_mut() {
+            if metadata.state != TxnState::Ongoing
+                || now - metadata.start_timestamp < metadata.timeout_ms as i64
+            {
+                continue;
+            }
+
+            if let Err(err) = self.fence_and_abort(metadata) {
+                eprintln!(
+                    "aborting timed out transaction {:?}: {err:#}",
+                    metadata.transactional_id
+                );
+            }
+        }
+    }
+
+    /// Gives the producer its next epoch. Epochs handed out stop short of `i16::MAX`, which
+    /// is kept for fencing the last one.
+    fn bump_epoch(&self, metadata: &mut TransactionMetadata) -> Result<(), ErrorCode> {
+        if metadata.producer_epoch >= i16::MAX - 1 {
+            self.rotate_producer_id(metadata)?;
+        } else {
+            metadata.producer_epoch += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Exhausted epochs move the transactional id onto a fresh producer id.
+    fn rotate_producer_id(&self, metadata: &mut TransactionMetadata) -> Result<(), ErrorCode> {
+        metadata.producer_id = self
+            .producer_ids
+            .generate()
+            .map_err(|_| ErrorCode::CoordinatorNotAvailable)?;
+        metadata.producer_epoch = 0;
+        Ok(())
+    }
+
+    /// Fences the producer with a bumped epoch and aborts its ongoing transaction. The
+    /// markers go out under the producer id the partitions know, so a producer id whose
+    /// epochs ran out is only rotated once its transaction is aborted.
+    fn fence_and_abort(&self, metadata: &mut TransactionMetadata) -> Result<()> {
+        metadata.producer_epoch = metadata.producer_epoch.saturating_add(1);
+        self.complete(metadata, false)?;
+
+        if metadata.producer_epoch >= i16::MAX - 1 {
+            self.rotate_producer_id(metadata)
+                .and_then(|_| self.persist(metadata))
+                .map_err(|err| anyhow::anyhow!("rotating producer id: {err:?}"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves the transaction through Prepare to Complete, writing a marker into every
+    /// partition it touched in between.
+    fn complete(&self, metadata: &mut TransactionMetadata, commit: bool) -> Result<()> {
+        let (prepare, complete, marker) = if commit {
+            (
+                TxnState::PrepareCommit,
+                TxnState::CompleteCommit,
+                ControlRecordType::Commit,
+            )
+        } else {
+            (
+                TxnState::PrepareAbort,
+                TxnState::CompleteAbort,
+                ControlRecordType::Abort,
+            )
+        };
+
+        metadata.state = prepare;
+        metadata.last_update_timestamp = current_time_ms();
+        self.persist(metadata)
+            .map_err(|err| anyhow::anyhow!("persisting prepare: {err:?}"))?;
+
+        for (topic, partition) in metadata.partitions.iter() {
+            write_txn_marker(
+                &self.logs,
+                topic,
+                *partition,
+                metadata.producer_id,
+                metadata.producer_epoch,
+                COORDINATOR_EPOCH,
+                marker,
+            )
+            .with_context(|| format!("writing marker to {topic:?}-{partition}"))?;
+
+            if topic == OFFSETS_TOPIC {
+                self.offsets
+                    .complete_transaction(*partition, metadata.producer_id, commit);
+            }
+        }
+
+        metadata.state = complete;
+        metadata.partitions.clear();
+        metadata.last_update_timestamp = current_time_ms();
+        self.persist(metadata)
+            .map_err(|err| anyhow::anyhow!("persisting completion: {err:?}"))
+    }
+
+    fn persist(&self, metadata: &TransactionMetadata) -> Result<(), ErrorCode> {
+        let record = Record::new(Some(metadata.encode_key()), Some(metadata.encode_value()));
+        let batch = RecordBatch::new(current_time_ms(), vec![record]);
+        let records = batch.encode().map_err(|_| ErrorCode::Unknown)?;
+
+        self.logs
+            .append(
+                &Bytes::from_static(TRANSACTION_STATE_TOPIC),
+                transaction_partition_for(&metadata.transactional_id),
+                records,
+            )
+            .map_err(|err| {
+                eprintln!("persisting transaction state: {err:#}");
+                ErrorCode::CoordinatorNotAvailable
+            })?;
+
+        Ok(())
+    }
+}
+
+/// Appends a COMMIT or ABORT control batch to a partition on behalf of a producer.
+pub fn write_txn_marker(
+    logs: &LogManager,
+    topic: &Bytes,
+    partition: i32,
+    producer_id: i64,
+    producer_epoch: i16,
+    coordinator_epoch: i32,
+    marker: ControlRecordType,
+) -> Result<()> {
+    let batch = RecordBatch::end_txn_marker(
+        producer_id,
+        producer_epoch,
+        coordinator_epoch,
+        marker,
+        current_time_ms(),
+    );
+    let records = batch.encode().context("encoding transaction marker")?;
+    logs.append(topic, partition, records)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::BrokerConfig, log::AbortedTxn, metadata::MetadataWriter};
+
+    const TOPIC: &[u8] = b"events";
+
+    /// A coordinator over fresh logs, unique to the test.
+    fn coordinator(name: &str) -> (Arc<LogManager>, TransactionCoordinator) {
+        let dir = std::env::temp_dir().join(format!(
+            "txn-test-{name}-{}-{}",
+            std::process::id(),
+            current_time_ms()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = BrokerConfig::from_args([
+            "--override".to_string(),
+            format!("log.dirs={}", dir.display()),
+        ])
+        .unwrap();
+
+        let logs = Arc::new(LogManager::new(&config));
+        let producer_ids = Arc::new(ProducerIdManager::new(
+            config.node_id,
+            0,
+            MetadataWriter::Log(Arc::clone(&logs)),
+        ));
+        let offsets = Arc::new(OffsetManager::new(Arc::clone(&logs)).unwrap());
+        let coordinator =
+            TransactionCoordinator::new(Arc::clone(&logs), producer_ids, offsets).unwrap();
+        (logs, coordinator)
+    }
+
+    /// Starts a transaction on `events-0` and writes one batch into it.
+    fn produce_in_transaction(
+        logs: &LogManager,
+        coordinator: &TransactionCoordinator,
+        transactional_id: &Bytes,
+    ) -> (i64, i16) {
+        let (producer_id, producer_epoch) = coordinator
+            .init_producer_id(transactional_id, 60_000, None)
+            .unwrap();
+        coordinator
+            .add_partitions(
+                transactional_id,
+                producer_id,
+                producer_epoch,
+                &[(Bytes::from_static(TOPIC), 0)],
+            )
+            .unwrap();
+
+        let mut batch = RecordBatch::new(
+            current_time_ms(),
+            vec![Record::new(None, Some(Bytes::from_static(b"value")))],
+        );
+        batch.producer_id = producer_id;
+        batch.producer_epoch = producer_epoch;
+        batch.set_transactional();
+        logs.append(&Bytes::from_static(TOPIC), 0, batch.encode().unwrap())
+            .unwrap();
+
+        (producer_id, producer_epoch)
+    }
+
+    fn stable_offsets(logs: &LogManager) -> (i64, i64) {
+        logs.with_partition(&Bytes::from_static(TOPIC), 0, |log| {
+            Ok((log.last_stable_offset(), log.log_end_offset()))
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn open_transactions_hold_back_the_last_stable_offset() {
+        let (logs, coordinator) = coordinator("commit");
+        let transactional_id = Bytes::from_static(b"commit");
+        let (producer_id, producer_epoch) =
+            produce_in_transaction(&logs, &coordinator, &transactional_id);
+        assert_eq!(stable_offsets(&logs), (0, 1));
+
+        coordinator
+            .end_transaction(&transactional_id, producer_id, producer_epoch, true)
+            .unwrap();
+        // The COMMIT marker sits at offset 1
+        assert_eq!(stable_offsets(&logs), (2, 2));
+        let aborted = logs
+            .with_partition(&Bytes::from_static(TOPIC), 0, |log| {
+                Ok(log.aborted_transactions(0, 2))
+            })
+            .unwrap();
+        assert!(aborted.is_empty());
+
+        // Retrying the EndTxn is fine, switching to an abort is not
+        assert!(
+            coordinator
+                .end_transaction(&transactional_id, producer_id, producer_epoch, true)
+                .is_ok()
+        );
+        assert_eq!(
+            coordinator.end_transaction(&transactional_id, producer_id, producer_epoch, false),
+            Err(ErrorCode::InvalidTxnState)
+        );
+    }
+
+    #[test]
+    fn transactions_are_reloaded_from_the_partition_their_id_hashes_to() {
+        let (logs, coordinator) = coordinator("reload");
+        let transactional_id = Bytes::from_static(b"reload");
+        let (producer_id, producer_epoch) = coordinator
+            .init_producer_id(&transactional_id, 60_000, None)
+            .unwrap();
+        let partition = transaction_partition_for(&transactional_id);
+        assert_ne!(partition, 0);
+        assert!(logs.has_partition(&Bytes::from_static(TRANSACTION_STATE_TOPIC), partition));
+
+        let producer_ids = Arc::new(ProducerIdManager::new(
+            0,
+            producer_id + 1,
+            MetadataWriter::Log(Arc::clone(&logs)),
+        ));
+        let offsets = Arc::new(OffsetManager::new(Arc::clone(&logs)).unwrap());
+        let reloaded =
+            TransactionCoordinator::new(Arc::clone(&logs), producer_ids, offsets).unwrap();
+        // The restarted producer keeps its id under a bumped epoch
+        assert_eq!(
+            reloaded.init_producer_id(
+                &transactional_id,
+                60_000,
+                Some((producer_id, producer_epoch))
+            ),
+            Ok((producer_id, producer_epoch + 1))
+        );
+    }
+
+    #[test]
+    fn aborted_transactions_are_indexed_for_read_committed_fetches() {
+        let (logs, coordinator) = coordinator("abort");
+        let transactional_id = Bytes::from_static(b"abort");
+        let (producer_id, producer_epoch) =
+            produce_in_transaction(&logs, &coordinator, &transactional_id);
+
+        coordinator
+            .end_transaction(&transactional_id, producer_id, producer_epoch, false)
+            .unwrap();
+        assert_eq!(stable_offsets(&logs), (2, 2));
+        let aborted = logs
+            .with_partition(&Bytes::from_static(TOPIC), 0, |log| {
+                Ok(log.aborted_transactions(0, 2))
+            })
+            .unwrap();
+        assert_eq!(
+            aborted,
+            [AbortedTxn {
+                producer_id,
+                first_offset: 0,
+                last_offset: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn transactional_offsets_only_show_once_committed() {
+        let (logs, coordinator) = coordinator("offsets");
+        let group_id = Bytes::from_static(b"group");
+        let key = OffsetCommitKey {
+            group_id: group_id.clone(),
+            topic: Bytes::from_static(TOPIC),
+            partition: 0,
+        };
+        let value = OffsetCommitValue {
+            offset: 42,
+            leader_epoch: -1,
+            metadata: Bytes::new(),
+            commit_timestamp: current_time_ms(),
+        };
+
+        for (transactional_id, commit) in [(&b"aborted"[..], false), (&b"committed"[..], true)] {
+            let transactional_id = Bytes::copy_from_slice(transactional_id);
+            let (producer_id, producer_epoch) =
+                produce_in_transaction(&logs, &coordinator, &transactional_id);
+            coordinator
+                .add_offsets(&transactional_id, producer_id, producer_epoch, &group_id)
+                .unwrap();
+            coordinator
+                .txn_offset_commit(
+                    &transactional_id,
+                    producer_id,
+                    producer_epoch,
+                    &group_id,
+                    vec![(key.clone(), value.clone())],
+                )
+                .unwrap();
+            assert_eq!(
+                coordinator.offsets.committed(&group_id, &key.topic, 0),
+                None
+            );
+
+            coordinator
+                .end_transaction(&transactional_id, producer_id, producer_epoch, commit)
+                .unwrap();
+            let committed = coordinator.offsets.committed(&group_id, &key.topic, 0);
+            assert_eq!(committed.is_some(), commit);
+        }
+    }
+
+    #[test]
+    fn truncated_transaction_state_is_an_error() {
+        let metadata = TransactionMetadata {
+            transactional_id: Bytes::from_static(b"txn"),
+            producer_id: 7,
+            producer_epoch: 2,
+            timeout_ms: 60_000,
+            state: TxnState::Ongoing,
+            partitions: BTreeSet::from([(Bytes::from_static(TOPIC), 3)]),
+            last_update_timestamp: 10,
+            start_timestamp: 5,
+        };
+        let key = metadata.encode_key();
+        let value = metadata.encode_value();
+
+        let decoded = TransactionMetadata::decode(key.clone(), value.clone()).unwrap();
+        assert_eq!(decoded.partitions, metadata.partitions);
+        assert_eq!(decoded.state, TxnState::Ongoing);
+
+        for len in [0, 1, 11, value.len() - 1] {
+            assert!(TransactionMetadata::decode(key.clone(), value.slice(..len)).is_err());
+        }
+        assert!(TransactionMetadata::decode(key.slice(..3), value).is_err());
+    }
+}